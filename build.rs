@@ -0,0 +1,9 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protox compiles the proto in Rust, so building needs no protoc
+    let descriptors = protox::compile(["proto/morganite.proto"], ["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(descriptors)?;
+    println!("cargo:rerun-if-changed=proto/morganite.proto");
+    Ok(())
+}
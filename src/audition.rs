@@ -0,0 +1,133 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{tts, utils, voices, writer};
+
+#[derive(clap::Args)]
+pub struct AuditionArgs {
+    /// Sample text read by every voice
+    #[arg(long)]
+    text: String,
+
+    /// Voices to try, comma separated, or `all`
+    #[arg(long, default_value = "all")]
+    voices: String,
+
+    /// Path for onnx tts model
+    #[arg(long, short, default_value = "kokoro-v1.1-zh.onnx")]
+    tts_model: String,
+
+    /// Path for voice bin model
+    #[arg(long, short, default_value = "voices-v1.1-zh.bin")]
+    voice_model: String,
+
+    /// Speech speed
+    #[arg(long, value_parser = utils::parse_speed, default_value_t = 1.0)]
+    speed: f32,
+
+    /// Concurrency
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Folder for the clips
+    #[arg(long, default_value = "audition")]
+    out_dir: PathBuf,
+}
+
+pub async fn run(args: &AuditionArgs) -> anyhow::Result<()> {
+    let voice_bin = voices::VoiceBin::load(Path::new(&args.voice_model))?;
+
+    let names = if args.voices == "all" {
+        utils::VOICE_NAMES
+            .iter()
+            .copied()
+            .filter(|name| voice_bin.contains(name))
+            .map(str::to_string)
+            .collect()
+    } else {
+        args.voices
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+    };
+
+    // Report every bad name at once rather than failing on the first
+    let mut voices = Vec::new();
+    let mut problems = Vec::new();
+    for name in &names {
+        match utils::parse_voice(name) {
+            Ok(voice) => match voice_bin.check(voice) {
+                Some(e) => problems.push(e),
+                None => voices.push(voice),
+            },
+            Err(e) => problems.push(e),
+        }
+    }
+    anyhow::ensure!(
+        problems.is_empty(),
+        "Invalid voices:\n  {}",
+        problems.join("\n  ")
+    );
+    anyhow::ensure!(!voices.is_empty(), "No voices to audition");
+
+    std::fs::create_dir_all(&args.out_dir)
+        .with_context(|| format!("Failed to create {}", args.out_dir.display()))?;
+
+    let engine = Arc::new(
+        tts::init_tts(
+            args.tts_model.clone(),
+            args.voice_model.clone(),
+            args.concurrency,
+        )
+        .await?,
+    );
+
+    let sem = Arc::new(Semaphore::new(args.concurrency));
+    let mut set = JoinSet::new();
+    for (index, voice) in voices.into_iter().enumerate() {
+        let permit = sem.clone().acquire_owned().await?;
+        let engine = engine.clone();
+        let text = args.text.clone();
+        let path = args
+            .out_dir
+            .join(format!("{}.mp3", utils::voice_name(voice)));
+        let voice = utils::change_voice_speed(voice, args.speed);
+        set.spawn(async move {
+            let _permit = permit;
+            let start = Instant::now();
+            let (audio, _) = engine
+                .synth::<String>(text, voice)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))?;
+            let took = start.elapsed();
+            writer::write_mp3(&path, writer::default_mono_24k_config(64), &audio)?;
+            let duration = Duration::from_secs_f64(audio.len() as f64 / writer::SAMPLE_RATE as f64);
+            anyhow::Ok((index, utils::voice_name(voice), duration, took))
+        });
+    }
+
+    let mut rows = Vec::new();
+    while let Some(r) = set.join_next().await {
+        rows.push(r??);
+    }
+    rows.sort_by_key(|(index, ..)| *index);
+
+    println!("{:<10} {:>10} {:>10}", "voice", "duration", "synth");
+    for (_, name, duration, took) in rows {
+        println!(
+            "{:<10} {:>9.2}s {:>9.2}s",
+            name,
+            duration.as_secs_f64(),
+            took.as_secs_f64()
+        );
+    }
+    println!("Clips written to {}", args.out_dir.display());
+    Ok(())
+}
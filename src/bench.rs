@@ -0,0 +1,348 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{lang, pipeline, preprocess, text, tts, utils, writer};
+
+/// Mixed-length lines used when no corpus is given.
+pub const BUILTIN_CORPUS: &[&str] = &[
+    "你好。",
+    "今天天气很好，我们去公园散步吧。",
+    "他推开门，屋里一片漆黑，只有窗外的路灯透进一点微弱的光。",
+    "“你到底想说什么？”她皱着眉头问道。",
+    "第一章",
+    "火车在夜色中穿过山谷，车厢里的乘客大多已经睡着了，只有角落里的老人还望着窗外出神。",
+    "好。",
+    "这件事说来话长，要从十年前的那个冬天讲起。",
+    "他们沿着河岸一直走到天黑，谁也没有再提起那封信。",
+    "“等一下！”",
+    "城市的另一头，钟楼敲响了午夜的第十二下，街上的行人渐渐散去，只剩下巡夜的警察和几只流浪猫。",
+    "她笑了笑，没有回答。",
+];
+
+#[derive(clap::Args)]
+pub struct BenchArgs {
+    /// Path for onnx tts model
+    #[arg(long, short, default_value = "kokoro-v1.1-zh.onnx")]
+    tts_model: String,
+
+    /// Path for voice bin model
+    #[arg(long, short, default_value = "voices-v1.1-zh.bin")]
+    voice_model: String,
+
+    /// Voice style, see list-voices
+    #[arg(long, default_value = "zf_048")]
+    voice: String,
+
+    /// Speech speed
+    #[arg(long, value_parser = utils::parse_speed, default_value_t = 1.0)]
+    speed: f32,
+
+    /// Lines to synthesize per configuration, cycling through the corpus
+    #[arg(long, default_value_t = 200)]
+    lines: usize,
+
+    /// Concurrency levels to compare, comma separated
+    #[arg(long, value_delimiter = ',', default_value = "1,2,4,8")]
+    concurrency: Vec<usize>,
+
+    /// Batch very short lines as in a run, to compare against unbatched synthesis
+    #[arg(long, value_parser = utils::parse_batch_size)]
+    batch_short_lines: Option<usize>,
+
+    /// Channel capacity in chunks [default: 2 x concurrency]
+    #[arg(long, value_parser = utils::parse_count)]
+    queue_depth: Option<usize>,
+
+    /// Lines scheduled ahead of the writer [default: 2 x concurrency]
+    #[arg(long, value_parser = utils::parse_count)]
+    lookahead: Option<usize>,
+
+    /// Write the audio as MP3, once encoded by the writer thread and once by the
+    /// workers as with --parallel-encode, and report the throughput gain
+    #[arg(long)]
+    encode: bool,
+
+    /// Text file to take the lines from instead of the built-in corpus
+    #[arg(long)]
+    corpus: Option<PathBuf>,
+
+    /// Also write the results as JSON
+    #[arg(long)]
+    json: Option<PathBuf>,
+}
+
+/// Where the synthesized audio goes in one measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Output {
+    /// Measured, never written.
+    Discard,
+    /// Encoded by the writer thread, as by default.
+    Writer,
+    /// Encoded by the workers and only concatenated by the writer, as with
+    /// `--parallel-encode`.
+    Workers,
+}
+
+impl Output {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Discard => "none",
+            Self::Writer => "writer",
+            Self::Workers => "workers",
+        }
+    }
+}
+
+/// Results for one concurrency level and output.
+#[derive(Debug, Serialize)]
+struct BenchResult {
+    concurrency: usize,
+    queue_depth: usize,
+    lookahead: usize,
+    output: Output,
+    lines: usize,
+    chars: usize,
+    wall_secs: f64,
+    lines_per_sec: f64,
+    chars_per_sec: f64,
+    audio_secs: f64,
+    /// Wall time over audio time; below 1 is faster than realtime.
+    rtf: f64,
+    /// Per-item synthesis latency.
+    p50_secs: f64,
+    p95_secs: f64,
+}
+
+pub async fn run(args: &BenchArgs) -> anyhow::Result<()> {
+    anyhow::ensure!(args.lines > 0, "--lines must be at least 1");
+    anyhow::ensure!(
+        !args.concurrency.is_empty() && !args.concurrency.contains(&0),
+        "--concurrency levels must be at least 1"
+    );
+
+    let corpus = match &args.corpus {
+        Some(path) => load_corpus(path)?,
+        None => BUILTIN_CORPUS.iter().map(|s| s.to_string()).collect(),
+    };
+    let items = Arc::new(
+        corpus
+            .iter()
+            .cycle()
+            .take(args.lines)
+            .enumerate()
+            .map(|(i, line)| text::Item {
+                text: line.clone(),
+                source_line: Some(i + 1),
+                chapter: None,
+                pause_after: Duration::ZERO,
+                voice: None,
+                speed: None,
+                lang: lang::detect(line).script,
+            })
+            .collect::<Vec<_>>(),
+    );
+    let chars = items.iter().map(|i| i.text.chars().count()).sum::<usize>();
+    let settings = pipeline::FileSettings {
+        voice: utils::parse_voice(&args.voice).map_err(|e| anyhow::anyhow!(e))?,
+        speed: args.speed,
+        bitrate: 64,
+    };
+
+    let outputs: &[Output] = if args.encode {
+        &[Output::Writer, Output::Workers]
+    } else {
+        &[Output::Discard]
+    };
+    let out_dir = std::env::temp_dir().join(format!("morganite-bench-{}", std::process::id()));
+    if args.encode {
+        std::fs::create_dir_all(&out_dir)
+            .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+    }
+    let results = measure_all(args, &items, chars, &settings, outputs, &out_dir).await;
+    if args.encode {
+        let _ = std::fs::remove_dir_all(&out_dir);
+    }
+    let results = results?;
+
+    println!(
+        "{:>11} {:>7} {:>5} {:>9} {:>9} {:>8} {:>8} {:>7} {:>8} {:>8}",
+        "concurrency",
+        "encoder",
+        "queue",
+        "lookahead",
+        "wall",
+        "lines/s",
+        "chars/s",
+        "rtf",
+        "p50",
+        "p95"
+    );
+    for r in &results {
+        println!(
+            "{:>11} {:>7} {:>5} {:>9} {:>8.1}s {:>8.2} {:>8.1} {:>7.3} {:>7.2}s {:>7.2}s",
+            r.concurrency,
+            r.output.name(),
+            r.queue_depth,
+            r.lookahead,
+            r.wall_secs,
+            r.lines_per_sec,
+            r.chars_per_sec,
+            r.rtf,
+            r.p50_secs,
+            r.p95_secs
+        );
+    }
+    // Each concurrency level's writer run is directly followed by its workers run
+    for pair in results.chunks(2) {
+        if let [writer, workers] = pair
+            && writer.output == Output::Writer
+        {
+            println!(
+                "x{}: --parallel-encode {:.1} chars/s against {:.1} with the single encoder ({:+.0}%)",
+                writer.concurrency,
+                workers.chars_per_sec,
+                writer.chars_per_sec,
+                (workers.chars_per_sec / writer.chars_per_sec.max(f64::EPSILON) - 1.0) * 100.0
+            );
+        }
+    }
+
+    if let Some(path) = &args.json {
+        let json = serde_json::to_string_pretty(&results)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Results written to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Run every concurrency level with each of `outputs`, writing MP3s under `out_dir`.
+async fn measure_all(
+    args: &BenchArgs,
+    items: &Arc<Vec<text::Item>>,
+    chars: usize,
+    settings: &pipeline::FileSettings,
+    outputs: &[Output],
+    out_dir: &Path,
+) -> anyhow::Result<Vec<BenchResult>> {
+    let mut results = Vec::new();
+    for &concurrency in &args.concurrency {
+        let engine = tts::init_tts(
+            args.tts_model.clone(),
+            args.voice_model.clone(),
+            concurrency,
+        )
+        .await?;
+        let engines = Arc::new(tts::Engines::new(engine, None));
+        // Measure steady-state throughput, not session setup
+        tts::warm_up(engines.clone(), settings.voice, concurrency).await?;
+        for &output in outputs {
+            let mut pipeline = pipeline::Pipeline::bare(
+                engines.clone(),
+                concurrency,
+                preprocess::Preprocessor::default(),
+            );
+            pipeline.batch_short_lines = args.batch_short_lines;
+            pipeline.queue_depth = args.queue_depth.unwrap_or(pipeline.queue_depth);
+            pipeline.lookahead = args.lookahead.unwrap_or(pipeline.lookahead);
+
+            let mut mp3 = match output {
+                Output::Discard => None,
+                Output::Writer | Output::Workers => Some(
+                    writer::Mp3Splitter::new(
+                        out_dir
+                            .join(format!("x{}_{}", concurrency, output.name()))
+                            .to_string_lossy(),
+                        writer::mp3_config(settings.bitrate, pipeline.channel_mode),
+                        Duration::from_hours(2),
+                    )?
+                    .spawn(pipeline.queue_depth),
+                ),
+            };
+            let mut latencies = Vec::with_capacity(items.len());
+            let mut samples = 0usize;
+            let start = Instant::now();
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<(usize, pipeline::Chunk)>(1);
+            let write = async {
+                while let Some((_, chunk)) = rx.recv().await {
+                    samples += chunk.audio.len();
+                    match (&mut mp3, chunk.encoded) {
+                        (Some(mp3), Some(bytes)) => mp3.write_encoded(chunk.audio, bytes).await?,
+                        (Some(mp3), None) => mp3.write_f32_interleaved(chunk.audio).await?,
+                        (None, _) => {}
+                    }
+                    if let Some(timing) = chunk.end {
+                        latencies.push(timing.took.as_secs_f64());
+                    }
+                }
+                anyhow::Ok(())
+            };
+            tokio::try_join!(
+                pipeline.synthesize(
+                    format!("bench x{}", concurrency),
+                    items.clone(),
+                    settings,
+                    (output == Output::Workers).then(|| {
+                        Arc::new(pipeline::ClipEncoding {
+                            bitrate: settings.bitrate,
+                            channel_mode: pipeline.channel_mode,
+                            quality: pipeline.encoder_quality,
+                            gain_db: 0.0,
+                            limiter: None,
+                        })
+                    }),
+                    tx,
+                ),
+                write
+            )
+            .with_context(|| format!("Benchmark at concurrency {} failed", concurrency))?;
+            // The writer's backlog counts: it is what a single encoder holds up
+            if let Some(mp3) = mp3 {
+                mp3.finalize().await?;
+            }
+            let wall = start.elapsed().as_secs_f64();
+
+            latencies.sort_by(f64::total_cmp);
+            let percentile =
+                |p: f64| latencies[((latencies.len() - 1) as f64 * p).round() as usize];
+            let audio_secs = samples as f64 / writer::SAMPLE_RATE as f64;
+            results.push(BenchResult {
+                concurrency,
+                queue_depth: pipeline.queue_depth,
+                lookahead: pipeline.lookahead,
+                output,
+                lines: items.len(),
+                chars,
+                wall_secs: wall,
+                lines_per_sec: items.len() as f64 / wall,
+                chars_per_sec: chars as f64 / wall,
+                audio_secs,
+                rtf: wall / audio_secs.max(f64::EPSILON),
+                p50_secs: percentile(0.5),
+                p95_secs: percentile(0.95),
+            });
+        }
+    }
+    Ok(results)
+}
+
+fn load_corpus(path: &Path) -> anyhow::Result<Vec<String>> {
+    let lines = text::read_non_empty_lines(path)?
+        .into_iter()
+        .map(|l| l.text)
+        .filter(|l| text::is_pronounceable(l))
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        !lines.is_empty(),
+        "Corpus {} has no lines to synthesize",
+        path.display()
+    );
+    Ok(lines)
+}
@@ -0,0 +1,229 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use sha2::{Digest, Sha256};
+
+use crate::{tts, utils};
+
+const MAGIC: &[u8; 4] = b"MGPC";
+/// Magic + sample count (u64) + SHA-256 of the sample bytes.
+const HEADER_LEN: usize = 4 + 8 + 32;
+
+/// Disk cache of synthesized clips, keyed by text, voice, speed and the model files'
+/// contents, so changing the model invalidates old entries.
+pub struct AudioCache {
+    dir: PathBuf,
+    model_hash: String,
+    max_bytes: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl AudioCache {
+    pub fn open(dir: PathBuf, model_files: &[&Path], max_bytes: u64) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache dir {}", dir.display()))?;
+
+        let model_hash = model_hash(model_files)?;
+        tracing::info!(
+            "Using audio cache {} (model hash {}, max {})",
+            dir.display(),
+            &model_hash[..12],
+            utils::format_bytes(max_bytes)
+        );
+
+        let cache = Self {
+            dir,
+            model_hash,
+            max_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+        };
+        cache.prune()?;
+        Ok(cache)
+    }
+
+    pub fn key(&self, engine: tts::EngineKind, text: &str, voice: Voice, speed: f32) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.model_hash.as_bytes());
+        hasher.update([0]);
+        hasher.update(engine.name().as_bytes());
+        hasher.update([0]);
+        hasher.update(utils::voice_name(voice).as_bytes());
+        hasher.update([0]);
+        hasher.update(speed.to_bits().to_le_bytes());
+        hasher.update(text.as_bytes());
+        hex(&hasher.finalize())
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(format!("{key}.pcm"))
+    }
+
+    /// Load a cached clip. Corrupt entries are removed and reported as a miss.
+    pub fn get(&self, key: &str) -> Option<Vec<f32>> {
+        let path = self.entry_path(key);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+
+        match decode(&bytes) {
+            Some(samples) => {
+                // Refresh the mtime so pruning is least-recently-used
+                if let Ok(f) = File::options().append(true).open(&path) {
+                    let _ = f.set_modified(SystemTime::now());
+                }
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                self.bytes_read
+                    .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                Some(samples)
+            }
+            None => {
+                tracing::warn!("Corrupt cache entry {}, resynthesizing", path.display());
+                let _ = std::fs::remove_file(&path);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, key: &str, samples: &[f32]) -> anyhow::Result<()> {
+        let path = self.entry_path(key);
+        let parent = path.parent().unwrap();
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create cache dir {}", parent.display()))?;
+
+        let data = samples
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let mut bytes = Vec::with_capacity(HEADER_LEN + data.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(samples.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&Sha256::digest(&data));
+        bytes.extend_from_slice(&data);
+
+        // Write then rename so concurrent readers never see a partial entry
+        let tmp = path.with_extension("tmp");
+        let mut f =
+            File::create(&tmp).with_context(|| format!("Failed to create {}", tmp.display()))?;
+        f.write_all(&bytes)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        drop(f);
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to store cache entry {}", path.display()))?;
+
+        self.bytes_written
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Remove least recently used entries until the cache fits `max_bytes`.
+    pub fn prune(&self) -> anyhow::Result<()> {
+        let mut entries = Vec::new();
+        for shard in std::fs::read_dir(&self.dir)?.filter_map(|e| e.ok()) {
+            if !shard.path().is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(shard.path())?.filter_map(|e| e.ok()) {
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                entries.push((mtime, meta.len(), entry.path()));
+            }
+        }
+
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|(mtime, _, _)| *mtime);
+        let mut removed = 0;
+        for (_, len, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                total -= len;
+                removed += 1;
+            }
+        }
+        tracing::info!(
+            "Pruned {} cache entries, cache now {}",
+            removed,
+            utils::format_bytes(total)
+        );
+        Ok(())
+    }
+
+    pub fn report(&self) {
+        tracing::info!(
+            "Cache stats: {} hits, {} misses, {} read, {} written",
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            utils::format_bytes(self.bytes_read.load(Ordering::Relaxed)),
+            utils::format_bytes(self.bytes_written.load(Ordering::Relaxed))
+        );
+    }
+}
+
+fn decode(bytes: &[u8]) -> Option<Vec<f32>> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+        return None;
+    }
+    let count = u64::from_le_bytes(bytes[4..12].try_into().ok()?) as usize;
+    let data = &bytes[HEADER_LEN..];
+    if data.len() != count * 4 || Sha256::digest(data)[..] != bytes[12..HEADER_LEN] {
+        return None;
+    }
+    Some(
+        data.chunks_exact(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+/// SHA-256 over the contents of the model files, as hex.
+pub fn model_hash(model_files: &[&Path]) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    for path in model_files {
+        hasher.update(hash_file(path)?);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hash_file(path: &Path) -> anyhow::Result<[u8; 32]> {
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut reader = BufReader::with_capacity(1 << 20, f);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,293 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{bench, cache, lang, pipeline, preprocess, text, tts, utils, writer};
+
+/// Sample for the English voices when no `--sample` is given.
+const BUILTIN_SAMPLE_EN: &[&str] = &[
+    "Hello.",
+    "The weather is lovely today, so we walked to the park after lunch.",
+    "He pushed the door open; the room was dark except for a thin line of light.",
+    "\"What exactly are you trying to say?\" she asked, frowning.",
+    "Chapter one",
+    "The train wound through the valley at night, and most of the passengers were asleep.",
+    "Fine.",
+    "It is a long story, and it begins on a winter morning ten years ago.",
+    "They walked along the river until dark, and neither of them mentioned the letter again.",
+    "Wait!",
+];
+
+#[derive(clap::Args)]
+pub struct CalibrateArgs {
+    /// Path for onnx tts model
+    #[arg(long, short, default_value = "kokoro-v1.1-zh.onnx")]
+    tts_model: String,
+
+    /// Path for voice bin model
+    #[arg(long, short, default_value = "voices-v1.1-zh.bin")]
+    voice_model: String,
+
+    /// Voice to calibrate, see list-voices
+    #[arg(long, default_value = "zf_048")]
+    voice: String,
+
+    /// Speech speed to calibrate
+    #[arg(long, value_parser = utils::parse_speed, default_value_t = 1.0)]
+    speed: f32,
+
+    /// Text file to read an excerpt of instead of the built-in sample
+    #[arg(long)]
+    sample: Option<PathBuf>,
+
+    /// Lines of --sample read
+    #[arg(long, value_parser = utils::parse_count, default_value_t = 60)]
+    sample_lines: usize,
+
+    /// Concurrent synthesis sessions, as in the runs to estimate
+    #[arg(long, value_parser = utils::parse_count)]
+    concurrency: Option<usize>,
+
+    /// Calibration file [default: $XDG_CACHE_HOME/morganite/calibration.json]
+    #[arg(long)]
+    file: Option<PathBuf>,
+}
+
+/// Rates measured for one voice, speed and pair of model files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub voice: String,
+    pub speed: f32,
+    /// [`cache::model_hash`] of the TTS and voice models.
+    pub model_hash: String,
+    /// Seconds of audio per input char.
+    pub audio_secs_per_char: f64,
+    /// Input chars synthesized per second of wall time, with `concurrency` sessions.
+    pub chars_per_sec: f64,
+    pub concurrency: usize,
+    pub chars: usize,
+    /// `built-in` or the `--sample` file.
+    pub sample: String,
+    pub measured: String,
+}
+
+impl Entry {
+    /// Says which calibration an estimate came from.
+    pub fn describe(&self) -> String {
+        format!(
+            "calibration for {} at {}x (models {}, measured {})",
+            self.voice,
+            self.speed,
+            &self.model_hash[..12],
+            self.measured
+        )
+    }
+
+    /// Wall rate with `concurrency` sessions, assuming it scales with them.
+    pub fn chars_per_sec_at(&self, concurrency: usize) -> f64 {
+        self.chars_per_sec * concurrency as f64 / self.concurrency.max(1) as f64
+    }
+}
+
+/// Every calibration measured so far, at most one per voice, speed and models.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Calibrations {
+    entries: Vec<Entry>,
+}
+
+impl Calibrations {
+    /// The file at `path`; none yet is no calibrations.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("Failed to parse calibration file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e)
+                .with_context(|| format!("Failed to read calibration file {}", path.display())),
+        }
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write calibration file {}", path.display()))
+    }
+
+    fn position(&self, voice: &str, speed: f32, model_hash: &str) -> Option<usize> {
+        self.entries.iter().position(|e| {
+            e.voice == voice && (e.speed - speed).abs() < 1e-3 && e.model_hash == model_hash
+        })
+    }
+
+    /// Add `entry`, replacing an earlier one for the same voice, speed and models.
+    fn upsert(&mut self, entry: Entry) {
+        match self.position(&entry.voice, entry.speed, &entry.model_hash) {
+            Some(i) => self.entries[i] = entry,
+            None => self.entries.push(entry),
+        }
+    }
+}
+
+pub fn default_file() -> Option<PathBuf> {
+    utils::cache_home().map(|dir| dir.join("calibration.json"))
+}
+
+/// The calibration matching `voice` (a canonical name), `speed` and the model
+/// files, if one was measured. The models are only hashed when the voice and speed
+/// have a calibration at all.
+pub fn lookup(
+    file: Option<&Path>,
+    tts_model: &str,
+    voice_model: &str,
+    voice: &str,
+    speed: f32,
+) -> anyhow::Result<Option<Entry>> {
+    let Some(path) = file.map(Path::to_path_buf).or_else(default_file) else {
+        return Ok(None);
+    };
+    let calibrations = Calibrations::load(&path)?;
+    if !calibrations
+        .entries
+        .iter()
+        .any(|e| e.voice == voice && (e.speed - speed).abs() < 1e-3)
+    {
+        return Ok(None);
+    }
+    let model_hash = cache::model_hash(&[Path::new(tts_model), Path::new(voice_model)])?;
+    Ok(calibrations
+        .position(voice, speed, &model_hash)
+        .map(|i| calibrations.entries[i].clone()))
+}
+
+pub async fn run(args: &CalibrateArgs) -> anyhow::Result<()> {
+    let voice = utils::parse_voice(&args.voice).map_err(|e| anyhow::anyhow!(e))?;
+    let voice_name = utils::voice_name(voice);
+    let path =
+        args.file.clone().or_else(default_file).context(
+            "No folder for the calibration file (no XDG_CACHE_HOME or HOME); give --file",
+        )?;
+    let (lines, sample) = match &args.sample {
+        Some(path) => (
+            excerpt(path, args.sample_lines)?,
+            path.display().to_string(),
+        ),
+        None => {
+            let builtin = if voice_name.starts_with('z') {
+                bench::BUILTIN_CORPUS
+            } else {
+                BUILTIN_SAMPLE_EN
+            };
+            (
+                builtin.iter().map(|s| s.to_string()).collect(),
+                "built-in".to_string(),
+            )
+        }
+    };
+    let items = Arc::new(
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| text::Item {
+                text: line.clone(),
+                source_line: Some(i + 1),
+                chapter: None,
+                pause_after: Duration::ZERO,
+                voice: None,
+                speed: None,
+                lang: lang::detect(line).script,
+            })
+            .collect::<Vec<_>>(),
+    );
+    let chars = items.iter().map(|i| i.text.chars().count()).sum::<usize>();
+    let settings = pipeline::FileSettings {
+        voice,
+        speed: args.speed,
+        bitrate: 64,
+    };
+
+    let model_hash = {
+        let (tts_model, voice_model) = (args.tts_model.clone(), args.voice_model.clone());
+        tokio::task::spawn_blocking(move || {
+            cache::model_hash(&[Path::new(&tts_model), Path::new(&voice_model)])
+        })
+        .await??
+    };
+    let concurrency = args
+        .concurrency
+        .unwrap_or_else(|| utils::auto_concurrency(utils::available_cores()));
+    let engine = tts::init_tts(
+        args.tts_model.clone(),
+        args.voice_model.clone(),
+        concurrency,
+    )
+    .await?;
+    let engines = Arc::new(tts::Engines::new(engine, None));
+    // Measure steady-state throughput, not session setup
+    tts::warm_up(engines.clone(), voice, concurrency).await?;
+    let pipeline =
+        pipeline::Pipeline::bare(engines, concurrency, preprocess::Preprocessor::default());
+
+    let mut samples = 0usize;
+    let start = Instant::now();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    let (synthesized, ()) = tokio::join!(
+        pipeline.synthesize("calibrate".to_string(), items, &settings, None, tx),
+        async {
+            while let Some((_, chunk)) = rx.recv().await {
+                samples += chunk.audio.len();
+            }
+        }
+    );
+    synthesized.context("Calibration synthesis failed")?;
+    let wall = start.elapsed().as_secs_f64();
+    let audio_secs = samples as f64 / writer::SAMPLE_RATE as f64;
+
+    let entry = Entry {
+        voice: voice_name.to_string(),
+        speed: args.speed,
+        model_hash,
+        audio_secs_per_char: audio_secs / chars as f64,
+        chars_per_sec: chars as f64 / wall,
+        concurrency,
+        chars,
+        sample,
+        measured: chrono::Local::now().format("%Y-%m-%d %H:%M").to_string(),
+    };
+    println!(
+        "{} chars of {} sample: {:.1}s of audio in {:.1}s",
+        chars, entry.sample, audio_secs, wall
+    );
+    println!(
+        "{:.3}s of audio per char, {:.1} chars/s with {} sessions",
+        entry.audio_secs_per_char, entry.chars_per_sec, concurrency
+    );
+    let mut calibrations = Calibrations::load(&path)?;
+    calibrations.upsert(entry);
+    calibrations.save(&path)?;
+    println!("Saved to {}", path.display());
+    Ok(())
+}
+
+/// The first `lines` pronounceable lines of `path`.
+fn excerpt(path: &Path, lines: usize) -> anyhow::Result<Vec<String>> {
+    let excerpt = text::read_non_empty_lines(path)?
+        .into_iter()
+        .map(|l| l.text)
+        .filter(|l| text::is_pronounceable(l))
+        .take(lines)
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        !excerpt.is_empty(),
+        "Sample {} has no lines to synthesize",
+        path.display()
+    );
+    Ok(excerpt)
+}
@@ -0,0 +1,229 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::utils;
+
+const DEFAULT_VERBS: &[&str] = &[
+    "说道",
+    "问道",
+    "笑道",
+    "喊道",
+    "叫道",
+    "答道",
+    "低声道",
+    "回答",
+    "说",
+    "道",
+    "问",
+    "喊",
+    "叫",
+    "答",
+    "嚷",
+    "said",
+    "asked",
+    "replied",
+    "answered",
+    "shouted",
+    "whispered",
+    "cried",
+];
+
+/// `--cast` file:
+///
+/// ```toml
+/// narrator = "zf_048"
+/// # verbs = ["说", "道", "said"]   # optional, replaces the built-in list
+///
+/// [characters]
+/// 李雷 = "zm_029"
+/// 韩梅梅 = { voice = "zf_002", speed = 1.05, aliases = ["梅梅"] }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CastFile {
+    narrator: Option<String>,
+    verbs: Option<Vec<String>>,
+    #[serde(default)]
+    characters: BTreeMap<String, CharacterEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum CharacterEntry {
+    Voice(String),
+    Full {
+        voice: String,
+        speed: Option<f32>,
+        #[serde(default)]
+        aliases: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Character {
+    pub name: String,
+    pub voice: Voice,
+    pub speed: Option<f32>,
+}
+
+pub struct Cast {
+    pub narrator: Option<Voice>,
+    pub characters: Vec<Character>,
+    /// Name (or alias) -> index into `characters`.
+    names: BTreeMap<String, usize>,
+    /// `”李雷说`, `," John said`, `," said John`
+    after_quote: Regex,
+    /// `李雷说：“`
+    before_quote: Regex,
+}
+
+impl Cast {
+    pub fn load(path: &Path, voice_names: &utils::VoiceNames) -> anyhow::Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cast file {}", path.display()))?;
+        let file: CastFile = toml::from_str(&raw)
+            .with_context(|| format!("Failed to parse cast file {}", path.display()))?;
+
+        let mut problems = Vec::new();
+        let narrator = match file.narrator.as_deref().map(|v| voice_names.parse(v)) {
+            Some(Ok(v)) => Some(v),
+            Some(Err(e)) => {
+                problems.push(format!("narrator: {e}"));
+                None
+            }
+            None => None,
+        };
+
+        let mut characters = Vec::new();
+        let mut names = BTreeMap::new();
+        for (name, entry) in file.characters {
+            let (voice, speed, aliases) = match entry {
+                CharacterEntry::Voice(voice) => (voice, None, Vec::new()),
+                CharacterEntry::Full {
+                    voice,
+                    speed,
+                    aliases,
+                } => (voice, speed, aliases),
+            };
+            if let Some(Err(e)) = speed.map(utils::check_speed) {
+                problems.push(format!("{name}: {e}"));
+            }
+            let voice = match voice_names.parse(&voice) {
+                Ok(v) => v,
+                Err(e) => {
+                    problems.push(format!("{name}: {e}"));
+                    continue;
+                }
+            };
+            for alias in std::iter::once(&name).chain(&aliases) {
+                if names.insert(alias.clone(), characters.len()).is_some() {
+                    problems.push(format!("{alias}: name used by more than one character"));
+                }
+            }
+            characters.push(Character { name, voice, speed });
+        }
+        anyhow::ensure!(
+            problems.is_empty(),
+            "Invalid entries in cast file {}:\n  {}",
+            path.display(),
+            problems.join("\n  ")
+        );
+        anyhow::ensure!(
+            !names.is_empty(),
+            "Cast file {} has no characters",
+            path.display()
+        );
+
+        let verbs = file
+            .verbs
+            .unwrap_or_else(|| DEFAULT_VERBS.iter().map(|v| v.to_string()).collect());
+        let name_alt = alternation(names.keys());
+        let verb_alt = alternation(verbs.iter());
+        let after_quote = Regex::new(&format!(
+            r#"[”」』"][\s,，]*(?:(?P<name>{name_alt})\s*(?:{verb_alt})|(?:{verb_alt})\s+(?P<name2>{name_alt}))"#
+        ))?;
+        let before_quote = Regex::new(&format!(
+            r#"(?P<name>{name_alt})\s*(?:{verb_alt})[\s:：,，]*[“「『"]"#
+        ))?;
+
+        tracing::info!(
+            "Loaded cast of {} characters from {}",
+            characters.len(),
+            path.display()
+        );
+        Ok(Self {
+            narrator,
+            characters,
+            names,
+            after_quote,
+            before_quote,
+        })
+    }
+
+    /// Conservative speaker attribution for a line containing dialogue: exactly one
+    /// character must be attributed next to a quote with a speech verb, otherwise
+    /// the line stays with the narrator.
+    pub fn attribute(&self, line: &str) -> Option<&Character> {
+        let mut found = None;
+        let after = self
+            .after_quote
+            .captures_iter(line)
+            .filter_map(|c| c.name("name").or_else(|| c.name("name2")));
+        let before = self
+            .before_quote
+            .captures_iter(line)
+            .filter_map(|c| c.name("name"));
+        for m in after.chain(before) {
+            let idx = self.names[m.as_str()];
+            match found {
+                None => found = Some(idx),
+                Some(prev) if prev != idx => return None,
+                _ => {}
+            }
+        }
+        found.map(|i| &self.characters[i])
+    }
+}
+
+/// Regex alternation of literal strings, longest first so the regex prefers them.
+fn alternation<'a>(items: impl Iterator<Item = &'a String>) -> String {
+    let mut items = items.collect::<Vec<_>>();
+    items.sort_by_key(|s| std::cmp::Reverse(s.chars().count()));
+    items
+        .iter()
+        .map(|s| regex::escape(s))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// Per-file attribution counts, to judge the heuristic's hit rate.
+#[derive(Default)]
+pub struct CastStats {
+    pub attributed: BTreeMap<String, usize>,
+    /// Lines with quotes that couldn't be attributed.
+    pub unattributed: usize,
+}
+
+impl CastStats {
+    pub fn report(&self, file_label: &str) {
+        let attributed = self
+            .attributed
+            .iter()
+            .map(|(name, n)| format!("{name} {n}"))
+            .collect::<Vec<_>>();
+        tracing::info!(
+            "Cast attribution for {}: {} (unattributed dialogue lines: {})",
+            file_label,
+            if attributed.is_empty() {
+                "none".to_string()
+            } else {
+                attributed.join(", ")
+            },
+            self.unattributed
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::LazyLock;
+
+use anyhow::Context;
+use regex::Regex;
+
+/// Matches headings like `第十二章 雪夜`, `第3回`, `Chapter 12: Snow` and `CHAPTER XII`.
+/// The optional `title` group is used for naming; without it the whole line is used.
+pub const DEFAULT_CHAPTER_REGEX: &str = r"^(?:第[0-9０-９零〇一二三四五六七八九十百千万两]+[章回节卷]|(?i:chapter)\s+(?:[0-9]+|[ivxlcdm]+)\b)[\s:：.、\-—]*(?P<title>.*)$";
+
+/// Longest sanitized title (in chars) used in folder names.
+const MAX_TITLE_CHARS: usize = 48;
+
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    /// 1-based, taken from occurrence order rather than the number in the heading.
+    pub number: usize,
+    pub title: String,
+    /// Sanitized `NNN_title` used for the output folder.
+    pub name: String,
+    /// Index of the heading into the file's line list.
+    pub line_index: usize,
+    /// Physical line number of the heading in the source file.
+    pub source_line: usize,
+}
+
+pub struct ChapterDetector {
+    re: Regex,
+}
+
+impl ChapterDetector {
+    pub fn new(pattern: &str) -> anyhow::Result<Self> {
+        let re = Regex::new(pattern).with_context(|| format!("Invalid chapter regex {pattern}"))?;
+        Ok(Self { re })
+    }
+
+    /// The title of `line` if it is a chapter heading.
+    pub fn title(&self, line: &str) -> Option<String> {
+        let caps = self.re.captures(line)?;
+        Some(
+            caps.name("title")
+                .map(|m| m.as_str().trim())
+                .filter(|t| !t.is_empty())
+                .unwrap_or(line)
+                .to_string(),
+        )
+    }
+}
+
+impl Chapter {
+    /// The `number`th heading of a file, `title`, found on its line `source_line`.
+    pub fn new(number: usize, title: String, line_index: usize, source_line: usize) -> Self {
+        Self {
+            number,
+            name: format!("{:03}_{}", number, sanitize_title(&title)),
+            title,
+            line_index,
+            source_line,
+        }
+    }
+}
+
+/// Whether a heading already carries its own chapter number (`第十二章`, `Chapter 12`).
+pub fn has_chapter_number(heading: &str) -> bool {
+    static NUMBERED: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"第\s*[0-9０-９零〇一二三四五六七八九十百千万两]+\s*[章回节卷]|(?i:chapter)\s+\S",
+        )
+        .unwrap()
+    });
+    NUMBERED.is_match(heading)
+}
+
+/// Make a title safe to use as a file or folder name on every platform.
+pub fn sanitize_title(title: &str) -> String {
+    let mut out = String::new();
+    for c in title.chars() {
+        let c = if c.is_control()
+            || c.is_whitespace()
+            || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+        {
+            '_'
+        } else {
+            c
+        };
+        if c == '_' && out.ends_with('_') {
+            continue;
+        }
+        out.push(c);
+    }
+
+    let out = out
+        .trim_matches(|c| c == '_' || c == '.')
+        .chars()
+        .take(MAX_TITLE_CHARS)
+        .collect::<String>();
+    let out = out.trim_end_matches(|c| c == '_' || c == '.');
+    if out.is_empty() {
+        "untitled".to_string()
+    } else {
+        out.to_string()
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::Local;
+
+/// How often `--clipboard-watch` looks at the clipboard.
+const POLL: Duration = Duration::from_secs(1);
+
+/// Open the system clipboard, with a message fit for the console when there is
+/// none (e.g. no display server).
+fn open() -> anyhow::Result<arboard::Clipboard> {
+    arboard::Clipboard::new().map_err(|e| anyhow::anyhow!("No clipboard available: {}", e))
+}
+
+/// The clipboard's text, or `None` when it is empty or holds something else.
+fn text(clipboard: &mut arboard::Clipboard) -> anyhow::Result<Option<String>> {
+    match clipboard.get_text() {
+        Ok(text) if text.trim().is_empty() => Ok(None),
+        Ok(text) => Ok(Some(text)),
+        Err(arboard::Error::ContentNotAvailable) => Ok(None),
+        Err(e) => Err(anyhow::anyhow!("Failed to read the clipboard: {}", e)),
+    }
+}
+
+/// `--clipboard`: save the clipboard's text as a `.txt` in `dir`, to be read like
+/// any input file.
+pub fn take(dir: &Path) -> anyhow::Result<PathBuf> {
+    let text =
+        text(&mut open()?)?.context("The clipboard has no text in it; copy some and try again")?;
+    save(dir, &text)
+}
+
+fn save(dir: &Path, text: &str) -> anyhow::Result<PathBuf> {
+    let path = dir.join(format!("clipboard_{}.txt", Local::now().format("%H%M%S")));
+    std::fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Give the single segment written for a clipboard `.txt` the text's name, e.g.
+/// `clipboard_153012.mp3`. Longer clips keep their segment names.
+pub fn name_output(txt_path: &Path, segments: &[String]) {
+    let [segment] = segments else {
+        return;
+    };
+    let target = txt_path.with_extension("mp3");
+    match std::fs::rename(segment, &target) {
+        Ok(()) => tracing::info!("Wrote {}", target.display()),
+        Err(e) => tracing::warn!(
+            "Failed to rename {} to {}: {}",
+            segment,
+            target.display(),
+            e
+        ),
+    }
+}
+
+/// `--clipboard-watch`: notices new text copied to the clipboard.
+pub struct Watch {
+    clipboard: arboard::Clipboard,
+    dir: PathBuf,
+    min_chars: usize,
+    last: Option<String>,
+}
+
+impl Watch {
+    /// Start watching; what is on the clipboard already doesn't count as new.
+    pub fn new(dir: PathBuf, min_chars: usize) -> anyhow::Result<Self> {
+        let mut clipboard = open()?;
+        let last = text(&mut clipboard).unwrap_or_default();
+        Ok(Self {
+            clipboard,
+            dir,
+            min_chars,
+            last,
+        })
+    }
+
+    /// Wait for text different from the last seen and at least `min_chars` long,
+    /// and save it as a `.txt` in the run folder.
+    pub async fn next(&mut self) -> PathBuf {
+        loop {
+            tokio::time::sleep(POLL).await;
+            let text = match text(&mut self.clipboard) {
+                Ok(Some(text)) => text,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::debug!("{:#}", e);
+                    continue;
+                }
+            };
+            if self.last.as_ref() == Some(&text) {
+                continue;
+            }
+            self.last = Some(text.clone());
+            let chars = text.trim().chars().count();
+            if chars < self.min_chars {
+                tracing::info!(
+                    "Ignoring {} copied chars (--clipboard-min-chars is {})",
+                    chars,
+                    self.min_chars
+                );
+                continue;
+            }
+            match save(&self.dir, &text) {
+                Ok(path) => return path,
+                Err(e) => tracing::error!("{:#}", e),
+            }
+        }
+    }
+}
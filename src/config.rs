@@ -0,0 +1,403 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{ArgAction, CommandFactory, FromArgMatches, parser::ValueSource};
+
+use crate::{Cli, overrides, podcast, utils};
+
+/// Read from the working directory when `--config` isn't given.
+pub const DEFAULT_FILE: &str = "morganite.toml";
+
+/// Options that don't belong in a config file, or in a dump of one.
+const SKIPPED: &[&str] = &["config", "dump_config", "webhook_secret", "force_unlock"];
+/// Options whose origin is logged at startup and named in their errors.
+const TRACED: &[&str] = &[
+    "tts_model",
+    "voice_model",
+    "voice",
+    "voice_prefix",
+    "output_dir",
+];
+
+/// The config file behind a run, once its options are in the [`Cli`]:
+///
+/// ```toml
+/// # morganite.toml
+/// tts_model = "models/kokoro-v1.1-zh.onnx"
+/// voice = "zf_048"
+/// bitrate = 96
+/// subtitles = ["srt", "lrc"]
+/// timings_json = true
+///
+/// [[files]]
+/// match = "appendix_*.txt"
+/// speed = 1.2
+///
+/// [podcast]
+/// title = "Collected Essays"
+/// author = "Morganite"
+/// ```
+#[derive(Default)]
+pub struct Config {
+    pub path: Option<PathBuf>,
+    /// `[[files]]` sections, for [`overrides::load_for`].
+    pub files: Vec<overrides::FileEntry>,
+    /// `[podcast]`: feed metadata under the `--podcast-*` options.
+    pub podcast: podcast::FeedMeta,
+    /// Problems worth a warning once logging is up.
+    pub warnings: Vec<String>,
+    /// `--lang`: the preset and what each of its options resolved to, for the log.
+    pub lang_preset: Option<String>,
+    /// Where each of the [`TRACED`] options got its value.
+    origins: HashMap<&'static str, String>,
+}
+
+impl Config {
+    /// Where option `id` got its value, e.g. `--voice`, `MORGANITE_VOICE`,
+    /// `voice in morganite.toml` or `default`; errors about the value start with it.
+    pub fn origin(&self, id: &str) -> &str {
+        self.origins.get(id).map_or("default", String::as_str)
+    }
+}
+
+/// Parse the command line, with the config file giving the options neither the
+/// command line nor the environment (`MORGANITE_*`, see `--help`) gives. Keys are
+/// option names (`bitrate`, `line_gap` or `line-gap`) and go through the same
+/// parsing and validation; flags take `true`, repeated options an array. Invalid arguments exit like clap, and `--dump-config` prints the
+/// effective options and exits. Subcommands don't read the config file.
+pub fn parse() -> anyhow::Result<(Cli, Config)> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    // Only to learn --config and what the command line gives; the real parse below
+    // reports any errors
+    let lenient = Cli::command().ignore_errors(true).get_matches_from(&args);
+    let path = match Cli::from_arg_matches(&lenient) {
+        Ok(cli) if cli.command.is_none() => cli.run.config.or_else(|| {
+            Path::new(DEFAULT_FILE)
+                .is_file()
+                .then(|| DEFAULT_FILE.into())
+        }),
+        _ => None,
+    };
+
+    let mut config = Config::default();
+    let mut from_file = Vec::new();
+    if let Some(path) = &path {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let mut table: toml::Table = toml::from_str(&text)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        take_sections(&mut table, path, &mut config)?;
+
+        let command = Cli::command();
+        let mut positional = Vec::new();
+        for (key, value) in table {
+            let id = key.replace('-', "_");
+            let Some(arg) = command
+                .get_arguments()
+                .find(|arg| arg.get_id() == id.as_str() && !SKIPPED.contains(&id.as_str()))
+            else {
+                config.warnings.push(format!(
+                    "Unknown key {} in {} is ignored",
+                    key,
+                    path.display()
+                ));
+                continue;
+            };
+            if matches!(
+                lenient.value_source(&id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            ) {
+                continue;
+            }
+            from_file.push(arg.get_id().to_string());
+            let values = values(value).with_context(|| format!("{} in {}", key, path.display()))?;
+            match arg.get_long() {
+                Some(long) if matches!(arg.get_action(), ArgAction::SetTrue) => {
+                    match values.as_slice() {
+                        [flag] if flag == "true" => args.push(format!("--{}", long).into()),
+                        [flag] if flag == "false" => {}
+                        _ => anyhow::bail!("{} in {} takes true or false", key, path.display()),
+                    }
+                }
+                Some(long) => {
+                    for value in values {
+                        args.push(format!("--{}={}", long, value).into());
+                    }
+                }
+                None => positional.extend(values),
+            }
+        }
+        if !positional.is_empty() {
+            args.push("--".into());
+            args.extend(positional.into_iter().map(OsString::from));
+        }
+        config.path = Some(path.clone());
+    }
+
+    let (preset, from_preset) = apply_lang_preset(&mut args)?;
+
+    let matches = Cli::command().get_matches_from(&args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let command = Cli::command();
+    for arg in command.get_arguments() {
+        let Some(&id) = TRACED.iter().find(|&&id| arg.get_id() == id) else {
+            continue;
+        };
+        let origin = if let Some(lang) = preset.filter(|_| from_preset.contains(&id)) {
+            format!("--lang {}", lang.name)
+        } else if from_file.iter().any(|f| f == id) {
+            format!(
+                "{} in {}",
+                id,
+                path.as_deref().unwrap_or(Path::new("")).display()
+            )
+        } else {
+            match (matches.value_source(id), arg.get_env(), arg.get_long()) {
+                (Some(ValueSource::EnvVariable), Some(env), _) => {
+                    env.to_string_lossy().into_owned()
+                }
+                (Some(ValueSource::CommandLine), _, Some(long)) => format!("--{}", long),
+                _ => "default".to_string(),
+            }
+        };
+        config.origins.insert(id, origin);
+    }
+    config.lang_preset = preset.map(|lang| describe_preset(lang, &matches, &from_preset));
+    if cli.run.dump_config {
+        print!("{}", dump(&matches, &config)?);
+        std::process::exit(0);
+    }
+    Ok((cli, config))
+}
+
+/// Move the `[[files]]` and `[podcast]` tables out of `table` into `config`,
+/// leaving only options.
+fn take_sections(table: &mut toml::Table, path: &Path, config: &mut Config) -> anyhow::Result<()> {
+    if let Some(files) = table.remove("files") {
+        config.files = files
+            .try_into()
+            .with_context(|| format!("[[files]] in {}", path.display()))?;
+    }
+    if let Some(podcast) = table.remove("podcast") {
+        config.podcast = podcast
+            .try_into()
+            .with_context(|| format!("[podcast] in {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Put the options of a `--lang` preset that the command line, environment and
+/// config file (already in `args`) don't give into `args`. Returns the preset and
+/// the options it gave.
+fn apply_lang_preset(
+    args: &mut Vec<OsString>,
+) -> anyhow::Result<(Option<&'static utils::LangPreset>, Vec<&'static str>)> {
+    let layered = Cli::command().ignore_errors(true).get_matches_from(&*args);
+    let mut from_preset = Vec::new();
+    let Ok(cli) = Cli::from_arg_matches(&layered) else {
+        return Ok((None, from_preset));
+    };
+    let Some(lang) = cli.run.lang.filter(|_| cli.command.is_none()) else {
+        return Ok((None, from_preset));
+    };
+    let command = Cli::command();
+    for &(id, value) in lang.options {
+        let long = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id)
+            .and_then(|arg| arg.get_long())
+            .with_context(|| format!("--lang {}: unknown option {}", lang.name, id))?;
+        if matches!(
+            layered.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        // Ahead of any positional inputs a config file appended after `--`
+        args.insert(1, format!("--{}={}", long, value).into());
+        from_preset.push(id);
+    }
+    Ok((Some(lang), from_preset))
+}
+
+/// `zh: tts_model kokoro-v1.1-zh.onnx, voice zm_029 (given; preset zf_048), ...`
+fn describe_preset(
+    lang: &utils::LangPreset,
+    matches: &clap::ArgMatches,
+    from_preset: &[&str],
+) -> String {
+    let options = lang
+        .options
+        .iter()
+        .map(|&(id, value)| {
+            let given = matches
+                .get_raw(id)
+                .and_then(|mut raw| raw.next())
+                .map(|raw| raw.to_string_lossy().into_owned())
+                .filter(|_| !from_preset.contains(&id));
+            match given {
+                Some(given) => format!("{} {} (given; preset {})", id, given, value),
+                None => format!("{} {}", id, value),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{}: {}", lang.name, options)
+}
+
+/// A config value as the strings an option would be given on the command line.
+fn values(value: toml::Value) -> anyhow::Result<Vec<String>> {
+    let scalar = |value: toml::Value| match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        other => anyhow::bail!("unsupported value {}", other),
+    };
+    match value {
+        toml::Value::Array(values) => values.into_iter().map(scalar).collect(),
+        value => Ok(vec![scalar(value)?]),
+    }
+}
+
+/// The effective options as a config file that reproduces the run.
+fn dump(matches: &clap::ArgMatches, config: &Config) -> anyhow::Result<String> {
+    let mut table = toml::Table::new();
+    for arg in Cli::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        if SKIPPED.contains(&id) {
+            continue;
+        }
+        let Some(raw) = matches.get_raw(id) else {
+            continue;
+        };
+        let mut values = raw.map(|v| typed(&v.to_string_lossy())).collect::<Vec<_>>();
+        let value = match arg.get_action() {
+            ArgAction::Append => toml::Value::Array(values),
+            _ if values.len() == 1 => values.remove(0),
+            _ => toml::Value::Array(values),
+        };
+        table.insert(id.to_string(), value);
+    }
+    if !config.files.is_empty() {
+        table.insert("files".to_string(), toml::Value::try_from(&config.files)?);
+    }
+    if config.podcast != podcast::FeedMeta::default() {
+        table.insert(
+            "podcast".to_string(),
+            toml::Value::try_from(&config.podcast)?,
+        );
+    }
+    Ok(toml::to_string(&table)?)
+}
+
+/// Numbers and booleans back as TOML numbers and booleans; the rest as strings.
+fn typed(value: &str) -> toml::Value {
+    if let Ok(b) = value.parse::<bool>() {
+        return toml::Value::Boolean(b);
+    }
+    let numeric = value
+        .chars()
+        .all(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+        && value.chars().any(|c| c.is_ascii_digit());
+    if numeric {
+        if let Ok(i) = value.parse::<i64>() {
+            return toml::Value::Integer(i);
+        }
+        if let Ok(f) = value.parse::<f64>() {
+            return toml::Value::Float(f);
+        }
+    }
+    toml::Value::String(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        std::iter::once("morganite")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect()
+    }
+
+    #[test]
+    fn every_preset_resolves() {
+        for lang in utils::LANG_PRESETS {
+            let mut args = args(&["--lang", lang.name, "book.txt"]);
+            let (preset, from_preset) = apply_lang_preset(&mut args).unwrap();
+            assert_eq!(preset.map(|p| p.name), Some(lang.name));
+            assert_eq!(from_preset.len(), lang.options.len(), "{}", lang.name);
+
+            let matches = Cli::command()
+                .try_get_matches_from(&args)
+                .unwrap_or_else(|e| panic!("--lang {}: {e}", lang.name));
+            for &(id, value) in lang.options {
+                let given = matches.get_raw(id).and_then(|mut raw| raw.next());
+                assert_eq!(given, Some(OsStr::new(value)), "--lang {}: {id}", lang.name);
+            }
+        }
+    }
+
+    #[test]
+    fn given_options_win_over_the_preset() {
+        let mut args = args(&["--lang", "en", "--speed", "1.2", "book.txt"]);
+        let (_, from_preset) = apply_lang_preset(&mut args).unwrap();
+        assert!(!from_preset.contains(&"speed"));
+        let matches = Cli::command().try_get_matches_from(&args).unwrap();
+        assert_eq!(matches.get_one::<f32>("speed"), Some(&1.2));
+    }
+
+    #[test]
+    fn no_preset_leaves_the_arguments_alone() {
+        let mut args = args(&["book.txt"]);
+        let before = args.clone();
+        let (preset, from_preset) = apply_lang_preset(&mut args).unwrap();
+        assert!(preset.is_none() && from_preset.is_empty());
+        assert_eq!(args, before);
+    }
+
+    #[test]
+    fn podcast_table_gives_the_feed_metadata() {
+        let mut table: toml::Table = toml::from_str(
+            r#"
+            voice = "zf_048"
+
+            [podcast]
+            title = "Collected Essays"
+            image = "https://example.com/cover.jpg"
+            "#,
+        )
+        .unwrap();
+        let mut config = Config::default();
+        take_sections(&mut table, Path::new("morganite.toml"), &mut config).unwrap();
+        assert_eq!(
+            config.podcast,
+            podcast::FeedMeta {
+                title: Some("Collected Essays".to_string()),
+                image: Some("https://example.com/cover.jpg".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(table.keys().collect::<Vec<_>>(), ["voice"]);
+    }
+
+    #[test]
+    fn podcast_table_rejects_unknown_keys() {
+        let mut table: toml::Table = toml::from_str("[podcast]\nowner = \"me\"").unwrap();
+        let e = take_sections(
+            &mut table,
+            Path::new("morganite.toml"),
+            &mut Config::default(),
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", e).starts_with("[podcast] in morganite.toml"));
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use crate::{summary, utils};
+
+/// Longest a notification may take to show before the run stops waiting for it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Show a native notification. Best effort: without a notification service this
+/// only logs at debug level.
+async fn show(title: String, body: String, urgent: bool) {
+    let shown = tokio::task::spawn_blocking(move || {
+        let mut notification = notify_rust::Notification::new();
+        notification
+            .appname("Morganite")
+            .summary(&title)
+            .body(&body);
+        #[cfg(all(unix, not(target_os = "macos")))]
+        if urgent {
+            notification.urgency(notify_rust::Urgency::Critical);
+        }
+        #[cfg(not(all(unix, not(target_os = "macos"))))]
+        let _ = urgent;
+        notification.show().map(|_| ())
+    });
+    match tokio::time::timeout(TIMEOUT, shown).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(e))) => tracing::debug!("Desktop notification failed: {}", e),
+        Ok(Err(e)) => tracing::debug!("Desktop notification failed: {}", e),
+        Err(_) => tracing::debug!("Desktop notification timed out"),
+    }
+}
+
+/// `--notify` in watching runs: one notification per input file, in the background.
+pub fn file_done(file: &summary::FileSummary, error: Option<&str>) {
+    let (title, body, urgent) = match error {
+        None => (
+            format!("Finished {}", file.file),
+            format!(
+                "{} of audio in {}{}",
+                utils::format_hms(file.audio),
+                utils::format_hms(file.wall),
+                failed_lines(file.failed)
+            ),
+            false,
+        ),
+        Some(error) => (format!("Failed {}", file.file), error.to_string(), true),
+    };
+    tokio::spawn(show(title, body, urgent));
+}
+
+/// `--notify`: the headline numbers once the run is over, urgent if it failed.
+pub async fn run_done(run: &summary::RunSummary, error: Option<&str>) {
+    let finished = run.files.iter().filter(|f| f.finished).count();
+    let audio = run.files.iter().map(|f| f.audio).sum();
+    let failed = run.files.iter().map(|f| f.failed).sum();
+    let stats = format!(
+        "{} of {} files, {} of audio in {}{}",
+        finished,
+        run.files.len(),
+        utils::format_hms(audio),
+        utils::format_hms(run.wall),
+        failed_lines(failed)
+    );
+    match error {
+        None => show("Morganite run finished".to_string(), stats, false).await,
+        Some(error) => {
+            show(
+                "Morganite run failed".to_string(),
+                format!("{}\n{}", error, stats),
+                true,
+            )
+            .await
+        }
+    }
+}
+
+fn failed_lines(failed: usize) -> String {
+    if failed == 0 {
+        String::new()
+    } else {
+        format!(", {} lines failed", failed)
+    }
+}
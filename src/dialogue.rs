@@ -0,0 +1,99 @@
+/// A run of a line that is either inside a quotation or narration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span<'a> {
+    pub text: &'a str,
+    pub quoted: bool,
+}
+
+fn closer_for(c: char) -> Option<char> {
+    match c {
+        '“' => Some('”'),
+        '「' => Some('」'),
+        '『' => Some('』'),
+        '"' => Some('"'),
+        _ => None,
+    }
+}
+
+/// Split a line into quoted and narration spans. Quote marks stay with the quoted
+/// span. Nested or unbalanced quotes make the whole line narration, and spans without
+/// anything readable (a trailing `。`) are folded into the previous span.
+pub fn split_quotes(line: &str) -> Vec<Span<'_>> {
+    split_quotes_min(line, 0)
+}
+
+/// [`split_quotes`], with quotes of fewer than `min_chars` readable chars (a single
+/// quoted word) left in the narration around them.
+pub fn split_quotes_min(line: &str, min_chars: usize) -> Vec<Span<'_>> {
+    let whole = vec![Span {
+        text: line,
+        quoted: false,
+    }];
+
+    let mut bounds = Vec::new();
+    let mut open: Option<(usize, char, char)> = None;
+    for (i, c) in line.char_indices() {
+        match open {
+            Some((start, opener, closer)) => {
+                if c == closer {
+                    bounds.push((start, i + c.len_utf8()));
+                    open = None;
+                } else if c == opener {
+                    // Nested quote of the same kind
+                    return whole;
+                }
+            }
+            None => {
+                if let Some(closer) = closer_for(c) {
+                    open = Some((i, c, closer));
+                } else if matches!(c, '”' | '」' | '』') {
+                    // Closing quote without an opener
+                    return whole;
+                }
+            }
+        }
+    }
+    if open.is_some() {
+        return whole;
+    }
+    bounds.retain(|&(start, end)| {
+        line[start..end]
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .count()
+            >= min_chars
+    });
+    if bounds.is_empty() {
+        return whole;
+    }
+
+    // (start, end, quoted) byte ranges
+    let mut ranges: Vec<(usize, usize, bool)> = Vec::new();
+    let mut push = |start: usize, end: usize, quoted: bool| {
+        let text = &line[start..end];
+        if text.trim().is_empty() {
+            return;
+        }
+        match ranges.last_mut() {
+            // Extend the previous span over bare punctuation
+            Some(prev) if !text.chars().any(char::is_alphanumeric) => prev.1 = end,
+            _ => ranges.push((start, end, quoted)),
+        }
+    };
+
+    let mut pos = 0;
+    for (start, end) in bounds {
+        push(pos, start, false);
+        push(start, end, true);
+        pos = end;
+    }
+    push(pos, line.len(), false);
+
+    ranges
+        .into_iter()
+        .map(|(start, end, quoted)| Span {
+            text: line[start..end].trim(),
+            quoted,
+        })
+        .collect()
+}
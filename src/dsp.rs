@@ -0,0 +1,681 @@
+use std::{collections::BTreeMap, f32::consts::PI, sync::Arc, time::Duration};
+
+use serde::Serialize;
+
+/// WSOLA analysis frame length and the search range for the best-matching
+/// overlap, in milliseconds.
+const STRETCH_FRAME_MS: usize = 20;
+const STRETCH_SEARCH_MS: usize = 5;
+
+/// Time-stretch `samples` so they play `tempo` times as fast without changing
+/// pitch (WSOLA: overlap-add of input frames, each shifted within a small window
+/// to line up with the previous frame's natural continuation).
+///
+/// The clip starts at full level and fades out over its last half frame, so
+/// joins with neighbouring clips or silence stay click-free.
+pub fn time_stretch(samples: &[f32], tempo: f32, sample_rate: u32) -> Vec<f32> {
+    let frame = (sample_rate as usize * STRETCH_FRAME_MS / 1000).max(4) & !1;
+    let hop = frame / 2;
+    let search = sample_rate as usize * STRETCH_SEARCH_MS / 1000;
+    let out_len = (samples.len() as f64 / tempo as f64).round() as usize;
+    if samples.len() < frame || out_len == 0 {
+        return samples.to_vec();
+    }
+
+    // Periodic Hann: at 50% overlap consecutive windows sum to exactly 1
+    let window = (0..frame)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / frame as f32).cos())
+        .collect::<Vec<_>>();
+    let at = |i: usize| samples.get(i).copied().unwrap_or(0.0);
+    let last_start = samples.len() - frame;
+
+    let mut out = vec![0.0f32; out_len + frame];
+    let mut prev_pos = 0usize;
+    let mut out_pos = 0usize;
+    while out_pos < out_len {
+        let ideal = ((out_pos as f64 * tempo as f64) as usize).min(last_start);
+        let pos = if out_pos == 0 {
+            0
+        } else {
+            // Pick the candidate whose start correlates best with what would have
+            // followed the previous frame in the input
+            let natural = prev_pos + hop;
+            let lo = ideal.saturating_sub(search);
+            let hi = (ideal + search).min(last_start);
+            (lo..=hi)
+                .max_by(|&a, &b| {
+                    let corr =
+                        |c: usize| (0..hop).map(|i| at(natural + i) * at(c + i)).sum::<f32>();
+                    corr(a).total_cmp(&corr(b))
+                })
+                .unwrap_or(ideal)
+        };
+
+        for (i, w) in window.iter().enumerate() {
+            // Don't fade in the very first frame
+            let w = if out_pos == 0 && i < hop { 1.0 } else { *w };
+            out[out_pos + i] += at(pos + i) * w;
+        }
+        prev_pos = pos;
+        out_pos += hop;
+    }
+
+    out.truncate(out_len);
+    out
+}
+
+/// Pitches, length and spacing of the pips in [`separator_tone`].
+const SEPARATOR_PIPS: [f32; 2] = [880.0, 1320.0];
+const SEPARATOR_PIP: Duration = Duration::from_millis(120);
+const SEPARATOR_GAP: Duration = Duration::from_millis(80);
+const SEPARATOR_PADDING: Duration = Duration::from_millis(400);
+
+/// A soft two-pip chime marking the boundary between two files, with silence on
+/// either side so it doesn't crowd the speech. Each pip fades in and out over a
+/// quarter of its length, so it starts and stops without a click.
+pub fn separator_tone(sample_rate: u32, channels: usize) -> Vec<f32> {
+    let frames = |d: Duration| (d.as_secs_f64() * sample_rate as f64).round() as usize;
+    let (pip, gap, padding) = (
+        frames(SEPARATOR_PIP),
+        frames(SEPARATOR_GAP),
+        frames(SEPARATOR_PADDING),
+    );
+    let fade = (pip / 4).max(1);
+    let mut mono = vec![0.0; padding];
+    for (n, freq) in SEPARATOR_PIPS.into_iter().enumerate() {
+        if n > 0 {
+            mono.resize(mono.len() + gap, 0.0);
+        }
+        mono.extend((0..pip).map(|i| {
+            let envelope = (i.min(pip - 1 - i) as f32 / fade as f32).min(1.0);
+            0.2 * envelope * (2.0 * PI * freq * i as f32 / sample_rate as f32).sin()
+        }));
+    }
+    mono.resize(mono.len() + padding, 0.0);
+    mono.iter()
+        .flat_map(|&s| std::iter::repeat_n(s, channels))
+        .collect()
+}
+
+/// Range of `samples` left after cutting leading/trailing audio below
+/// `threshold`, widened by `guard` samples on each side so soft onsets and
+/// breaths survive. `None` if nothing reaches the threshold.
+pub fn trim_silence(
+    samples: &[f32],
+    threshold: f32,
+    guard: usize,
+) -> Option<std::ops::Range<usize>> {
+    let first = samples.iter().position(|s| s.abs() >= threshold)?;
+    let last = samples.iter().rposition(|s| s.abs() >= threshold)?;
+    Some(first.saturating_sub(guard)..(last + 1 + guard).min(samples.len()))
+}
+
+/// What to cut from `samples` to shorten every run of at least `min_run` samples
+/// below `threshold` lying inside it (between audible parts, not at either end) to
+/// `max_len` samples. Each run keeps its two ends, so the speech on either side
+/// winds down and starts up naturally. Ranges are in order.
+pub fn long_pauses(
+    samples: &[f32],
+    threshold: f32,
+    min_run: usize,
+    max_len: usize,
+) -> Vec<std::ops::Range<usize>> {
+    let longest = min_run.max(max_len + 1);
+    let mut cuts = Vec::new();
+    let mut run_start = None;
+    let mut heard = false;
+    for (i, s) in samples.iter().enumerate() {
+        if s.abs() < threshold {
+            run_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = run_start.take()
+            && heard
+            && i - start >= longest
+        {
+            let keep = max_len / 2;
+            cuts.push(start + keep..i - (max_len - keep));
+        }
+        heard = true;
+    }
+    cuts
+}
+
+/// Cut `samples` into `parts` clips in the middle of the longest stretches below
+/// `threshold` lasting at least `min_gap` samples. Silence at either end doesn't
+/// separate anything and is left on the outer clips. `None` if there aren't
+/// enough such pauses.
+pub fn split_at_pauses(
+    samples: &[f32],
+    parts: usize,
+    threshold: f32,
+    min_gap: usize,
+) -> Option<Vec<Vec<f32>>> {
+    let mut gaps = Vec::new();
+    let mut run_start = None;
+    for (i, s) in samples.iter().enumerate() {
+        if s.abs() < threshold {
+            run_start.get_or_insert(i);
+        } else if let Some(start) = run_start.take()
+            && start > 0
+            && i - start >= min_gap
+        {
+            gaps.push(start..i);
+        }
+    }
+    if gaps.len() + 1 < parts {
+        return None;
+    }
+
+    gaps.sort_by_key(|g| std::cmp::Reverse(g.end - g.start));
+    let mut cuts = gaps[..parts - 1]
+        .iter()
+        .map(|g| (g.start + g.end) / 2)
+        .collect::<Vec<_>>();
+    cuts.sort_unstable();
+    let mut clips = Vec::with_capacity(parts);
+    let mut from = 0;
+    for cut in cuts.into_iter().chain([samples.len()]) {
+        clips.push(samples[from..cut].to_vec());
+        from = cut;
+    }
+    Some(clips)
+}
+
+/// Amount of gated audio a voice needs before its level is trusted.
+const LOUDNESS_WARMUP_SECS: f64 = 10.0;
+/// Samples quieter than this (about -40 dBFS) don't count towards loudness.
+const LOUDNESS_GATE: f32 = 0.01;
+/// Largest change of a voice's correction from one clip to the next.
+const LOUDNESS_MAX_STEP_DB: f32 = 1.0;
+const LOUDNESS_MAX_CORRECTION_DB: f32 = 12.0;
+
+#[derive(Debug, Default)]
+struct VoiceLoudness {
+    energy: f64,
+    samples: u64,
+    applied_db: f32,
+}
+
+impl VoiceLoudness {
+    fn level_db(&self) -> Option<f64> {
+        (self.samples > 0).then(|| 10.0 * (self.energy / self.samples as f64).log10())
+    }
+
+    fn warmed_up(&self, sample_rate: u32) -> bool {
+        self.samples as f64 >= LOUDNESS_WARMUP_SECS * sample_rate as f64
+    }
+}
+
+/// Running per-voice loudness, used to pull every voice towards the narrator's
+/// level. Corrections move in small steps so a character's lines don't pump.
+#[derive(Debug, Default)]
+pub struct LoudnessMatcher {
+    voices: BTreeMap<&'static str, VoiceLoudness>,
+}
+
+impl LoudnessMatcher {
+    /// Measure `audio` as spoken by `voice`, then scale it towards `narrator`.
+    pub fn process(
+        &mut self,
+        voice: &'static str,
+        narrator: &'static str,
+        audio: &mut [f32],
+        sample_rate: u32,
+    ) {
+        let entry = self.voices.entry(voice).or_default();
+        for s in audio.iter().filter(|s| s.abs() >= LOUDNESS_GATE) {
+            entry.energy += (*s as f64).powi(2);
+            entry.samples += 1;
+        }
+        if voice == narrator {
+            return;
+        }
+
+        let Some(reference) = self
+            .voices
+            .get(narrator)
+            .filter(|n| n.warmed_up(sample_rate))
+            .and_then(VoiceLoudness::level_db)
+        else {
+            return;
+        };
+        let entry = self.voices.get_mut(voice).unwrap();
+        if !entry.warmed_up(sample_rate) {
+            return;
+        }
+        let target = ((reference - entry.level_db().unwrap()) as f32)
+            .clamp(-LOUDNESS_MAX_CORRECTION_DB, LOUDNESS_MAX_CORRECTION_DB);
+        entry.applied_db +=
+            (target - entry.applied_db).clamp(-LOUDNESS_MAX_STEP_DB, LOUDNESS_MAX_STEP_DB);
+
+        if entry.applied_db != 0.0 {
+            let gain = 10f32.powf(entry.applied_db / 20.0);
+            audio.iter_mut().for_each(|s| *s *= gain);
+        }
+    }
+
+    pub fn report(&self) {
+        tracing::info!("Loudness per voice (measured / applied offset):");
+        for (voice, loudness) in &self.voices {
+            match loudness.level_db() {
+                Some(level) => tracing::info!(
+                    "  {:<8} {:>7.1} dBFS {:>+6.1} dB",
+                    voice,
+                    level,
+                    loudness.applied_db
+                ),
+                None => tracing::info!("  {:<8} (silent)", voice),
+            }
+        }
+    }
+}
+
+/// Second-order Butterworth low-pass (RBJ cookbook biquad) for mono audio, with
+/// its state carried from one call to the next.
+pub struct LowPass {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl LowPass {
+    pub fn new(cutoff_hz: f32, sample_rate: u32) -> Self {
+        let w0 = 2.0 * PI * cutoff_hz / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let a0 = 1.0 + alpha;
+        let b1 = (1.0 - w0.cos()) / a0;
+        Self {
+            b: [b1 / 2.0, b1, b1 / 2.0],
+            a: [-2.0 * w0.cos() / a0, (1.0 - alpha) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for s in samples {
+            let y = self.b[0] * *s + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+                - self.a[0] * self.y[0]
+                - self.a[1] * self.y[1];
+            self.x = [*s, self.x[0]];
+            self.y = [y, self.y[0]];
+            *s = y;
+        }
+    }
+}
+
+/// Lookahead peak limiter. Gain reduction ramps in over the lookahead so it is in
+/// place when the peak arrives, and recovers exponentially over the release time.
+/// Material below the ceiling passes through untouched.
+pub struct Limiter {
+    ceiling: f32,
+    lookahead: usize,
+    release_coef: f32,
+    gain: f32,
+    /// Samples written with gain reduction engaged.
+    pub engaged: u64,
+}
+
+impl Limiter {
+    pub fn new(ceiling_db: f32, attack: Duration, release: Duration, sample_rate: u32) -> Self {
+        let release_samples = (release.as_secs_f32() * sample_rate as f32).max(1.0);
+        Self {
+            ceiling: 10f32.powf(ceiling_db / 20.0),
+            lookahead: ((attack.as_secs_f32() * sample_rate as f32) as usize).max(1),
+            release_coef: (-1.0 / release_samples).exp(),
+            gain: 1.0,
+            engaged: 0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for s in samples.iter_mut().filter(|s| !s.is_finite()) {
+            *s = 0.0;
+        }
+        let target = samples
+            .iter()
+            .map(|s| {
+                let peak = s.abs();
+                if peak > self.ceiling {
+                    self.ceiling / peak
+                } else {
+                    1.0
+                }
+            })
+            .collect::<Vec<_>>();
+        if self.gain == 1.0 && target.iter().all(|&g| g == 1.0) {
+            return;
+        }
+
+        for (i, s) in samples.iter_mut().enumerate() {
+            // Lowest gain needed by any upcoming peak, eased in linearly towards it
+            let wanted = target[i..(i + self.lookahead + 1).min(target.len())]
+                .iter()
+                .enumerate()
+                .map(|(d, &g)| g + (1.0 - g) * d as f32 / self.lookahead as f32)
+                .fold(1.0f32, f32::min);
+            self.gain = if wanted < self.gain {
+                wanted
+            } else {
+                (1.0 - (1.0 - self.gain) * self.release_coef).min(wanted)
+            };
+            if self.gain < 1.0 {
+                self.engaged += 1;
+            }
+            *s = (*s * self.gain).clamp(-self.ceiling, self.ceiling);
+        }
+    }
+}
+
+/// Loudness measurement block, as in BS.1770 (without the K-weighting filter).
+const LEVEL_BLOCK_SECS: f64 = 0.4;
+/// Blocks quieter than this are ignored; the histogram covers -70..0 dBFS.
+const LEVEL_ABSOLUTE_GATE_DB: f64 = -70.0;
+const LEVEL_BINS_PER_DB: f64 = 10.0;
+/// Samples below this (-60 dBFS) count as silence.
+const SILENCE_SAMPLE: f32 = 0.001;
+
+/// Summary levels of a stretch of audio.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Levels {
+    pub duration_secs: f64,
+    /// Gated loudness in dBFS; `None` for (near) silence.
+    pub loudness_db: Option<f64>,
+    pub peak_db: f64,
+    pub silence_pct: f64,
+}
+
+/// Streaming level meter. Block loudness goes into a fixed histogram so memory
+/// stays constant however long the audio runs.
+pub struct LevelMeter {
+    block_len: usize,
+    block_energy: f64,
+    block_fill: usize,
+    histogram: Vec<u64>,
+    peak: f32,
+    silent: u64,
+    total: u64,
+    sample_rate: u32,
+}
+
+impl LevelMeter {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            block_len: (LEVEL_BLOCK_SECS * sample_rate as f64) as usize,
+            block_energy: 0.0,
+            block_fill: 0,
+            histogram: vec![0; (-LEVEL_ABSOLUTE_GATE_DB * LEVEL_BINS_PER_DB) as usize],
+            peak: 0.0,
+            silent: 0,
+            total: 0,
+            sample_rate,
+        }
+    }
+
+    pub fn add(&mut self, samples: &[f32]) {
+        for &s in samples {
+            let a = s.abs();
+            self.peak = self.peak.max(a);
+            if a < SILENCE_SAMPLE {
+                self.silent += 1;
+            }
+            self.block_energy += (s as f64).powi(2);
+            self.block_fill += 1;
+            if self.block_fill == self.block_len {
+                self.close_block();
+            }
+        }
+        self.total += samples.len() as u64;
+    }
+
+    fn close_block(&mut self) {
+        let db = 10.0 * (self.block_energy / self.block_fill as f64).log10();
+        if db >= LEVEL_ABSOLUTE_GATE_DB {
+            let bin = ((db - LEVEL_ABSOLUTE_GATE_DB) * LEVEL_BINS_PER_DB) as usize;
+            let last = self.histogram.len() - 1;
+            self.histogram[bin.min(last)] += 1;
+        }
+        self.block_energy = 0.0;
+        self.block_fill = 0;
+    }
+
+    /// Levels so far; the meter is reset for the next stretch.
+    pub fn finish(&mut self) -> Levels {
+        if self.block_fill >= self.block_len / 2 {
+            self.close_block();
+        }
+
+        let bin_energy = |bin: usize| {
+            10f64.powf((LEVEL_ABSOLUTE_GATE_DB + bin as f64 / LEVEL_BINS_PER_DB) / 10.0)
+        };
+        let mean_db = |from: usize| {
+            let (energy, blocks) = self.histogram[from..]
+                .iter()
+                .enumerate()
+                .fold((0.0, 0u64), |(e, n), (i, &count)| {
+                    (e + bin_energy(from + i) * count as f64, n + count)
+                });
+            (blocks > 0).then(|| 10.0 * (energy / blocks as f64).log10())
+        };
+        // Relative gate: drop blocks 10 dB under the absolute-gated mean
+        let loudness_db = mean_db(0).and_then(|ungated| {
+            let relative = ((ungated - 10.0 - LEVEL_ABSOLUTE_GATE_DB) * LEVEL_BINS_PER_DB).max(0.0);
+            mean_db((relative as usize).min(self.histogram.len() - 1))
+        });
+
+        let levels = Levels {
+            duration_secs: self.total as f64 / self.sample_rate as f64,
+            loudness_db,
+            peak_db: 20.0 * (self.peak.max(1e-10) as f64).log10(),
+            silence_pct: self.silent as f64 * 100.0 / self.total.max(1) as f64,
+        };
+        *self = Self::new(self.sample_rate);
+        levels
+    }
+}
+
+/// A looping background track mixed under everything written. The position
+/// carries across segment rotations so the bed never jumps.
+pub struct MusicBed {
+    samples: Arc<Vec<f32>>,
+    gain: f32,
+    pos: usize,
+    /// `(remaining, total)` samples of a fade-out in progress.
+    fade: Option<(usize, usize)>,
+}
+
+impl MusicBed {
+    pub fn new(samples: Arc<Vec<f32>>, gain_db: f32) -> Self {
+        Self {
+            samples,
+            gain: 10f32.powf(gain_db / 20.0),
+            pos: 0,
+            fade: None,
+        }
+    }
+
+    /// Fade the bed out linearly over the next `samples` samples, then stay silent.
+    pub fn fade_out(&mut self, samples: usize) {
+        self.fade = Some((samples, samples.max(1)));
+    }
+
+    /// Add the bed (interleaved like `buf`) on top of `buf`.
+    pub fn mix(&mut self, buf: &mut [f32]) {
+        if self.samples.is_empty() {
+            return;
+        }
+        for s in buf {
+            let level = match &mut self.fade {
+                None => 1.0,
+                Some((0, _)) => return,
+                Some((remaining, total)) => {
+                    *remaining -= 1;
+                    *remaining as f32 / *total as f32
+                }
+            };
+            *s += self.samples[self.pos] * self.gain * level;
+            self.pos = (self.pos + 1) % self.samples.len();
+        }
+    }
+}
+
+/// Joins consecutive clips with a short linear overlap-add instead of butting
+/// them together. The last `overlap` samples of each clip are held back until
+/// the next clip (or a flush) arrives, so every join shortens the output by the
+/// overlap actually used.
+pub struct Crossfader {
+    overlap: usize,
+    held: Vec<f32>,
+}
+
+impl Crossfader {
+    pub fn new(overlap: usize) -> Self {
+        Self {
+            overlap,
+            held: Vec::new(),
+        }
+    }
+
+    /// Join `clip` onto the held tail and return what is ready to be written.
+    pub fn push(&mut self, clip: &[f32]) -> Vec<f32> {
+        if self.overlap == 0 {
+            return clip.to_vec();
+        }
+        if clip.is_empty() {
+            return Vec::new();
+        }
+
+        // Short clips overlap by as much as they have
+        let n = self.held.len().min(clip.len());
+        let mut out = Vec::with_capacity(self.held.len() + clip.len());
+        out.extend_from_slice(&self.held[..self.held.len() - n]);
+        let tail = &self.held[self.held.len() - n..];
+        for (i, (a, b)) in tail.iter().zip(&clip[..n]).enumerate() {
+            let t = (i + 1) as f32 / (n + 1) as f32;
+            out.push(a * (1.0 - t) + b * t);
+        }
+        out.extend_from_slice(&clip[n..]);
+
+        let keep = self.overlap.min(clip.len());
+        self.held = out.split_off(out.len() - keep);
+        out
+    }
+
+    /// Continue the clip last pushed with `more`, without fading: only the held tail
+    /// moves on to its end.
+    pub fn extend(&mut self, more: &[f32]) -> Vec<f32> {
+        if self.overlap == 0 {
+            return more.to_vec();
+        }
+        let mut out = std::mem::take(&mut self.held);
+        out.extend_from_slice(more);
+        let keep = self.overlap.min(out.len());
+        self.held = out.split_off(out.len() - keep);
+        out
+    }
+
+    /// Release the held tail unchanged, e.g. before silence or at the end of a file.
+    pub fn flush(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 24_000;
+
+    fn limiter() -> Limiter {
+        Limiter::new(
+            -1.0,
+            Duration::from_millis(5),
+            Duration::from_millis(50),
+            RATE,
+        )
+    }
+
+    /// A 440 Hz sine of `amplitude`, `ms` long.
+    fn sine(amplitude: f32, ms: usize) -> Vec<f32> {
+        (0..RATE as usize * ms / 1000)
+            .map(|i| amplitude * (i as f32 * 440.0 * std::f32::consts::TAU / RATE as f32).sin())
+            .collect()
+    }
+
+    /// Peak of the second half of a unit `hz` sine, `ms` long, through `filter`.
+    fn filtered_peak(filter: &mut LowPass, hz: f32, ms: usize) -> f32 {
+        let mut samples = (0..RATE as usize * ms / 1000)
+            .map(|i| (i as f32 * hz * std::f32::consts::TAU / RATE as f32).sin())
+            .collect::<Vec<_>>();
+        filter.process(&mut samples);
+        samples[samples.len() / 2..]
+            .iter()
+            .fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn low_pass_keeps_speech_and_cuts_above_the_cutoff() {
+        assert!((filtered_peak(&mut LowPass::new(7_000.0, RATE), 440.0, 200) - 1.0).abs() < 0.01);
+        assert!(filtered_peak(&mut LowPass::new(7_000.0, RATE), 10_000.0, 200) < 0.2);
+    }
+
+    #[test]
+    fn low_pass_carries_its_state_across_calls() {
+        let mut whole = vec![0.0; 100];
+        whole[0] = 1.0;
+        let mut split = whole.clone();
+        LowPass::new(7_000.0, RATE).process(&mut whole);
+        let mut filter = LowPass::new(7_000.0, RATE);
+        let (head, tail) = split.split_at_mut(37);
+        filter.process(head);
+        filter.process(tail);
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn quiet_material_passes_untouched() {
+        let mut limiter = limiter();
+        let original = sine(0.5, 200);
+        let mut samples = original.clone();
+        limiter.process(&mut samples);
+        assert_eq!(samples, original);
+        assert_eq!(limiter.engaged, 0);
+    }
+
+    #[test]
+    fn bursts_stay_under_the_ceiling() {
+        let mut limiter = limiter();
+        let ceiling = 10f32.powf(-1.0 / 20.0);
+        for amplitude in [0.3, 4.0, 0.3, 1.5, 0.3] {
+            let mut samples = sine(amplitude, 100);
+            limiter.process(&mut samples);
+            assert!(samples.iter().all(|s| s.abs() <= ceiling), "{amplitude}");
+        }
+        assert!(limiter.engaged > 0);
+    }
+
+    #[test]
+    fn gain_is_reduced_ahead_of_a_peak() {
+        let mut limiter = limiter();
+        let mut samples = vec![0.5; 1000];
+        samples[500] = 2.0;
+        limiter.process(&mut samples);
+        // Eased in over the lookahead rather than cutting in at the peak
+        assert!(samples[499] < 0.5);
+        assert_eq!(samples[0], 0.5);
+        assert!(samples[500] <= 10f32.powf(-1.0 / 20.0));
+    }
+
+    #[test]
+    fn non_finite_samples_become_silence() {
+        let mut limiter = limiter();
+        let mut samples = vec![f32::NAN, f32::INFINITY, 3.0, f32::NEG_INFINITY, 0.2];
+        limiter.process(&mut samples);
+        assert!(samples.iter().all(|s| s.is_finite()));
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[1], 0.0);
+        assert_eq!(samples[3], 0.0);
+    }
+}
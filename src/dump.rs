@@ -0,0 +1,99 @@
+use std::{
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use serde::Serialize;
+
+use crate::{text, tts, utils, wav, writer};
+
+/// Where a dumped clip's samples came from.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Engine,
+    Cache,
+    /// Cut out of a batch of short lines synthesized together.
+    Batch,
+}
+
+/// What is known about a clip besides its samples.
+pub struct Clip<'a> {
+    pub file: &'a str,
+    pub index: usize,
+    pub item: &'a text::Item,
+    pub voice: Voice,
+    pub speed: f32,
+    pub engine: tts::EngineKind,
+    pub took: Duration,
+    pub source: Source,
+}
+
+#[derive(Serialize)]
+struct ClipJson<'a> {
+    file: &'a str,
+    index: usize,
+    source_line: Option<usize>,
+    text: &'a str,
+    samples: usize,
+    duration_secs: f64,
+    synth_secs: f64,
+    voice: &'static str,
+    speed: f32,
+    engine: &'static str,
+    source: Source,
+}
+
+/// `--debug-dump-pcm`: the engine's output for each item, before trimming, tempo,
+/// gain or encoding, as a float WAV with a JSON description next to it.
+pub struct PcmDump {
+    dir: PathBuf,
+    /// Item indices to dump; all of them when unset.
+    indices: Option<RangeInclusive<usize>>,
+}
+
+impl PcmDump {
+    pub fn new(dir: PathBuf, indices: Option<RangeInclusive<usize>>) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self { dir, indices })
+    }
+
+    pub fn wants(&self, index: usize) -> bool {
+        self.indices.as_ref().is_none_or(|r| r.contains(&index))
+    }
+
+    /// Write `audio` for `clip`; failures are only logged, the run goes on.
+    pub fn save(&self, clip: &Clip, audio: &[f32]) {
+        if let Err(e) = self.try_save(clip, audio) {
+            tracing::warn!("Failed to dump audio idx {}: {:#}", clip.index, e);
+        }
+    }
+
+    fn try_save(&self, clip: &Clip, audio: &[f32]) -> anyhow::Result<()> {
+        let stem = Path::new(clip.file)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown");
+        let base = self.dir.join(format!("{}_{:06}", stem, clip.index));
+        wav::save(&base.with_extension("wav"), audio, writer::SAMPLE_RATE)?;
+        let json = serde_json::to_string_pretty(&ClipJson {
+            file: clip.file,
+            index: clip.index,
+            source_line: clip.item.source_line,
+            text: &clip.item.text,
+            samples: audio.len(),
+            duration_secs: audio.len() as f64 / writer::SAMPLE_RATE as f64,
+            synth_secs: clip.took.as_secs_f64(),
+            voice: utils::voice_name(clip.voice),
+            speed: clip.speed,
+            engine: clip.engine.name(),
+            source: clip.source,
+        })?;
+        let path = base.with_extension("json");
+        std::fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
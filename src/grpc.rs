@@ -0,0 +1,291 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Context;
+use tokio::{
+    net::TcpListener,
+    sync::{OnceCell, mpsc},
+};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Code, Request, Response, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+use crate::{pipe, pipeline, serve, text, writer};
+
+pub mod proto {
+    tonic::include_proto!("morganite.v1");
+}
+
+use proto::{
+    AudioChunk, AudioFormat, GetStatusRequest, ListVoicesRequest, ListVoicesResponse, ServerStatus,
+    SynthRequest,
+    synthesis_server::{Synthesis, SynthesisServer},
+};
+
+/// `--listen-grpc`: the Synthesis service, on the same [`serve::Server`] as the
+/// other listeners.
+struct Service {
+    server: Arc<OnceCell<Arc<serve::Server>>>,
+    stream_buffer: usize,
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    server: Arc<OnceCell<Arc<serve::Server>>>,
+    stream_buffer: usize,
+) {
+    let service = Service {
+        server,
+        stream_buffer,
+    };
+    if let Err(e) = tonic::transport::Server::builder()
+        .add_service(SynthesisServer::new(service))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+    {
+        tracing::error!("gRPC server stopped: {}", e);
+    }
+}
+
+impl Service {
+    fn ready(&self) -> Result<Arc<serve::Server>, Status> {
+        self.server
+            .get()
+            .cloned()
+            .ok_or_else(|| Status::unavailable("The engine is still warming up"))
+    }
+}
+
+#[tonic::async_trait]
+impl Synthesis for Service {
+    type SynthesizeStream = ReceiverStream<Result<AudioChunk, Status>>;
+
+    async fn synthesize(
+        &self,
+        request: Request<SynthRequest>,
+    ) -> Result<Response<Self::SynthesizeStream>, Status> {
+        let server = self.ready()?;
+        let request = request.into_inner();
+        let format = AudioFormat::try_from(request.format).map_err(|_| {
+            invalid(
+                "format",
+                "unknown_format",
+                format!("Unknown audio format {}", request.format),
+            )
+        })?;
+        let (settings, items) = server
+            .validate(serve::SynthesizeRequest {
+                text: request.text,
+                voice: request.voice,
+                speed: request.speed,
+                bitrate: request.bitrate,
+            })
+            .map_err(rejected)?;
+        let items = Arc::new(serve::sentences(items));
+
+        let id = server.next_id();
+        let buffer = self.stream_buffer;
+        let (tx, rx) = mpsc::channel(buffer);
+        tokio::spawn(async move {
+            // Dropping the synthesis future cancels what is still in flight
+            tokio::select! {
+                r = stream(&server, id, settings, items, format, &tx, buffer) => {
+                    if let Err(e) = r {
+                        let status = match e.downcast::<Status>() {
+                            Ok(status) => status,
+                            Err(e) => Status::internal(format!("{:#}", e)),
+                        };
+                        tracing::warn!("gRPC call {}: {}", id, status.message());
+                        let _ = tx.try_send(Err(status));
+                    }
+                }
+                _ = tx.closed() => tracing::debug!("gRPC call {}: cancelled by the client", id),
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn list_voices(
+        &self,
+        _: Request<ListVoicesRequest>,
+    ) -> Result<Response<ListVoicesResponse>, Status> {
+        let (voices, default_voice) = self.ready()?.voices();
+        Ok(Response::new(ListVoicesResponse {
+            voices: voices.into_iter().map(String::from).collect(),
+            default_voice: default_voice.to_string(),
+        }))
+    }
+
+    async fn get_status(
+        &self,
+        _: Request<GetStatusRequest>,
+    ) -> Result<Response<ServerStatus>, Status> {
+        let status = match self.server.get() {
+            Some(server) => ServerStatus {
+                ready: true,
+                sessions: server.pipeline().concurrency as u32,
+                busy_sessions: server.busy_sessions() as u32,
+                requests: server.requests(),
+            },
+            None => ServerStatus::default(),
+        };
+        Ok(Response::new(status))
+    }
+}
+
+/// Synthesize `items` into `tx` sentence by sentence, failing with a [`Status`]
+/// once the client falls `buffer` chunks behind.
+async fn stream(
+    server: &serve::Server,
+    id: u64,
+    settings: pipeline::FileSettings,
+    items: Arc<Vec<text::Item>>,
+    format: AudioFormat,
+    tx: &mpsc::Sender<Result<AudioChunk, Status>>,
+    buffer: usize,
+) -> anyhow::Result<()> {
+    let pcm = match format {
+        AudioFormat::Mp3 => None,
+        AudioFormat::PcmS16le => Some(pipe::PcmFormat::S16),
+        AudioFormat::PcmF32le => Some(pipe::PcmFormat::F32),
+    };
+    let encoding = pcm.is_none().then(|| {
+        Arc::new(pipeline::ClipEncoding {
+            bitrate: settings.bitrate,
+            channel_mode: writer::ChannelMode::Mono,
+            quality: writer::EncoderQuality::Standard,
+            gain_db: 0.0,
+            limiter: None,
+        })
+    });
+    let (chunk_tx, mut chunks) = mpsc::channel::<(usize, pipeline::Chunk)>(1);
+    let send = async {
+        while let Some((idx, chunk)) = chunks.recv().await {
+            let pause = match chunk.end {
+                Some(_) => vec![
+                    0.0;
+                    (items[idx].pause_after.as_secs_f64() * writer::SAMPLE_RATE as f64)
+                        as usize
+                ],
+                None => Vec::new(),
+            };
+            let data = match pcm {
+                None => {
+                    let mut data = chunk.encoded.context("Clip was not encoded")?;
+                    if !pause.is_empty() {
+                        let config = writer::default_mono_24k_config(settings.bitrate);
+                        data.extend(writer::encode_clip(config, &pause)?);
+                    }
+                    data
+                }
+                Some(pcm) => {
+                    let mut data = Vec::new();
+                    pcm.encode(&chunk.audio, &mut data);
+                    pcm.encode(&pause, &mut data);
+                    data
+                }
+            };
+            let chunk = AudioChunk {
+                data,
+                sentence: idx as u32,
+            };
+            match tx.try_send(Ok(chunk)) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    return Err(Status::resource_exhausted(format!(
+                        "Client fell {} chunks behind",
+                        buffer
+                    ))
+                    .into());
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    return Err(Status::cancelled("Client went away").into());
+                }
+            }
+        }
+        anyhow::Ok(())
+    };
+    tokio::try_join!(
+        server.pipeline().synthesize(
+            format!("grpc {}", id),
+            items.clone(),
+            &settings,
+            encoding,
+            chunk_tx,
+        ),
+        send
+    )
+    .map(|_| ())
+}
+
+/// `INVALID_ARGUMENT` with the machine-readable code as `ErrorInfo.reason` and
+/// the offending request field as a `BadRequest` violation.
+fn invalid(field: &str, code: &str, message: String) -> Status {
+    let mut details = ErrorDetails::with_error_info(code, "morganite", HashMap::new());
+    details.add_bad_request_violation(field, message.clone());
+    Status::with_error_details(Code::InvalidArgument, message, details)
+}
+
+fn rejected(rejected: serve::Rejected) -> Status {
+    let field = match rejected.code {
+        "unknown_voice" => "voice",
+        "invalid_speed" => "speed",
+        "invalid_bitrate" => "bitrate",
+        _ => "text",
+    };
+    invalid(field, rejected.code, rejected.message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warming_up() -> Service {
+        Service {
+            server: Arc::new(OnceCell::new()),
+            stream_buffer: 4,
+        }
+    }
+
+    #[tokio::test]
+    async fn calls_wait_for_the_engine() {
+        let service = warming_up();
+        let status = service
+            .list_voices(Request::new(ListVoicesRequest::default()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+        let status = service
+            .synthesize(Request::new(SynthRequest::default()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn status_reports_not_ready_while_warming_up() {
+        let status = warming_up()
+            .get_status(Request::new(GetStatusRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status, ServerStatus::default());
+        assert!(!status.ready);
+    }
+
+    #[test]
+    fn invalid_names_the_code_and_field() {
+        let status = invalid("speed", "invalid_speed", "Speed 9 out of range".to_string());
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "Speed 9 out of range");
+        let details = status.get_error_details();
+        let info = details.error_info().unwrap();
+        assert_eq!(info.reason, "invalid_speed");
+        assert_eq!(info.domain, "morganite");
+        let violations = &details.bad_request().unwrap().field_violations;
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].field, "speed");
+        assert_eq!(violations[0].description, "Speed 9 out of range");
+    }
+}
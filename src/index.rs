@@ -0,0 +1,148 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Status {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+/// Where the next row lands: segment file, offset within it and offset within the
+/// input file's whole audio.
+pub type Position = ((String, Duration), Duration);
+
+/// `index.csv` mapping audio offsets back to source lines, flushed after every row
+/// so an aborted run still leaves a usable index behind.
+pub struct LineIndex {
+    dir: PathBuf,
+    out: BufWriter<File>,
+    /// Char count of the source lines read but not yet accounted for, by physical
+    /// line number.
+    line_chars: BTreeMap<usize, usize>,
+    /// Last source line accounted for, so lines that got no item show up as skipped.
+    last_line: usize,
+}
+
+impl LineIndex {
+    pub fn create(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join("index.csv");
+        let file =
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        writeln!(
+            out,
+            "segment,start_secs,abs_secs,line,chars,synth_secs,status,voice"
+        )?;
+        out.flush()?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            out,
+            line_chars: BTreeMap::new(),
+            last_line: 0,
+        })
+    }
+
+    /// Note source line `line` of `chars` chars as read, to be listed as skipped if
+    /// no item covers it. Lines are noted before the items that follow them.
+    pub fn note_line(&mut self, line: usize, chars: usize) {
+        self.line_chars.insert(line, chars);
+    }
+
+    /// Record an item written at `pos` in `voice`. Source lines between the previous
+    /// row and `line` are listed as skipped at the same position first.
+    pub fn push(
+        &mut self,
+        pos: &Position,
+        line: Option<usize>,
+        chars: usize,
+        took: Duration,
+        voice: &str,
+    ) -> anyhow::Result<()> {
+        if let Some(line) = line {
+            self.skip_until(pos, line)?;
+            self.last_line = self.last_line.max(line);
+        }
+        self.row(pos, line, chars, Some(took), Status::Ok, voice)?;
+        self.out.flush().context("Failed to flush index.csv")
+    }
+
+    /// Record the item whose synthesis aborted the file. Lines before it aren't
+    /// backfilled since they may still have been in flight.
+    pub fn failed(
+        &mut self,
+        pos: &Position,
+        line: Option<usize>,
+        chars: usize,
+        voice: &str,
+    ) -> anyhow::Result<()> {
+        self.row(pos, line, chars, None, Status::Failed, voice)?;
+        self.out.flush().context("Failed to flush index.csv")
+    }
+
+    /// List the source lines after the last row as skipped, at `pos`.
+    pub fn finish(mut self, pos: &Position) -> anyhow::Result<()> {
+        self.skip_until(pos, usize::MAX)?;
+        self.out.flush().context("Failed to flush index.csv")
+    }
+
+    fn skip_until(&mut self, pos: &Position, line: usize) -> anyhow::Result<()> {
+        let skipped = self
+            .line_chars
+            .range(self.last_line + 1..line)
+            .map(|(&l, &c)| (l, c))
+            .collect::<Vec<_>>();
+        for (l, chars) in skipped {
+            self.row(pos, Some(l), chars, None, Status::Skipped, "")?;
+        }
+        // Everything before `line` is accounted for now
+        self.line_chars = self.line_chars.split_off(&line);
+        Ok(())
+    }
+
+    fn row(
+        &mut self,
+        ((segment, offset), abs): &Position,
+        line: Option<usize>,
+        chars: usize,
+        took: Option<Duration>,
+        status: Status,
+        voice: &str,
+    ) -> anyhow::Result<()> {
+        let segment = Path::new(segment);
+        writeln!(
+            self.out,
+            "{},{:.3},{:.3},{},{},{},{},{}",
+            segment
+                .strip_prefix(&self.dir)
+                .unwrap_or(segment)
+                .to_string_lossy()
+                .replace('\\', "/"),
+            offset.as_secs_f64(),
+            abs.as_secs_f64(),
+            line.map_or(String::new(), |l| l.to_string()),
+            chars,
+            took.map_or(String::new(), |t| format!("{:.3}", t.as_secs_f64())),
+            status.label(),
+            voice
+        )
+        .context("Failed to write index.csv")
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::mpsc,
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::serve;
+
+/// One line from a client.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Job {
+    /// Echoed back in the reply; any JSON value.
+    id: serde_json::Value,
+    text: String,
+    voice: Option<String>,
+    speed: Option<f32>,
+    bitrate: Option<u32>,
+    /// Where the MP3 goes, relative to the server's working directory.
+    out_path: Option<PathBuf>,
+}
+
+/// One line back to the client, sent when its job is done, in completion order.
+#[derive(Debug, Serialize)]
+struct Reply {
+    id: serde_json::Value,
+    ok: bool,
+    /// Time from receiving the job to replying.
+    duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// `--listen-socket`, taking jobs until dropped. On Unix dropping it also removes
+/// the socket file.
+pub struct Listener {
+    task: JoinHandle<()>,
+    #[cfg(unix)]
+    path: PathBuf,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.task.abort();
+        #[cfg(unix)]
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Start taking jobs on the Unix socket at `path`, replacing a stale socket file
+/// left by a process that died without cleaning up.
+#[cfg(unix)]
+pub fn listen(
+    path: &Path,
+    server: Arc<serve::Server>,
+    out_dir: PathBuf,
+) -> anyhow::Result<Listener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if path.exists() {
+        anyhow::ensure!(
+            std::os::unix::net::UnixStream::connect(path).is_err(),
+            "{} is in use by another process",
+            path.display()
+        );
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    // Only this user gets to hand us work
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict {}", path.display()))?;
+
+    let out_dir = Arc::new(out_dir);
+    let task = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_connection(stream, server.clone(), out_dir.clone()));
+                }
+                Err(e) => tracing::warn!("Failed to accept socket connection: {}", e),
+            }
+        }
+    });
+    Ok(Listener {
+        task,
+        path: path.to_path_buf(),
+    })
+}
+
+/// Start taking jobs on the named pipe `path`, e.g. `\\.\pipe\morganite`.
+#[cfg(windows)]
+pub fn listen(
+    path: &Path,
+    server: Arc<serve::Server>,
+    out_dir: PathBuf,
+) -> anyhow::Result<Listener> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = path.as_os_str().to_os_string();
+    // The first instance fails if another process already owns the name
+    let mut pipe = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&name)
+        .with_context(|| format!("Failed to create pipe {}", path.display()))?;
+    let out_dir = Arc::new(out_dir);
+    let task = tokio::spawn(async move {
+        loop {
+            if let Err(e) = pipe.connect().await {
+                tracing::warn!("Failed to accept pipe connection: {}", e);
+                continue;
+            }
+            // The next client needs an instance of its own
+            let next = match ServerOptions::new().create(&name) {
+                Ok(next) => next,
+                Err(e) => {
+                    tracing::error!("Failed to create pipe instance: {}", e);
+                    return;
+                }
+            };
+            let client = std::mem::replace(&mut pipe, next);
+            tokio::spawn(serve_connection(client, server.clone(), out_dir.clone()));
+        }
+    });
+    Ok(Listener { task })
+}
+
+/// Take jobs from one client until it closes its side, running them side by side
+/// and replying to each as it finishes. A line that isn't a valid job only gets an
+/// error reply.
+async fn serve_connection<S>(conn: S, server: Arc<serve::Server>, out_dir: Arc<PathBuf>)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, mut writer) = tokio::io::split(conn);
+    let (tx, mut rx) = mpsc::unbounded_channel::<Reply>();
+    let replies = tokio::spawn(async move {
+        while let Some(reply) = rx.recv().await {
+            let mut line = serde_json::to_vec(&reply).unwrap();
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+        }
+        writer.shutdown().await
+    });
+
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    let mut jobs = JoinSet::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                tracing::debug!("Socket client read failed: {}", e);
+                break;
+            }
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let received = Instant::now();
+        let value = match serde_json::from_slice::<serde_json::Value>(&line) {
+            Ok(value) => value,
+            Err(e) => {
+                let _ = tx.send(failed(serde_json::Value::Null, received, e.to_string()));
+                continue;
+            }
+        };
+        // Keep the id even when the rest is wrong, so the client can tell which failed
+        let id = value.get("id").cloned().unwrap_or_default();
+        let job = match serde_json::from_value::<Job>(value) {
+            Ok(job) => job,
+            Err(e) => {
+                let _ = tx.send(failed(id, received, e.to_string()));
+                continue;
+            }
+        };
+        let Job {
+            id,
+            text,
+            voice,
+            speed,
+            bitrate,
+            out_path,
+        } = job;
+        let request = serve::SynthesizeRequest {
+            text,
+            voice,
+            speed,
+            bitrate,
+        };
+        let (server, out_dir, tx) = (server.clone(), out_dir.clone(), tx.clone());
+        jobs.spawn(async move {
+            let reply = match run_job(&server, request, out_path, &out_dir).await {
+                Ok(path) => Reply {
+                    id,
+                    ok: true,
+                    duration_ms: received.elapsed().as_millis() as u64,
+                    path: Some(path),
+                    error: None,
+                },
+                Err(e) => failed(id, received, format!("{:#}", e)),
+            };
+            let _ = tx.send(reply);
+        });
+    }
+
+    // Jobs already taken still finish and get their replies
+    while jobs.join_next().await.is_some() {}
+    drop(tx);
+    if let Ok(Err(e)) = replies.await {
+        tracing::debug!("Socket client write failed: {}", e);
+    }
+}
+
+async fn run_job(
+    server: &serve::Server,
+    request: serve::SynthesizeRequest,
+    out_path: Option<PathBuf>,
+    out_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let (settings, items) = server
+        .validate(request)
+        .map_err(|r| anyhow::anyhow!("{}: {}", r.code, r.message))?;
+    let id = server.next_id();
+    let (mp3, _) = server
+        .render(format!("job {}", id), &settings, items)
+        .await?;
+    let path = out_path.unwrap_or_else(|| out_dir.join(format!("job-{}.mp3", id)));
+    tokio::fs::write(&path, mp3)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+fn failed(id: serde_json::Value, received: Instant, error: String) -> Reply {
+    Reply {
+        id,
+        ok: false,
+        duration_ms: received.elapsed().as_millis() as u64,
+        path: None,
+        error: Some(error),
+    }
+}
@@ -0,0 +1,77 @@
+use crate::utils;
+
+/// Dominant script of a piece of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Script {
+    Zh,
+    En,
+    Mixed,
+    /// Nothing letter-like at all (digits, punctuation).
+    Other,
+}
+
+impl Script {
+    pub fn label(self) -> &'static str {
+        match self {
+            Script::Zh => "zh",
+            Script::En => "en",
+            Script::Mixed => "mixed",
+            Script::Other => "other",
+        }
+    }
+}
+
+/// Share of the text (by rough syllable weight) a script needs to count as dominant.
+const DOMINANT_SHARE: f64 = 0.8;
+
+/// What a line is written in, with the weight of each script.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub script: Script,
+    zh: f64,
+    en: f64,
+}
+
+impl Detection {
+    /// Fraction of the line's letter-like content in `script`. Lines without any
+    /// count as fully covered by every script: there is nothing to misread.
+    pub fn share(&self, script: Script) -> f64 {
+        let total = self.zh + self.en;
+        if total == 0.0 {
+            return 1.0;
+        }
+        match script {
+            Script::Zh => self.zh / total,
+            Script::En => self.en / total,
+            Script::Mixed | Script::Other => 0.0,
+        }
+    }
+}
+
+/// Classify by codepoint ranges. Each CJK character counts as a syllable, Latin
+/// letters count a third each so an English word weighs about the same as a
+/// couple of characters.
+pub fn detect(text: &str) -> Detection {
+    let (cjk, latin) = text.chars().fold((0usize, 0usize), |(cjk, latin), c| {
+        if utils::is_cjk(c) {
+            (cjk + 1, latin)
+        } else if c.is_ascii_alphabetic() {
+            (cjk, latin + 1)
+        } else {
+            (cjk, latin)
+        }
+    });
+
+    let zh = cjk as f64;
+    let en = latin as f64 / 3.0;
+    let script = if zh + en == 0.0 {
+        Script::Other
+    } else if zh / (zh + en) >= DOMINANT_SHARE {
+        Script::Zh
+    } else if en / (zh + en) >= DOMINANT_SHARE {
+        Script::En
+    } else {
+        Script::Mixed
+    };
+    Detection { script, zh, en }
+}
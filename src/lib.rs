@@ -0,0 +1,43 @@
+//! Morganite's synthesis pipeline: Kokoro TTS over text files, written out as
+//! segmented MP3s. The `Morganite` binary is a command line around it.
+//!
+//! [`run`] reads a [`SynthesisJob`] to completion; a [`Synthesizer`] keeps the
+//! models loaded for repeated use, e.g. [`Synthesizer::to_mp3`] for one text.
+//! Logging goes through `tracing`; install a subscriber to see it.
+
+pub mod cache;
+pub mod cast;
+pub mod chapters;
+pub mod dialogue;
+pub mod dsp;
+pub mod dump;
+pub mod index;
+pub mod lang;
+pub mod markup;
+pub mod metrics;
+pub mod numbers;
+pub mod pauses;
+pub mod pcm_hash;
+pub mod pipe;
+pub mod pipeline;
+pub mod playback;
+pub mod podcast;
+pub mod preprocess;
+pub mod progress;
+pub mod speakers;
+pub mod subtitles;
+pub mod summary;
+pub mod synthesis;
+pub mod text;
+pub mod throttle;
+pub mod timings;
+pub mod toc;
+pub mod tts;
+pub mod tune;
+pub mod utils;
+pub mod verify;
+pub mod voices;
+pub mod wav;
+pub mod writer;
+
+pub use synthesis::{Error, Event, OutputConfig, SynthesisJob, Synthesizer, run};
@@ -0,0 +1,147 @@
+use std::{
+    fs::{File, TryLockError},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Lockfile name inside a locked folder.
+pub const DIR_LOCK: &str = ".morganite.lock";
+
+/// Who holds a lock, as written into its file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Holder {
+    pid: u32,
+    started: String,
+}
+
+impl std::fmt::Display for Holder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pid {} (started {})", self.pid, self.started)
+    }
+}
+
+/// An advisory OS lock on a lockfile, so a second run fails fast instead of writing
+/// into what this one is writing. Dropping it removes the file; after a crash the
+/// OS releases the lock and the file left behind is taken over as stale.
+pub struct Lock {
+    path: PathBuf,
+    file: File,
+}
+
+impl Lock {
+    /// Lock the folder `dir` through a [`DIR_LOCK`] file in it, creating it if needed.
+    pub fn dir(dir: &Path, force: bool) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Self::acquire(dir.join(DIR_LOCK), force)
+    }
+
+    /// Lock the state file `path` through a `.lock` file next to it.
+    pub fn file(path: &Path, force: bool) -> anyhow::Result<Self> {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        Self::acquire(path.with_file_name(name), force)
+    }
+
+    /// Take the lock at `path`. With `force`, a lock whose holder is no longer
+    /// running is broken even if the OS still reports it held (e.g. on a network
+    /// filesystem); a live holder is never overridden.
+    pub fn acquire(path: PathBuf, force: bool) -> anyhow::Result<Self> {
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lockfile {}", path.display()))?;
+        let previous = read_holder(&mut file);
+
+        match file.try_lock() {
+            Ok(()) => {
+                if let Some(holder) = previous {
+                    tracing::warn!(
+                        "Taking over the stale lock {} left by {}",
+                        path.display(),
+                        holder
+                    );
+                }
+            }
+            Err(e) => {
+                let locked = matches!(e, TryLockError::WouldBlock);
+                match previous {
+                    // No OS locks here (e.g. some network filesystems) and nobody
+                    // recorded: the file itself is the lock
+                    None if !locked => tracing::warn!(
+                        "Can't lock {} ({}), relying on the lockfile alone",
+                        path.display(),
+                        e
+                    ),
+                    Some(holder) if !is_running(holder.pid) => {
+                        anyhow::ensure!(
+                            force,
+                            "{} is locked by {}, which is no longer running; \
+                             pass --force-unlock to break the stale lock",
+                            path.display(),
+                            holder
+                        );
+                        tracing::warn!("Breaking the stale lock {} of {}", path.display(), holder);
+                        drop(file);
+                        std::fs::remove_file(&path).with_context(|| {
+                            format!("Failed to remove lockfile {}", path.display())
+                        })?;
+                        return Self::acquire(path, false);
+                    }
+                    holder => anyhow::bail!(
+                        "{} is locked by another run, {}; wait for it to finish",
+                        path.display(),
+                        holder.map_or("pid unknown".to_string(), |h| h.to_string())
+                    ),
+                }
+            }
+        }
+
+        let holder = Holder {
+            pid: std::process::id(),
+            started: chrono::Local::now().to_rfc3339(),
+        };
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| file.write_all(serde_json::to_string(&holder)?.as_bytes()))
+            .and_then(|_| file.sync_all())
+            .with_context(|| format!("Failed to write lockfile {}", path.display()))?;
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // Removed before the unlock, so no run finds it unlocked but still claiming us
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove lockfile {}: {}", self.path.display(), e);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+fn read_holder(file: &mut File) -> Option<Holder> {
+    let mut text = String::new();
+    file.read_to_string(&mut text).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Whether process `pid` still exists. Only Linux can tell, through `/proc`; on
+/// other platforms every holder counts as running, so a stale lock there is never
+/// broken, not even with `--force-unlock`, and its file has to be removed by hand.
+fn is_running(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
@@ -1,294 +1,2558 @@
 use std::{
-    collections::BTreeMap,
-    fs::File,
-    io::{BufRead, BufReader},
+    io::IsTerminal,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
     time::Duration,
 };
 
 use anyhow::Context;
 use chrono::Local;
-use clap::Parser;
 use kokoro_tts::Voice;
-use tokio::{
-    sync::{Semaphore, mpsc},
-    task::JoinSet,
-};
 use tracing::Level;
 use tracing_appender::non_blocking;
 use tracing_indicatif::{IndicatifLayer, span_ext::IndicatifSpanExt, style::ProgressStyle};
 use tracing_subscriber::{fmt, layer::SubscriberExt};
 use tracing_unwrap::ResultExt;
 
-mod tts;
-mod utils;
-mod writer;
+use morganite::{
+    cache, cast, chapters, dsp, dump, lang, markup, metrics, numbers, pauses, pcm_hash, pipe,
+    pipeline, playback, podcast, preprocess, progress, speakers, subtitles, summary, text,
+    throttle, timings, tts, tune, utils,
+    utils::{collect_txt_files, file_stem_string, list_txt_files},
+    verify, voices, wav, writer,
+};
+
+mod audition;
+mod bench;
+mod calibrate;
+mod clipboard;
+mod config;
+mod desktop;
+mod grpc;
+mod jobs;
+mod lock;
+mod models;
+mod overrides;
+mod queue;
+mod repl;
+mod serve;
+mod stats;
+mod upload;
+mod watch;
+mod webhook;
 
 #[derive(clap::Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Analyze input text without synthesizing anything
+    Stats(stats::StatsArgs),
+    /// List the voice identifiers known for the Kokoro v1.1-zh voices
+    ListVoices(voices::ListVoicesArgs),
+    /// Read a sample text with several voices to compare them
+    Audition(audition::AuditionArgs),
+    /// Measure synthesis throughput at several concurrency levels
+    Bench(bench::BenchArgs),
+    /// Measure how much audio and wall time a voice takes per char, for estimates
+    Calibrate(calibrate::CalibrateArgs),
+    /// Keep the engine loaded and synthesize text sent over HTTP
+    Serve(serve::ServeArgs),
+    /// Check that the segments of a finished run are whole and as long as written
+    Verify(verify::VerifyArgs),
+    /// Type lines at a prompt and hear them right away, adjusting voice and speed as you go
+    Repl(repl::ReplArgs),
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
     /// Path to a single .txt file OR a folder containing multiple .txt files
-    text_file: String,
+    #[arg(required_unless_present_any = ["clipboard", "clipboard_watch"])]
+    text_file: Option<String>,
+
+    /// Read the text on the clipboard instead of a file, and play it (see --play)
+    #[arg(long, conflicts_with_all = ["text_file", "watch", "clipboard_watch"])]
+    clipboard: bool,
+
+    /// Keep reading each new text copied to the clipboard until Ctrl-C; SIGHUP reloads the
+    /// model files
+    #[arg(long, conflicts_with_all = ["text_file", "watch"])]
+    clipboard_watch: bool,
+
+    /// Shorter copies are ignored by --clipboard-watch, so stray words don't start reading
+    #[arg(long, default_value_t = 20, requires = "clipboard_watch")]
+    clipboard_min_chars: usize,
+
+    /// After the existing files, keep watching the input folder and process new or changed .txt
+    /// files; SIGHUP reloads the model files between two files
+    #[arg(long)]
+    watch: bool,
+
+    /// Where --watch records the files it has done, so a restart skips them
+    #[arg(long, default_value = "watch-state.json", requires = "watch")]
+    watch_state: PathBuf,
+
+    /// Read only this machine's part of the input folder, e.g. 1/3; each file goes to one
+    /// shard by a hash of its name, so the same command with every index covers the folder
+    /// exactly once
+    #[arg(long, value_parser = utils::parse_shard, conflicts_with_all = ["clipboard", "clipboard_watch"])]
+    shard: Option<utils::Shard>,
+
+    /// Leave out input files that an earlier run in --output-dir already wrote
+    /// segments for, so a re-run only reads the new ones
+    #[arg(long, conflicts_with_all = ["clipboard", "clipboard_watch", "stdout"])]
+    skip_existing: bool,
+
+    /// Also play the output on the default audio device while it is generated
+    #[arg(long)]
+    play: bool,
+
+    /// Most audio --play holds ahead of what is heard; past it, playback skips ahead
+    #[arg(long, value_parser = utils::parse_duration, default_value = "10m", requires = "play")]
+    play_buffer: Duration,
+
+    /// Write one continuous MP3 stream to standard output instead of segment files,
+    /// e.g. `| mpv -`; a folder's files follow one another. Logs go to stderr
+    #[arg(long, conflicts_with = "podcast_feed")]
+    stdout: bool,
+
+    /// Also feed each file's audio as raw PCM (24kHz mono) to this command, started once
+    /// per file, e.g. "ffmpeg -f f32le -ar 24000 -ac 1 -i pipe:0 {out_dir}/{stem}.opus".
+    /// {stem}, {input} and {out_dir} are filled in; a non-zero exit fails the file
+    #[arg(long)]
+    pipe_to: Option<String>,
+
+    /// Sample format written to --pipe-to
+    #[arg(long, value_enum, default_value = "f32", requires = "pipe_to")]
+    pipe_format: pipe::PcmFormat,
+
+    /// Upload each segment as soon as it is finished, to s3://bucket/prefix/ (AWS
+    /// credentials and region from the usual environment and config files) or a WebDAV
+    /// https:// URL (user and password in the URL or MORGANITE_WEBDAV_USER/_PASSWORD)
+    #[arg(long, conflicts_with = "stdout")]
+    upload: Option<String>,
+
+    /// Uploads running at once
+    #[arg(long, value_parser = utils::parse_count, default_value_t = 4, requires = "upload")]
+    upload_concurrency: usize,
+
+    /// Retries of a failed upload, with doubling waits starting at 1s
+    #[arg(long, default_value_t = 3, requires = "upload")]
+    upload_retries: u32,
+
+    /// Delete each segment locally once its upload is confirmed
+    #[arg(long, requires = "upload", conflicts_with = "podcast_feed")]
+    upload_remove_local: bool,
+
+    /// POST JSON events about the run (started, file completed, completed, failed) to this URL
+    #[arg(long)]
+    webhook_url: Option<String>,
+
+    /// Which events to send, comma separated
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "run_started,file_completed,run_completed,run_failed",
+        requires = "webhook_url"
+    )]
+    webhook_events: Vec<webhook::EventKind>,
+
+    /// Sign each payload with this shared secret in X-Morganite-Signature (sha256=<hex HMAC>);
+    /// MORGANITE_WEBHOOK_SECRET works too and stays out of the process list
+    #[arg(long, requires = "webhook_url")]
+    webhook_secret: Option<String>,
+
+    /// Show a desktop notification when the run finishes or fails (per file with --watch
+    /// or --clipboard-watch)
+    #[arg(long)]
+    notify: bool,
+
+    /// Defaults for one language's Kokoro model: model files, voice, voice prefix,
+    /// speed, sentence ends and number reading (zh or en); any of them given on the
+    /// command line, in the environment or in the config file wins
+    #[arg(long, value_parser = utils::parse_lang_preset)]
+    lang: Option<&'static utils::LangPreset>,
 
     /// Path for onnx tts model
-    #[arg(long, short, default_value = "kokoro-v1.1-zh.onnx")]
+    #[arg(
+        long,
+        short,
+        env = "MORGANITE_TTS_MODEL",
+        default_value = "kokoro-v1.1-zh.onnx"
+    )]
     tts_model: String,
 
     /// Path for voice bin model
-    #[arg(long, short, default_value = "voices-v1.1-zh.bin")]
+    #[arg(
+        long,
+        short,
+        env = "MORGANITE_VOICE_MODEL",
+        default_value = "voices-v1.1-zh.bin"
+    )]
     voice_model: String,
 
-    /// Voice name, e.g. zf_048, zm_029, af_maple
-    #[arg(long, value_parser = utils::parse_voice, default_value = "zf_048")]
-    voice: Voice,
+    /// Voice name, e.g. zf_048, zm_029, af_maple; also zf:48, or 48 with --voice-prefix
+    #[arg(long, env = "MORGANITE_VOICE", default_value = "zf_048")]
+    voice: String,
+
+    /// Folder the timestamped run folder is created in
+    #[arg(long, env = "MORGANITE_OUTPUT_DIR", default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Break the lock on the output folder (and --watch-state) left by a run that is
+    /// no longer running, e.g. on a network filesystem that kept it; only on Linux,
+    /// elsewhere remove the .morganite.lock file by hand
+    #[arg(long)]
+    force_unlock: bool,
 
-    /// Speech speed
-    #[arg(long, default_value_t = 1.0)]
+    /// How segment files are named; chapter names them after the chapters --chapter-regex
+    /// finds, side by side instead of in a folder per chapter
+    #[arg(long, value_enum, default_value_t, requires = "chapter_regex")]
+    segment_naming: writer::SegmentNaming,
+
+    /// Once done, walk the frames of every segment written and check it is whole and
+    /// as long as what was written to it; failures exit non-zero. See also
+    /// `morganite verify <run folder>`
+    #[arg(long, conflicts_with = "stdout")]
+    verify: bool,
+
+    /// Stop every file after about this much audio (e.g. 10m), written to one
+    /// audio_000_preview.mp3 with the settings of a full run
+    #[arg(long, value_parser = utils::parse_duration, conflicts_with = "stdout")]
+    preview: Option<Duration>,
+
+    /// Open every segment with its part number, spoken in the voice (第三部分, Part
+    /// three): part 1 is audio_000.mp3, or the chapter's first file. Ignored with
+    /// --stdout, which doesn't split
+    #[arg(long)]
+    announce_parts: bool,
+
+    /// Download --tts-model and --voice-model into --model-cache-dir when they don't
+    /// exist, checked against their published SHA-256
+    #[arg(long)]
+    download_models: bool,
+
+    /// Where --download-models gets the files: a base URL or hf:owner/repo[@revision]
+    #[arg(long, default_value = models::DEFAULT_SOURCE, requires = "download_models")]
+    model_source: String,
+
+    /// Folder for downloaded models [default: $XDG_CACHE_HOME/morganite/models]
+    #[arg(long, env = "MORGANITE_MODEL_CACHE", requires = "download_models")]
+    model_cache_dir: Option<PathBuf>,
+
+    /// SHA-256 to check a downloaded file against, e.g. kokoro-v1.1-zh.onnx=<hex>, for
+    /// sources that publish none; repeat for more files
+    #[arg(long, value_parser = models::parse_checksum, requires = "download_models")]
+    model_sha256: Vec<(String, String)>,
+
+    /// Speech speed, 0.5 to 2.0
+    #[arg(long, value_parser = utils::parse_speed, default_value_t = 1.0)]
     speed: f32,
 
-    /// Concurrency
-    #[arg(long, default_value_t = 4)]
-    concurrency: usize,
-}
+    /// Where calibrate stored its measurements, which the ETAs start from
+    /// [default: $XDG_CACHE_HOME/morganite/calibration.json]
+    #[arg(long)]
+    calibration: Option<PathBuf>,
+
+    /// Check the inputs and settings, then report each file's lines, chars and the
+    /// synthesis time the calibration predicts, without loading the engines
+    #[arg(long, conflicts_with_all = ["watch", "clipboard", "clipboard_watch"])]
+    dry_run: bool,
+
+    /// How each input file picks its voice
+    #[arg(long, value_enum, default_value_t = voices::VoicePolicy::Fixed)]
+    voice_policy: voices::VoicePolicy,
+
+    /// Voices for --voice-policy random/rotate, e.g. zf_048,zf_002,zm_029
+    #[arg(long, value_delimiter = ',')]
+    voice_pool: Vec<String>,
+
+    /// Seed for --voice-policy random (a fresh one is logged when omitted)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Instead of a full run, read the start of the input once per listed voice
+    #[arg(long, value_delimiter = ',')]
+    compare_voices: Vec<String>,
+
+    /// Number of input lines read by --compare-voices
+    #[arg(long, default_value_t = 20)]
+    compare_lines: usize,
+
+    /// MP3 bitrate in kbps
+    #[arg(long, default_value_t = 64)]
+    bitrate: u32,
+
+    /// MPEG channel mode of the MP3s; the audio is mono, so only mono passes the
+    /// encoder's checks until there is stereo output
+    #[arg(long, value_enum, default_value_t)]
+    stereo_mode: writer::ChannelMode,
+
+    /// high low-passes the audio before encoding, the lower the bitrate the lower the
+    /// cutoff, as the encoder has no quality setting of its own
+    #[arg(long, value_enum, default_value_t)]
+    encoder_quality: writer::EncoderQuality,
+
+    /// Concurrent synthesis sessions, auto to fit them to the available cores, or
+    /// auto-tune to start there and keep adjusting by measured throughput
+    #[arg(long, value_parser = utils::parse_concurrency, default_value = "auto")]
+    concurrency: utils::Concurrency,
+
+    /// Synthesize runs of up to this many very short lines (under 10 chars) in one call,
+    /// splitting the audio at the pauses between them; batched lines bypass the cache
+    #[arg(long, value_parser = utils::parse_batch_size)]
+    batch_short_lines: Option<usize>,
+
+    /// Capacity of the channel from the synthesis tasks to the writer, in audio chunks
+    /// of up to one second each; more smooths out bursts at the cost of memory
+    /// [default: 2 x concurrency]
+    #[arg(long, value_parser = utils::parse_count)]
+    queue_depth: Option<usize>,
+
+    /// How many lines past the one being written may be scheduled for synthesis;
+    /// finished lines wait in memory until their turn, so a wider window keeps the
+    /// sessions busier on mixed-length lines but holds more audio [default: 2 x concurrency]
+    #[arg(long, value_parser = utils::parse_count)]
+    lookahead: Option<usize>,
+
+    /// Encode each clip to MP3 in the worker that synthesized it, so a single encoder
+    /// thread is no longer the ceiling (experimental: adds up to 24ms after each clip;
+    /// bench --encode measures the gain)
+    #[arg(long)]
+    parallel_encode: bool,
+
+    /// Keep synthesis busy only this share of the time, e.g. 50%, to run quietly in the
+    /// background; SIGUSR1 raises it and SIGUSR2 lowers it by 10 points during the run
+    #[arg(long, value_parser = utils::parse_percent)]
+    throttle: Option<f64>,
+
+    /// Skip synthesizing a throwaway line on every session before the run starts
+    #[arg(long)]
+    no_warmup: bool,
+
+    /// Sample rate the model outputs, for Kokoro variants at 22050 or 44100 Hz; their
+    /// clips are resampled to the 24000 Hz everything is written at
+    #[arg(long, default_value_t = writer::SAMPLE_RATE)]
+    engine_sample_rate: u32,
+
+    /// Second ONNX model used for lines that are predominantly English
+    #[arg(long, requires = "voice_model_en")]
+    tts_model_en: Option<String>,
+
+    /// Voice bin model for --tts-model-en
+    #[arg(long, requires = "tts_model_en")]
+    voice_model_en: Option<String>,
+
+    /// Voice used with --tts-model-en
+    #[arg(long, default_value = "af_maple")]
+    voice_en: String,
+
+    #[command(flatten)]
+    text: TextArgs,
+
+    /// Cache synthesized clips here and reuse them across runs
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
 
-type Msg = (usize, anyhow::Result<(Vec<f32>, Duration)>);
+    /// Size cap for --cache-dir; least recently used clips are pruned beyond it
+    #[arg(long, value_parser = utils::parse_size, default_value = "2GiB")]
+    cache_max_size: u64,
 
-fn is_txt(p: &Path) -> bool {
-    p.extension()
-        .and_then(|e| e.to_str())
-        .map(|e| e.eq_ignore_ascii_case("txt"))
-        .unwrap_or(false)
+    /// Let pauses run across a segment rotation, so a segment may start with silence
+    #[arg(long)]
+    lead_in_padding: bool,
+
+    /// Silence between consecutive lines, e.g. 250ms
+    #[arg(
+        long,
+        value_parser = utils::parse_duration,
+        default_value = "0ms",
+        conflicts_with = "punctuation_pauses"
+    )]
+    line_gap: Duration,
+
+    /// Crossfade directly adjacent clips over this long (2ms to 10ms) to avoid clicks at the joins
+    #[arg(long, value_parser = utils::parse_crossfade, default_value = "0ms")]
+    crossfade: Duration,
+
+    /// Subtitle formats written from the line timings, comma-separated
+    #[arg(long, value_enum, value_delimiter = ',')]
+    subtitles: Vec<subtitles::Format>,
+
+    /// One subtitle file per MP3 segment instead of per input file
+    #[arg(long)]
+    subtitles_per_segment: bool,
+
+    /// Extend each cue over the pause that follows its line
+    #[arg(long)]
+    subtitles_include_gap: bool,
+
+    /// Repeat a split line's LRC time tag at each of its chunks so highlighting keeps up
+    #[arg(long)]
+    lrc_chunk_tags: bool,
+
+    /// Write index.csv per input file mapping audio offsets to source line numbers and
+    /// the voice reading them
+    #[arg(long)]
+    index_csv: bool,
+
+    /// Write playlist.m3u8 per output folder listing its segments in order, titled
+    /// after their chapters
+    #[arg(long, conflicts_with = "stdout")]
+    playlist: bool,
+
+    /// Write timings.json with per-line synthesis time, realtime factor and queue wait
+    #[arg(long)]
+    timings_json: bool,
+
+    /// Keep Prometheus metrics in this file for a textfile collector, rewritten every few seconds
+    #[arg(long)]
+    metrics_file: Option<PathBuf>,
+
+    /// Serve Prometheus metrics at http://<address>/metrics, e.g. 127.0.0.1:9090
+    #[arg(long)]
+    metrics_listen: Option<std::net::SocketAddr>,
+
+    /// Dump each line's raw engine output (WAV plus a JSON description) into this folder
+    #[arg(long)]
+    debug_dump_pcm: Option<PathBuf>,
+
+    /// Only dump these item indices, e.g. 1040-1060
+    #[arg(long, value_parser = utils::parse_index_range, requires = "debug_dump_pcm")]
+    debug_dump_lines: Option<std::ops::RangeInclusive<usize>>,
+
+    /// Write pcm_hashes.jsonl with a SHA-256 of each line's raw engine output and one over
+    /// the run; only the PCM before trimming, tempo, gain and encoding is covered, not the MP3s
+    #[arg(long)]
+    hash_pcm: bool,
+
+    /// Synthesize everything again without writing audio and report where it first differs
+    /// from an earlier run's pcm_hashes.jsonl; the cache is not read
+    #[arg(long, conflicts_with_all = ["watch", "clipboard_watch", "compare_voices"])]
+    compare_hashes: Option<PathBuf>,
+
+    /// Keep progress.json in the run folder up to date for external monitoring
+    #[arg(long)]
+    progress_json: bool,
+
+    /// Don't read each input through once up front to count its lines and chars; the
+    /// progress bars become spinners without an ETA, which saves a pass over huge inputs
+    #[arg(long)]
+    no_prescan: bool,
+
+    /// How often progress.json is rewritten
+    #[arg(long, value_parser = utils::parse_duration, default_value = "5s")]
+    progress_interval: Duration,
+
+    /// Write (or extend) an RSS podcast feed with one item per output segment
+    #[arg(long, requires = "podcast_base_url")]
+    podcast_feed: Option<PathBuf>,
+
+    /// URL the run folder is published under; enclosure URLs append the segment paths
+    #[arg(long)]
+    podcast_base_url: Option<String>,
+
+    /// Feed title; the config file's [podcast] table can also give it and the
+    /// description, author and image (defaults to the input's name)
+    #[arg(long)]
+    podcast_title: Option<String>,
+
+    #[arg(long)]
+    podcast_description: Option<String>,
+
+    #[arg(long)]
+    podcast_author: Option<String>,
+
+    /// Cover image URL for the feed
+    #[arg(long)]
+    podcast_image: Option<String>,
+
+    /// Add --line-gap on top of heading/paragraph pauses instead of using the larger one
+    #[arg(long)]
+    stack_pauses: bool,
+
+    /// Time-stretch every clip by this factor without changing pitch, e.g. 1.05
+    #[arg(long, value_parser = utils::parse_tempo, default_value_t = 1.0)]
+    tempo: f32,
+
+    /// Output gain in dB applied to all audio, e.g. +3 or -2.5dB
+    #[arg(long, value_parser = utils::parse_db, allow_hyphen_values = true, default_value = "0")]
+    gain: f32,
+
+    /// Pull every voice's loudness towards the narrator's as the run progresses
+    #[arg(long)]
+    match_loudness: bool,
+
+    /// Peak limiter before encoding (on automatically with positive --gain or --match-loudness)
+    #[arg(long)]
+    limiter: bool,
+
+    /// Limiter ceiling in dBFS
+    #[arg(long, value_parser = utils::parse_db, allow_hyphen_values = true, default_value = "-1")]
+    limiter_ceiling: f32,
+
+    /// Limiter lookahead/attack time
+    #[arg(long, value_parser = utils::parse_duration, default_value = "5ms")]
+    limiter_attack: Duration,
+
+    /// Limiter release time
+    #[arg(long, value_parser = utils::parse_duration, default_value = "100ms")]
+    limiter_release: Duration,
+
+    /// Cut the engine's own leading/trailing silence from every clip
+    #[arg(long)]
+    trim_clip_silence: bool,
+
+    /// Level in dBFS below which clip edges count as silence
+    #[arg(long, value_parser = utils::parse_dbfs, allow_hyphen_values = true, default_value = "-50")]
+    trim_threshold: f32,
+
+    /// Audio kept on each side of the trimmed clip
+    #[arg(long, value_parser = utils::parse_duration, default_value = "30ms")]
+    trim_guard: Duration,
+
+    /// Shorten pauses inside a clip longer than this to it, e.g. 1.2s, against the
+    /// engine's occasional dead air; independent of --trim-clip-silence
+    #[arg(long, value_parser = utils::parse_duration)]
+    max_internal_silence: Option<Duration>,
+
+    /// Level in dBFS below which clip audio counts as silence for --max-internal-silence
+    #[arg(
+        long,
+        value_parser = utils::parse_dbfs,
+        allow_hyphen_values = true,
+        default_value = "-45",
+        requires = "max_internal_silence"
+    )]
+    internal_silence_threshold: f32,
+
+    /// Shortest stretch below --internal-silence-threshold that counts as a pause
+    #[arg(
+        long,
+        value_parser = utils::parse_duration,
+        default_value = "300ms",
+        requires = "max_internal_silence"
+    )]
+    internal_silence_min: Duration,
+
+    /// Also collect every segment's loudness, peak and silence share in levels.csv
+    #[arg(long)]
+    levels_csv: bool,
+
+    /// WAV or MP3 file looped quietly under the narration
+    #[arg(long)]
+    music: Option<PathBuf>,
+
+    /// Level of the music bed
+    #[arg(long, value_parser = utils::parse_db, allow_hyphen_values = true, default_value = "-22dB")]
+    music_gain: f32,
+
+    /// Music-only fade-out after each file's narration
+    #[arg(long, value_parser = utils::parse_duration, default_value = "3s")]
+    music_fade: Duration,
+
+    /// WAV or MP3 file played at the start of each input file's output
+    #[arg(long)]
+    intro: Option<PathBuf>,
+
+    /// WAV or MP3 file played at the end of each input file's output
+    #[arg(long)]
+    outro: Option<PathBuf>,
+
+    /// What goes between two input files in the --stdout stream: tone for a short
+    /// chime, a WAV file, or none. Only works with --stdout; written to files, each
+    /// input already gets outputs of its own
+    #[arg(
+        long,
+        value_parser = utils::parse_file_separator,
+        default_value = "none",
+        requires = "stdout"
+    )]
+    file_separator: utils::FileSeparator,
+
+    /// TOML file of option values, keys named like the options (bitrate = 96), plus
+    /// [[files]] sections like voices.toml; the command line wins over it
+    /// [default: ./morganite.toml when present]
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Print the effective options as a config file and exit
+    #[arg(long)]
+    dump_config: bool,
 }
 
-fn file_stem_string(p: &Path) -> String {
-    p.file_stem()
-        .and_then(|s| s.to_str())
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| "unknown".to_string())
+/// Options that shape how input text becomes synthesis items.
+#[derive(clap::Args)]
+struct TextArgs {
+    /// Split output per chapter on lines matching this regex (zh/en defaults if no value given)
+    #[arg(long, num_args = 0..=1, default_missing_value = chapters::DEFAULT_CHAPTER_REGEX)]
+    chapter_regex: Option<String>,
+
+    /// Prefix for bare voice indices, e.g. `zf` makes `--voice 48` mean zf_048
+    #[arg(long)]
+    voice_prefix: Option<String>,
+
+    /// Friendly voice name usable anywhere a voice is accepted, e.g. narrator=zf_048 (repeatable)
+    #[arg(long = "alias", value_parser = utils::parse_alias)]
+    aliases: Vec<(String, String)>,
+
+    /// Honor per-line voice tags like `[zm_029] text`; `{}` marks the voice name
+    #[arg(long, num_args = 0..=1, default_missing_value = "[{}]")]
+    voice_tag: Option<String>,
+
+    /// TOML file mapping `NAME:` line prefixes to voices (and optional speeds)
+    #[arg(long)]
+    speaker_map: Option<PathBuf>,
+
+    /// Keep the speaker name in the spoken text instead of stripping it
+    #[arg(long)]
+    speak_speaker_names: bool,
+
+    /// TOML cast file assigning voices to characters for attributed dialogue
+    #[arg(long)]
+    cast: Option<PathBuf>,
+
+    /// Log every line the cast attributed to a character
+    #[arg(long)]
+    cast_debug: bool,
+
+    /// Read anything in “…” or 「…」 quotes in this voice, the rest in the narrator's;
+    /// a cast file's characters and per-line voices win
+    #[arg(long)]
+    dialogue_voice: Option<String>,
+
+    /// Quotes with fewer readable chars stay with the narrator, so a quoted word
+    /// doesn't chop the line up
+    #[arg(long, default_value_t = 2, requires = "dialogue_voice")]
+    dialogue_min_chars: usize,
+
+    /// Voices taking turns line by line, e.g. zf_048,zm_029 for question and answer
+    /// drills; lines with a voice tag or speaker keep theirs but still take a turn
+    #[arg(long, value_delimiter = ',')]
+    alternate_voices: Vec<String>,
+
+    /// Where --alternate-voices starts over at the first voice
+    #[arg(long, value_enum, default_value_t, requires = "alternate_voices")]
+    alternate_reset: preprocess::AlternateReset,
+
+    /// Speak the file name (folder mode) and/or the chapter heading as an intro
+    #[arg(long, value_enum, default_value_t = preprocess::Announce::Off)]
+    announce: preprocess::Announce,
+
+    /// Silence after an announcement, e.g. 700ms, 1.5s
+    #[arg(long, value_parser = utils::parse_duration, default_value = "700ms")]
+    announce_pause: Duration,
+
+    /// Silence after chapter heading lines, e.g. 1500ms
+    #[arg(long, value_parser = utils::parse_duration, default_value = "0ms")]
+    pause_after_heading: Duration,
+
+    /// Voice for chapter heading lines, so titles stand apart from the text
+    #[arg(long, requires = "chapter_regex")]
+    heading_voice: Option<String>,
+
+    /// Speed for chapter heading lines, e.g. 0.8 to read titles slower; pair with
+    /// --pause-after-heading for a longer rest after them
+    #[arg(long, value_parser = utils::parse_speed, requires = "chapter_regex")]
+    heading_speed: Option<f32>,
+
+    /// Silence at paragraph breaks (blank lines in the source), e.g. 800ms
+    #[arg(long, value_parser = utils::parse_duration, default_value = "0ms")]
+    pause_between_paragraphs: Duration,
+
+    /// Pause after every line and sentence by the punctuation it ends on (comma,
+    /// period, question, ...), with zh and en defaults, in place of --line-gap
+    #[arg(long)]
+    punctuation_pauses: bool,
+
+    /// Change a --punctuation-pauses entry, e.g. comma=200ms or en.period=300ms; classes
+    /// are comma, clause, period, question, exclamation, ellipsis and paragraph
+    #[arg(
+        long,
+        value_parser = pauses::parse_override,
+        value_delimiter = ',',
+        requires = "punctuation_pauses"
+    )]
+    pause_map: Vec<pauses::Override>,
+
+    /// What to do with lines mostly in a language the models don't speak
+    #[arg(long, value_enum, default_value_t = preprocess::LangPolicy::Warn)]
+    lang_policy: preprocess::LangPolicy,
+
+    /// Readable share (0-1) below which --lang-policy applies to a line
+    #[arg(long, default_value_t = 0.6)]
+    lang_threshold: f64,
+
+    /// Synthesize punctuation-only lines like `。。。` instead of skipping them
+    #[arg(long)]
+    keep_unpronounceable: bool,
+
+    /// Silence in place of skipped scene-break lines like `＊＊＊`, e.g. 1500ms
+    #[arg(long, value_parser = utils::parse_duration, default_value = "0ms")]
+    scene_break_pause: Duration,
+
+    /// Punctuation long lines are cut after for synthesis, e.g. `.!?` for English;
+    /// a `.` only counts before a space or the end of the line
+    #[arg(long, value_parser = text::SentenceEnds::parse, default_value = text::SENTENCE_ENDS)]
+    sentence_ends: text::SentenceEnds,
+
+    /// Spell out numbers written in digits before synthesis, as Chinese or English
+    /// words; digits run into Latin letters, like MP3 or 2nd, are left alone
+    #[arg(long, value_enum, default_value_t)]
+    numbers: numbers::NumberStyle,
 }
 
-fn read_non_empty_lines(path: &Path) -> anyhow::Result<Vec<String>> {
-    let f =
-        File::open(path).with_context(|| format!("Failed to open text file {}", path.display()))?;
-    let reader = BufReader::new(f);
-    Ok(reader
-        .lines()
-        .map(|l| {
-            l.expect_or_log("Failed to get line of text file")
-                .trim()
-                .to_string()
+impl TextArgs {
+    fn preprocessor(&self) -> anyhow::Result<preprocess::Preprocessor> {
+        let voice_names = utils::VoiceNames::new(self.voice_prefix.as_deref(), &self.aliases)
+            .context("init voice aliases")?;
+
+        let chapter_detector = self
+            .chapter_regex
+            .as_deref()
+            .map(chapters::ChapterDetector::new)
+            .transpose()
+            .context("init chapter detection")?;
+
+        let voice_tags = self
+            .voice_tag
+            .as_deref()
+            .map(markup::VoiceTagSyntax::new)
+            .transpose()
+            .context("init voice tags")?;
+
+        let speakers = self
+            .speaker_map
+            .as_deref()
+            .map(|path| speakers::SpeakerMap::load(path, &voice_names))
+            .transpose()
+            .context("init speaker map")?;
+
+        let cast = self
+            .cast
+            .as_deref()
+            .map(|path| cast::Cast::load(path, &voice_names))
+            .transpose()
+            .context("init cast")?;
+
+        let dialogue_voice = self
+            .dialogue_voice
+            .as_deref()
+            .map(|v| voice_names.parse(v))
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .context("--dialogue-voice")?;
+        let alternate_voices = self
+            .alternate_voices
+            .iter()
+            .map(|v| voice_names.parse(v))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(anyhow::Error::msg)
+            .context("--alternate-voices")?;
+        let heading_voice = self
+            .heading_voice
+            .as_deref()
+            .map(|v| voice_names.parse(v))
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .context("--heading-voice")?;
+
+        let pause_map = self.punctuation_pauses.then(|| {
+            let map = pauses::PauseMap::new(&self.pause_map);
+            for line in map.describe() {
+                tracing::info!("Punctuation pauses {}", line);
+            }
+            map
+        });
+
+        Ok(preprocess::Preprocessor {
+            voice_names,
+            chapter_detector,
+            voice_tags,
+            speakers,
+            speak_speaker_names: self.speak_speaker_names,
+            cast,
+            cast_debug: self.cast_debug,
+            dialogue_voice,
+            dialogue_min_chars: self.dialogue_min_chars,
+            alternate_voices,
+            alternate_reset: self.alternate_reset,
+            announce: self.announce,
+            announce_pause: self.announce_pause,
+            pause_after_heading: self.pause_after_heading,
+            heading_voice,
+            heading_speed: self.heading_speed,
+            pause_between_paragraphs: self.pause_between_paragraphs,
+            pause_map,
+            lang_policy: self.lang_policy,
+            lang_threshold: self.lang_threshold,
+            english_model: false,
+            keep_unpronounceable: self.keep_unpronounceable,
+            scene_break_pause: self.scene_break_pause,
+            sentence_ends: self.sentence_ends.clone(),
+            numbers: self.numbers,
         })
-        .filter(|l| !l.is_empty())
-        .collect::<Vec<_>>())
+    }
+}
+
+/// Where a watching run gets its next input file from.
+enum Incoming {
+    /// `--watch`: new or changed files in the input folder.
+    Folder(watch::Folder, watch::WatchState),
+    /// `--clipboard-watch`: each new text copied, saved to the run folder.
+    Clipboard(clipboard::Watch),
+}
+
+impl Incoming {
+    async fn next(&mut self) -> PathBuf {
+        match self {
+            Incoming::Folder(folder, state) => folder.next(state).await,
+            Incoming::Clipboard(watch) => watch.next().await,
+        }
+    }
+}
+
+/// `--cache-dir`, keyed by the model files `spec` loads.
+fn open_cache(
+    dir: PathBuf,
+    spec: &tts::EngineSpec,
+    max_bytes: u64,
+) -> anyhow::Result<cache::AudioCache> {
+    let mut models = vec![Path::new(&spec.tts_model), Path::new(&spec.voice_model)];
+    if let Some((tts_model, voice_model, _)) = &spec.english {
+        models.extend([Path::new(tts_model), Path::new(voice_model)]);
+    }
+    cache::AudioCache::open(dir, &models, max_bytes)
+}
+
+/// SIGHUP in watch mode: load the model files again, e.g. after they were
+/// replaced, and move the cache over to clips of the new models.
+async fn reload(
+    pipeline: &mut pipeline::Pipeline,
+    voice_bin: &mut voices::VoiceBin,
+    spec: &tts::EngineSpec,
+    voice: Voice,
+    cache_dir: Option<&Path>,
+    cache_max_size: u64,
+) -> anyhow::Result<std::time::Duration> {
+    let new_bin = voices::VoiceBin::load(Path::new(&spec.voice_model))?;
+    if let Some(e) = new_bin.check(voice) {
+        anyhow::bail!("{}", e);
+    }
+    let cache = match cache_dir {
+        Some(dir) => Some(Arc::new(open_cache(
+            dir.to_path_buf(),
+            spec,
+            cache_max_size,
+        )?)),
+        None => None,
+    };
+    let took = tts::reload(&pipeline.engine, spec, voice).await?;
+    *voice_bin = new_bin;
+    pipeline.cache = cache;
+    Ok(took)
+}
+
+/// Whether `e` comes from writing to a pipe whose reader went away, e.g. the
+/// player behind `--stdout` was closed.
+fn is_broken_pipe(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// Completes on Ctrl-C; never, if it can't be listened for.
+async fn interrupted() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Plain stderr logging for subcommands that don't produce a run folder.
+fn init_console_tracing(level: Level) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).expect_or_log("Init tracing failed");
 }
 
 #[tokio::main]
 async fn main() {
-    let timestamp = Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let (cli, config) = match config::parse() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            std::process::exit(2);
+        }
+    };
+
+    match cli.command {
+        Some(Command::Stats(args)) => {
+            init_console_tracing(Level::WARN);
+            if let Err(e) = stats::run(&args) {
+                tracing::error!("{:#}", e);
+            }
+        }
+        Some(Command::ListVoices(args)) => {
+            init_console_tracing(Level::WARN);
+            if let Err(e) = voices::run(&args) {
+                tracing::error!("{:#}", e);
+            }
+        }
+        Some(Command::Audition(args)) => {
+            init_console_tracing(Level::WARN);
+            if let Err(e) = audition::run(&args).await {
+                tracing::error!("{:#}", e);
+            }
+        }
+        Some(Command::Bench(args)) => {
+            init_console_tracing(Level::WARN);
+            if let Err(e) = bench::run(&args).await {
+                tracing::error!("{:#}", e);
+            }
+        }
+        Some(Command::Calibrate(args)) => {
+            init_console_tracing(Level::WARN);
+            if let Err(e) = calibrate::run(&args).await {
+                tracing::error!("{:#}", e);
+            }
+        }
+        Some(Command::Serve(args)) => {
+            init_console_tracing(Level::WARN);
+            if let Err(e) = serve::run(&args).await {
+                tracing::error!("{:#}", e);
+            }
+        }
+        Some(Command::Verify(args)) => {
+            init_console_tracing(Level::WARN);
+            match verify::run(&args) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    tracing::error!("{:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::Repl(args)) => {
+            init_console_tracing(Level::WARN);
+            if let Err(e) = repl::run(&args).await {
+                tracing::error!("{:#}", e);
+            }
+        }
+        None => {
+            if !run(cli.run, config).await {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Returns whether the run succeeded, which decides the exit code.
+async fn run(mut cli: RunArgs, config: config::Config) -> bool {
+    let started = Local::now();
+    let timestamp = started.format("%Y%m%d_%H%M%S").to_string();
+
+    // Held until the run ends, so a second run into the same output folder fails
+    // fast instead of writing next to this one (and skipping by its segments). A
+    // dry run writes nothing, so it neither locks nor creates the folders below.
+    let _lock = if cli.dry_run {
+        None
+    } else {
+        match lock::Lock::dir(&cli.output_dir, cli.force_unlock) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                // Logging isn't up yet
+                eprintln!("error: {}: {:#}", config.origin("output_dir"), e);
+                std::process::exit(1);
+            }
+        }
+    };
 
     // Keep a top-level timestamp folder for logs (and for single-file output, like before)
-    let target_dir = PathBuf::from(&timestamp);
-    if !target_dir.exists() {
-        std::fs::create_dir(&target_dir).expect("Failed to create target dir");
+    let target_dir = cli.output_dir.join(&timestamp);
+    if !cli.dry_run
+        && !target_dir.exists()
+        && let Err(e) = std::fs::create_dir_all(&target_dir)
+    {
+        // Logging isn't up yet
+        eprintln!(
+            "error: {}: failed to create {}: {}",
+            config.origin("output_dir"),
+            target_dir.display(),
+            e
+        );
+        std::process::exit(1);
     }
 
-    let file_path = format!("{}/app.log", timestamp);
-    let file_appender = std::fs::OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .append(false)
-        .write(true)
-        .open(&file_path)
-        .expect("failed to create log file");
-    let (non_blocking_writer, _guard) = non_blocking(file_appender);
+    let (log_file, _guard) = if cli.dry_run {
+        (None, None)
+    } else {
+        let file_path = target_dir.join("app.log");
+        let file_appender = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .append(false)
+            .write(true)
+            .open(&file_path)
+            .expect("failed to create log file");
+        let (non_blocking_writer, guard) = non_blocking(file_appender);
+        (Some(non_blocking_writer), Some(guard))
+    };
 
     let indicatif_layer = IndicatifLayer::new();
 
+    // Standard output carries the audio with --stdout
+    let console = if cli.stdout {
+        fmt::writer::BoxMakeWriter::new(std::io::stderr)
+    } else {
+        fmt::writer::BoxMakeWriter::new(std::io::stdout)
+    };
     let subscriber = tracing_subscriber::fmt()
         .with_max_level(Level::INFO)
         .with_level(true)
+        .with_writer(console)
         .finish()
         .with(indicatif_layer)
-        .with(fmt::Layer::default().with_writer(non_blocking_writer));
+        .with(log_file.map(|writer| fmt::Layer::default().with_writer(writer)));
 
     tracing::subscriber::set_global_default(subscriber).expect_or_log("Init tracing failed");
 
-    let cli = Cli::parse();
+    if let Some(path) = &config.path {
+        tracing::info!("Using config file {}", path.display());
+    }
+    for warning in &config.warnings {
+        tracing::warn!("{}", warning);
+    }
+    if let Some(preset) = &config.lang_preset {
+        tracing::info!("Language preset {}", preset);
+    }
+
+    if cli.stdout && std::io::stdout().is_terminal() {
+        tracing::error!("--stdout writes MP3 data; pipe it somewhere, e.g. `| mpv -`");
+        return false;
+    }
+
+    if cli.download_models {
+        let downloader = match models::Downloader::new(
+            &cli.model_source,
+            cli.model_cache_dir.clone(),
+            cli.model_sha256.clone(),
+        ) {
+            Ok(downloader) => downloader,
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                return false;
+            }
+        };
+        for model in [&mut cli.tts_model, &mut cli.voice_model] {
+            match downloader.ensure(model).await {
+                Ok(path) => *model = path.display().to_string(),
+                Err(e) => {
+                    tracing::error!("{:#}", e);
+                    return false;
+                }
+            }
+        }
+    }
 
     if !PathBuf::from(&cli.tts_model).exists() {
-        tracing::error!("Unable to finx ONNX TTS model file {}", cli.tts_model);
-        return;
+        tracing::error!(
+            "Unable to find ONNX TTS model file {} ({}); --download-models fetches it",
+            cli.tts_model,
+            config.origin("tts_model")
+        );
+        return false;
     }
 
     if !PathBuf::from(&cli.voice_model).exists() {
-        tracing::error!("Unable to find voice model file {}", cli.voice_model);
-        return;
+        tracing::error!(
+            "Unable to find voice model file {} ({}); --download-models fetches it",
+            cli.voice_model,
+            config.origin("voice_model")
+        );
+        return false;
     }
 
-    tracing::info!("Using ONNX TTS model {}", cli.tts_model);
-    tracing::info!("Using voice model {}", cli.voice_model);
+    tracing::info!(
+        "Using ONNX TTS model {} ({})",
+        cli.tts_model,
+        config.origin("tts_model")
+    );
+    tracing::info!(
+        "Using voice model {} ({})",
+        cli.voice_model,
+        config.origin("voice_model")
+    );
+    if !cli.dry_run {
+        tracing::info!(
+            "Writing to {} ({})",
+            target_dir.display(),
+            config.origin("output_dir")
+        );
+    }
 
-    let input_path = PathBuf::from(&cli.text_file);
-    if !input_path.exists() {
-        tracing::error!("Unable to find input path {}", cli.text_file);
-        return;
+    for model in [&cli.tts_model_en, &cli.voice_model_en]
+        .into_iter()
+        .flatten()
+    {
+        if !PathBuf::from(model).exists() {
+            tracing::error!("Unable to find English model file {}", model);
+            return false;
+        }
     }
 
-    // Build the list of txt files to process
-    let (txt_files, folder_mode): (Vec<PathBuf>, bool) = if input_path.is_file() {
-        if !is_txt(&input_path) {
-            tracing::error!("Input file is not a .txt: {}", input_path.display());
-            return;
-        }
-        (vec![input_path.clone()], false)
-    } else if input_path.is_dir() {
-        let mut files = std::fs::read_dir(&input_path)
-            .expect_or_log("Failed to read input directory")
-            .filter_map(|e| e.ok().map(|e| e.path()))
-            .filter(|p| p.is_file() && is_txt(p))
-            .collect::<Vec<_>>();
-        files.sort();
-        if files.is_empty() {
-            tracing::error!("No .txt files found in folder {}", input_path.display());
-            return;
-        }
-        (files, true)
+    let mut clipboard_watch = None;
+    let (mut txt_files, folder_mode) = if cli.clipboard {
+        match clipboard::take(&target_dir) {
+            Ok(path) => (vec![path], false),
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                return false;
+            }
+        }
+    } else if cli.clipboard_watch {
+        match clipboard::Watch::new(target_dir.clone(), cli.clipboard_min_chars) {
+            Ok(watch) => clipboard_watch = Some(watch),
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                return false;
+            }
+        }
+        (Vec::new(), false)
     } else {
+        match collect_txt_files(cli.text_file.as_deref().unwrap(), cli.watch) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                return false;
+            }
+        }
+    };
+    let mut other_shards = Vec::new();
+    if let Some(shard) = cli.shard {
+        if !folder_mode {
+            tracing::error!("--shard needs an input folder");
+            return false;
+        }
+        let total = txt_files.len();
+        txt_files.retain(|p| {
+            let index = shard.of(p);
+            if index != shard.index {
+                other_shards.push((p.display().to_string(), index));
+            }
+            index == shard.index
+        });
+        tracing::info!(
+            "Shard {}: {} of {} files, by a hash of their names",
+            shard,
+            txt_files.len(),
+            total
+        );
+        for p in &txt_files {
+            tracing::info!("Shard {} takes {}", shard, p.display());
+        }
+        if txt_files.is_empty() && !cli.watch {
+            tracing::warn!("Nothing for shard {} in this folder", shard);
+        }
+    }
+    let mut existing = Vec::new();
+    if cli.skip_existing {
+        if !folder_mode {
+            tracing::error!("--skip-existing needs an input folder");
+            return false;
+        }
+        let finished = match verify::finished_outputs(&cli.output_dir) {
+            Ok(finished) => finished,
+            Err(e) => {
+                tracing::error!("--skip-existing: {:#}", e);
+                return false;
+            }
+        };
+        txt_files.retain(|p| {
+            let done = finished.contains(&file_stem_string(p));
+            if done {
+                tracing::info!(
+                    "Skipping {}, which an earlier run has output for",
+                    p.display()
+                );
+                existing.push(p.display().to_string());
+            }
+            !done
+        });
+        tracing::info!(
+            "--skip-existing: {} files done by earlier runs, {} left",
+            existing.len(),
+            txt_files.len()
+        );
+    }
+    // The state file may live outside the output folder, so it has a lock of its own
+    let _watch_lock = match cli
+        .watch
+        .then(|| lock::Lock::file(&cli.watch_state, cli.force_unlock))
+        .transpose()
+    {
+        Ok(lock) => lock,
+        Err(e) => {
+            tracing::error!("--watch-state: {:#}", e);
+            return false;
+        }
+    };
+    let mut watch_state = None;
+    if cli.watch {
+        if !folder_mode {
+            tracing::error!("--watch needs an input folder");
+            return false;
+        }
+        let state = match watch::WatchState::load(cli.watch_state.clone()) {
+            Ok(state) => state,
+            Err(e) => {
+                tracing::error!("--watch-state: {:#}", e);
+                return false;
+            }
+        };
+        let before = txt_files.len();
+        txt_files.retain(|p| !state.is_handled(p));
+        if txt_files.len() < before {
+            tracing::info!(
+                "Skipping {} files already done according to {}",
+                before - txt_files.len(),
+                cli.watch_state.display()
+            );
+        }
+        watch_state = Some(state);
+    }
+
+    let mut preprocessor = match cli.text.preprocessor() {
+        Ok(preprocessor) => preprocessor,
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            return false;
+        }
+    };
+    preprocessor.english_model = cli.tts_model_en.is_some();
+
+    if let Some(prefix) = preprocessor.voice_names.prefix() {
+        tracing::info!(
+            "Expanding bare voice indices as {}_NNN ({})",
+            prefix,
+            config.origin("voice_prefix")
+        );
+    }
+    let (voice, voice_en) = match (
+        preprocessor.voice_names.parse(&cli.voice),
+        preprocessor.voice_names.parse(&cli.voice_en),
+    ) {
+        (Ok(voice), Ok(voice_en)) => (voice, voice_en),
+        (Err(e), _) => {
+            tracing::error!("{}: {}", config.origin("voice"), e);
+            return false;
+        }
+        (_, Err(e)) => {
+            tracing::error!("--voice-en: {}", e);
+            return false;
+        }
+    };
+    tracing::info!(
+        "Using voice {} ({})",
+        utils::voice_name(voice),
+        config.origin("voice")
+    );
+    if cli.gain != 0.0 {
+        tracing::info!("Applying output gain of {:+} dB", cli.gain);
+    }
+
+    let voice_pool = match cli
+        .voice_pool
+        .iter()
+        .map(|v| preprocessor.voice_names.parse(v))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(pool) => pool,
+        Err(e) => {
+            tracing::error!("--voice-pool: {}", e);
+            return false;
+        }
+    };
+    let compare_voices = match cli
+        .compare_voices
+        .iter()
+        .map(|v| preprocessor.voice_names.parse(v))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(voices) => voices,
+        Err(e) => {
+            tracing::error!("--compare-voices: {}", e);
+            return false;
+        }
+    };
+    if !compare_voices.is_empty() && (folder_mode || cli.clipboard_watch) {
+        tracing::error!("--compare-voices needs a single input file");
+        return false;
+    }
+    // These process the stream across clips, which per-clip encoding can't see
+    if cli.parallel_encode
+        && let Some(other) = [
+            ("--crossfade", !cli.crossfade.is_zero()),
+            ("--match-loudness", cli.match_loudness),
+            ("--music", cli.music.is_some()),
+        ]
+        .into_iter()
+        .find_map(|(flag, on)| on.then_some(flag))
+    {
+        tracing::error!("--parallel-encode can't be combined with {}", other);
+        return false;
+    }
+    let seed = cli.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    if cli.voice_policy == voices::VoicePolicy::Random {
+        tracing::info!(
+            "Random voice policy with seed {} (pass --seed to reproduce)",
+            seed
+        );
+    }
+    let mut voice_picker =
+        match voices::VoicePicker::new(cli.voice_policy, voice, voice_pool.clone(), seed) {
+            Ok(picker) => picker,
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                return false;
+            }
+        };
+
+    // Settle every file's voice, speed and bitrate up front so overrides are validated
+    // with everything else
+    let mut file_settings = Vec::with_capacity(txt_files.len());
+    let mut override_errors = Vec::new();
+    for (file_index, txt_path) in txt_files.iter().enumerate() {
+        let file_override = overrides::load_for(
+            txt_path,
+            &config.files,
+            config.path.as_deref(),
+            &preprocessor.voice_names,
+        )
+        .unwrap_or_else(|e| {
+            override_errors.push(format!("{:#}", e));
+            overrides::Override::default()
+        });
+        let settings = pipeline::FileSettings {
+            voice: file_override
+                .voice
+                .unwrap_or_else(|| voice_picker.pick(file_index)),
+            speed: file_override.speed.unwrap_or(cli.speed),
+            bitrate: file_override.bitrate.unwrap_or(cli.bitrate),
+        };
+        tracing::info!(
+            "Settings for {}: voice {}, speed {}, {} kbps",
+            txt_path.display(),
+            utils::voice_name(settings.voice),
+            settings.speed,
+            settings.bitrate
+        );
+        file_settings.push(settings);
+    }
+    if !override_errors.is_empty() {
         tracing::error!(
-            "Input path is neither a file nor a directory: {}",
-            input_path.display()
+            "Invalid per-file overrides:\n  {}",
+            override_errors.join("\n  ")
         );
-        return;
+        return false;
+    }
+
+    // Every bitrate in use, with the channel mode, before anything is synthesized
+    let mut bitrates = file_settings.iter().map(|s| s.bitrate).collect::<Vec<_>>();
+    bitrates.sort_unstable();
+    bitrates.dedup();
+    for bitrate in &bitrates {
+        if let Err(e) = writer::mp3_config(*bitrate, cli.stereo_mode)
+            .validate()
+            .with_context(|| {
+                format!(
+                    "--stereo-mode {} at {} kbps and {} Hz",
+                    cli.stereo_mode.name(),
+                    bitrate,
+                    writer::SAMPLE_RATE
+                )
+            })
+        {
+            tracing::error!("{:#}", e);
+            return false;
+        }
+    }
+    tracing::info!(
+        "Encoding MP3 in {} at {} Hz, {} quality",
+        cli.stereo_mode.name(),
+        writer::SAMPLE_RATE,
+        cli.encoder_quality.name()
+    );
+
+    let mut voice_bin = match voices::VoiceBin::load(Path::new(&cli.voice_model)) {
+        Ok(voice_bin) => voice_bin,
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            return false;
+        }
     };
+    let override_origins = txt_files
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>();
+    let global_voices = [("--voice", voice)]
+        .into_iter()
+        .chain(voice_pool.iter().map(|v| ("--voice-pool", *v)))
+        .chain(compare_voices.iter().map(|v| ("--compare-voices", *v)))
+        .chain(
+            override_origins
+                .iter()
+                .zip(&file_settings)
+                .map(|(origin, s)| (origin.as_str(), s.voice)),
+        )
+        .collect::<Vec<_>>();
+    if let Err(e) = preprocessor.validate_inputs(&txt_files, &voice_bin, &global_voices) {
+        tracing::error!("{:#}", e);
+        return false;
+    }
+    if let Some(voice_model_en) = &cli.voice_model_en {
+        let voice_bin_en = match voices::VoiceBin::load(Path::new(voice_model_en)) {
+            Ok(voice_bin) => voice_bin,
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                return false;
+            }
+        };
+        if let Some(e) = voice_bin_en.check(voice_en) {
+            tracing::error!("--voice-en: {}", e);
+            return false;
+        }
+    }
 
-    // Init TTS once; share via Arc so tasks can clone handles safely.
-    let tts_engine = Arc::new(tts::init_tts(cli.tts_model, cli.voice_model, cli.concurrency).await);
-    tracing::info!("Initialized KokoroTTS engine");
+    let (concurrency, tuner) = match cli.concurrency {
+        utils::Concurrency::Fixed(n) => (n, None),
+        utils::Concurrency::AutoTune => {
+            let cores = utils::available_cores();
+            let start = utils::auto_concurrency(cores);
+            // The pool is sized for the most the tuner may use
+            let max = (start * 2).min(cores).max(start);
+            tracing::info!(
+                "Concurrency auto-tune: starting at {} sessions, adjusting between 1 and {}",
+                start,
+                max
+            );
+            (max, Some(Arc::new(tune::AutoTuner::new(start, 1, max))))
+        }
+        utils::Concurrency::Auto => {
+            let cores = utils::available_cores();
+            let n = utils::auto_concurrency(cores);
+            tracing::info!("Concurrency auto: {} from {} available cores", n, cores);
+            (n, None)
+        }
+    };
 
-    let voice = utils::change_voice_speed(cli.voice, cli.speed);
+    let queue_depth = cli.queue_depth.unwrap_or(concurrency * 2);
+    let lookahead = cli.lookahead.unwrap_or(concurrency * 2);
+    tracing::info!(
+        "Scheduling up to {} lines ahead of the writer, queue depth {} chunks",
+        lookahead,
+        queue_depth
+    );
 
-    // Process each txt file (single file => one iteration)
-    for txt_path in txt_files {
-        let file_label = txt_path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown.txt")
-            .to_string();
+    let engine_options = tts::EngineOptions {
+        sample_rate: cli.engine_sample_rate,
+    };
+    if let Err(e) = engine_options.validate() {
+        tracing::error!("{:#}", e);
+        return false;
+    }
 
-        tracing::info!("Processing {}", txt_path.display());
+    if cli.dry_run {
+        dry_run(&cli, &txt_files, &file_settings, concurrency).await;
+        return true;
+    }
 
-        // Decide mp3 output prefix and ensure output folder exists
-        let mp3_prefix = if folder_mode {
-            let file_name = file_stem_string(&txt_path);
-            let out_dir = PathBuf::from(&timestamp).join(&file_name);
-            std::fs::create_dir_all(&out_dir)
-                .with_context(|| format!("Failed to create output folder {}", out_dir.display()))
-                .unwrap_or_log();
-            format!("{}/{}/audio", timestamp, file_name)
+    let music = match cli
+        .music
+        .as_deref()
+        .map(|path| wav::load(path, writer::SAMPLE_RATE, writer::CHANNELS as usize))
+        .transpose()
+    {
+        Ok(music) => music.map(Arc::new),
+        Err(e) => {
+            tracing::error!("--music: {:#}", e);
+            return false;
+        }
+    };
+
+    let load_clip = |path: &Option<PathBuf>| {
+        path.as_deref()
+            .map(|path| wav::load(path, writer::SAMPLE_RATE, writer::CHANNELS as usize))
+            .transpose()
+    };
+    let (intro, outro) = match (load_clip(&cli.intro), load_clip(&cli.outro)) {
+        (Ok(intro), Ok(outro)) => (intro, outro),
+        (Err(e), _) => {
+            tracing::error!("--intro: {:#}", e);
+            return false;
+        }
+        (_, Err(e)) => {
+            tracing::error!("--outro: {:#}", e);
+            return false;
+        }
+    };
+    let file_separator = match &cli.file_separator {
+        utils::FileSeparator::None => None,
+        utils::FileSeparator::Tone => Some(dsp::separator_tone(
+            writer::SAMPLE_RATE,
+            writer::CHANNELS as usize,
+        )),
+        utils::FileSeparator::Wav(path) => {
+            match wav::load(path, writer::SAMPLE_RATE, writer::CHANNELS as usize) {
+                Ok(clip) => Some(clip),
+                Err(e) => {
+                    tracing::error!("--file-separator: {:#}", e);
+                    return false;
+                }
+            }
+        }
+    };
+
+    let pcm_dump = match cli
+        .debug_dump_pcm
+        .clone()
+        .map(|dir| dump::PcmDump::new(dir, cli.debug_dump_lines.clone()))
+        .transpose()
+    {
+        Ok(dump) => dump.map(Arc::new),
+        Err(e) => {
+            tracing::error!("--debug-dump-pcm: {:#}", e);
+            return false;
+        }
+    };
+
+    // Kept to load the same model files again on SIGHUP in watch mode
+    let spec = tts::EngineSpec {
+        tts_model: cli.tts_model.clone(),
+        voice_model: cli.voice_model.clone(),
+        english: cli
+            .tts_model_en
+            .clone()
+            .zip(cli.voice_model_en.clone())
+            .map(|(tts_model, voice_model)| (tts_model, voice_model, voice_en)),
+        options: engine_options,
+        concurrency,
+    };
+    let pcm_hashes = match (cli.hash_pcm || cli.compare_hashes.is_some())
+        .then(|| pcm_hash::PcmHashes::new(&target_dir, cli.compare_hashes.as_deref()))
+        .transpose()
+    {
+        Ok(hashes) => hashes.map(Arc::new),
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            return false;
+        }
+    };
+
+    // A cache hit would only compare the earlier run with itself
+    if cli.compare_hashes.is_some() && cli.cache_dir.is_some() {
+        tracing::info!("--compare-hashes: synthesizing every line, the cache is not read");
+    }
+    let cache = match cli
+        .cache_dir
+        .clone()
+        .filter(|_| cli.compare_hashes.is_none())
+        .map(|dir| open_cache(dir, &spec, cli.cache_max_size))
+        .transpose()
+    {
+        Ok(cache) => cache.map(Arc::new),
+        Err(e) => {
+            tracing::error!("init audio cache: {:#}", e);
+            return false;
+        }
+    };
+
+    // Init TTS once; share via Arc so tasks can clone handles safely.
+    let tts_engine = match spec.build().await {
+        Ok(engines) => Arc::new(engines),
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            return false;
+        }
+    };
+
+    // First-run costs would otherwise land on the first lines and skew the ETAs
+    if !cli.no_warmup {
+        match tts::warm_up(tts_engine.clone(), voice, concurrency).await {
+            Ok(took) => tracing::info!("Warmed up {} sessions in {:?}", concurrency, took),
+            Err(e) => {
+                tracing::error!("Warm-up synthesis failed: {:#}", e);
+                return false;
+            }
+        }
+    }
+
+    let throughput = {
+        let voice = utils::voice_name(voice);
+        match lookup_calibration(&cli, voice, cli.speed).await {
+            Ok(Some(entry)) => {
+                tracing::info!("ETAs start from the {}", entry.describe());
+                progress::Throughput::calibrated(entry.chars_per_sec_at(concurrency))
+            }
+            Ok(None) => {
+                tracing::info!(
+                    "No calibration for {} at {}x, so ETAs wait for the first lines",
+                    voice,
+                    cli.speed
+                );
+                progress::Throughput::default()
+            }
+            Err(e) => {
+                tracing::warn!("Calibration not used: {:#}", e);
+                progress::Throughput::default()
+            }
+        }
+    };
+
+    // Folder runs get an overall bar above the per-file ones, measured in source chars;
+    // without the pre-scan the totals stay unknown (zero)
+    let (file_lines, file_chars): (Vec<u64>, Vec<u64>) = txt_files
+        .iter()
+        .map(|p| {
+            if cli.no_prescan {
+                return (0, 0);
+            }
+            text::prescan(p).unwrap_or((0, 0))
+        })
+        .unzip();
+    let progress = folder_mode.then(|| {
+        let span = tracing::info_span!("run");
+        let (template, total) = if cli.no_prescan {
+            ("{spinner} {msg} {pos} chars", None)
         } else {
-            // Original behavior: put audio_000.mp3... under the timestamp folder
-            format!("{}/audio", timestamp)
+            (
+                "{spinner} {msg}\n{wide_bar} {pos}/{len} chars",
+                Some(file_chars.iter().sum()),
+            )
         };
+        span.pb_set_style(&ProgressStyle::with_template(template).unwrap());
+        let progress = progress::RunProgress::new(span, total);
+        progress.span.pb_start();
+        progress
+    });
 
-        // Fresh config per file (cheap)
-        let spec = writer::default_mono_24k_config(64);
+    let pipe_to = match cli
+        .pipe_to
+        .as_deref()
+        .map(|command| pipe::PipeTo::parse(command, cli.pipe_format))
+        .transpose()
+    {
+        Ok(pipe_to) => pipe_to,
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            return false;
+        }
+    };
+
+    let uploader = match &cli.upload {
+        Some(url) => {
+            let options = upload::Options {
+                concurrency: cli.upload_concurrency,
+                retries: cli.upload_retries,
+                remove_local: cli.upload_remove_local,
+            };
+            match upload::Uploader::start(url, options).await {
+                Ok(uploader) => Some(uploader),
+                Err(e) => {
+                    tracing::error!("{:#}", e);
+                    return false;
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Clipboard text is played by default, as long as there is somewhere to play it
+    let clipboard_play = (cli.clipboard || cli.clipboard_watch) && !cli.stdout;
+    let playback = if cli.play || clipboard_play {
+        match playback::LiveOutput::start(cli.play_buffer) {
+            Ok(output) => Some(output),
+            Err(e) if !cli.play => {
+                tracing::warn!("{:#}; only writing the MP3", e);
+                None
+            }
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                return false;
+            }
+        }
+    } else {
+        None
+    };
 
-        let mut mp3 = writer::Mp3Splitter::new(mp3_prefix, spec, Duration::from_hours(2))
-            .context("init mp3 writer")
-            .unwrap_or_log();
+    let mut pipeline = pipeline::Pipeline {
+        engine: tts::EngineCell::new(tts_engine),
+        concurrency,
+        preprocessor,
+        cache,
+        lead_in_padding: cli.lead_in_padding,
+        queue_depth,
+        lookahead,
+        batch_short_lines: cli.batch_short_lines,
+        tuner,
+        slots: None,
+        throttle: cli
+            .throttle
+            .map(|duty| Arc::new(throttle::Throttle::new(duty))),
+        line_gap: cli.line_gap,
+        stack_pauses: cli.stack_pauses,
+        tempo: cli.tempo,
+        parallel_encode: cli.parallel_encode,
+        channel_mode: cli.stereo_mode,
+        encoder_quality: cli.encoder_quality,
+        gain_db: cli.gain,
+        loudness: cli
+            .match_loudness
+            .then(|| Mutex::new(dsp::LoudnessMatcher::default())),
+        limiter: (cli.limiter || cli.gain > 0.0 || cli.match_loudness || music.is_some())
+            .then_some(pipeline::LimiterSettings {
+                ceiling_db: cli.limiter_ceiling,
+                attack: cli.limiter_attack,
+                release: cli.limiter_release,
+            }),
+        trim: cli.trim_clip_silence.then_some(pipeline::TrimSettings {
+            threshold_db: cli.trim_threshold,
+            guard: cli.trim_guard,
+        }),
+        trimmed: Arc::new(AtomicU64::new(0)),
+        pause_cap: cli
+            .max_internal_silence
+            .map(|max| pipeline::PauseCapSettings {
+                max,
+                threshold_db: cli.internal_silence_threshold,
+                min_run: cli.internal_silence_min,
+            }),
+        levels_csv: cli.levels_csv.then(|| target_dir.join("levels.csv")),
+        segments_csv: (!cli.stdout).then(|| target_dir.join(verify::SEGMENTS_CSV)),
+        music: music.map(|samples| pipeline::MusicSettings {
+            samples,
+            gain_db: cli.music_gain,
+            fade: cli.music_fade,
+        }),
+        intro,
+        outro,
+        file_separator,
+        stream_started: AtomicBool::new(false),
+        playback,
+        stdout: cli.stdout,
+        pipe_to,
+        on_segment_closed: uploader.as_ref().map(upload::Uploader::hook),
+        crossfade: cli.crossfade,
+        segment_naming: cli.segment_naming,
+        announce_parts: cli.announce_parts,
+        preview: cli.preview,
+        subtitles: (!cli.subtitles.is_empty()).then(|| subtitles::Settings {
+            formats: cli.subtitles.clone(),
+            per_segment: cli.subtitles_per_segment,
+            include_gap: cli.subtitles_include_gap,
+            chunk_tags: cli.lrc_chunk_tags,
+        }),
+        index_csv: cli.index_csv,
+        playlist: cli.playlist,
+        timings: cli.timings_json.then(timings::Timings::default),
+        pcm_dump,
+        pcm_hashes,
+        metrics: (cli.metrics_file.is_some() || cli.metrics_listen.is_some())
+            .then(|| Arc::new(metrics::Metrics::default())),
+        progress,
+        throughput: Mutex::new(throughput),
+        live: progress::LiveStats::default(),
+        status_file: cli.progress_json.then(|| {
+            progress::StatusFile::new(
+                target_dir.join("progress.json"),
+                cli.progress_interval,
+                file_lines.iter().sum(),
+                file_chars.iter().sum(),
+            )
+        }),
+        prescan: !cli.no_prescan,
+    };
+    if let Some(throttle) = &pipeline.throttle {
+        adjust_throttle_on_signals(throttle.clone());
+    }
 
-        let total_lines = read_non_empty_lines(&txt_path)
-            .with_context(|| format!("Failed reading lines for {}", txt_path.display()))
-            .unwrap_or_log();
+    if !compare_voices.is_empty() {
+        let txt_path = &txt_files[0];
+        let stem = file_stem_string(txt_path);
+        let mut summary = Vec::new();
+        let mut failed = false;
+        for voice in compare_voices {
+            let settings = pipeline::FileSettings {
+                voice,
+                ..file_settings[0]
+            };
+            let out_path =
+                target_dir.join(format!("{}_preview_{}.mp3", stem, utils::voice_name(voice)));
+            // One voice failing still leaves the others to compare
+            match pipeline
+                .preview(txt_path, cli.compare_lines, &out_path, &settings)
+                .await
+            {
+                Ok((duration, chars)) => summary.push((voice, duration, chars)),
+                Err(e) => {
+                    tracing::error!("Preview in {}: {:#}", utils::voice_name(voice), e);
+                    failed = true;
+                }
+            }
+        }
 
         tracing::info!(
-            "Target file {} total {} line",
-            file_label,
-            total_lines.len()
+            "Voice comparison over {} lines of {}:",
+            cli.compare_lines,
+            stem
         );
+        for (voice, duration, chars) in summary {
+            tracing::info!(
+                "  {:<8} {:>8.1}s {:>6.2} chars/s",
+                utils::voice_name(voice),
+                duration.as_secs_f64(),
+                chars as f64 / duration.as_secs_f64().max(f64::EPSILON)
+            );
+        }
+        return !failed;
+    }
 
-        let sem = Arc::new(Semaphore::new(cli.concurrency * 2));
-        let (tx, mut rx) = mpsc::channel::<Msg>(cli.concurrency * 2);
+    if let Some(previous) = &cli.compare_hashes {
+        for (txt_path, settings) in txt_files.iter().zip(&file_settings) {
+            tracing::info!("Hashing {}", txt_path.display());
+            match pipeline.hash_file(txt_path, folder_mode, settings).await {
+                Ok(None) => {}
+                Ok(Some(divergence)) => {
+                    tracing::error!(
+                        "First divergence from {}: {}",
+                        previous.display(),
+                        divergence
+                    );
+                    return false;
+                }
+                Err(e) => {
+                    tracing::error!("{}: {:#}", txt_path.display(), e);
+                    return false;
+                }
+            }
+        }
+        return match pipeline.pcm_hashes.as_ref().unwrap().finish() {
+            Ok((run_sha256, lines)) => {
+                tracing::info!(
+                    "All {} lines match {} (run PCM hash {})",
+                    lines,
+                    previous.display(),
+                    run_sha256
+                );
+                true
+            }
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                false
+            }
+        };
+    }
 
-        let tts_engine2 = tts_engine.clone();
-        let voice2 = voice;
+    // Process each txt file (single file => one iteration)
+    let run_start = std::time::Instant::now();
+    let exporter = match &pipeline.metrics {
+        Some(metrics) => {
+            match metrics::Exporter::start(
+                metrics.clone(),
+                cli.metrics_file.clone(),
+                cli.metrics_listen,
+            )
+            .await
+            {
+                Ok(exporter) => Some(exporter),
+                Err(e) => {
+                    tracing::error!("--metrics-listen: {:#}", e);
+                    return false;
+                }
+            }
+        }
+        None => None,
+    };
 
-        let producer: tokio::task::JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-            let mut set = JoinSet::<anyhow::Result<()>>::new();
+    let notifier = cli.webhook_url.clone().map(|url| {
+        let secret = cli
+            .webhook_secret
+            .clone()
+            .or_else(|| std::env::var("MORGANITE_WEBHOOK_SECRET").ok());
+        webhook::Notifier::start(url, secret, cli.webhook_events.clone(), timestamp.clone())
+    });
+    if let Some(notifier) = &notifier {
+        notifier.send(webhook::Event::RunStarted {
+            inputs: txt_files.iter().map(|p| p.display().to_string()).collect(),
+        });
+    }
 
-            let header_span = tracing::info_span!("task");
-            header_span.pb_set_style(
-                &ProgressStyle::with_template("{spinner} {msg}\n{wide_bar} {pos}/{len}").unwrap(),
-            );
-            header_span.pb_set_length(total_lines.len() as u64);
-            header_span.pb_set_message(format!("Processing {}", file_label).as_str());
-            header_span
-                .pb_set_finish_message(format!("All items processed ({})", file_label).as_str());
+    let mut outcome = RunOutcome {
+        summary: summary::RunSummary {
+            channel_mode: cli.stereo_mode.name(),
+            encoder_quality: cli.encoder_quality.name(),
+            shard: cli.shard,
+            other_shards,
+            existing,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let file_count = txt_files.len();
+    let mut done_chars = 0;
+    let mut done_lines = 0;
+    for (i, (((txt_path, settings), chars), lines)) in txt_files
+        .into_iter()
+        .zip(file_settings)
+        .zip(file_chars)
+        .zip(file_lines)
+        .enumerate()
+    {
+        tracing::info!("Processing {}", txt_path.display());
+        if let Some(status) = &pipeline.status_file {
+            status.set_file(&txt_path.display().to_string());
+        }
+        if let Some(progress) = &pipeline.progress {
+            progress.set_label(format!(
+                "{} ({}/{} files)",
+                file_stem_string(&txt_path),
+                i + 1,
+                file_count
+            ));
+            progress.refresh(&pipeline.throughput.lock().unwrap());
+        }
 
-            let header_span_enter = header_span.enter();
+        // Decide output folder; in folder mode each file gets its own
+        let out_dir = if folder_mode {
+            target_dir.join(file_stem_string(&txt_path))
+        } else {
+            // Original behavior: put audio_000.mp3... under the timestamp folder
+            target_dir.clone()
+        };
 
-            for (line_index, line) in total_lines.iter().enumerate() {
-                let line = line.clone();
-                if line.is_empty() {
-                    unreachable!()
-                }
+        let file_start = std::time::Instant::now();
+        let mut file_summary = summary::FileSummary::new(txt_path.display().to_string());
+        let created = if folder_mode {
+            std::fs::create_dir_all(&out_dir)
+                .with_context(|| format!("Failed to create output folder {}", out_dir.display()))
+        } else {
+            Ok(())
+        };
+        // Ctrl-C stops the synthesis, but the audio written so far is still finalized
+        let result = match created {
+            Ok(()) => {
+                pipeline
+                    .process_file(
+                        &txt_path,
+                        &out_dir,
+                        folder_mode,
+                        &settings,
+                        &mut file_summary,
+                        interrupted(),
+                    )
+                    .await
+            }
+            // A failed file like any other
+            Err(e) => Err(e),
+        };
+        if result
+            .as_ref()
+            .is_err_and(|e| e.is::<pipeline::Cancelled>())
+        {
+            outcome.interrupted = true;
+        }
+        file_summary.wall = file_start.elapsed();
+        file_summary.finished = result.is_ok();
+        outcome.summary.files.push(file_summary);
+        let file = outcome.summary.files.last().unwrap();
+
+        if let Err(e) = result {
+            outcome.summary.partial = true;
+            if cli.stdout && is_broken_pipe(&e) {
+                tracing::warn!("Standard output was closed, stopping");
+                outcome.interrupted = true;
+                break;
+            }
+            tracing::error!("{}: {:#}", txt_path.display(), e);
+            let error = format!("{}: {:#}", txt_path.display(), e);
+            announce_file(file, Some(&error), None, cli.notify && cli.watch);
+            outcome.error = Some(error);
+            // A watching run carries on; the file is retried once it changes
+            if !outcome.interrupted
+                && let Some(state) = &mut watch_state
+            {
+                state.record(&txt_path, watch::Outcome::Failed, &out_dir);
+                continue;
+            }
+            break;
+        }
+        tracing::info!("Finished {}", txt_path.display());
+        announce_file(file, None, notifier.as_ref(), cli.notify && cli.watch);
+        if let Some(state) = &mut watch_state {
+            state.record(&txt_path, watch::Outcome::Done, &out_dir);
+        }
+        if cli.clipboard && !cli.stdout {
+            clipboard::name_output(&txt_path, &file.segments);
+        }
 
-                let permit = sem.clone().acquire_owned().await?;
-                let tx2 = tx.clone();
-                let header_span = header_span.clone();
-                let current_audio_idx = line_index;
+        // Lines that never became audio count as done too, when they were counted
+        if !cli.no_prescan {
+            done_chars += chars;
+            done_lines += lines;
+            if let Some(progress) = &pipeline.progress {
+                progress.set_chars(done_chars);
+            }
+            if let Some(status) = &pipeline.status_file {
+                status.set_done(done_lines, done_chars);
+            }
+        }
+    }
+    let incoming = match (watch_state, clipboard_watch) {
+        (Some(state), _) => {
+            let input_dir = PathBuf::from(cli.text_file.as_deref().unwrap());
+            tracing::info!("Watching {} for new .txt files", input_dir.display());
+            Some(Incoming::Folder(
+                watch::Folder::new(input_dir).with_shard(cli.shard),
+                state,
+            ))
+        }
+        (None, Some(watch)) => {
+            tracing::info!("Watching the clipboard for new text");
+            Some(Incoming::Clipboard(watch))
+        }
+        (None, None) => None,
+    };
+    if let Some(incoming) = incoming
+        && !outcome.interrupted
+    {
+        // The bar's total only covered the files that were there at the start
+        pipeline.progress = None;
+        let watching = Watching {
+            cli: &cli,
+            config: &config,
+            spec: &spec,
+            voice,
+            target_dir: &target_dir,
+            notifier: notifier.as_ref(),
+        };
+        watching
+            .run(
+                incoming,
+                &mut pipeline,
+                &mut voice_bin,
+                &mut voice_picker,
+                file_count,
+                &mut outcome,
+            )
+            .await;
+    }
+    if let Some(playback) = &pipeline.playback {
+        if outcome.interrupted {
+            playback.stop();
+        } else {
+            tracing::info!("Waiting for playback to finish (Ctrl-C to stop)");
+            tokio::select! {
+                _ = playback.drain() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            playback.stop();
+        }
+    }
+    if let Some(uploader) = uploader {
+        outcome.summary.upload = Some(uploader.finish().await);
+    }
+    outcome.summary.wall = run_start.elapsed();
+    complete_summary(&mut outcome.summary, &pipeline, concurrency);
+    write_podcast_feed(
+        &cli,
+        &config.podcast,
+        &outcome.summary,
+        &target_dir,
+        &started,
+    );
+    if cli.verify {
+        outcome.summary.verify = Some(verify_output(&target_dir));
+    }
+    if let Some(status) = &pipeline.status_file {
+        let state = if outcome.interrupted {
+            "interrupted"
+        } else if outcome.summary.partial {
+            "failed"
+        } else {
+            "finished"
+        };
+        status.finish(state, &pipeline.live);
+    }
+    if let Some(exporter) = exporter {
+        exporter.shutdown();
+    }
+    if let Err(e) = outcome.summary.report(&target_dir) {
+        tracing::warn!("{:#}", e);
+    }
+    // Watching runs told about every file already
+    let desktop = cli.notify && !cli.watch && !cli.clipboard_watch;
+    notify_run_end(&outcome, notifier, desktop).await;
 
-                let engine = tts_engine2.clone();
-                let voice = voice2;
+    if let Some(timings) = &pipeline.timings
+        && let Err(e) = timings.write(&target_dir.join("timings.json"), run_start.elapsed())
+    {
+        tracing::warn!("{:#}", e);
+    }
+    report_processing(&pipeline);
 
-                set.spawn(async move {
-                    let _permit = permit;
-                    tracing::info!("Audio idx {} started", current_audio_idx);
+    outcome.succeeded()
+}
 
-                    let res = engine
-                        .synth::<String>(line, voice)
-                        .await
-                        .map_err(|e| anyhow::anyhow!("{}", e));
+/// What the file loops of a run leave for its end.
+#[derive(Default)]
+struct RunOutcome {
+    summary: summary::RunSummary,
+    /// The last file error, for the run_failed webhook.
+    error: Option<String>,
+    /// Stopped by Ctrl-C or a closed standard output rather than by an error.
+    interrupted: bool,
+}
 
-                    tracing::info!("Audio idx {} finished", current_audio_idx);
-                    let _ = tx2.send((current_audio_idx, res)).await;
-                    tracing::info!("Audio idx {} sent to channel", current_audio_idx);
+impl RunOutcome {
+    /// Whether the run exits successfully: no file failed, every segment was
+    /// uploaded and `--verify` found nothing.
+    fn succeeded(&self) -> bool {
+        let mut ok = self.error.is_none();
+        if let Some(upload) = &self.summary.upload
+            && !upload.failed.is_empty()
+        {
+            tracing::error!(
+                "{} segments were not uploaded: {}",
+                upload.failed.len(),
+                upload.failed.join(", ")
+            );
+            ok = false;
+        }
+        if self
+            .summary
+            .verify
+            .as_ref()
+            .is_some_and(|verify| !verify.problems.is_empty())
+        {
+            ok = false;
+        }
+        ok
+    }
 
-                    header_span.pb_inc(1);
-                    Ok(())
-                });
+    /// The reason the run_failed webhook and the desktop notification give.
+    fn failure(&self) -> Option<String> {
+        self.summary.partial.then(|| {
+            if self.interrupted {
+                "Interrupted".to_string()
+            } else {
+                self.error
+                    .clone()
+                    .unwrap_or_else(|| "Some files failed".to_string())
             }
+        })
+    }
+}
 
-            drop(tx);
-            while let Some(r) = set.join_next().await {
-                r??;
-            }
-            drop(header_span_enter);
-            Ok(())
+/// Webhook and desktop notifications for a file that just finished, or failed
+/// with `error`.
+fn announce_file(
+    file: &summary::FileSummary,
+    error: Option<&str>,
+    notifier: Option<&webhook::Notifier>,
+    desktop: bool,
+) {
+    if error.is_none()
+        && let Some(notifier) = notifier
+    {
+        notifier.send(webhook::Event::FileCompleted { file: file.into() });
+    }
+    if desktop {
+        desktop::file_done(file, error);
+    }
+}
+
+/// The run_completed or run_failed webhook, and the desktop notification when
+/// `desktop` is set.
+async fn notify_run_end(outcome: &RunOutcome, notifier: Option<webhook::Notifier>, desktop: bool) {
+    let failure = outcome.failure();
+    if let Some(notifier) = notifier {
+        let summary = webhook::RunStats::from(&outcome.summary);
+        notifier.send(match failure.clone() {
+            Some(error) => webhook::Event::RunFailed { error, summary },
+            None => webhook::Event::RunCompleted { summary },
+        });
+        notifier.finish().await;
+    }
+    if desktop {
+        desktop::run_done(&outcome.summary, failure.as_deref()).await;
+    }
+}
+
+/// Fill in the parts of the summary only known once synthesis is over.
+fn complete_summary(
+    summary: &mut summary::RunSummary,
+    pipeline: &pipeline::Pipeline,
+    concurrency: usize,
+) {
+    if let Some(hashes) = &pipeline.pcm_hashes {
+        match hashes.finish() {
+            Ok(hash) => summary.pcm_hash = Some(hash),
+            Err(e) => tracing::error!("{:#}", e),
+        }
+    }
+    if let Some(throttle) = &pipeline.throttle {
+        summary.throttle = Some(summary::ThrottleReport {
+            target: throttle.duty(),
+            utilization: throttle.busy().as_secs_f64()
+                / (summary.wall.as_secs_f64() * concurrency as f64).max(f64::EPSILON),
+            cost: throttle.idle() / concurrency as u32,
         });
+    }
+}
 
-        let mut next_expected: usize = 0;
-        let mut buffer: BTreeMap<usize, (Vec<f32>, Duration)> = BTreeMap::new();
+/// `--podcast-feed`: add the run's finished episodes to the feed, with `meta` for
+/// what the `--podcast-*` options leave out.
+fn write_podcast_feed(
+    cli: &RunArgs,
+    meta: &podcast::FeedMeta,
+    summary: &summary::RunSummary,
+    target_dir: &Path,
+    started: &chrono::DateTime<Local>,
+) {
+    let (Some(path), Some(base_url)) = (cli.podcast_feed.clone(), cli.podcast_base_url.clone())
+    else {
+        return;
+    };
+    let feed = podcast::FeedSettings {
+        path,
+        base_url,
+        title: cli
+            .podcast_title
+            .clone()
+            .or_else(|| meta.title.clone())
+            .unwrap_or_else(|| {
+                cli.text_file
+                    .as_deref()
+                    .map_or("Clipboard".to_string(), |f| file_stem_string(Path::new(f)))
+            }),
+        description: cli
+            .podcast_description
+            .clone()
+            .or_else(|| meta.description.clone()),
+        author: cli.podcast_author.clone().or_else(|| meta.author.clone()),
+        image: cli.podcast_image.clone().or_else(|| meta.image.clone()),
+    };
+    let episodes = summary
+        .files
+        .iter()
+        .filter(|f| f.finished)
+        .flat_map(|f| f.episodes.iter().cloned())
+        .collect::<Vec<_>>();
+    if let Err(e) = podcast::write_feed(&feed, target_dir, &episodes, &started.to_rfc2822()) {
+        tracing::warn!("{:#}", e);
+    }
+}
 
-        while let Some((idx, res)) = rx.recv().await {
-            let (audio, took) = res.expect_or_log("Failed to get synth result");
-            buffer.insert(idx, (audio, took));
+/// The `--calibration` entry for `voice` at `speed` with the models in use, if any.
+async fn lookup_calibration(
+    cli: &RunArgs,
+    voice: &'static str,
+    speed: f32,
+) -> anyhow::Result<Option<calibrate::Entry>> {
+    let file = cli.calibration.clone();
+    let (tts_model, voice_model) = (cli.tts_model.clone(), cli.voice_model.clone());
+    tokio::task::spawn_blocking(move || {
+        calibrate::lookup(file.as_deref(), &tts_model, &voice_model, voice, speed)
+    })
+    .await?
+}
 
-            while let Some((audio, took)) = buffer.remove(&next_expected) {
-                mp3.write_f32_mono(&audio)
-                    .expect_or_log("Failed to write to mp3");
-                tracing::info!("Audio idx {next_expected} took {:?}", took);
-                next_expected += 1;
+/// `--dry-run`: each file's size and, where a calibration matches its voice and
+/// speed, the synthesis time it predicts at `concurrency`.
+async fn dry_run(
+    cli: &RunArgs,
+    txt_files: &[PathBuf],
+    file_settings: &[pipeline::FileSettings],
+    concurrency: usize,
+) {
+    let (mut total_chars, mut total_eta, mut estimated) = (0, Duration::ZERO, true);
+    for (path, settings) in txt_files.iter().zip(file_settings) {
+        let (lines, chars) = match text::prescan(path) {
+            Ok(counts) => counts,
+            Err(e) => {
+                tracing::warn!("{}: {:#}", path.display(), e);
+                (0, 0)
             }
+        };
+        total_chars += chars;
+        let voice = utils::voice_name(settings.voice);
+        let eta = match lookup_calibration(cli, voice, settings.speed).await {
+            Ok(Some(entry)) => {
+                progress::Throughput::calibrated(entry.chars_per_sec_at(concurrency)).eta(chars)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("Calibration not used: {:#}", e);
+                None
+            }
+        };
+        match eta {
+            Some(eta) => total_eta += eta,
+            None => estimated = false,
         }
+        tracing::info!(
+            "{}: {} lines, {} chars, about {}",
+            path.display(),
+            lines,
+            chars,
+            progress::format_eta(eta)
+        );
+        if eta.is_none() {
+            tracing::info!(
+                "No calibration for {} at {}x; run calibrate for an estimate",
+                voice,
+                settings.speed
+            );
+        }
+    }
+    tracing::info!(
+        "Dry run: {} files, {} chars, about {} at {} sessions",
+        txt_files.len(),
+        total_chars,
+        progress::format_eta(estimated.then_some(total_eta)),
+        concurrency
+    );
+}
 
-        producer
-            .await
-            .unwrap()
-            .expect_or_log("Failed to finish synth task");
+/// `--throttle`: SIGUSR1 and SIGUSR2 step the duty cycle up and down for the rest
+/// of the run.
+fn adjust_throttle_on_signals(throttle: Arc<throttle::Throttle>) {
+    #[cfg(unix)]
+    for (kind, by) in [
+        (
+            tokio::signal::unix::SignalKind::user_defined1(),
+            throttle::STEP,
+        ),
+        (
+            tokio::signal::unix::SignalKind::user_defined2(),
+            -throttle::STEP,
+        ),
+    ] {
+        match tokio::signal::unix::signal(kind) {
+            Ok(mut signal) => {
+                let throttle = throttle.clone();
+                tokio::spawn(async move {
+                    while signal.recv().await.is_some() {
+                        let duty = throttle.adjust(by);
+                        tracing::info!("Throttle now at {:.0}%", duty * 100.0);
+                    }
+                });
+            }
+            Err(e) => tracing::warn!("Can't listen for signals, the throttle stays put: {}", e),
+        }
+    }
+    #[cfg(not(unix))]
+    drop(throttle);
+}
 
-        mp3.finalize().expect_or_log("Failed to finalize mp3 write");
+/// `--verify`: walk the run's segments, with a failure to do so as a problem.
+fn verify_output(target_dir: &Path) -> verify::Report {
+    verify::verify_run(target_dir).unwrap_or_else(|e| {
+        tracing::error!("Verification failed: {:#}", e);
+        verify::Report {
+            checked: 0,
+            problems: vec![verify::Problem {
+                segment: verify::SEGMENTS_CSV.to_string(),
+                reason: format!("{:#}", e),
+            }],
+        }
+    })
+}
 
-        tracing::info!("Finished {}", txt_path.display());
+/// Log what trimming, loudness matching and the cache did over the run.
+fn report_processing(pipeline: &pipeline::Pipeline) {
+    if pipeline.trim.is_some() {
+        let trimmed = pipeline.trimmed.load(Ordering::Relaxed);
+        tracing::info!(
+            "Trimmed {:.1}s of clip silence",
+            trimmed as f64 / writer::SAMPLE_RATE as f64
+        );
+    }
+
+    if let Some(loudness) = &pipeline.loudness {
+        loudness.lock().unwrap().report();
+    }
+
+    if let Some(cache) = &pipeline.cache {
+        if let Err(e) = cache.prune() {
+            tracing::warn!("Failed to prune audio cache: {:#}", e);
+        }
+        cache.report();
+    }
+}
+
+/// What `--watch` and `--clipboard-watch` need from the run around them.
+struct Watching<'a> {
+    cli: &'a RunArgs,
+    config: &'a config::Config,
+    spec: &'a tts::EngineSpec,
+    voice: Voice,
+    target_dir: &'a Path,
+    notifier: Option<&'a webhook::Notifier>,
+}
+
+impl Watching<'_> {
+    /// Process files from `incoming` until Ctrl-C or a closed standard output,
+    /// reloading the models on SIGHUP between files.
+    async fn run(
+        &self,
+        mut incoming: Incoming,
+        pipeline: &mut pipeline::Pipeline,
+        voice_bin: &mut voices::VoiceBin,
+        voice_picker: &mut voices::VoicePicker,
+        mut file_index: usize,
+        outcome: &mut RunOutcome,
+    ) {
+        let cli = self.cli;
+        let (stop_tx, mut stop) = tokio::sync::watch::channel(false);
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                tracing::warn!("Ctrl-C: stopping once the file in progress is finished");
+                let _ = stop_tx.send(true);
+            }
+        });
+        let mut hangups = serve::hangups();
+        loop {
+            let txt_path = tokio::select! {
+                path = incoming.next() => path,
+                // Between files, so each file is read with one set of models
+                Some(()) = hangups.recv() => {
+                    tracing::info!(
+                        "SIGHUP: reloading {} and {}",
+                        self.spec.tts_model,
+                        self.spec.voice_model
+                    );
+                    match reload(
+                        pipeline,
+                        voice_bin,
+                        self.spec,
+                        self.voice,
+                        cli.cache_dir.as_deref(),
+                        cli.cache_max_size,
+                    )
+                    .await {
+                        Ok(took) => tracing::info!("Reloaded the models in {:?}", took),
+                        Err(e) => tracing::error!("Reload failed, keeping the loaded models: {:#}", e),
+                    }
+                    continue;
+                }
+                _ = stop.changed() => break,
+            };
+            tracing::info!("Processing {}", txt_path.display());
+            if let Some(status) = &pipeline.status_file {
+                status.set_file(&txt_path.display().to_string());
+            }
+            // Clipboard texts are saved in the run folder and their audio goes next to them
+            let from_folder = matches!(incoming, Incoming::Folder(..));
+            let out_dir = if from_folder {
+                self.target_dir.join(file_stem_string(&txt_path))
+            } else {
+                self.target_dir.to_path_buf()
+            };
+            let file_start = std::time::Instant::now();
+            let mut file_summary = summary::FileSummary::new(txt_path.display().to_string());
+            // Validated here rather than up front, as the file didn't exist then
+            let result = async {
+                let file_override = overrides::load_for(
+                    &txt_path,
+                    &self.config.files,
+                    self.config.path.as_deref(),
+                    &pipeline.preprocessor.voice_names,
+                )?;
+                let settings = pipeline::FileSettings {
+                    voice: file_override
+                        .voice
+                        .unwrap_or_else(|| voice_picker.pick(file_index)),
+                    speed: file_override.speed.unwrap_or(cli.speed),
+                    bitrate: file_override.bitrate.unwrap_or(cli.bitrate),
+                };
+                let origin = txt_path.display().to_string();
+                pipeline.preprocessor.validate_inputs(
+                    std::slice::from_ref(&txt_path),
+                    voice_bin,
+                    &[(origin.as_str(), settings.voice)],
+                )?;
+                std::fs::create_dir_all(&out_dir).with_context(|| {
+                    format!("Failed to create output folder {}", out_dir.display())
+                })?;
+                // Ctrl-C waits for the file in progress
+                pipeline
+                    .process_file(
+                        &txt_path,
+                        &out_dir,
+                        from_folder,
+                        &settings,
+                        &mut file_summary,
+                        std::future::pending(),
+                    )
+                    .await
+            }
+            .await;
+            file_index += 1;
+            file_summary.wall = file_start.elapsed();
+            file_summary.finished = result.is_ok();
+            outcome.summary.files.push(file_summary);
+            let file = outcome.summary.files.last().unwrap();
+            match result {
+                Ok(()) => {
+                    tracing::info!("Finished {}", txt_path.display());
+                    announce_file(file, None, self.notifier, cli.notify);
+                    match &mut incoming {
+                        Incoming::Folder(_, state) => {
+                            state.record(&txt_path, watch::Outcome::Done, &out_dir)
+                        }
+                        Incoming::Clipboard(_) if cli.stdout => {}
+                        Incoming::Clipboard(_) => clipboard::name_output(&txt_path, &file.segments),
+                    }
+                }
+                Err(e) if cli.stdout && is_broken_pipe(&e) => {
+                    tracing::warn!("Standard output was closed, stopping");
+                    outcome.summary.partial = true;
+                    break;
+                }
+                Err(e) => {
+                    tracing::error!("{}: {:#}", txt_path.display(), e);
+                    let error = format!("{}: {:#}", txt_path.display(), e);
+                    announce_file(file, Some(&error), None, cli.notify);
+                    outcome.error = Some(error);
+                    outcome.summary.partial = true;
+                    if let Incoming::Folder(_, state) = &mut incoming {
+                        state.record(&txt_path, watch::Outcome::Failed, &out_dir);
+                    }
+                }
+            }
+            if *stop.borrow() {
+                break;
+            }
+        }
     }
 }
@@ -0,0 +1,302 @@
+use std::{sync::LazyLock, time::Duration};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use regex::Regex;
+
+use crate::utils;
+
+/// A piece of a line after markup has been resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+    Text(String),
+    /// Explicit pause from `<break time=".."/>`, written as silence.
+    Break(Duration),
+}
+
+static TAG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<(/?)([A-Za-z][\w-]*)((?:\s+[\w-]+\s*=\s*"[^"]*")*)\s*(/?)>"#).unwrap()
+});
+static SPEED_DIRECTIVE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\{\s*speed\s*=\s*([^}\s]*)\s*\}\s*").unwrap());
+static ATTR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"([\w-]+)\s*=\s*"([^"]*)""#).unwrap());
+
+/// Resolve the supported SSML subset in one line:
+/// `<break time="2s"/>`, `<say-as interpret-as="digits">..</say-as>` and
+/// `<sub alias="..">..</sub>`. Unknown tags are stripped with a warning, tags
+/// missing their closing counterpart are kept as literal text, and
+/// `&lt;`/`&gt;`/`&amp;` decode to the plain characters.
+pub fn parse(line: &str, line_number: usize) -> Vec<Piece> {
+    if !line.contains('<') && !line.contains('&') {
+        return vec![Piece::Text(line.to_string())];
+    }
+
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut rest = line;
+
+    while let Some(caps) = TAG.captures(rest) {
+        let whole = caps.get(0).unwrap();
+        text.push_str(&rest[..whole.start()]);
+        let after = &rest[whole.end()..];
+
+        let closing = !caps[1].is_empty();
+        let name = caps[2].to_ascii_lowercase();
+        let attrs = attributes(&caps[3]);
+        let self_closing = !caps[4].is_empty();
+
+        match (name.as_str(), closing) {
+            ("break", false) => {
+                match attrs.iter().find(|(k, _)| k == "time") {
+                    Some((_, time)) => match utils::parse_duration(time) {
+                        Ok(d) => {
+                            flush_text(&mut pieces, &mut text);
+                            pieces.push(Piece::Break(d));
+                        }
+                        Err(e) => tracing::warn!("Line {line_number}: ignoring <break>: {e}"),
+                    },
+                    None => tracing::warn!("Line {line_number}: ignoring <break> without time"),
+                }
+                rest = after;
+            }
+            ("say-as" | "sub", false) if !self_closing => {
+                let close = format!("</{name}>");
+                let Some(end) = after.find(&close) else {
+                    // Unclosed: keep the tag as literal text
+                    text.push_str(whole.as_str());
+                    rest = after;
+                    continue;
+                };
+                let inner = &after[..end];
+                text.push_str(&resolve_inner(&name, &attrs, inner, line_number));
+                rest = &after[end + close.len()..];
+            }
+            _ => {
+                tracing::warn!(
+                    "Line {line_number}: stripped unsupported tag {}",
+                    whole.as_str()
+                );
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    flush_text(&mut pieces, &mut text);
+
+    pieces
+}
+
+/// Strip a leading `{speed=0.85}` directive, returning the (clamped) speed and the rest
+/// of the line.
+pub fn take_speed_directive(line: &str, line_number: usize) -> (Option<f32>, &str) {
+    let Some(caps) = SPEED_DIRECTIVE.captures(line) else {
+        return (None, line);
+    };
+    let rest = &line[caps.get(0).unwrap().end()..];
+    match caps[1].parse::<f32>() {
+        Ok(speed) if speed.is_finite() => {
+            let clamped = speed.clamp(utils::MIN_SPEED, utils::MAX_SPEED);
+            if clamped != speed {
+                tracing::warn!(
+                    "Line {line_number}: speed {speed} out of range, clamped to {clamped}"
+                );
+            }
+            (Some(clamped), rest)
+        }
+        _ => {
+            tracing::warn!(
+                "Line {line_number}: ignoring invalid speed directive {}",
+                &caps[0]
+            );
+            (None, rest)
+        }
+    }
+}
+
+/// Line-prefix voice tags such as `[zm_029] “不行。”`, built from a template where `{}`
+/// stands for the voice name. Tags are only recognized at the very start of a line;
+/// a leading backslash (`\[zm_029]`) keeps the tag as literal text.
+pub struct VoiceTagSyntax {
+    re: Regex,
+    prefix: String,
+}
+
+impl VoiceTagSyntax {
+    pub fn new(template: &str) -> anyhow::Result<Self> {
+        let (prefix, suffix) = template
+            .split_once("{}")
+            .context("Voice tag syntax must contain {} for the voice name")?;
+        anyhow::ensure!(
+            !prefix.is_empty(),
+            "Voice tag syntax needs a prefix before {{}}"
+        );
+        let pattern = if suffix.is_empty() {
+            format!(r"^{}(\S+)\s+", regex::escape(prefix))
+        } else {
+            format!(
+                r"^{}(\S+?){}\s*",
+                regex::escape(prefix),
+                regex::escape(suffix)
+            )
+        };
+        let re = Regex::new(&pattern).context("Invalid voice tag syntax")?;
+        Ok(Self {
+            re,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Split a leading tag off `line`, returning the tag's voice name and the remaining text.
+    pub fn take<'a>(&self, line: &'a str) -> (Option<&'a str>, &'a str) {
+        if let Some(escaped) = line.strip_prefix('\\')
+            && escaped.starts_with(&self.prefix)
+        {
+            return (None, escaped);
+        }
+        match self.re.captures(line) {
+            Some(caps) => (
+                Some(caps.get(1).unwrap().as_str()),
+                &line[caps.get(0).unwrap().end()..],
+            ),
+            None => (None, line),
+        }
+    }
+
+    /// Like [`take`](Self::take), resolving the name to a voice with `names`.
+    pub fn take_voice<'a>(
+        &self,
+        line: &'a str,
+        names: &utils::VoiceNames,
+    ) -> Result<(Option<Voice>, &'a str), String> {
+        match self.take(line) {
+            (Some(name), rest) => Ok((Some(names.parse(name)?), rest)),
+            (None, rest) => Ok((None, rest)),
+        }
+    }
+}
+
+fn resolve_inner(
+    name: &str,
+    attrs: &[(String, String)],
+    inner: &str,
+    line_number: usize,
+) -> String {
+    let attr = |key: &str| {
+        attrs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+    match name {
+        "say-as" => match attr("interpret-as") {
+            Some("digits" | "characters" | "spell-out") => {
+                let spaced = inner
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>();
+                spaced.join(" ")
+            }
+            other => {
+                tracing::warn!(
+                    "Line {line_number}: unsupported say-as interpret-as {:?}, reading as-is",
+                    other
+                );
+                inner.to_string()
+            }
+        },
+        "sub" => match attr("alias") {
+            Some(alias) => alias.to_string(),
+            None => {
+                tracing::warn!("Line {line_number}: <sub> without alias, reading as-is");
+                inner.to_string()
+            }
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn attributes(s: &str) -> Vec<(String, String)> {
+    ATTR.captures_iter(s)
+        .map(|c| (c[1].to_ascii_lowercase(), c[2].to_string()))
+        .collect()
+}
+
+fn flush_text(pieces: &mut Vec<Piece>, text: &mut String) {
+    let decoded = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    let trimmed = decoded.trim();
+    if !trimmed.is_empty() {
+        pieces.push(Piece::Text(trimmed.to_string()));
+    }
+    text.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brackets() -> VoiceTagSyntax {
+        VoiceTagSyntax::new("[{}]").unwrap()
+    }
+
+    #[test]
+    fn leading_tag_is_stripped() {
+        assert_eq!(
+            brackets().take("[zm_029] “不行。”"),
+            (Some("zm_029"), "“不行。”")
+        );
+        assert_eq!(
+            brackets().take("[zm_029]“不行。”"),
+            (Some("zm_029"), "“不行。”")
+        );
+    }
+
+    #[test]
+    fn tags_mid_line_are_text() {
+        let line = "他说 [zm_029] 不行。";
+        assert_eq!(brackets().take(line), (None, line));
+    }
+
+    #[test]
+    fn escaped_bracket_is_literal() {
+        assert_eq!(
+            brackets().take(r"\[zm_029] 不行。"),
+            (None, "[zm_029] 不行。")
+        );
+    }
+
+    #[test]
+    fn template_without_suffix_ends_at_whitespace() {
+        let syntax = VoiceTagSyntax::new("@{}").unwrap();
+        assert_eq!(syntax.take("@zf_048 你好"), (Some("zf_048"), "你好"));
+        assert_eq!(syntax.take("@zf_048"), (None, "@zf_048"));
+    }
+
+    #[test]
+    fn bad_templates_are_rejected() {
+        assert!(VoiceTagSyntax::new("[voice]").is_err());
+        assert!(VoiceTagSyntax::new("{}:").is_err());
+    }
+
+    #[test]
+    fn tags_resolve_to_voices() {
+        let names = utils::VoiceNames::default();
+        let (voice, rest) = brackets().take_voice("[zm_029] 不行。", &names).unwrap();
+        assert!(matches!(voice, Some(Voice::Zm029(_))));
+        assert_eq!(rest, "不行。");
+        assert!(matches!(
+            brackets().take_voice("没有标签", &names),
+            Ok((None, "没有标签"))
+        ));
+    }
+
+    #[test]
+    fn unknown_voice_tags_fail() {
+        let names = utils::VoiceNames::default();
+        assert!(brackets().take_voice("[zm_999] 不行。", &names).is_err());
+        assert!(brackets().take_voice("[narator] 不行。", &names).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// How often `--metrics-file` is rewritten.
+const WRITE_INTERVAL: Duration = Duration::from_secs(5);
+/// Largest request head read from a scraper.
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// Per input file and voice. Labels: `file` is the input's file stem, `voice` the
+/// file's narrator voice.
+#[derive(Debug, Default)]
+struct FileMetrics {
+    /// `morganite_lines_completed_total`: source lines whose audio was written.
+    lines_completed: u64,
+    /// `morganite_lines_failed_total`: items whose synthesis failed.
+    lines_failed: u64,
+    /// `morganite_chars_processed_total`: chars of the completed source lines.
+    chars: u64,
+    /// `morganite_audio_seconds_total`: audio written, pauses included.
+    audio_secs: f64,
+    /// `morganite_segments_written_total` counts these and
+    /// `morganite_bytes_written_total` sums their sizes.
+    segments: Vec<String>,
+}
+
+/// Run metrics in the Prometheus text format, for `--metrics-file` and
+/// `--metrics-listen`. Besides the per-file counters above there are three
+/// unlabelled gauges: `morganite_concurrency` (synthesis sessions in use),
+/// `morganite_synthesis_in_flight` (items scheduled and not yet written) and
+/// `morganite_reorder_buffer_items` (finished items waiting for an earlier one).
+#[derive(Debug, Default)]
+pub struct Metrics {
+    files: Mutex<BTreeMap<(String, String), FileMetrics>>,
+    concurrency: AtomicU64,
+    in_flight: AtomicU64,
+    reorder: AtomicU64,
+}
+
+/// `(file stem, voice)` labels of a file's metrics.
+pub type Labels = (String, String);
+
+impl Metrics {
+    fn update(&self, labels: &Labels, f: impl FnOnce(&mut FileMetrics)) {
+        f(self
+            .files
+            .lock()
+            .unwrap()
+            .entry(labels.clone())
+            .or_default());
+    }
+
+    pub fn line_done(&self, labels: &Labels, chars: u64) {
+        self.update(labels, |m| {
+            m.lines_completed += 1;
+            m.chars += chars;
+        });
+    }
+
+    pub fn line_failed(&self, labels: &Labels) {
+        self.update(labels, |m| m.lines_failed += 1);
+    }
+
+    /// The file's audio so far, pauses included.
+    pub fn set_audio(&self, labels: &Labels, audio: Duration) {
+        self.update(labels, |m| m.audio_secs = audio.as_secs_f64());
+    }
+
+    pub fn set_segments(&self, labels: &Labels, segments: &[String]) {
+        self.update(labels, |m| {
+            if m.segments.len() != segments.len() {
+                m.segments = segments.to_vec();
+            }
+        });
+    }
+
+    pub fn set_scheduling(&self, concurrency: usize, in_flight: usize, reorder: usize) {
+        self.concurrency
+            .store(concurrency as u64, Ordering::Relaxed);
+        self.in_flight.store(in_flight as u64, Ordering::Relaxed);
+        self.reorder.store(reorder as u64, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let files = self.files.lock().unwrap();
+        let mut out = String::new();
+        let mut family =
+            |name: &str, kind: &str, help: &str, value: &dyn Fn(&FileMetrics) -> f64| {
+                let _ = writeln!(out, "# HELP morganite_{name} {help}");
+                let _ = writeln!(out, "# TYPE morganite_{name} {kind}");
+                for ((file, voice), m) in files.iter() {
+                    let _ = writeln!(
+                        out,
+                        "morganite_{name}{{file=\"{}\",voice=\"{}\"}} {}",
+                        escape(file),
+                        escape(voice),
+                        value(m)
+                    );
+                }
+            };
+        family(
+            "lines_completed_total",
+            "counter",
+            "Source lines whose audio was written.",
+            &|m: &FileMetrics| m.lines_completed as f64,
+        );
+        family(
+            "lines_failed_total",
+            "counter",
+            "Items whose synthesis failed.",
+            &|m: &FileMetrics| m.lines_failed as f64,
+        );
+        family(
+            "chars_processed_total",
+            "counter",
+            "Characters of the completed source lines.",
+            &|m: &FileMetrics| m.chars as f64,
+        );
+        family(
+            "audio_seconds_total",
+            "counter",
+            "Seconds of audio written, pauses included.",
+            &|m: &FileMetrics| m.audio_secs,
+        );
+        family(
+            "segments_written_total",
+            "counter",
+            "MP3 segment files opened.",
+            &|m: &FileMetrics| m.segments.len() as f64,
+        );
+        family(
+            "bytes_written_total",
+            "counter",
+            "Bytes in the MP3 segment files so far.",
+            &|m: &FileMetrics| {
+                m.segments
+                    .iter()
+                    .filter_map(|s| std::fs::metadata(s).ok())
+                    .map(|meta| meta.len())
+                    .sum::<u64>() as f64
+            },
+        );
+        for (name, help, value) in [
+            (
+                "concurrency",
+                "Synthesis sessions in use.",
+                &self.concurrency,
+            ),
+            (
+                "synthesis_in_flight",
+                "Items scheduled and not yet written.",
+                &self.in_flight,
+            ),
+            (
+                "reorder_buffer_items",
+                "Finished items waiting for an earlier one to be written.",
+                &self.reorder,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP morganite_{name} {help}");
+            let _ = writeln!(out, "# TYPE morganite_{name} gauge");
+            let _ = writeln!(out, "morganite_{name} {}", value.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Background tasks publishing [`Metrics`]; stopped with [`Exporter::shutdown`].
+pub struct Exporter {
+    metrics: Arc<Metrics>,
+    file: Option<PathBuf>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Exporter {
+    pub async fn start(
+        metrics: Arc<Metrics>,
+        file: Option<PathBuf>,
+        listen: Option<SocketAddr>,
+    ) -> anyhow::Result<Self> {
+        let mut tasks = Vec::new();
+        if let Some(addr) = listen {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to listen on {}", addr))?;
+            tracing::info!("Serving metrics on http://{}/metrics", addr);
+            tasks.push(tokio::spawn(serve(listener, metrics.clone())));
+        }
+        if let Some(path) = file.clone() {
+            let metrics = metrics.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(WRITE_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    write_file(&path, &metrics.render());
+                }
+            }));
+        }
+        Ok(Self {
+            metrics,
+            file,
+            tasks,
+        })
+    }
+
+    /// Stop serving and leave the file with the final values.
+    pub fn shutdown(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if let Some(path) = &self.file {
+            write_file(path, &self.metrics.render());
+        }
+    }
+}
+
+/// Write then rename, so a collector never reads a half-written file.
+fn write_file(path: &Path, body: &str) {
+    let tmp = path.with_extension("prom.tmp");
+    let res = std::fs::write(&tmp, body)
+        .with_context(|| format!("Failed to write {}", tmp.display()))
+        .and_then(|_| {
+            std::fs::rename(&tmp, path)
+                .with_context(|| format!("Failed to replace {}", path.display()))
+        });
+    if let Err(e) = res {
+        tracing::warn!("{:#}", e);
+    }
+}
+
+async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, &metrics).await {
+                        tracing::debug!("Metrics request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => tracing::warn!("Failed to accept metrics connection: {}", e),
+        }
+    }
+}
+
+/// Answer one scrape: `GET /metrics` (or `/`) gets the metrics, anything else a 404.
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = match path {
+        "/metrics" | "/" => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
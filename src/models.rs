@@ -0,0 +1,290 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tracing_indicatif::{span_ext::IndicatifSpanExt, style::ProgressStyle};
+
+use crate::utils;
+
+/// Where `--download-models` looks without `--model-source`: the release the
+/// kokoro-tts crate takes its models from.
+pub const DEFAULT_SOURCE: &str = "https://github.com/mzdk100/kokoro/releases/download/V1.1";
+
+/// `--model-sha256`: `<file name>=<hex SHA-256>`.
+pub fn parse_checksum(s: &str) -> Result<(String, String), String> {
+    let (name, hex) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{} is not <file name>=<sha256>", s))?;
+    let hex = hex.trim().to_ascii_lowercase();
+    if !is_sha256(&hex) {
+        return Err(format!("{} is not a hex SHA-256", hex));
+    }
+    Ok((name.trim().to_string(), hex))
+}
+
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Where model files are downloaded from, and where their checksums come from.
+enum Source {
+    /// `hf:owner/repo[@revision]`; the Hub publishes the SHA-256 of every LFS file.
+    HuggingFace { repo: String, revision: String },
+    /// Files at `<base>/<name>`, checked against a `<name>.sha256` next to them.
+    Url(String),
+}
+
+impl Source {
+    fn parse(spec: &str) -> anyhow::Result<Self> {
+        if let Some(rest) = spec.strip_prefix("hf:") {
+            let (repo, revision) = rest.split_once('@').unwrap_or((rest, "main"));
+            anyhow::ensure!(
+                repo.split('/').count() == 2 && !repo.contains("//"),
+                "--model-source {}: expected hf:owner/repo[@revision]",
+                spec
+            );
+            return Ok(Self::HuggingFace {
+                repo: repo.to_string(),
+                revision: revision.to_string(),
+            });
+        }
+        anyhow::ensure!(
+            spec.starts_with("https://") || spec.starts_with("http://"),
+            "--model-source {}: expected an http(s):// URL or hf:owner/repo[@revision]",
+            spec
+        );
+        Ok(Self::Url(spec.trim_end_matches('/').to_string()))
+    }
+
+    fn file_url(&self, name: &str) -> String {
+        match self {
+            Self::HuggingFace { repo, revision } => {
+                format!(
+                    "https://huggingface.co/{}/resolve/{}/{}",
+                    repo, revision, name
+                )
+            }
+            Self::Url(base) => format!("{}/{}", base, name),
+        }
+    }
+
+    /// The SHA-256 the source publishes for `name`, if any.
+    async fn checksum(
+        &self,
+        client: &reqwest::Client,
+        name: &str,
+    ) -> anyhow::Result<Option<String>> {
+        match self {
+            Self::HuggingFace { repo, revision } => {
+                let url = format!(
+                    "https://huggingface.co/api/models/{}/tree/{}",
+                    repo, revision
+                );
+                let body = client
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .with_context(|| format!("Failed to list {}", url))?
+                    .bytes()
+                    .await?;
+                let entries: Vec<serde_json::Value> = serde_json::from_slice(&body)
+                    .with_context(|| format!("Unexpected listing from {}", url))?;
+                // Only LFS files carry a SHA-256; the plain `oid` is a git SHA-1
+                Ok(entries
+                    .iter()
+                    .find(|entry| entry["path"] == name)
+                    .and_then(|entry| entry["lfs"]["oid"].as_str())
+                    .map(str::to_ascii_lowercase)
+                    .filter(|hex| is_sha256(hex)))
+            }
+            Self::Url(_) => {
+                let url = format!("{}.sha256", self.file_url(name));
+                let response = client.get(&url).send().await?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let text = response
+                    .error_for_status()
+                    .with_context(|| format!("Failed to fetch {}", url))?
+                    .text()
+                    .await?;
+                // `sha256sum` output: the hash, then the file name
+                Ok(text
+                    .split_whitespace()
+                    .next()
+                    .map(str::to_ascii_lowercase)
+                    .filter(|hex| is_sha256(hex)))
+            }
+        }
+    }
+}
+
+/// `--download-models`: fetches model files that aren't where the options say.
+pub struct Downloader {
+    source: Source,
+    cache_dir: PathBuf,
+    /// `--model-sha256`, taking precedence over what the source publishes.
+    checksums: Vec<(String, String)>,
+    client: reqwest::Client,
+}
+
+impl Downloader {
+    pub fn new(
+        source: &str,
+        cache_dir: Option<PathBuf>,
+        checksums: Vec<(String, String)>,
+    ) -> anyhow::Result<Self> {
+        let cache_dir = cache_dir.or_else(default_cache_dir).context(
+            "No cache folder for downloaded models (no XDG_CACHE_HOME or HOME); give --model-cache-dir",
+        )?;
+        Ok(Self {
+            source: Source::parse(source)?,
+            cache_dir,
+            checksums,
+            client: reqwest::Client::builder()
+                .connect_timeout(std::time::Duration::from_secs(30))
+                .build()?,
+        })
+    }
+
+    /// `path` itself when it exists, otherwise the verified copy of its file in
+    /// the cache, downloaded first if it isn't there yet.
+    pub async fn ensure(&self, path: &str) -> anyhow::Result<PathBuf> {
+        if Path::new(path).exists() {
+            return Ok(PathBuf::from(path));
+        }
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .with_context(|| format!("{} has no file name to download", path))?;
+        let cached = self.cache_dir.join(name);
+        // Written only once the file has passed its check
+        let verified = self.cache_dir.join(format!("{}.sha256", name));
+        if cached.is_file() && verified.is_file() {
+            tracing::info!("Using downloaded {}", cached.display());
+            return Ok(cached);
+        }
+
+        let expected = match self.checksums.iter().find(|(n, _)| n == name) {
+            Some((_, hex)) => hex.clone(),
+            None => self
+                .source
+                .checksum(&self.client, name)
+                .await?
+                .with_context(|| {
+                    format!(
+                        "{} publishes no SHA-256 for {}, so a download can't be checked; \
+                         give it with --model-sha256 {}=<sha256>",
+                        self.source.file_url(name),
+                        name,
+                        name
+                    )
+                })?,
+        };
+        tokio::fs::create_dir_all(&self.cache_dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.cache_dir.display()))?;
+        let part = self.cache_dir.join(format!("{}.part", name));
+        self.download(name, &part).await?;
+
+        let actual = sha256_file(&part).await?;
+        if actual != expected {
+            // Resuming a corrupt file would never succeed; the next run starts over
+            let _ = tokio::fs::remove_file(&part).await;
+            anyhow::bail!(
+                "Refusing {}: its SHA-256 is {}, expected {}",
+                name,
+                actual,
+                expected
+            );
+        }
+        tokio::fs::rename(&part, &cached)
+            .await
+            .with_context(|| format!("Failed to move {} into place", part.display()))?;
+        tokio::fs::write(&verified, format!("{}  {}\n", expected, name)).await?;
+        tracing::info!("Downloaded {} (SHA-256 checked)", cached.display());
+        Ok(cached)
+    }
+
+    /// Download `name` into `part`, resuming what an interrupted run left there
+    /// when the server supports ranges and starting over when it doesn't.
+    async fn download(&self, name: &str, part: &Path) -> anyhow::Result<()> {
+        let url = self.source.file_url(name);
+        let mut have = tokio::fs::metadata(part).await.map_or(0, |m| m.len());
+        let mut request = self.client.get(&url);
+        if have > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", have));
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to download {}", url))?;
+        if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            // Already complete; the checksum decides
+            return Ok(());
+        }
+        let mut response = response
+            .error_for_status()
+            .with_context(|| format!("Failed to download {}", url))?;
+        if have > 0 && response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            tracing::info!("{} can't resume {}, starting over", url, name);
+            have = 0;
+        }
+        let total = response.content_length().map(|len| len + have);
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(have > 0)
+            .truncate(have == 0)
+            .open(part)
+            .await
+            .with_context(|| format!("Failed to open {}", part.display()))?;
+
+        let span = tracing::info_span!("download");
+        span.pb_set_style(
+            &ProgressStyle::with_template(
+                "{spinner} {msg}\n{wide_bar} {bytes}/{total_bytes} ({bytes_per_sec})",
+            )
+            .unwrap(),
+        );
+        if let Some(total) = total {
+            span.pb_set_length(total);
+        }
+        span.pb_set_position(have);
+        span.pb_set_message(&format!("Downloading {}", name));
+        span.pb_start();
+        if have > 0 {
+            tracing::info!("Resuming {} at {} bytes", name, have);
+        }
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("Download of {} interrupted; run again to resume it", name))?
+        {
+            file.write_all(&chunk).await?;
+            span.pb_inc(chunk.len() as u64);
+        }
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+async fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await?
+}
+
+/// `models` in [`utils::cache_home`].
+fn default_cache_dir() -> Option<PathBuf> {
+    utils::cache_home().map(|dir| dir.join("models"))
+}
@@ -0,0 +1,377 @@
+//! `--numbers`: digits spelled out as words before synthesis, so the model reads
+//! them the way the text's language does.
+
+/// How numbers written in digits are read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum NumberStyle {
+    /// Leave digits to the model
+    #[default]
+    Keep,
+    /// Chinese readings, e.g. 365 as 三百六十五 and 2024年 as 二零二四年
+    Zh,
+    /// English words, e.g. 42 as forty-two and 1999 as nineteen ninety-nine
+    En,
+}
+
+/// Longer numbers are IDs or codes more often than amounts, and are read digit by
+/// digit.
+const MAX_CARDINAL_DIGITS: usize = 12;
+
+const ZH_DIGITS: [char; 10] = ['零', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+
+const EN_ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const EN_TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+/// A number as written in the text.
+struct Number {
+    /// Digits of the integer part, without group separators.
+    int: String,
+    /// Digits after the decimal point.
+    frac: Option<String>,
+    /// Written with group separators, like 1,000.
+    grouped: bool,
+    percent: bool,
+}
+
+impl Number {
+    /// Leading zeros or a long run: digits to read one by one.
+    fn is_code(&self) -> bool {
+        !self.grouped
+            && self.frac.is_none()
+            && ((self.int.len() > 1 && self.int.starts_with('0'))
+                || self.int.len() > MAX_CARDINAL_DIGITS)
+    }
+
+    fn value(&self) -> u64 {
+        self.int.parse().unwrap_or_default()
+    }
+}
+
+/// `text` with its numbers spelled out in `style`. Digits run into Latin letters
+/// (MP3, 2nd) and dotted runs like version numbers are left as they are.
+pub fn spell_out(text: &str, style: NumberStyle) -> String {
+    if style == NumberStyle::Keep || !text.chars().any(|c| digit(c).is_some()) {
+        return text.to_string();
+    }
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if digit(chars[i]).is_none() {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let (number, end) = scan(&chars, start);
+        i = end;
+        let touches_letter = (start > 0 && chars[start - 1].is_ascii_alphabetic())
+            || chars.get(end).is_some_and(char::is_ascii_alphabetic);
+        match number.filter(|_| !touches_letter) {
+            Some(number) => out.push_str(&match style {
+                NumberStyle::Zh => zh(&number, chars.get(end).copied()),
+                _ => en(&number),
+            }),
+            None => out.extend(&chars[start..end]),
+        }
+    }
+    out
+}
+
+/// ASCII or fullwidth digit, as ASCII.
+fn digit(c: char) -> Option<char> {
+    match c {
+        '0'..='9' => Some(c),
+        '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32),
+        _ => None,
+    }
+}
+
+fn is_digit_at(chars: &[char], i: usize) -> bool {
+    chars.get(i).is_some_and(|&c| digit(c).is_some())
+}
+
+/// The number starting at `start` and the index after it; `None` for a dotted run
+/// like 1.2.3, which is returned whole.
+fn scan(chars: &[char], start: usize) -> (Option<Number>, usize) {
+    let mut i = start;
+    let mut int = String::new();
+    let mut grouped = false;
+    loop {
+        while let Some(d) = chars.get(i).and_then(|&c| digit(c)) {
+            int.push(d);
+            i += 1;
+        }
+        // A comma before exactly three digits separates groups, as in 1,000
+        let group = chars.get(i) == Some(&',')
+            && (1..=3).all(|k| is_digit_at(chars, i + k))
+            && !is_digit_at(chars, i + 4);
+        if !group {
+            break;
+        }
+        grouped = true;
+        i += 1;
+    }
+    let is_point = |i: usize| matches!(chars.get(i), Some('.' | '．')) && is_digit_at(chars, i + 1);
+    let mut frac = None;
+    if is_point(i) {
+        i += 1;
+        let mut digits = String::new();
+        while let Some(d) = chars.get(i).and_then(|&c| digit(c)) {
+            digits.push(d);
+            i += 1;
+        }
+        frac = Some(digits);
+        if is_point(i) {
+            while is_digit_at(chars, i) || is_point(i) {
+                i += 1;
+            }
+            return (None, i);
+        }
+    }
+    let percent = matches!(chars.get(i), Some('%' | '％'));
+    if percent {
+        i += 1;
+    }
+    let number = Number {
+        int,
+        frac,
+        grouped,
+        percent,
+    };
+    (Some(number), i)
+}
+
+/// `number` read in Chinese; `next` is the char after it, which tells years apart.
+fn zh(number: &Number, next: Option<char>) -> String {
+    let mut out = String::new();
+    if number.percent {
+        out.push_str("百分之");
+    }
+    let year = next == Some('年') && number.int.len() == 4 && !number.grouped;
+    if year || number.is_code() {
+        out.extend(number.int.chars().map(zh_digit));
+    } else {
+        out.push_str(&zh_cardinal(number.value()));
+    }
+    if let Some(frac) = &number.frac {
+        out.push('点');
+        out.extend(frac.chars().map(zh_digit));
+    }
+    out
+}
+
+fn zh_digit(d: char) -> char {
+    ZH_DIGITS[d.to_digit(10).unwrap_or_default() as usize]
+}
+
+fn zh_cardinal(n: u64) -> String {
+    const UNITS: [&str; 3] = ["", "万", "亿"];
+    if n == 0 {
+        return ZH_DIGITS[0].to_string();
+    }
+    let mut sections = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        sections.push(rest % 10_000);
+        rest /= 10_000;
+    }
+    let mut out = String::new();
+    let mut gap = false;
+    for (i, &section) in sections.iter().enumerate().rev() {
+        if section == 0 {
+            gap |= !out.is_empty();
+            continue;
+        }
+        if !out.is_empty() && (gap || section < 1000) {
+            out.push(ZH_DIGITS[0]);
+        }
+        out.push_str(&zh_section(section));
+        out.push_str(UNITS[i]);
+        gap = false;
+    }
+    // 十五, not 一十五, at the start
+    match out.strip_prefix("一十") {
+        Some(rest) => format!("十{rest}"),
+        None => out,
+    }
+}
+
+/// 1 to 9999, with 零 for the places skipped inside it.
+fn zh_section(n: u64) -> String {
+    let mut out = String::new();
+    let mut gap = false;
+    for (place, unit) in [(1000, "千"), (100, "百"), (10, "十"), (1, "")] {
+        let d = (n / place % 10) as usize;
+        if d == 0 {
+            gap |= !out.is_empty();
+            continue;
+        }
+        if gap {
+            out.push(ZH_DIGITS[0]);
+            gap = false;
+        }
+        out.push(ZH_DIGITS[d]);
+        out.push_str(unit);
+    }
+    out
+}
+
+fn en(number: &Number) -> String {
+    let value = number.value();
+    let mut out = if number.is_code() {
+        en_digits(&number.int)
+    } else if !number.grouped
+        && number.frac.is_none()
+        && let Some(year) = en_year(value)
+    {
+        year
+    } else {
+        en_cardinal(value)
+    };
+    if let Some(frac) = &number.frac {
+        out.push_str(" point ");
+        out.push_str(&en_digits(frac));
+    }
+    if number.percent {
+        out.push_str(" percent");
+    }
+    out
+}
+
+fn en_digits(digits: &str) -> String {
+    digits
+        .chars()
+        .map(|d| EN_ONES[d.to_digit(10).unwrap_or_default() as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn en_cardinal(n: u64) -> String {
+    const SCALES: [&str; 4] = ["", " thousand", " million", " billion"];
+    if n == 0 {
+        return EN_ONES[0].to_string();
+    }
+    let mut groups = Vec::new();
+    let mut rest = n;
+    let mut scale = 0;
+    while rest > 0 {
+        let group = rest % 1000;
+        if group > 0 {
+            groups.push(format!("{}{}", en_below_thousand(group), SCALES[scale]));
+        }
+        rest /= 1000;
+        scale += 1;
+    }
+    groups.reverse();
+    groups.join(" ")
+}
+
+/// 1 to 999.
+fn en_below_thousand(n: u64) -> String {
+    let mut parts = Vec::new();
+    if n >= 100 {
+        parts.push(format!("{} hundred", EN_ONES[(n / 100) as usize]));
+    }
+    let rest = (n % 100) as usize;
+    match rest {
+        0 => {}
+        1..20 => parts.push(EN_ONES[rest].to_string()),
+        _ => parts.push(match rest % 10 {
+            0 => EN_TENS[rest / 10].to_string(),
+            ones => format!("{}-{}", EN_TENS[rest / 10], EN_ONES[ones]),
+        }),
+    }
+    parts.join(" ")
+}
+
+/// Four digits from 1100 to 2099 read as a year, in pairs: nineteen ninety-nine,
+/// twenty twenty-four. The 2000s up to 2009 are read as plain numbers.
+fn en_year(n: u64) -> Option<String> {
+    if !(1100..=2099).contains(&n) || (2000..2010).contains(&n) {
+        return None;
+    }
+    let (high, low) = (en_below_thousand(n / 100), n % 100);
+    Some(match low {
+        0 => format!("{high} hundred"),
+        1..10 => format!("{high} oh {}", EN_ONES[low as usize]),
+        _ => format!("{high} {}", en_below_thousand(low)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_leaves_the_text_alone() {
+        assert_eq!(spell_out("第3章 共42人", NumberStyle::Keep), "第3章 共42人");
+    }
+
+    #[test]
+    fn chinese_readings() {
+        let zh = |s| spell_out(s, NumberStyle::Zh);
+        assert_eq!(zh("第3章"), "第三章");
+        assert_eq!(zh("共15人"), "共十五人");
+        assert_eq!(zh("105"), "一百零五");
+        assert_eq!(zh("1010"), "一千零一十");
+        assert_eq!(zh("10005"), "一万零五");
+        assert_eq!(zh("150000"), "十五万");
+        assert_eq!(zh("100010000"), "一亿零一万");
+        assert_eq!(zh("1,000"), "一千");
+        assert_eq!(zh("０"), "零");
+        assert_eq!(zh("2024年"), "二零二四年");
+        assert_eq!(zh("20年"), "二十年");
+        assert_eq!(zh("3.14"), "三点一四");
+        assert_eq!(zh("50%"), "百分之五十");
+        assert_eq!(zh("编号007"), "编号零零七");
+    }
+
+    #[test]
+    fn english_words() {
+        let en = |s| spell_out(s, NumberStyle::En);
+        assert_eq!(en("42 apples"), "forty-two apples");
+        assert_eq!(en("105"), "one hundred five");
+        assert_eq!(en("1,250,000"), "one million two hundred fifty thousand");
+        assert_eq!(en("in 1999."), "in nineteen ninety-nine.");
+        assert_eq!(en("1905"), "nineteen oh five");
+        assert_eq!(en("1900"), "nineteen hundred");
+        assert_eq!(en("2005"), "two thousand five");
+        assert_eq!(en("2024"), "twenty twenty-four");
+        assert_eq!(en("3.5"), "three point five");
+        assert_eq!(en("20%"), "twenty percent");
+        assert_eq!(en("007"), "zero zero seven");
+    }
+
+    #[test]
+    fn codes_and_units_are_left_alone() {
+        for style in [NumberStyle::Zh, NumberStyle::En] {
+            assert_eq!(spell_out("MP3 and 2nd", style), "MP3 and 2nd");
+            assert_eq!(spell_out("v1.2.3", style), "v1.2.3");
+            assert_eq!(spell_out("1.2.3", style), "1.2.3");
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+/// Name of the folder-wide override file, next to the input files.
+pub const FOLDER_FILE: &str = "voices.toml";
+
+/// Settings for one input file, from a `<stem>.voice.toml` sidecar or a matching
+/// entry in the folder's `voices.toml`:
+///
+/// ```toml
+/// # chapter_05.voice.toml
+/// voice = "zm_029"
+/// speed = 0.9
+/// bitrate = 96
+///
+/// # voices.toml
+/// [[files]]
+/// match = "chapter_0*.txt"
+/// voice = "zf_002"
+/// ```
+///
+/// The `--config` file takes the same `[[files]]` entries.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct OverrideEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    voice: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitrate: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FolderFile {
+    #[serde(default)]
+    files: Vec<FileEntry>,
+}
+
+/// One `[[files]]` entry: settings for the files whose name matches the glob.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileEntry {
+    #[serde(rename = "match")]
+    glob: String,
+    #[serde(flatten)]
+    settings: OverrideEntry,
+}
+
+/// Per-file overrides; unset fields fall back to the command line.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Override {
+    pub voice: Option<Voice>,
+    pub speed: Option<f32>,
+    pub bitrate: Option<u32>,
+}
+
+impl Override {
+    fn apply(
+        &mut self,
+        entry: &OverrideEntry,
+        origin: &Path,
+        names: &utils::VoiceNames,
+    ) -> anyhow::Result<()> {
+        if let Some(voice) = &entry.voice {
+            self.voice = Some(
+                names
+                    .parse(voice)
+                    .map_err(|e| anyhow::anyhow!("{}: {e}", origin.display()))?,
+            );
+        }
+        if let Some(speed) = entry.speed {
+            self.speed = Some(
+                utils::check_speed(speed)
+                    .map_err(|e| anyhow::anyhow!("{}: {e}", origin.display()))?,
+            );
+        }
+        self.bitrate = entry.bitrate.or(self.bitrate);
+        Ok(())
+    }
+}
+
+/// Collect the overrides for `txt_path`: `[[files]]` entries of the `--config`
+/// file (from `config_origin`) first, then the folder's `voices.toml` (the last
+/// matching glob wins in each), then the file's own sidecar on top.
+pub fn load_for(
+    txt_path: &Path,
+    config_files: &[FileEntry],
+    config_origin: Option<&Path>,
+    names: &utils::VoiceNames,
+) -> anyhow::Result<Override> {
+    let mut result = Override::default();
+    let file_name = txt_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+
+    if let Some(origin) = config_origin {
+        apply_matching(&mut result, config_files, file_name, origin, names)?;
+    }
+
+    let folder_file = txt_path.with_file_name(FOLDER_FILE);
+    if folder_file.is_file() {
+        let folder: FolderFile = read_toml(&folder_file)?;
+        apply_matching(&mut result, &folder.files, file_name, &folder_file, names)?;
+    }
+
+    let sidecar = txt_path.with_extension("voice.toml");
+    if sidecar.is_file() {
+        let entry: OverrideEntry = read_toml(&sidecar)?;
+        result.apply(&entry, &sidecar, names)?;
+    }
+    Ok(result)
+}
+
+fn apply_matching(
+    result: &mut Override,
+    entries: &[FileEntry],
+    file_name: &str,
+    origin: &Path,
+    names: &utils::VoiceNames,
+) -> anyhow::Result<()> {
+    let mut matched = Vec::new();
+    for entry in entries {
+        if glob_regex(&entry.glob)?.is_match(file_name) {
+            result.apply(&entry.settings, origin, names)?;
+            matched.push(entry.glob.as_str());
+        }
+    }
+    if matched.len() > 1 {
+        tracing::warn!(
+            "{} matches several entries in {} ({}), later ones win",
+            file_name,
+            origin.display(),
+            matched.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// `*` and `?` wildcards over a whole file name.
+fn glob_regex(glob: &str) -> anyhow::Result<Regex> {
+    let mut re = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).with_context(|| format!("Invalid file pattern {glob}"))
+}
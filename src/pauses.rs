@@ -0,0 +1,239 @@
+use std::time::Duration;
+
+use crate::{lang, text, utils};
+
+/// The punctuation a join between two clips falls on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseClass {
+    /// `，` `,` `、`
+    Comma,
+    /// `；` `;` `：` `:`
+    Clause,
+    /// `。` `.`, and text ending in no punctuation at all
+    Period,
+    /// `？` `?`
+    Question,
+    /// `！` `!`
+    Exclamation,
+    /// `…` `...`
+    Ellipsis,
+    /// A blank line in the source.
+    Paragraph,
+}
+
+const CLASSES: [PauseClass; 7] = [
+    PauseClass::Comma,
+    PauseClass::Clause,
+    PauseClass::Period,
+    PauseClass::Question,
+    PauseClass::Exclamation,
+    PauseClass::Ellipsis,
+    PauseClass::Paragraph,
+];
+
+impl PauseClass {
+    pub fn name(self) -> &'static str {
+        match self {
+            PauseClass::Comma => "comma",
+            PauseClass::Clause => "clause",
+            PauseClass::Period => "period",
+            PauseClass::Question => "question",
+            PauseClass::Exclamation => "exclamation",
+            PauseClass::Ellipsis => "ellipsis",
+            PauseClass::Paragraph => "paragraph",
+        }
+    }
+
+    /// The class of a join after `spoken`, from its last punctuation; closing quotes
+    /// and brackets are looked through.
+    pub fn after(spoken: &str) -> Self {
+        let spoken = spoken
+            .trim_end_matches(|c: char| c.is_whitespace() || text::SENTENCE_CLOSERS.contains(c));
+        if spoken.ends_with("...") {
+            return PauseClass::Ellipsis;
+        }
+        match spoken.chars().last() {
+            Some('，' | ',' | '、') => PauseClass::Comma,
+            Some('；' | ';' | '：' | ':') => PauseClass::Clause,
+            Some('？' | '?') => PauseClass::Question,
+            Some('！' | '!') => PauseClass::Exclamation,
+            Some('…') => PauseClass::Ellipsis,
+            _ => PauseClass::Period,
+        }
+    }
+}
+
+/// `--pause-map` entry: `class=duration`, for one language with a `zh.` or `en.`
+/// prefix, for both without.
+#[derive(Debug, Clone, Copy)]
+pub struct Override {
+    pub lang: Option<lang::Script>,
+    pub class: PauseClass,
+    pub pause: Duration,
+}
+
+/// `--pause-map` value parser.
+pub fn parse_override(s: &str) -> Result<Override, String> {
+    let (key, pause) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CLASS=DURATION, got {s}"))?;
+    let (lang, class) = match key.trim().split_once('.') {
+        Some(("zh", class)) => (Some(lang::Script::Zh), class),
+        Some(("en", class)) => (Some(lang::Script::En), class),
+        Some((lang, _)) => return Err(format!("Unknown language {lang:?} (use zh or en)")),
+        None => (None, key.trim()),
+    };
+    let class = CLASSES
+        .into_iter()
+        .find(|c| c.name() == class)
+        .ok_or_else(|| {
+            format!(
+                "Unknown punctuation class {class:?} (one of {})",
+                CLASSES.map(PauseClass::name).join(", ")
+            )
+        })?;
+    Ok(Override {
+        lang,
+        class,
+        pause: utils::parse_duration(pause)?,
+    })
+}
+
+/// Silence at a join between clips by the punctuation it falls on, with a set for
+/// Chinese and one for English text. Only the English set is used for English.
+#[derive(Debug, Clone)]
+pub struct PauseMap {
+    zh: [Duration; CLASSES.len()],
+    en: [Duration; CLASSES.len()],
+}
+
+impl Default for PauseMap {
+    fn default() -> Self {
+        let ms = |ms: [u64; CLASSES.len()]| ms.map(Duration::from_millis);
+        Self {
+            // comma, clause, period, question, exclamation, ellipsis, paragraph
+            zh: ms([150, 250, 400, 450, 400, 600, 800]),
+            en: ms([120, 200, 350, 400, 350, 500, 700]),
+        }
+    }
+}
+
+impl PauseMap {
+    /// The defaults with `overrides` applied in order.
+    pub fn new(overrides: &[Override]) -> Self {
+        let mut map = Self::default();
+        for o in overrides {
+            let slot = o.class as usize;
+            if o.lang != Some(lang::Script::En) {
+                map.zh[slot] = o.pause;
+            }
+            if o.lang != Some(lang::Script::Zh) {
+                map.en[slot] = o.pause;
+            }
+        }
+        map
+    }
+
+    pub fn get(&self, script: lang::Script, class: PauseClass) -> Duration {
+        match script {
+            lang::Script::En => self.en[class as usize],
+            _ => self.zh[class as usize],
+        }
+    }
+
+    /// The pause after `text`, in `script`.
+    pub fn after(&self, script: lang::Script, text: &str) -> Duration {
+        self.get(script, PauseClass::after(text))
+    }
+
+    /// One line per language, for the log.
+    pub fn describe(&self) -> Vec<String> {
+        [("zh", &self.zh), ("en", &self.en)]
+            .into_iter()
+            .map(|(lang, pauses)| {
+                let entries = CLASSES
+                    .iter()
+                    .zip(pauses)
+                    .map(|(c, p)| format!("{} {}ms", c.name(), p.as_millis()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{lang}: {entries}")
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn joins_are_classed_by_their_last_punctuation() {
+        let cases = [
+            ("他说，", PauseClass::Comma),
+            ("apples, pears,", PauseClass::Comma),
+            ("一、", PauseClass::Comma),
+            ("如下：", PauseClass::Clause),
+            ("first; ", PauseClass::Clause),
+            ("好了。", PauseClass::Period),
+            ("Done.", PauseClass::Period),
+            ("没有标点", PauseClass::Period),
+            ("真的吗？", PauseClass::Question),
+            ("Really?", PauseClass::Question),
+            ("快走！", PauseClass::Exclamation),
+            ("然后……", PauseClass::Ellipsis),
+            ("and then...", PauseClass::Ellipsis),
+        ];
+        for (text, class) in cases {
+            assert_eq!(PauseClass::after(text), class, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn closing_quotes_are_looked_through() {
+        assert_eq!(PauseClass::after("“不行？”"), PauseClass::Question);
+        assert_eq!(PauseClass::after("（见下，）"), PauseClass::Comma);
+        assert_eq!(PauseClass::after("\"Stop!\" "), PauseClass::Exclamation);
+    }
+
+    #[test]
+    fn default_pauses_per_class() {
+        let map = PauseMap::default();
+        let zh = CLASSES.map(|c| map.get(lang::Script::Zh, c));
+        let en = CLASSES.map(|c| map.get(lang::Script::En, c));
+        assert_eq!(zh, [150, 250, 400, 450, 400, 600, 800].map(ms));
+        assert_eq!(en, [120, 200, 350, 400, 350, 500, 700].map(ms));
+        assert_eq!(map.after(lang::Script::Zh, "他说，"), ms(150));
+        assert_eq!(map.after(lang::Script::En, "Really?"), ms(400));
+    }
+
+    #[test]
+    fn only_english_uses_the_english_set() {
+        let map = PauseMap::default();
+        for script in [lang::Script::Mixed, lang::Script::Other] {
+            assert_eq!(map.get(script, PauseClass::Period), ms(400));
+        }
+    }
+
+    #[test]
+    fn overrides_apply_per_language_in_order() {
+        let overrides =
+            ["comma=200ms", "en.comma=90ms", "zh.paragraph=1s"].map(|s| parse_override(s).unwrap());
+        let map = PauseMap::new(&overrides);
+        assert_eq!(map.get(lang::Script::Zh, PauseClass::Comma), ms(200));
+        assert_eq!(map.get(lang::Script::En, PauseClass::Comma), ms(90));
+        assert_eq!(map.get(lang::Script::Zh, PauseClass::Paragraph), ms(1000));
+        assert_eq!(map.get(lang::Script::En, PauseClass::Paragraph), ms(700));
+    }
+
+    #[test]
+    fn bad_overrides_are_rejected() {
+        for s in ["comma", "fr.comma=100ms", "semicolon=100ms", "comma=100"] {
+            assert!(parse_override(s).is_err(), "{s:?}");
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// File the hashes of a run are written to, in its output folder.
+pub const HASHES_JSONL: &str = "pcm_hashes.jsonl";
+
+/// Samples per block hashed on its own as well, so a divergence can be placed
+/// within a line.
+const BLOCK_SAMPLES: usize = 4096;
+const BLOCK_BYTES: usize = BLOCK_SAMPLES * size_of::<f32>();
+
+/// One item's audio as hashed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LineHash {
+    pub file: String,
+    pub index: usize,
+    pub source_line: Option<usize>,
+    pub samples: usize,
+    pub sha256: String,
+    /// First 8 bytes of the SHA-256 of each block of `BLOCK_SAMPLES`, as hex.
+    pub blocks: Vec<String>,
+}
+
+/// The last record of the file: every line's hash, in order, hashed together.
+#[derive(Debug, Serialize, Deserialize)]
+struct RunHash {
+    run_sha256: String,
+    lines: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Line(LineHash),
+    Run(RunHash),
+}
+
+/// Where a run first stopped matching the hashes it is compared against.
+#[derive(Debug)]
+pub struct Divergence {
+    pub file: String,
+    pub index: usize,
+    pub source_line: Option<usize>,
+    /// Byte offset into the item's f32 samples, to the start of the first block
+    /// that differs.
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} idx {} (line {}): {} at byte {}",
+            self.file,
+            self.index,
+            self.source_line
+                .map_or("-".to_string(), |line| line.to_string()),
+            self.reason,
+            self.offset
+        )
+    }
+}
+
+/// `--hash-pcm`: a SHA-256 of each item's f32 samples as the engine returned them
+/// (what the cache keeps), before trimming, tempo, gain, limiting or encoding; the
+/// MP3s themselves are not covered. With `--compare-hashes` each file is checked
+/// against an earlier run's hashes once it is done.
+pub struct PcmHashes {
+    path: PathBuf,
+    /// Items hashed by the synthesis tasks, until their file is done.
+    pending: Mutex<BTreeMap<(String, usize), LineHash>>,
+    run: Mutex<(Sha256, usize)>,
+    previous: Option<BTreeMap<(String, usize), LineHash>>,
+}
+
+impl PcmHashes {
+    /// Start [`HASHES_JSONL`] in `dir`, checking against the hashes at `previous`.
+    pub fn new(dir: &Path, previous: Option<&Path>) -> anyhow::Result<Self> {
+        let previous = previous.map(load).transpose()?;
+        let path = dir.join(HASHES_JSONL);
+        std::fs::File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self {
+            path,
+            pending: Mutex::default(),
+            run: Mutex::new((Sha256::new(), 0)),
+            previous,
+        })
+    }
+
+    /// Hash `audio`, item `index` of `file` from `source_line`. Cheap next to
+    /// synthesizing it.
+    pub fn record(&self, file: &str, index: usize, source_line: Option<usize>, audio: &[f32]) {
+        let mut whole = Sha256::new();
+        let mut blocks = Vec::with_capacity(audio.len().div_ceil(BLOCK_SAMPLES));
+        let mut bytes = Vec::with_capacity(BLOCK_BYTES);
+        for block in audio.chunks(BLOCK_SAMPLES) {
+            bytes.clear();
+            bytes.extend(block.iter().flat_map(|s| s.to_le_bytes()));
+            whole.update(&bytes);
+            let digest = Sha256::digest(&bytes);
+            blocks.push(format!(
+                "{:016x}",
+                u64::from_be_bytes(digest[..8].try_into().unwrap())
+            ));
+        }
+        let sha256 = format!("{:x}", whole.finalize());
+        self.pending.lock().unwrap().insert(
+            (file.to_string(), index),
+            LineHash {
+                file: file.to_string(),
+                index,
+                source_line,
+                samples: audio.len(),
+                sha256,
+                blocks,
+            },
+        );
+    }
+
+    /// Write the hashes of `file`, whose items are done, and with `--compare-hashes`
+    /// return where they first differ from the earlier run's.
+    pub fn finish_file(&self, file: &str) -> anyhow::Result<Option<Divergence>> {
+        let lines = {
+            let mut pending = self.pending.lock().unwrap();
+            let keys = pending
+                .range((file.to_string(), 0)..=(file.to_string(), usize::MAX))
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            keys.iter()
+                .filter_map(|key| pending.remove(key))
+                .collect::<Vec<_>>()
+        };
+
+        let mut out = String::new();
+        {
+            let mut run = self.run.lock().unwrap();
+            for line in &lines {
+                run.0.update(line.sha256.as_bytes());
+                run.1 += 1;
+                out.push_str(&serde_json::to_string(line)?);
+                out.push('\n');
+            }
+        }
+        self.append(&out)?;
+
+        let Some(previous) = &self.previous else {
+            return Ok(None);
+        };
+        let before = previous
+            .range((file.to_string(), 0)..=(file.to_string(), usize::MAX))
+            .map(|(_, line)| line);
+        Ok(first_divergence(before, &lines))
+    }
+
+    /// Close the file with the run-level hash, and return it.
+    pub fn finish(&self) -> anyhow::Result<(String, usize)> {
+        let (hasher, lines) = std::mem::replace(&mut *self.run.lock().unwrap(), (Sha256::new(), 0));
+        let run_sha256 = format!("{:x}", hasher.finalize());
+        self.append(&format!(
+            "{}\n",
+            serde_json::to_string(&RunHash {
+                run_sha256: run_sha256.clone(),
+                lines,
+            })?
+        ))?;
+        Ok((run_sha256, lines))
+    }
+
+    fn append(&self, text: &str) -> anyhow::Result<()> {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| f.write_all(text.as_bytes()))
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// Line hashes of an earlier run, by file and item index.
+fn load(path: &Path) -> anyhow::Result<BTreeMap<(String, usize), LineHash>> {
+    let f =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut lines = BTreeMap::new();
+    let mut run = None;
+    for (n, line) in BufReader::new(f).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Record>(&line)
+            .with_context(|| format!("{}:{}: not a PCM hash record", path.display(), n + 1))?
+        {
+            Record::Line(hash) => {
+                lines.insert((hash.file.clone(), hash.index), hash);
+            }
+            Record::Run(hash) => run = Some(hash),
+        }
+    }
+    anyhow::ensure!(!lines.is_empty(), "No PCM hashes in {}", path.display());
+    match run {
+        Some(run) if run.lines == lines.len() => tracing::info!(
+            "Comparing against {} line hashes of run {}",
+            run.lines,
+            run.run_sha256
+        ),
+        _ => tracing::warn!(
+            "{} is from a run that didn't finish; only its {} line hashes are compared",
+            path.display(),
+            lines.len()
+        ),
+    }
+    Ok(lines)
+}
+
+/// The first item, in index order, whose hash is missing on either side or differs.
+fn first_divergence<'a>(
+    before: impl Iterator<Item = &'a LineHash>,
+    now: &[LineHash],
+) -> Option<Divergence> {
+    let mut before = before.peekable();
+    let mut now = now.iter().peekable();
+    loop {
+        let (line, reason, offset) = match (before.peek(), now.peek()) {
+            (None, None) => return None,
+            (Some(b), Some(n)) if b.index == n.index => {
+                if b.sha256 == n.sha256 {
+                    before.next();
+                    now.next();
+                    continue;
+                }
+                let block = b.blocks.iter().zip(&n.blocks).position(|(b, n)| b != n);
+                match block {
+                    Some(block) => (*n, "audio differs", block * BLOCK_BYTES),
+                    // Same up to where the shorter one ends
+                    None => (
+                        *n,
+                        "length differs",
+                        b.samples.min(n.samples) * size_of::<f32>(),
+                    ),
+                }
+            }
+            (Some(b), n) if n.is_none_or(|n| b.index < n.index) => (*b, "missing from this run", 0),
+            (_, Some(n)) => (*n, "not in the earlier run", 0),
+            (_, None) => unreachable!(),
+        };
+        return Some(Divergence {
+            file: line.file.clone(),
+            index: line.index,
+            source_line: line.source_line,
+            offset,
+            reason,
+        });
+    }
+}
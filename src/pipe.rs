@@ -0,0 +1,195 @@
+use std::{
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use anyhow::Context;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum PcmFormat {
+    /// 32-bit float, little endian (ffmpeg `-f f32le`)
+    F32,
+    /// 16-bit signed, little endian (ffmpeg `-f s16le`)
+    S16,
+}
+
+impl PcmFormat {
+    /// Append `samples` to `out` as little-endian bytes.
+    pub fn encode(self, samples: &[f32], out: &mut Vec<u8>) {
+        match self {
+            Self::F32 => {
+                for s in samples {
+                    out.extend_from_slice(&s.to_le_bytes());
+                }
+            }
+            Self::S16 => {
+                for s in samples {
+                    let s = (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                    out.extend_from_slice(&s.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// `--pipe-to`: a command run once per input file, fed the file's final PCM on
+/// its stdin. Placeholders `{stem}`, `{input}` and `{out_dir}` are filled in
+/// per file.
+#[derive(Debug, Clone)]
+pub struct PipeTo {
+    args: Vec<String>,
+    pub format: PcmFormat,
+}
+
+impl PipeTo {
+    /// Split `command` into arguments at whitespace; single or double quotes keep
+    /// an argument together. No shell is involved.
+    pub fn parse(command: &str, format: PcmFormat) -> anyhow::Result<Self> {
+        let mut args = Vec::new();
+        let mut arg = String::new();
+        let mut in_arg = false;
+        let mut quote = None;
+        for c in command.chars() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), c) => arg.push(c),
+                (None, '"' | '\'') => {
+                    quote = Some(c);
+                    in_arg = true;
+                }
+                (None, c) if c.is_whitespace() => {
+                    if in_arg {
+                        args.push(std::mem::take(&mut arg));
+                        in_arg = false;
+                    }
+                }
+                (None, c) => {
+                    arg.push(c);
+                    in_arg = true;
+                }
+            }
+        }
+        anyhow::ensure!(quote.is_none(), "Unclosed quote in --pipe-to command");
+        if in_arg {
+            args.push(arg);
+        }
+        anyhow::ensure!(!args.is_empty(), "--pipe-to command is empty");
+        Ok(Self { args, format })
+    }
+
+    /// Start the command for `input`, whose output goes to `out_dir`. Its stdout
+    /// and stderr end up in the run log, prefixed with the file's stem.
+    pub fn spawn(&self, input: &Path, out_dir: &Path) -> anyhow::Result<(PipeChild, PcmSink)> {
+        let stem = input
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown");
+        let args = self
+            .args
+            .iter()
+            .map(|a| {
+                a.replace("{stem}", stem)
+                    .replace("{input}", &input.display().to_string())
+                    .replace("{out_dir}", &out_dir.display().to_string())
+            })
+            .collect::<Vec<_>>();
+        let mut child = Command::new(&args[0])
+            .args(&args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start --pipe-to command {}", args[0]))?;
+        tracing::info!("Piping {} into {}", input.display(), args.join(" "));
+
+        let stdin = child.stdin.take().unwrap();
+        if let Some(stdout) = child.stdout.take() {
+            log_lines(stdout, stem.to_string());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            log_lines(stderr, stem.to_string());
+        }
+        Ok((
+            PipeChild {
+                child: Some(child),
+                program: args[0].clone(),
+            },
+            PcmSink {
+                stdin: BufWriter::new(stdin),
+                format: self.format,
+                bytes: Vec::new(),
+            },
+        ))
+    }
+}
+
+/// Log every line `from` produces, on a thread of its own, until it closes.
+fn log_lines(from: impl std::io::Read + Send + 'static, prefix: String) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(from).lines() {
+            match line {
+                Ok(line) => tracing::info!("[{}] {}", prefix, line),
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+/// The running command. Dropped before [`PipeChild::wait`] returns, e.g. when the
+/// run is cancelled, it kills the command.
+pub struct PipeChild {
+    child: Option<Child>,
+    program: String,
+}
+
+impl PipeChild {
+    /// Wait for the command to exit once its [`PcmSink`] is gone; a non-zero exit
+    /// fails the file.
+    pub async fn wait(mut self) -> anyhow::Result<()> {
+        let mut child = self.child.take().unwrap();
+        let program = std::mem::take(&mut self.program);
+        let status = tokio::task::spawn_blocking(move || child.wait())
+            .await
+            .context("--pipe-to wait panicked")?
+            .with_context(|| format!("Failed to wait for {}", program))?;
+        anyhow::ensure!(status.success(), "{} exited with {}", program, status);
+        Ok(())
+    }
+}
+
+impl Drop for PipeChild {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// The command's stdin, taking final samples from the writer thread. Writes
+/// block while the command is behind, which holds back the writer and, through
+/// its bounded queue, synthesis.
+pub struct PcmSink {
+    stdin: BufWriter<ChildStdin>,
+    format: PcmFormat,
+    /// Scratch buffer for the little-endian bytes.
+    bytes: Vec<u8>,
+}
+
+impl PcmSink {
+    pub fn write(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.bytes.clear();
+        self.format.encode(samples, &mut self.bytes);
+        self.stdin
+            .write_all(&self.bytes)
+            .context("failed writing to --pipe-to command")
+    }
+
+    /// Flush what is buffered; dropping the sink afterwards closes the command's stdin.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.stdin
+            .flush()
+            .context("failed writing to --pipe-to command")
+    }
+}
@@ -0,0 +1,2032 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    iter::Peekable,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use tokio::{
+    sync::{Semaphore, mpsc, watch},
+    task::JoinSet,
+};
+use tracing_indicatif::{span_ext::IndicatifSpanExt, style::ProgressStyle};
+
+use crate::{
+    cache, dsp, dump, index, metrics, pcm_hash, pipe, playback, podcast, preprocess, progress,
+    subtitles, summary, text, throttle, timings, toc, tts, tune, utils, verify, writer,
+};
+
+type Msg = (usize, anyhow::Result<Chunk>);
+
+/// Lines at least this long are synthesized a few sentences at a time, so their
+/// audio can be written while the rest of the line is still being synthesized.
+///
+/// This is not the engine streaming: each piece is its own utterance, cut only
+/// after `--sentence-ends` punctuation (see [`text::sentence_pieces`]), so no
+/// word or clause is split, but intonation starts afresh at every cut, where a
+/// single utterance would carry it across. With `--punctuation-pauses` the gap at
+/// a cut is the mapped pause for its punctuation. Trimming, pause capping, tempo
+/// and loudness matching work on whole lines, so with any of them on, lines are
+/// synthesized whole (see [`ClipFinish::keeps_clips`]).
+const STREAM_CHARS: usize = 200;
+/// About how many chars each of those pieces holds.
+const STREAM_PIECE_CHARS: usize = 80;
+
+/// Pause after each `--announce-parts` announcement.
+const ANNOUNCE_PAUSE: Duration = Duration::from_millis(600);
+
+/// Lines shorter than this may be batched (`--batch-short-lines`).
+const BATCH_MAX_CHARS: usize = 10;
+/// Below this (about -40 dBFS) batched audio counts as a pause between lines.
+const BATCH_GAP_THRESHOLD: f32 = 0.01;
+/// Shortest pause a batched utterance is split at.
+const BATCH_MIN_GAP: Duration = Duration::from_millis(60);
+
+/// Largest slice of a clip sent to the consumer at once (one second), so writing
+/// an item can begin before all of it has crossed the channel.
+const CHUNK_SAMPLES: usize = writer::SAMPLE_RATE as usize;
+
+/// Consecutive audio of one item; the last chunk carries the item's timing.
+#[derive(Debug)]
+pub struct Chunk {
+    pub item: Arc<text::Item>,
+    /// The item is the file's last.
+    pub last: bool,
+    pub audio: Vec<f32>,
+    /// `audio` as a self-contained MP3 stream, when the workers encode.
+    pub encoded: Option<Vec<u8>>,
+    pub end: Option<Timing>,
+}
+
+/// How long one item took to get through the producer.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    /// Synthesis wall time (near zero on a cache hit).
+    pub took: Duration,
+    /// Synthesis time until the first chunk was ready; `took` unless streamed.
+    pub first: Duration,
+    /// Wait from submission for a free slot until synthesis started.
+    pub queued: Duration,
+    pub cached: bool,
+    /// Synthesis call that produced the item: 2 for a line synthesized on its own
+    /// after its batch failed or couldn't be split, else 1.
+    pub attempt: u32,
+}
+
+/// Settings shared by every input file of a run.
+pub struct Pipeline {
+    pub engine: tts::EngineCell,
+    pub concurrency: usize,
+    pub preprocessor: preprocess::Preprocessor,
+    pub cache: Option<Arc<cache::AudioCache>>,
+    /// Capacity of the channel to the consumer, in chunks.
+    pub queue_depth: usize,
+    /// How far past the item being written the producer may schedule.
+    pub lookahead: usize,
+    /// Largest run of very short lines synthesized in a single call.
+    pub batch_short_lines: Option<usize>,
+    /// Set by `--concurrency auto-tune`; limits how many of the sessions are in use.
+    pub tuner: Option<Arc<tune::AutoTuner>>,
+    /// Synthesis slots shared by every `synthesize` call, for callers that run
+    /// several at once (the server's requests); each call's lookahead is its own.
+    pub slots: Option<Arc<Semaphore>>,
+    pub throttle: Option<Arc<throttle::Throttle>>,
+    pub lead_in_padding: bool,
+    pub line_gap: Duration,
+    pub stack_pauses: bool,
+    /// Post-synthesis time-stretch factor; 1.0 leaves clips untouched.
+    pub tempo: f32,
+    /// Encode clips in the synthesis workers instead of the writer thread.
+    pub parallel_encode: bool,
+    /// `--stereo-mode`, for every MP3 written.
+    pub channel_mode: writer::ChannelMode,
+    /// `--encoder-quality`, for every MP3 written.
+    pub encoder_quality: writer::EncoderQuality,
+    /// Output gain in dB, applied by the writer.
+    pub gain_db: f32,
+    /// Per-voice loudness matching, kept across all files of the run.
+    pub loudness: Option<Mutex<dsp::LoudnessMatcher>>,
+    pub limiter: Option<LimiterSettings>,
+    pub trim: Option<TrimSettings>,
+    /// Samples cut by `trim` over the whole run.
+    pub trimmed: Arc<AtomicU64>,
+    pub pause_cap: Option<PauseCapSettings>,
+    /// Run-wide CSV collecting the levels of every segment.
+    pub levels_csv: Option<PathBuf>,
+    /// Run-wide [`verify::SEGMENTS_CSV`] recording what went into every segment.
+    pub segments_csv: Option<PathBuf>,
+    pub music: Option<MusicSettings>,
+    /// Audio written at the start and end of every input file's output.
+    pub intro: Option<Vec<f32>>,
+    pub outro: Option<Vec<f32>>,
+    /// `--file-separator`: audio between two files of the `--stdout` stream.
+    pub file_separator: Option<Vec<f32>>,
+    /// Set once a file has started the stream, so every later one is separated.
+    pub stream_started: AtomicBool,
+    /// `--play`: gets the final samples of every file as they are written.
+    pub playback: Option<Arc<playback::LiveOutput>>,
+    /// `--stdout`: one unsegmented MP3 stream to standard output, file after file.
+    pub stdout: bool,
+    /// `--pipe-to`: started per file and fed its final samples.
+    pub pipe_to: Option<pipe::PipeTo>,
+    /// Gets every segment once its file is complete, e.g. for `--upload`.
+    pub on_segment_closed: Option<writer::SegmentHook>,
+    /// Overlap between directly adjacent clips; zero concatenates them as-is.
+    pub crossfade: Duration,
+    /// `--segment-naming`; only files with chapters are named after them.
+    pub segment_naming: writer::SegmentNaming,
+    /// `--announce-parts`: every segment opens with its part number, spoken.
+    pub announce_parts: bool,
+    /// `--preview`: every file stops after about this much audio, in one unsplit
+    /// `_preview` segment.
+    pub preview: Option<Duration>,
+    pub subtitles: Option<subtitles::Settings>,
+    /// Write `index.csv` mapping audio offsets to source lines for every file.
+    pub index_csv: bool,
+    /// Write a [`toc::PLAYLIST`] of the segments in every output folder.
+    pub playlist: bool,
+    /// Per-item synthesis timings, collected over the whole run.
+    pub timings: Option<timings::Timings>,
+    pub pcm_dump: Option<Arc<dump::PcmDump>>,
+    pub pcm_hashes: Option<Arc<pcm_hash::PcmHashes>>,
+    pub metrics: Option<Arc<metrics::Metrics>>,
+    pub progress: Option<progress::RunProgress>,
+    /// Chars per second over the run, for the ETAs.
+    pub throughput: Mutex<progress::Throughput>,
+    pub live: progress::LiveStats,
+    pub status_file: Option<progress::StatusFile>,
+    /// Count each file's chars before synthesizing it, for its bar and ETA; off with
+    /// `--no-prescan`.
+    pub prescan: bool,
+}
+
+/// Error context naming the item whose synthesis failed.
+#[derive(Debug)]
+pub struct SynthFailed {
+    pub idx: usize,
+    pub item: Arc<text::Item>,
+}
+
+impl std::fmt::Display for SynthFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to get synth result for idx {} (line {:?}, lang {})",
+            self.idx,
+            self.item.source_line,
+            self.item.lang.label()
+        )
+    }
+}
+
+/// Error of a [`Pipeline::process_file`] stopped by its `cancel` future.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// A file's items as [`Pipeline::synthesize_until`] takes them, one at a time.
+struct ItemSource<I> {
+    items: I,
+    /// Chars to synthesize in total, when known up front, for the bar and the ETA.
+    chars: Option<u64>,
+}
+
+/// An item handed to the producer: its index, and whether it is the file's last.
+#[derive(Clone)]
+struct Queued {
+    idx: usize,
+    item: Arc<text::Item>,
+    last: bool,
+}
+
+impl Queued {
+    fn failed(&self, e: anyhow::Error) -> anyhow::Error {
+        e.context(SynthFailed {
+            idx: self.idx,
+            item: self.item.clone(),
+        })
+    }
+}
+
+/// Background music, decoded once to the output format.
+pub struct MusicSettings {
+    pub samples: Arc<Vec<f32>>,
+    pub gain_db: f32,
+    /// Bed-only tail after each file's last clip.
+    pub fade: Duration,
+}
+
+fn append_levels_csv(path: &Path, levels: &[writer::SegmentLevels]) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let new = !path.exists();
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    if new {
+        writeln!(f, "segment,duration_secs,loudness_db,peak_db,silence_pct")?;
+    }
+    for s in levels {
+        writeln!(
+            f,
+            "{},{:.3},{},{:.2},{:.2}",
+            s.segment,
+            s.levels.duration_secs,
+            s.levels
+                .loudness_db
+                .map_or(String::new(), |db| format!("{db:.2}")),
+            s.levels.peak_db,
+            s.levels.silence_pct
+        )?;
+    }
+    Ok(())
+}
+
+/// Title every segment after its chapter (or the input file), numbering the parts
+/// when a chapter spans several segments.
+fn episodes(
+    levels: &[writer::SegmentLevels],
+    out_dir: &Path,
+    txt_path: &Path,
+    chapters: &[Arc<crate::chapters::Chapter>],
+) -> Vec<podcast::Episode> {
+    let stem = txt_path
+        .file_stem()
+        .map_or("unknown".into(), |s| s.to_string_lossy());
+    // Segments are in the chapter's folder, or named after it with --segment-naming chapter
+    let group_title = |segment: &str| {
+        let segment = Path::new(segment);
+        let dir = segment.parent();
+        let file_stem = segment.file_stem().map(|s| s.to_string_lossy());
+        chapters
+            .iter()
+            .find(|c| {
+                dir == Some(out_dir.join(&c.name).as_path())
+                    || (dir == Some(out_dir)
+                        && file_stem.as_deref().is_some_and(|s| {
+                            s == c.name
+                                || s.strip_prefix(c.name.as_str())
+                                    .is_some_and(|rest| rest.starts_with('_'))
+                        }))
+            })
+            .map_or(stem.to_string(), |c| c.title.clone())
+    };
+    let titles = levels
+        .iter()
+        .map(|s| group_title(&s.segment))
+        .collect::<Vec<_>>();
+    let mut part = 0;
+    levels
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            part = if i > 0 && titles[i] == titles[i - 1] {
+                part + 1
+            } else {
+                1
+            };
+            let parts = part
+                + titles[i + 1..]
+                    .iter()
+                    .take_while(|t| **t == titles[i])
+                    .count();
+            podcast::Episode {
+                path: s.segment.clone(),
+                title: if part == 1 && parts == 1 {
+                    titles[i].clone()
+                } else {
+                    format!("{} (part {})", titles[i], part)
+                },
+                duration: Duration::from_secs_f64(s.levels.duration_secs),
+            }
+        })
+        .collect()
+}
+
+/// Take the lines of `lines` up to and including source line `upto`.
+fn lines_through(
+    lines: &mut Peekable<text::NonEmptyLines>,
+    upto: usize,
+) -> anyhow::Result<Vec<text::SourceLine>> {
+    let mut taken = Vec::new();
+    while let Some(read) = lines.next_if(|l| !l.as_ref().is_ok_and(|l| l.number > upto)) {
+        taken.push(read?);
+    }
+    Ok(taken)
+}
+
+/// Log the time of a source line that was synthesized in several pieces.
+fn log_line_took(line_took: Option<(usize, Duration, usize)>) {
+    if let Some((line, took, pieces)) = line_took.filter(|(.., pieces)| *pieces > 1) {
+        tracing::info!("Line {} took {:?} over {} pieces", line, took, pieces);
+    }
+}
+
+/// Aborts a task when dropped.
+struct AbortOnDrop(tokio::task::AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Send `audio` in slices of up to `chunk_samples`, the last one carrying `end`.
+/// With `encoding` it goes as one piece, encoded off the async threads.
+async fn send_chunks(
+    tx: &mpsc::Sender<Msg>,
+    to: &Queued,
+    audio: Vec<f32>,
+    chunk_samples: usize,
+    encoding: Option<&Arc<ClipEncoding>>,
+    end: Option<Timing>,
+) {
+    if let Some(encoding) = encoding {
+        let encoding = encoding.clone();
+        let (item, last) = (to.item.clone(), to.last);
+        let chunk = tokio::task::spawn_blocking(move || {
+            let mut audio = audio;
+            let encoded = encoding.encode(&mut audio)?;
+            anyhow::Ok(Chunk {
+                item,
+                last,
+                audio,
+                encoded: Some(encoded),
+                end,
+            })
+        })
+        .await
+        .context("mp3 encoder panicked")
+        .and_then(|r| r)
+        .map_err(|e| to.failed(e));
+        let _ = tx.send((to.idx, chunk)).await;
+        return;
+    }
+    if audio.len() <= chunk_samples {
+        let chunk = Chunk {
+            item: to.item.clone(),
+            last: to.last,
+            audio,
+            encoded: None,
+            end,
+        };
+        let _ = tx.send((to.idx, Ok(chunk))).await;
+        return;
+    }
+    let count = audio.len().div_ceil(chunk_samples);
+    for (i, slice) in audio.chunks(chunk_samples).enumerate() {
+        let chunk = Chunk {
+            item: to.item.clone(),
+            last: to.last,
+            audio: slice.to_vec(),
+            encoded: None,
+            end: end.filter(|_| i + 1 == count),
+        };
+        if tx.send((to.idx, Ok(chunk))).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Clips losing more than this share to `--max-internal-silence` are left alone,
+/// since that is more likely a quiet voice than a stall.
+const MAX_CAPPED_SHARE: f64 = 0.5;
+
+/// How clips from the engine or the cache are finished before writing: trimmed,
+/// long pauses inside them shortened and time-stretched, as configured.
+#[derive(Clone)]
+struct ClipFinish {
+    trim: Option<TrimSettings>,
+    pause_cap: Option<PauseCapSettings>,
+    tempo: f32,
+    /// Samples cut by `trim` over the whole run.
+    trimmed: Arc<AtomicU64>,
+    /// Samples cut by `pause_cap` in this file.
+    capped: Arc<AtomicU64>,
+}
+
+impl ClipFinish {
+    /// Whether [`Self::apply`] leaves clips as they are, so a line's pieces can be
+    /// finished one by one and still come out as the whole line would.
+    fn keeps_clips(&self) -> bool {
+        self.trim.is_none() && self.pause_cap.is_none() && self.tempo == 1.0
+    }
+
+    fn apply(&self, audio: Vec<f32>, idx: usize) -> Vec<f32> {
+        let mut audio = match self.trim {
+            Some(trim) => {
+                let threshold = 10f32.powf(trim.threshold_db / 20.0);
+                let guard = (trim.guard.as_secs_f64() * writer::SAMPLE_RATE as f64) as usize;
+                match dsp::trim_silence(&audio, threshold, guard) {
+                    Some(range) => {
+                        self.trimmed
+                            .fetch_add((audio.len() - range.len()) as u64, Ordering::Relaxed);
+                        audio[range].to_vec()
+                    }
+                    None => {
+                        tracing::warn!(
+                            "Audio idx {} is entirely below the trim threshold, writing only its pause",
+                            idx
+                        );
+                        self.trimmed
+                            .fetch_add(audio.len() as u64, Ordering::Relaxed);
+                        Vec::new()
+                    }
+                }
+            }
+            None => audio,
+        };
+        if let Some(cap) = self.pause_cap {
+            let samples = |d: Duration| (d.as_secs_f64() * writer::SAMPLE_RATE as f64) as usize;
+            let cuts = dsp::long_pauses(
+                &audio,
+                10f32.powf(cap.threshold_db / 20.0),
+                samples(cap.min_run),
+                samples(cap.max),
+            );
+            let removed = cuts.iter().map(|c| c.len()).sum::<usize>();
+            if removed as f64 > audio.len() as f64 * MAX_CAPPED_SHARE {
+                tracing::warn!(
+                    "Audio idx {}: capping its pauses would cut {:.0}% of it, left as is",
+                    idx,
+                    removed as f64 / audio.len() as f64 * 100.0
+                );
+            } else if removed > 0 {
+                tracing::debug!(
+                    "Audio idx {}: shortened {} long pauses by {:.1}s",
+                    idx,
+                    cuts.len(),
+                    removed as f64 / writer::SAMPLE_RATE as f64
+                );
+                for cut in cuts.into_iter().rev() {
+                    audio.drain(cut);
+                }
+                self.capped.fetch_add(removed as u64, Ordering::Relaxed);
+            }
+        }
+        if self.tempo != 1.0 {
+            dsp::time_stretch(&audio, self.tempo, writer::SAMPLE_RATE)
+        } else {
+            audio
+        }
+    }
+}
+
+/// Write out the tail the crossfader is holding back, if any.
+async fn flush_crossfade(
+    xfade: &mut dsp::Crossfader,
+    mp3: &mut writer::BackgroundSplitter,
+) -> anyhow::Result<()> {
+    let tail = xfade.flush();
+    if !tail.is_empty() {
+        mp3.write_f32_interleaved(tail)
+            .await
+            .context("Failed to write to mp3")?;
+    }
+    Ok(())
+}
+
+/// `--parallel-encode`: every clip is encoded to MP3 by the worker that synthesized
+/// it, and the writer only concatenates. Gain and limiting move along with it, with
+/// a fresh limiter per clip.
+#[derive(Debug, Clone, Copy)]
+pub struct ClipEncoding {
+    pub bitrate: u32,
+    pub channel_mode: writer::ChannelMode,
+    pub quality: writer::EncoderQuality,
+    pub gain_db: f32,
+    pub limiter: Option<LimiterSettings>,
+}
+
+impl ClipEncoding {
+    /// Apply gain and limiting to `audio` in place and encode the result.
+    fn encode(&self, audio: &mut [f32]) -> anyhow::Result<Vec<u8>> {
+        if self.gain_db != 0.0 {
+            let gain = 10f32.powf(self.gain_db / 20.0);
+            audio.iter_mut().for_each(|s| *s *= gain);
+        }
+        if let Some(mut low_pass) = self.quality.low_pass(self.bitrate) {
+            low_pass.process(audio);
+        }
+        if let Some(l) = self.limiter {
+            dsp::Limiter::new(l.ceiling_db, l.attack, l.release, writer::SAMPLE_RATE)
+                .process(audio);
+        }
+        writer::encode_clip(writer::mp3_config(self.bitrate, self.channel_mode), audio)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrimSettings {
+    pub threshold_db: f32,
+    pub guard: Duration,
+}
+
+/// `--max-internal-silence`: pauses inside a clip longer than `max` are cut down to it.
+#[derive(Debug, Clone, Copy)]
+pub struct PauseCapSettings {
+    pub max: Duration,
+    /// Level below which a sample counts as silence.
+    pub threshold_db: f32,
+    /// Shorter stretches below the threshold aren't pauses.
+    pub min_run: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LimiterSettings {
+    pub ceiling_db: f32,
+    pub attack: Duration,
+    pub release: Duration,
+}
+
+/// Per-file choices that can differ across the inputs of one run.
+#[derive(Debug, Clone, Copy)]
+pub struct FileSettings {
+    pub voice: Voice,
+    pub speed: f32,
+    pub bitrate: u32,
+}
+
+impl Pipeline {
+    /// A pipeline that only synthesizes: no cache, processing or side outputs.
+    pub fn bare(
+        engine: Arc<tts::Engines>,
+        concurrency: usize,
+        preprocessor: preprocess::Preprocessor,
+    ) -> Self {
+        Self {
+            engine: tts::EngineCell::new(engine),
+            concurrency,
+            preprocessor,
+            cache: None,
+            queue_depth: concurrency * 2,
+            lookahead: concurrency * 2,
+            batch_short_lines: None,
+            tuner: None,
+            slots: None,
+            throttle: None,
+            lead_in_padding: false,
+            line_gap: Duration::ZERO,
+            stack_pauses: false,
+            tempo: 1.0,
+            parallel_encode: false,
+            channel_mode: writer::ChannelMode::Mono,
+            encoder_quality: writer::EncoderQuality::Standard,
+            gain_db: 0.0,
+            loudness: None,
+            limiter: None,
+            trim: None,
+            trimmed: Arc::new(AtomicU64::new(0)),
+            pause_cap: None,
+            levels_csv: None,
+            segments_csv: None,
+            music: None,
+            intro: None,
+            outro: None,
+            file_separator: None,
+            stream_started: AtomicBool::new(false),
+            playback: None,
+            stdout: false,
+            pipe_to: None,
+            on_segment_closed: None,
+            crossfade: Duration::ZERO,
+            segment_naming: writer::SegmentNaming::Index,
+            announce_parts: false,
+            preview: None,
+            subtitles: None,
+            index_csv: false,
+            playlist: false,
+            timings: None,
+            pcm_dump: None,
+            pcm_hashes: None,
+            metrics: None,
+            progress: None,
+            throughput: Mutex::new(progress::Throughput::default()),
+            live: progress::LiveStats::default(),
+            status_file: None,
+            prescan: true,
+        }
+    }
+
+    /// Read `txt_path` into segmented MP3s under `out_dir`. Once `cancel` completes
+    /// no more audio is synthesized; what was written so far is finalized and the
+    /// file fails with [`Cancelled`].
+    pub async fn process_file(
+        &self,
+        txt_path: &Path,
+        out_dir: &Path,
+        folder_mode: bool,
+        settings: &FileSettings,
+        summary: &mut summary::FileSummary,
+        cancel: impl Future<Output = ()>,
+    ) -> anyhow::Result<()> {
+        let file_label = txt_path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown.txt")
+            .to_string();
+
+        let source = self.stream(txt_path, folder_mode)?;
+        // The input is read a second time, level with what is written, for the lines'
+        // own text and the lines that got no item
+        let mut lines = source.items.source_lines()?.peekable();
+        let mut last_line = None;
+        let mut index = self
+            .index_csv
+            .then(|| index::LineIndex::create(out_dir))
+            .transpose()?;
+        // Only the subtitles need the text of every line, once the file is done
+        let mut line_texts = BTreeMap::new();
+        // Chapters started so far, for the episode titles
+        let mut chapters = Vec::new();
+        let file_start = Instant::now();
+
+        // Fresh config per file (cheap)
+        let spec = writer::mp3_config(settings.bitrate, self.channel_mode);
+
+        let (pipe_child, pcm_sink) = match &self.pipe_to {
+            Some(pipe_to) => {
+                let (child, sink) = pipe_to.spawn(txt_path, out_dir)?;
+                (Some(child), Some(sink))
+            }
+            None => (None, None),
+        };
+
+        let naming = match self.segment_naming {
+            writer::SegmentNaming::Chapter
+                if self.preview.is_none() && self.preprocessor.has_chapters(txt_path)? =>
+            {
+                writer::SegmentNaming::Chapter
+            }
+            _ => writer::SegmentNaming::Index,
+        };
+        // Items before the first chapter heading (or all items without chapters) keep the flat
+        // layout; named after chapters, they come first as chapter 000
+        let first_prefix = match naming {
+            writer::SegmentNaming::Index => out_dir.join("audio"),
+            writer::SegmentNaming::Chapter => out_dir.join(format!(
+                "000_{}",
+                crate::chapters::sanitize_title(
+                    &txt_path.file_stem().unwrap_or_default().to_string_lossy()
+                )
+            )),
+        };
+        let mut mp3 = writer::Mp3Splitter::new(
+            first_prefix.to_string_lossy(),
+            spec,
+            if self.stdout || self.preview.is_some() {
+                Duration::MAX
+            } else {
+                Duration::from_hours(2)
+            },
+        )
+        .context("init mp3 writer")?
+        .with_lead_in_padding(self.lead_in_padding)
+        .with_gain_db(self.gain_db)
+        .with_low_pass(self.encoder_quality.low_pass(settings.bitrate))
+        .with_music(
+            self.music
+                .as_ref()
+                .map(|m| dsp::MusicBed::new(m.samples.clone(), m.gain_db)),
+        )
+        .with_limiter(
+            self.limiter
+                .map(|l| dsp::Limiter::new(l.ceiling_db, l.attack, l.release, writer::SAMPLE_RATE)),
+        )
+        .with_tag("TPE1", utils::voice_name(settings.voice))
+        .with_tag("TXXX", format!("speed\0{}", settings.speed))
+        .with_tag(
+            "TSSE",
+            format!(
+                "shine, {} kbps {}, {} quality",
+                settings.bitrate,
+                self.channel_mode.name(),
+                self.encoder_quality.name()
+            ),
+        )
+        .with_tap(self.playback.clone())
+        .with_stdout(self.stdout)
+        .with_pipe(pcm_sink)
+        .with_segment_hook(self.on_segment_closed.clone())
+        .with_segment_naming(naming)
+        .with_suffix(if self.preview.is_some() {
+            "_preview"
+        } else {
+            ""
+        })
+        .with_announcer(self.announcer(settings))
+        .spawn(self.concurrency * 2);
+        mp3.announce_first().await?;
+
+        let mut toc = toc::Toc::new(out_dir);
+        let mut timeline = self
+            .subtitles
+            .as_ref()
+            .map(|_| subtitles::Timeline::default());
+        let mut xfade = dsp::Crossfader::new(
+            (self.crossfade.as_secs_f64() * writer::SAMPLE_RATE as f64).round() as usize,
+        );
+        // Start position and samples so far of the item being written
+        let mut current: Option<(index::Position, usize)> = None;
+        let mut histograms = timings::FileHistograms::new(&file_label);
+        // Source line, synthesis time and pieces so far of the line being written
+        let mut line_took: Option<(usize, Duration, usize)> = None;
+        let labels = (
+            txt_path
+                .file_stem()
+                .map_or("unknown".to_string(), |s| s.to_string_lossy().into_owned()),
+            utils::voice_name(settings.voice).to_string(),
+        );
+        let encoding = self.parallel_encode.then(|| {
+            Arc::new(ClipEncoding {
+                bitrate: settings.bitrate,
+                channel_mode: self.channel_mode,
+                quality: self.encoder_quality,
+                gain_db: self.gain_db,
+                limiter: self.limiter,
+            })
+        });
+
+        // Set once the preview is long enough; what is already in flight still gets written
+        let stop = AtomicBool::new(false);
+        let (tx, rx) = mpsc::channel::<(usize, Chunk)>(1);
+        let synthesis =
+            self.synthesize_until(file_label.clone(), source, settings, encoding, &stop, tx);
+        let synthesis = async {
+            // Dropping the synthesis closes the channel, so the writer still gets
+            // what was already sent
+            tokio::select! {
+                r = synthesis => r,
+                () = cancel => Err(Cancelled.into()),
+            }
+        };
+        let write = async {
+            // Dropped on an error, which stops the synthesis
+            let mut rx = rx;
+            while let Some((idx, chunk)) = rx.recv().await {
+                let item = chunk.item.clone();
+                let mut audio = chunk.audio;
+
+                if let Some(loudness) = &self.loudness {
+                    loudness.lock().unwrap().process(
+                        utils::voice_name(item.voice.unwrap_or(settings.voice)),
+                        utils::voice_name(settings.voice),
+                        &mut audio,
+                        writer::SAMPLE_RATE,
+                    );
+                }
+
+                // Everything up to the start position happens once, on the first chunk
+                let first = current.is_none();
+                // A heading starts a new chapter folder with its own segment numbering;
+                // a preview stays in its one file
+                let chapter = item.chapter.clone().filter(|_| first);
+                if let Some(chapter) = chapter.as_ref().filter(|_| self.preview.is_none()) {
+                    flush_crossfade(&mut xfade, &mut mp3).await?;
+                    chapters.push(chapter.clone());
+                    tracing::info!("Chapter {:03} {}", chapter.number, chapter.title);
+                    let prefix = match naming {
+                        writer::SegmentNaming::Index => {
+                            let chapter_dir = out_dir.join(&chapter.name);
+                            std::fs::create_dir_all(&chapter_dir).with_context(|| {
+                                format!("Failed to create chapter folder {}", chapter_dir.display())
+                            })?;
+                            chapter_dir.join("audio")
+                        }
+                        writer::SegmentNaming::Chapter => out_dir.join(&chapter.name),
+                    };
+                    mp3.start_group(prefix.to_string_lossy()).await?;
+                }
+
+                // The separator and intro open the first segment that actually gets
+                // audio, and the chapter offsets below already count them
+                if idx == 0 && first {
+                    if let Some(separator) = &self.file_separator
+                        && self.stream_started.swap(true, Ordering::Relaxed)
+                    {
+                        mp3.write_f32_interleaved(separator.clone())
+                            .await
+                            .context("Failed to write file separator")?;
+                    }
+                    if let Some(intro) = &self.intro {
+                        mp3.write_f32_interleaved(intro.clone())
+                            .await
+                            .context("Failed to write intro")?;
+                    }
+                }
+
+                if let Some(chapter) = &chapter {
+                    let (segment, offset) = mp3.position();
+                    toc.push(chapter, Path::new(&segment), offset)?;
+                }
+
+                let (_, samples) =
+                    current.get_or_insert_with(|| ((mp3.position(), mp3.elapsed()), 0));
+                if let Some(bytes) = chunk.encoded {
+                    *samples += audio.len();
+                    mp3.write_encoded(audio, bytes)
+                        .await
+                        .context("Failed to write to mp3")?;
+                } else {
+                    // Only the item's first audible chunk fades in; the rest continue it
+                    let ready = if *samples == 0 {
+                        xfade.push(&audio)
+                    } else {
+                        xfade.extend(&audio)
+                    };
+                    *samples += audio.len();
+                    mp3.write_f32_interleaved(ready)
+                        .await
+                        .context("Failed to write to mp3")?;
+                }
+                let Some(timing) = chunk.end else {
+                    continue;
+                };
+                let (start, samples) = current.take().unwrap();
+                if let Some(line) = item.source_line
+                    && item.source_line != last_line
+                {
+                    summary.lines += 1;
+                    last_line = item.source_line;
+                    let mut chars = 0;
+                    for read in lines_through(&mut lines, line)? {
+                        let read_chars = read.text.chars().count();
+                        if let Some(index) = index.as_mut() {
+                            index.note_line(read.number, read_chars);
+                        }
+                        if read.number != line {
+                            summary.skipped += 1;
+                            continue;
+                        }
+                        chars = read_chars as u64;
+                        if timeline.is_some() {
+                            line_texts.insert(line, read.text);
+                        }
+                    }
+                    if let Some(progress) = &self.progress {
+                        progress.add_chars(chars);
+                    }
+                    if let Some(status) = &self.status_file {
+                        status.line_done(chars);
+                    }
+                    if let Some(metrics) = &self.metrics {
+                        metrics.line_done(&labels, chars);
+                    }
+                }
+                if let Some(index) = index.as_mut() {
+                    index.push(
+                        &start,
+                        item.source_line,
+                        item.text.chars().count(),
+                        timing.took,
+                        utils::voice_name(item.voice.unwrap_or(settings.voice)),
+                    )?;
+                }
+                let spoken_end = mp3.elapsed();
+                summary.audio = spoken_end;
+                if self.preview.is_some_and(|preview| spoken_end >= preview) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                self.live.update(mp3.elapsed(), &mp3.position().0);
+                if summary.segments.len() != mp3.segment_paths().len() {
+                    summary.segments = mp3.segment_paths().to_vec();
+                }
+                if let Some(metrics) = &self.metrics {
+                    metrics.set_audio(&labels, mp3.elapsed());
+                    metrics.set_segments(&labels, mp3.segment_paths());
+                }
+                let pause = self.pause_after(&item, chunk.last);
+                if !pause.is_zero() {
+                    flush_crossfade(&mut xfade, &mut mp3).await?;
+                    mp3.write_silence(pause)
+                        .await
+                        .context("Failed to write pause to mp3")?;
+                }
+                if let (Some(timeline), Some(settings), Some(line)) =
+                    (timeline.as_mut(), &self.subtitles, item.source_line)
+                {
+                    let end = if settings.include_gap {
+                        mp3.elapsed()
+                    } else {
+                        spoken_end
+                    };
+                    timeline.record(line, &line_texts[&line], start.0, start.1, end);
+                }
+                let audio_secs = samples as f64 / writer::SAMPLE_RATE as f64;
+                histograms.record(timing.took, audio_secs, timing.queued, timing.cached);
+                if let Some(timings) = &self.timings {
+                    let synth_secs = timing.took.as_secs_f64();
+                    timings.push(timings::LineTiming {
+                        file: file_label.clone(),
+                        index: idx,
+                        source_line: item.source_line,
+                        chars: item.text.chars().count(),
+                        synth_secs,
+                        first_audio_secs: timing.first.as_secs_f64(),
+                        audio_secs,
+                        rtf: synth_secs / audio_secs.max(f64::EPSILON),
+                        queue_secs: timing.queued.as_secs_f64(),
+                        written_secs: file_start.elapsed().as_secs_f64(),
+                        cached: timing.cached,
+                        attempt: timing.attempt,
+                    });
+                }
+                tracing::info!(
+                    "Audio idx {idx} took {:?}, first audio after {:?} (speed {})",
+                    timing.took,
+                    timing.first,
+                    item.speed.unwrap_or(settings.speed)
+                );
+                // A line split into pieces (dialogue and narration voices) is timed
+                // as a whole too, once the next line starts
+                if let Some(line) = item.source_line {
+                    let (took, pieces) = match line_took {
+                        Some((l, took, pieces)) if l == line => (took, pieces),
+                        previous => {
+                            log_line_took(previous);
+                            (Duration::ZERO, 0)
+                        }
+                    };
+                    line_took = Some((line, took + timing.took, pieces + 1));
+                }
+            }
+            anyhow::Ok(())
+        };
+        // Chunks already sent are still written after a synthesis error
+        let (synthesized, written) = tokio::join!(synthesis, write);
+        let result = synthesized.and(written);
+        log_line_took(line_took);
+        histograms.log();
+        if let Some(timings) = &self.timings {
+            timings.push_file(histograms);
+        }
+        if let Err(e) = &result
+            && let Some(failed) = e.downcast_ref::<SynthFailed>()
+        {
+            summary.failed += 1;
+            if let Some(status) = &self.status_file {
+                status.failed();
+            }
+            if let Some(metrics) = &self.metrics {
+                metrics.line_failed(&labels);
+            }
+            let item = &failed.item;
+            let pos = (mp3.position(), mp3.elapsed());
+            if let Some(index) = index.as_mut()
+                && let Err(e) = index.failed(
+                    &pos,
+                    item.source_line,
+                    item.text.chars().count(),
+                    utils::voice_name(item.voice.unwrap_or(settings.voice)),
+                )
+            {
+                tracing::warn!("{:#}", e);
+            }
+        }
+        // The end of the file, only once every item got written
+        let completed = match result {
+            Ok(()) => {
+                async {
+                    if stop.load(Ordering::Relaxed) {
+                        summary.preview_end = last_line;
+                    }
+                    if let Some(hashes) = &self.pcm_hashes {
+                        hashes.finish_file(&file_label)?;
+                    }
+                    // Lines after the last item got none, unless a preview stopped short of them
+                    if !stop.load(Ordering::Relaxed) {
+                        for read in lines {
+                            let read = read?;
+                            if let Some(index) = index.as_mut() {
+                                index.note_line(read.number, read.text.chars().count());
+                            }
+                            summary.skipped += 1;
+                        }
+                    }
+
+                    flush_crossfade(&mut xfade, &mut mp3).await?;
+                    if let Some(index) = index {
+                        index.finish(&(mp3.position(), mp3.elapsed()))?;
+                    }
+                    if let Some(outro) = &self.outro {
+                        mp3.write_f32_interleaved(outro.clone())
+                            .await
+                            .context("Failed to write outro")?;
+                    }
+                    if let Some(music) = &self.music {
+                        mp3.write_music_tail(music.fade)
+                            .await
+                            .context("Failed to write music fade-out")?;
+                    }
+                    anyhow::Ok(())
+                }
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        // However the file ended, the audio written so far is closed properly and
+        // its segments get their sidecars and summary
+        let finalized = async {
+            // A no-op after a complete file
+            flush_crossfade(&mut xfade, &mut mp3).await?;
+            summary.audio = mp3.elapsed();
+            summary.segments = mp3.segment_paths().to_vec();
+            if let Some(metrics) = &self.metrics {
+                metrics.set_audio(&labels, mp3.elapsed());
+                metrics.set_segments(&labels, mp3.segment_paths());
+            }
+            self.live.update(mp3.elapsed(), &mp3.position().0);
+            self.live.finish_file();
+            let levels = mp3
+                .finalize()
+                .await
+                .context("Failed to finalize mp3 write")?;
+            if let Some(child) = pipe_child {
+                child.wait().await?;
+            }
+            for s in &levels {
+                tracing::info!(
+                    "Levels {}: loudness {}, peak {:.1} dBFS, {:.1}% silence",
+                    s.segment,
+                    s.levels
+                        .loudness_db
+                        .map_or("-".to_string(), |db| format!("{db:.1} dBFS")),
+                    s.levels.peak_db,
+                    s.levels.silence_pct
+                );
+            }
+            if let Some(path) = &self.levels_csv {
+                append_levels_csv(path, &levels)?;
+            }
+            if let Some(path) = &self.segments_csv {
+                verify::append_segments(path, &levels)?;
+            }
+            summary.episodes = episodes(&levels, out_dir, txt_path, &chapters);
+            if self.playlist {
+                toc::write_playlist(out_dir, &summary.episodes)?;
+            }
+            if let (Some(timeline), Some(settings)) = (&timeline, &self.subtitles) {
+                let stem = txt_path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("unknown");
+                timeline.write(settings, out_dir, stem, &line_texts)?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        match completed {
+            Ok(()) => finalized,
+            Err(e) => {
+                if let Err(f) = finalized {
+                    tracing::warn!("Failed to finish {} after an error: {:#}", file_label, f);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Read the first `line_count` lines of `txt_path` into a single MP3 at
+    /// `out_path`. Returns the audio duration and the number of spoken chars.
+    pub async fn preview(
+        &self,
+        txt_path: &Path,
+        line_count: usize,
+        out_path: &Path,
+        settings: &FileSettings,
+    ) -> anyhow::Result<(Duration, usize)> {
+        let prepared = self.preprocessor.prepare(txt_path, false)?;
+        let last_line = prepared
+            .lines
+            .get(line_count.min(prepared.lines.len()).saturating_sub(1))
+            .map_or(0, |l| l.number);
+        let mut items = prepared.items;
+        items.retain(|item| item.source_line.is_some_and(|n| n <= last_line));
+        let items = Arc::new(items);
+        let chars = items.iter().map(|item| item.text.chars().count()).sum();
+
+        let mut samples = Vec::new();
+        let label = out_path.display().to_string();
+        let (tx, mut rx) = mpsc::channel::<(usize, Chunk)>(1);
+        let collect = async {
+            while let Some((idx, chunk)) = rx.recv().await {
+                samples.extend_from_slice(&chunk.audio);
+                if chunk.end.is_none() {
+                    continue;
+                }
+                let pause = self.pause_after(&items[idx], idx + 1 == items.len());
+                let frames = (pause.as_secs_f64() * writer::SAMPLE_RATE as f64).round() as usize;
+                samples.resize(samples.len() + frames, 0.0);
+            }
+        };
+        let (synthesized, ()) = tokio::join!(
+            self.synthesize(label, items.clone(), settings, None, tx),
+            collect
+        );
+        synthesized?;
+
+        if let Some(mut low_pass) = self.encoder_quality.low_pass(settings.bitrate) {
+            low_pass.process(&mut samples);
+        }
+        writer::write_mp3(
+            out_path,
+            writer::mp3_config(settings.bitrate, self.channel_mode),
+            &samples,
+        )?;
+        let duration = Duration::from_secs_f64(samples.len() as f64 / writer::SAMPLE_RATE as f64);
+        Ok((duration, chars))
+    }
+
+    /// `--compare-hashes`: synthesize `txt_path` as [`Self::process_file`] would, but
+    /// only hash its audio, and return where it first differs from the earlier run.
+    pub async fn hash_file(
+        &self,
+        txt_path: &Path,
+        folder_mode: bool,
+        settings: &FileSettings,
+    ) -> anyhow::Result<Option<pcm_hash::Divergence>> {
+        let hashes = self.pcm_hashes.as_ref().context("PCM hashing is off")?;
+        let file_label = txt_path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown.txt")
+            .to_string();
+        let stop = AtomicBool::new(false);
+        let (tx, mut rx) = mpsc::channel(1);
+        let (synthesized, ()) = tokio::join!(
+            self.synthesize_until(
+                file_label.clone(),
+                self.stream(txt_path, folder_mode)?,
+                settings,
+                None,
+                &stop,
+                tx,
+            ),
+            async { while rx.recv().await.is_some() {} }
+        );
+        synthesized?;
+        hashes.finish_file(&file_label)
+    }
+
+    /// The items of `txt_path`, read as synthesis gets to them, with the file's chars
+    /// counted in a first pass unless `--no-prescan`.
+    fn stream(
+        &self,
+        txt_path: &Path,
+        folder_mode: bool,
+    ) -> anyhow::Result<ItemSource<preprocess::ItemStream<'_>>> {
+        let items = self.preprocessor.stream(txt_path, folder_mode)?;
+        let chars = self
+            .prescan
+            .then(|| text::prescan(txt_path))
+            .transpose()?
+            .map(|(_, chars)| chars);
+        Ok(ItemSource { items, chars })
+    }
+
+    /// Speaks the part numbers for `--announce-parts`, in the file's voice. A
+    /// single unsplit stream has no parts to announce.
+    fn announcer(&self, settings: &FileSettings) -> Option<writer::Announcer> {
+        if !self.announce_parts || self.stdout {
+            return None;
+        }
+        let engines = self.engine.load();
+        let voice = settings.voice;
+        let speed_voice = utils::change_voice_speed(voice, settings.speed);
+        let pause = (ANNOUNCE_PAUSE.as_secs_f64() * writer::SAMPLE_RATE as f64) as usize;
+        Some(Arc::new(move |part| -> writer::Announcement {
+            let engines = engines.clone();
+            Box::pin(async move {
+                let text = utils::part_announcement(part, voice);
+                let (mut audio, _) = engines
+                    .synth(tts::EngineKind::Primary, text, speed_voice)
+                    .await?;
+                audio.resize(audio.len() + pause, 0.0);
+                Ok(audio)
+            })
+        }))
+    }
+
+    /// Synthesize `items` with bounded concurrency and send their audio to `out`
+    /// strictly in item order, chunk by chunk for streamed lines. With `encoding`
+    /// every chunk also arrives encoded. Closing `out` stops the synthesis early,
+    /// without an error; a small channel keeps the lookahead counted from the chunk
+    /// being consumed.
+    pub async fn synthesize(
+        &self,
+        file_label: String,
+        items: Arc<Vec<text::Item>>,
+        settings: &FileSettings,
+        encoding: Option<Arc<ClipEncoding>>,
+        out: mpsc::Sender<(usize, Chunk)>,
+    ) -> anyhow::Result<()> {
+        let stop = AtomicBool::new(false);
+        let source = ItemSource {
+            items: items.iter().cloned().map(Ok),
+            chars: Some(
+                items
+                    .iter()
+                    .map(|item| item.text.chars().count() as u64)
+                    .sum(),
+            ),
+        };
+        self.synthesize_until(file_label, source, settings, encoding, &stop, out)
+            .await
+    }
+
+    /// [`Self::synthesize`] over items taken from `source` only as far ahead of the
+    /// one being consumed as the producer may schedule, scheduling no further items
+    /// once the consumer sets `stop`; the items already scheduled are still sent.
+    async fn synthesize_until(
+        &self,
+        file_label: String,
+        source: ItemSource<impl Iterator<Item = anyhow::Result<text::Item>>>,
+        settings: &FileSettings,
+        encoding: Option<Arc<ClipEncoding>>,
+        stop: &AtomicBool,
+        out: mpsc::Sender<(usize, Chunk)>,
+    ) -> anyhow::Result<()> {
+        let sem = Arc::new(Semaphore::new(self.lookahead));
+        let scheduled = sem.clone();
+        let (tx, mut rx) = mpsc::channel::<Msg>(self.queue_depth);
+        let (item_tx, mut item_rx) = mpsc::unbounded_channel::<Arc<text::Item>>();
+        // Items written so far, which bounds how far ahead the producer may go, and
+        // whether it should stop
+        let (written_tx, mut written_rx) = watch::channel((0usize, false));
+        let lookahead = self.lookahead;
+
+        // The whole file stays on these engines, even if a reload swaps them meanwhile
+        let tts_engine = self.engine.load();
+        let default_voice = utils::change_voice_speed(settings.voice, settings.speed);
+        let default_speed = settings.speed;
+        let cache = self.cache.clone();
+        let pause_map = self.preprocessor.pause_map.clone().map(Arc::new);
+        let sentence_ends = Arc::new(self.preprocessor.sentence_ends.clone());
+        let finish = ClipFinish {
+            trim: self.trim,
+            pause_cap: self.pause_cap,
+            tempo: self.tempo,
+            trimmed: self.trimmed.clone(),
+            capped: Arc::new(AtomicU64::new(0)),
+        };
+        let capped = finish.capped.clone();
+        let stream_long_lines = finish.keeps_clips() && self.loudness.is_none();
+        let producer_label = file_label.clone();
+        let batch_short_lines = self.batch_short_lines;
+        let tuner = self.tuner.clone();
+        let slots = self.slots.clone();
+        let throttle = self.throttle.clone();
+        // Only cloned into the tasks when dumping
+        let pcm_dump = self
+            .pcm_dump
+            .clone()
+            .map(|d| (d, Arc::<str>::from(file_label.as_str())));
+        let pcm_hashes = self
+            .pcm_hashes
+            .clone()
+            .map(|h| (h, Arc::<str>::from(file_label.as_str())));
+        // Loudness matching measures and scales whole clips, and encoded clips must
+        // not be split mid-line, so they stay in one piece
+        let chunk_samples = if self.loudness.is_some() || encoding.is_some() {
+            usize::MAX
+        } else {
+            CHUNK_SAMPLES
+        };
+
+        let header_span = match &self.progress {
+            Some(parent) => tracing::info_span!(parent: &parent.span, "task"),
+            None => tracing::info_span!("task"),
+        };
+        match source.chars {
+            Some(chars) => {
+                header_span.pb_set_style(
+                    &ProgressStyle::with_template("{spinner} {msg}\n{wide_bar} {pos}/{len} chars")
+                        .unwrap(),
+                );
+                header_span.pb_set_length(chars);
+            }
+            // Without a total (`--no-prescan`) there is nothing to fill a bar towards
+            None => header_span.pb_set_style(
+                &ProgressStyle::with_template("{spinner} {msg} {pos} chars").unwrap(),
+            ),
+        }
+        header_span.pb_set_message(format!("Processing {}", file_label).as_str());
+        header_span.pb_set_finish_message(format!("All items processed ({})", file_label).as_str());
+        let producer_span = header_span.clone();
+
+        let producer: tokio::task::JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
+            let mut set = JoinSet::<anyhow::Result<()>>::new();
+
+            let header_span = producer_span;
+            let header_span_enter = header_span.enter();
+
+            // Items received but not scheduled yet: enough to batch from, and to tell
+            // whether an item is the file's last
+            let mut ahead = VecDeque::<Arc<text::Item>>::new();
+            let mut exhausted = false;
+            let mut item_index = 0;
+            loop {
+                let submitted = Instant::now();
+                let (_, stopped) = *written_rx
+                    .wait_for(|&(written, stop)| stop || item_index < written + lookahead)
+                    .await?;
+                if stopped {
+                    tracing::info!("Stopping {} before item {}", producer_label, item_index);
+                    break;
+                }
+                // The consumer sends items further ahead than this, so these are
+                // already on their way
+                while !exhausted && ahead.len() <= batch_short_lines.unwrap_or(1) {
+                    match item_rx.recv().await {
+                        Some(item) => ahead.push_back(item),
+                        None => exhausted = true,
+                    }
+                }
+                let Some(item) = ahead.front().cloned() else {
+                    break;
+                };
+                let line = item.text.clone();
+                if line.is_empty() {
+                    unreachable!()
+                }
+
+                let permit = sem.clone().acquire_owned().await?;
+                let tx2 = tx.clone();
+                let header_span = header_span.clone();
+                let current_audio_idx = item_index;
+
+                let engine = tts_engine.clone();
+                let voice = match (item.voice, item.speed) {
+                    (None, None) => default_voice,
+                    // Overrides still get the speed applied, per-line or global
+                    (voice, speed) => utils::change_voice_speed(
+                        voice.unwrap_or(default_voice),
+                        speed.unwrap_or(default_speed),
+                    ),
+                };
+
+                let speed = item.speed.unwrap_or(default_speed);
+                let engine_kind = engine.route(item.lang, &line);
+                let voice = match (engine_kind, &engine.english) {
+                    (tts::EngineKind::English, Some(en)) => {
+                        utils::change_voice_speed(en.voice, speed)
+                    }
+                    _ => voice,
+                };
+
+                // Runs of very short lines with the same voice share one synthesis call
+                let batch = batch_short_lines.map_or(1, |size| {
+                    ahead
+                        .iter()
+                        .take(size)
+                        .take_while(|next| {
+                            next.text.chars().count() < BATCH_MAX_CHARS
+                                && next.voice.map(utils::voice_name)
+                                    == item.voice.map(utils::voice_name)
+                                && next.speed == item.speed
+                                && engine.route(next.lang, &next.text) == engine_kind
+                        })
+                        .count()
+                });
+                // The last of them is the file's last if nothing is left after the batch
+                let batch = batch.max(1);
+                let ends = exhausted && ahead.len() == batch;
+                let taken = ahead
+                    .drain(..batch)
+                    .enumerate()
+                    .map(|(offset, item)| Queued {
+                        idx: item_index + offset,
+                        item,
+                        last: ends && offset + 1 == batch,
+                    })
+                    .collect::<Vec<_>>();
+                item_index += batch;
+                if batch >= 2 {
+                    let lines = taken
+                        .iter()
+                        .map(|q| q.item.text.clone())
+                        .collect::<Vec<_>>();
+                    let finish = finish.clone();
+                    let tuner = tuner.clone();
+                    let slots = slots.clone();
+                    let encoding = encoding.clone();
+                    let throttle = throttle.clone();
+                    let dump = pcm_dump.clone();
+                    let hashes = pcm_hashes.clone();
+
+                    set.spawn(async move {
+                        let _permit = permit;
+                        let _slot = match &tuner {
+                            Some(tuner) => Some(tuner.slot().await?),
+                            None => None,
+                        };
+                        let _shared = match &slots {
+                            Some(slots) => Some(slots.acquire().await?),
+                            None => None,
+                        };
+                        let last_idx = current_audio_idx + lines.len() - 1;
+                        let chars = lines.iter().map(|l| l.chars().count()).sum();
+                        tracing::info!(
+                            "Audio idx {}..={} started as one batch",
+                            current_audio_idx,
+                            last_idx
+                        );
+
+                        let start = Instant::now();
+                        let queued = start - submitted;
+                        let joined = lines
+                            .iter()
+                            .map(|l| text::with_sentence_end(l))
+                            .collect::<String>();
+                        let min_gap =
+                            (BATCH_MIN_GAP.as_secs_f64() * writer::SAMPLE_RATE as f64) as usize;
+                        let mut busy = Duration::ZERO;
+                        let batched = match engine.synth(engine_kind, joined, voice).await {
+                            Ok((audio, took)) => {
+                                busy += took;
+                                dsp::split_at_pauses(&audio, lines.len(), BATCH_GAP_THRESHOLD, min_gap)
+                                    .map(|clips| (clips, took))
+                            }
+                            Err(e) => {
+                                tracing::warn!(
+                                    "Batched synthesis of idx {}..={} failed: {}",
+                                    current_audio_idx,
+                                    last_idx,
+                                    e
+                                );
+                                None
+                            }
+                        };
+
+                        match batched {
+                            Some((clips, took)) => {
+                                let share = took / clips.len() as u32;
+                                for (q, audio) in taken.iter().zip(clips) {
+                                    if let Some((d, file)) = &dump
+                                        && d.wants(q.idx)
+                                    {
+                                        let clip = dump::Clip {
+                                            file,
+                                            index: q.idx,
+                                            item: &q.item,
+                                            voice,
+                                            speed,
+                                            engine: engine_kind,
+                                            took: share,
+                                            source: dump::Source::Batch,
+                                        };
+                                        d.save(&clip, &audio);
+                                    }
+                                    if let Some((h, file)) = &hashes {
+                                        h.record(file, q.idx, q.item.source_line, &audio);
+                                    }
+                                    let audio = finish.apply(audio, q.idx);
+                                    let end = Some(Timing {
+                                        took: share,
+                                        first: share,
+                                        queued,
+                                        cached: false,
+                                        attempt: 1,
+                                    });
+                                    send_chunks(
+                                        &tx2,
+                                        q,
+                                        audio,
+                                        chunk_samples,
+                                        encoding.as_ref(),
+                                        end,
+                                    )
+                                    .await;
+                                    header_span.pb_inc(q.item.text.chars().count() as u64);
+                                }
+                            }
+                            None => {
+                                tracing::warn!(
+                                    "Could not split batched audio idx {}..={}, synthesizing the lines one by one",
+                                    current_audio_idx,
+                                    last_idx
+                                );
+                                for (q, line) in taken.iter().zip(lines) {
+                                    match engine.synth(engine_kind, line, voice).await {
+                                        Ok((audio, took)) => {
+                                            busy += took;
+                                            if let Some((d, file)) = &dump
+                                                && d.wants(q.idx)
+                                            {
+                                                let clip = dump::Clip {
+                                                    file,
+                                                    index: q.idx,
+                                                    item: &q.item,
+                                                    voice,
+                                                    speed,
+                                                    engine: engine_kind,
+                                                    took,
+                                                    source: dump::Source::Engine,
+                                                };
+                                                d.save(&clip, &audio);
+                                            }
+                                            if let Some((h, file)) = &hashes {
+                                                h.record(file, q.idx, q.item.source_line, &audio);
+                                            }
+                                            let audio =
+                                                finish.apply(audio, q.idx);
+                                            let end = Some(Timing {
+                                                took,
+                                                first: took,
+                                                queued,
+                                                cached: false,
+                                                attempt: 2,
+                                            });
+                                            send_chunks(
+                                                &tx2,
+                                                q,
+                                                audio,
+                                                chunk_samples,
+                                                encoding.as_ref(),
+                                                end,
+                                            )
+                                            .await;
+                                        }
+                                        Err(e) => {
+                                            let _ = tx2.send((q.idx, Err(q.failed(e)))).await;
+                                            return Ok(());
+                                        }
+                                    }
+                                    header_span.pb_inc(q.item.text.chars().count() as u64);
+                                }
+                            }
+                        }
+                        tracing::info!("Audio idx {}..={} sent to channel", current_audio_idx, last_idx);
+                        if let Some(tuner) = &tuner {
+                            tuner.record(chars, busy);
+                        }
+                        if let Some(throttle) = &throttle {
+                            throttle.idle_after(busy).await;
+                        }
+                        Ok(())
+                    });
+                    continue;
+                }
+
+                let cache = cache.clone();
+                let finish = finish.clone();
+                let pause_map = pause_map.clone();
+                let sentence_ends = sentence_ends.clone();
+                let lang = item.lang;
+                let tuner = tuner.clone();
+                let slots = slots.clone();
+                let encoding = encoding.clone();
+                let throttle = throttle.clone();
+                let dump = pcm_dump.clone();
+                let hashes = pcm_hashes.clone();
+                let q = taken.into_iter().next().unwrap();
+
+                set.spawn(async move {
+                    let _permit = permit;
+                    let _slot = match &tuner {
+                        Some(tuner) => Some(tuner.slot().await?),
+                        None => None,
+                    };
+                    let _shared = match &slots {
+                        Some(slots) => Some(slots.acquire().await?),
+                        None => None,
+                    };
+                    tracing::info!("Audio idx {} started", current_audio_idx);
+
+                    let start = Instant::now();
+                    let queued = start - submitted;
+                    let cached = cache
+                        .as_ref()
+                        .map(|c| (c, c.key(engine_kind, &line, voice, speed)));
+                    let hit = cached.as_ref().and_then(|(c, key)| c.get(key));
+                    let from_cache = hit.is_some();
+                    let mut first = None;
+                    let dump = dump.filter(|(d, _)| d.wants(current_audio_idx));
+                    let save_dump = |audio: &[f32], took, source| {
+                        if let Some((d, file)) = &dump {
+                            let clip = dump::Clip {
+                                file,
+                                index: current_audio_idx,
+                                item: &q.item,
+                                voice,
+                                speed,
+                                engine: engine_kind,
+                                took,
+                                source,
+                            };
+                            d.save(&clip, audio);
+                        }
+                    };
+                    let res: anyhow::Result<Duration> = async {
+                        if let Some(audio) = hit {
+                            save_dump(&audio, start.elapsed(), dump::Source::Cache);
+                            if let Some((h, file)) = &hashes {
+                                h.record(file, current_audio_idx, q.item.source_line, &audio);
+                            }
+                            let audio = finish.apply(audio, current_audio_idx);
+                            let took = start.elapsed();
+                            first = Some(took);
+                            let end = Some(Timing {
+                                took,
+                                first: took,
+                                queued,
+                                cached: true,
+                                attempt: 1,
+                            });
+                            send_chunks(&tx2, &q, audio, chunk_samples, encoding.as_ref(), end)
+                                .await;
+                            return Ok(took);
+                        }
+                        // Long lines go sentence by sentence, each piece sent as soon as it
+                        // is ready; this task sends all of them, so they arrive in order
+                        let pieces = if stream_long_lines && line.chars().count() >= STREAM_CHARS {
+                            text::sentence_pieces(&line, STREAM_PIECE_CHARS, &sentence_ends)
+                        } else {
+                            vec![line.clone()]
+                        };
+                        let count = pieces.len();
+                        let mut raw = Vec::new();
+                        let mut took = Duration::ZERO;
+                        for (seq, piece) in pieces.into_iter().enumerate() {
+                            let join = pause_map
+                                .as_ref()
+                                .filter(|_| seq + 1 < count)
+                                .map_or(Duration::ZERO, |map| map.after(lang, &piece));
+                            let (audio, piece_took) =
+                                engine.synth(engine_kind, piece, voice).await?;
+                            took += piece_took;
+                            let first = *first.get_or_insert(took);
+                            let join =
+                                (join.as_secs_f64() * writer::SAMPLE_RATE as f64).round() as usize;
+                            if cached.is_some() || dump.is_some() || hashes.is_some() {
+                                // Joins included, so a cache hit still has them
+                                raw.extend_from_slice(&audio);
+                                raw.resize(raw.len() + join, 0.0);
+                            }
+                            // The cache keeps the engine's output; trimming and stretching are cheap to redo
+                            let mut audio = finish.apply(audio, current_audio_idx);
+                            // After trimming, so the join is exactly the mapped pause
+                            audio.resize(audio.len() + join, 0.0);
+                            let end = (seq + 1 == count).then_some(Timing {
+                                took,
+                                first,
+                                queued,
+                                cached: false,
+                                attempt: 1,
+                            });
+                            send_chunks(&tx2, &q, audio, chunk_samples, encoding.as_ref(), end)
+                                .await;
+                        }
+                        save_dump(&raw, took, dump::Source::Engine);
+                        if let Some((h, file)) = &hashes {
+                            h.record(file, current_audio_idx, q.item.source_line, &raw);
+                        }
+                        if let Some((c, key)) = &cached
+                            && let Err(e) = c.put(key, &raw)
+                        {
+                            tracing::warn!(
+                                "Failed to cache audio idx {}: {:#}",
+                                current_audio_idx,
+                                e
+                            );
+                        }
+                        Ok(took)
+                    }
+                    .await;
+
+                    match res {
+                        Ok(took) => {
+                            tracing::info!(
+                                "Audio idx {} finished in {:?} (first audio after {:?})",
+                                current_audio_idx,
+                                took,
+                                first.unwrap_or_default()
+                            );
+                            // Cache hits say nothing about how the sessions are doing
+                            if let Some(tuner) = &tuner
+                                && !from_cache
+                            {
+                                tuner.record(line.chars().count(), took);
+                            }
+                            if let Some(throttle) = &throttle {
+                                throttle.idle_after(took).await;
+                            }
+                        }
+                        Err(e) => {
+                            let _ = tx2.send((current_audio_idx, Err(q.failed(e)))).await;
+                        }
+                    }
+                    tracing::info!("Audio idx {} sent to channel", current_audio_idx);
+
+                    header_span.pb_inc(q.item.text.chars().count() as u64);
+                    Ok(())
+                });
+            }
+
+            drop(tx);
+            while let Some(r) = set.join_next().await {
+                r??;
+            }
+            drop(header_span_enter);
+            Ok(())
+        });
+
+        // A caller giving up early (closing `out`, or dropping this future) also
+        // cancels the syntheses still in flight
+        let _abort = AbortOnDrop(producer.abort_handle());
+
+        // Items go to the producer as far ahead of the one being written as it may
+        // schedule, plus a batch and the one after it, so it never waits on them;
+        // the rest of the file stays unread
+        let feed_ahead = lookahead + batch_short_lines.unwrap_or(1) + 1;
+        let mut items = source.items;
+        let mut item_tx = Some(item_tx);
+        let mut fed = 0;
+        let mut feed = |upto: usize| -> anyhow::Result<()> {
+            while fed < upto
+                && let Some(tx) = &item_tx
+            {
+                match items.next().transpose()? {
+                    Some(item) => {
+                        let _ = tx.send(Arc::new(item));
+                        fed += 1;
+                    }
+                    // Closing the channel tells the producer the last item is in
+                    None => item_tx = None,
+                }
+            }
+            Ok(())
+        };
+        feed(feed_ahead)?;
+
+        // The ETA only counts time spent on this file
+        self.throughput.lock().unwrap().resume();
+        let mut remaining_chars = source.chars;
+
+        let mut next_expected: usize = 0;
+        // Chunks of later items wait here until every item before them is written
+        let mut buffer: BTreeMap<usize, VecDeque<Chunk>> = BTreeMap::new();
+
+        loop {
+            let (idx, res) = tokio::select! {
+                msg = rx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                // The consumer is gone; dropping the producer cancels the rest
+                _ = out.closed() => return Ok(()),
+            };
+            let chunk = res?;
+            buffer.entry(idx).or_default().push_back(chunk);
+            if let Some(metrics) = &self.metrics {
+                let concurrency = self.tuner.as_ref().map_or(self.concurrency, |t| t.limit());
+                metrics.set_scheduling(
+                    concurrency,
+                    lookahead - scheduled.available_permits(),
+                    // The item being written isn't waiting on another
+                    buffer.len() - usize::from(buffer.contains_key(&next_expected)),
+                );
+            }
+
+            while let Some(chunk) = buffer.get_mut(&next_expected).and_then(VecDeque::pop_front) {
+                let done = chunk.end.is_some();
+                let item = chunk.item.clone();
+                if out.send((next_expected, chunk)).await.is_err() {
+                    return Ok(());
+                }
+                if !done {
+                    continue;
+                }
+                buffer.remove(&next_expected);
+
+                let chars = item.text.chars().count();
+                let mut throughput = self.throughput.lock().unwrap();
+                throughput.record(chars);
+                let eta = match remaining_chars.as_mut() {
+                    Some(remaining) => {
+                        *remaining = remaining.saturating_sub(chars as u64);
+                        format!("ETA {}", progress::format_eta(throughput.eta(*remaining)))
+                    }
+                    None => "no ETA without a pre-scan".to_string(),
+                };
+                header_span.pb_set_message(&format!(
+                    "Processing {}, {} ({})",
+                    file_label,
+                    eta,
+                    self.live.describe()
+                ));
+                self.live.maybe_log();
+                if let Some(status) = &self.status_file {
+                    status.tick(&self.live, &throughput);
+                }
+                if let Some(progress) = &self.progress {
+                    progress.refresh(&throughput);
+                }
+                drop(throughput);
+                next_expected += 1;
+                feed(next_expected + feed_ahead)?;
+                let _ = written_tx.send((next_expected, stop.load(Ordering::Relaxed)));
+            }
+        }
+
+        let result = match producer.await {
+            Ok(result) => result.context("Failed to finish synth task"),
+            Err(e) => Err(e).context("synth task panicked"),
+        };
+        if self.pause_cap.is_some() {
+            tracing::info!(
+                "Shortened long pauses inside the clips of {} by {:.1}s",
+                file_label,
+                capped.load(Ordering::Relaxed) as f64 / writer::SAMPLE_RATE as f64
+            );
+        }
+        result
+    }
+
+    fn pause_after(&self, item: &text::Item, is_last: bool) -> Duration {
+        // The pause map already gave every item its pause
+        if self.preprocessor.pause_map.is_some() {
+            item.pause_after
+        } else {
+            pause_after(item.pause_after, self.line_gap, self.stack_pauses, is_last)
+        }
+    }
+}
+
+/// Silence following an item: the structural `pause` and the line gap don't stack
+/// unless asked to, and the gap is only used between items.
+fn pause_after(pause: Duration, line_gap: Duration, stack: bool, is_last: bool) -> Duration {
+    if is_last {
+        pause
+    } else if stack {
+        pause + line_gap
+    } else {
+        pause.max(line_gap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAP: Duration = Duration::from_millis(250);
+
+    #[test]
+    fn larger_pause_wins_unless_stacked() {
+        let short = Duration::from_millis(100);
+        let long = Duration::from_millis(600);
+        assert_eq!(pause_after(short, GAP, false, false), GAP);
+        assert_eq!(pause_after(long, GAP, false, false), long);
+        assert_eq!(pause_after(short, GAP, true, false), short + GAP);
+        assert_eq!(pause_after(long, GAP, true, false), long + GAP);
+    }
+
+    #[test]
+    fn no_gap_after_the_last_line() {
+        let pause = Duration::from_millis(100);
+        assert_eq!(pause_after(pause, GAP, false, true), pause);
+        assert_eq!(pause_after(pause, GAP, true, true), pause);
+    }
+
+    #[test]
+    fn zero_gap_keeps_the_structural_pauses() {
+        for pause in [Duration::ZERO, Duration::from_millis(400)] {
+            for stack in [false, true] {
+                assert_eq!(pause_after(pause, Duration::ZERO, stack, false), pause);
+            }
+        }
+    }
+
+    #[test]
+    fn total_is_clips_plus_a_gap_between_lines() {
+        for lines in 1..=12u32 {
+            let clips: Vec<Duration> = (0..lines)
+                .map(|i| Duration::from_millis(300 + 37 * i as u64))
+                .collect();
+            let total: Duration = clips
+                .iter()
+                .enumerate()
+                .map(|(i, clip)| {
+                    *clip + pause_after(Duration::ZERO, GAP, false, i + 1 == clips.len())
+                })
+                .sum();
+            assert_eq!(total, clips.iter().sum::<Duration>() + GAP * (lines - 1));
+        }
+    }
+
+    fn queued() -> Queued {
+        Queued {
+            idx: 3,
+            item: Arc::new(text::Item {
+                text: "你好。".to_string(),
+                source_line: Some(1),
+                chapter: None,
+                pause_after: Duration::ZERO,
+                voice: None,
+                speed: None,
+                lang: crate::lang::Script::Zh,
+            }),
+            last: true,
+        }
+    }
+
+    /// A clip of `len` samples that differ from one another.
+    fn clip(len: usize) -> Vec<f32> {
+        (0..len).map(|i| (i as f32 * 0.01).sin() * 0.5).collect()
+    }
+
+    async fn chunks(audio: Vec<f32>) -> Vec<Chunk> {
+        let (tx, mut rx) = mpsc::channel(16);
+        let end = Timing {
+            took: Duration::from_millis(40),
+            first: Duration::from_millis(40),
+            queued: Duration::ZERO,
+            cached: false,
+            attempt: 1,
+        };
+        send_chunks(&tx, &queued(), audio, CHUNK_SAMPLES, None, Some(end)).await;
+        drop(tx);
+        let mut chunks = Vec::new();
+        while let Some((idx, chunk)) = rx.recv().await {
+            assert_eq!(idx, 3);
+            chunks.push(chunk.unwrap());
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn long_clips_go_in_one_second_chunks() {
+        let audio = clip(CHUNK_SAMPLES * 5 / 2);
+        let chunks = chunks(audio.clone()).await;
+        let lens: Vec<usize> = chunks.iter().map(|c| c.audio.len()).collect();
+        assert_eq!(lens, [CHUNK_SAMPLES, CHUNK_SAMPLES, CHUNK_SAMPLES / 2]);
+        assert_eq!(
+            chunks
+                .iter()
+                .flat_map(|c| c.audio.iter().copied())
+                .collect::<Vec<_>>(),
+            audio
+        );
+        // Only the last chunk ends the item
+        let ends: Vec<bool> = chunks.iter().map(|c| c.end.is_some()).collect();
+        assert_eq!(ends, [false, false, true]);
+        assert!(chunks.iter().all(|c| c.last && c.encoded.is_none()));
+    }
+
+    #[tokio::test]
+    async fn short_clips_go_whole() {
+        let audio = clip(CHUNK_SAMPLES);
+        let chunks = chunks(audio.clone()).await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].audio, audio);
+        assert!(chunks[0].end.is_some());
+    }
+
+    #[tokio::test]
+    async fn chunked_clips_write_the_same_samples() {
+        let clips = [
+            clip(CHUNK_SAMPLES * 5 / 2),
+            clip(CHUNK_SAMPLES / 3),
+            clip(CHUNK_SAMPLES + 7),
+        ];
+        let overlap = 240;
+
+        let mut xfade = dsp::Crossfader::new(overlap);
+        let mut whole = Vec::new();
+        for clip in &clips {
+            whole.extend(xfade.push(clip));
+        }
+        whole.extend(xfade.flush());
+
+        // As the consumer does: the first chunk fades in, the rest continue it
+        let mut xfade = dsp::Crossfader::new(overlap);
+        let mut chunked = Vec::new();
+        for clip in &clips {
+            for (i, chunk) in chunks(clip.clone()).await.iter().enumerate() {
+                chunked.extend(match i {
+                    0 => xfade.push(&chunk.audio),
+                    _ => xfade.extend(&chunk.audio),
+                });
+            }
+        }
+        chunked.extend(xfade.flush());
+
+        assert_eq!(chunked, whole);
+    }
+}
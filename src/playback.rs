@@ -0,0 +1,204 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crate::writer;
+
+/// Audio held back before playback starts, and again after it ran dry, so short
+/// delays in synthesis don't become gaps.
+const PREBUFFER: Duration = Duration::from_millis(500);
+/// Samples the device callback takes per lock.
+const BLOCK: usize = 1024;
+
+#[derive(Default)]
+struct Queue {
+    samples: VecDeque<f32>,
+    playing: bool,
+    /// Nothing more is coming: play out what's left even below the prebuffer.
+    draining: bool,
+    /// Samples dropped because the buffer was full, since the last notice.
+    spilled: u64,
+}
+
+/// `--play`: plays the run's output on the default device while it is written.
+/// The writer pushes its final samples and never waits; when the buffer is full
+/// the audio is left out of playback only, the files still get all of it.
+pub struct LiveOutput {
+    queue: Mutex<Queue>,
+    capacity: usize,
+    stopped: AtomicBool,
+    /// Dropping it closes the output stream's thread.
+    _stop: std::sync::mpsc::Sender<()>,
+}
+
+impl LiveOutput {
+    /// Open the default output device with a buffer of up to `buffer` of audio.
+    pub fn start(buffer: Duration) -> anyhow::Result<Arc<Self>> {
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+        let output = Arc::new(Self {
+            queue: Mutex::new(Queue::default()),
+            capacity: samples(buffer),
+            stopped: AtomicBool::new(false),
+            _stop: stop_tx,
+        });
+
+        // The output stream can't move between threads, so it gets one of its own
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let feed = Feed {
+            output: Arc::downgrade(&output),
+            block: Vec::new(),
+            pos: 0,
+        };
+        std::thread::spawn(move || {
+            let (_stream, handle) = match rodio::OutputStream::try_default() {
+                Ok(output) => output,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e.to_string()));
+                    return;
+                }
+            };
+            if let Err(e) = handle.play_raw(feed) {
+                let _ = ready_tx.send(Err(e.to_string()));
+                return;
+            }
+            let _ = ready_tx.send(Ok(()));
+            // Returns once the sender is dropped
+            let _ = stop_rx.recv();
+        });
+        ready_rx
+            .recv()
+            .context("Audio output thread stopped")?
+            .map_err(|e| anyhow::anyhow!("No audio output for --play: {}", e))?;
+        Ok(output)
+    }
+
+    /// Queue final samples for playback.
+    pub fn push(&self, samples: &[f32]) {
+        if self.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        if queue.samples.len() + samples.len() > self.capacity {
+            if queue.spilled == 0 {
+                tracing::warn!(
+                    "Playback is {:.0}s behind, skipping audio in playback until it catches up (the files are complete)",
+                    self.capacity as f64 / writer::SAMPLE_RATE as f64
+                );
+            }
+            queue.spilled += samples.len() as u64;
+            return;
+        }
+        if queue.spilled > 0 {
+            tracing::info!(
+                "Playback skipped {:.1}s of audio",
+                queue.spilled as f64 / writer::SAMPLE_RATE as f64
+            );
+            queue.spilled = 0;
+        }
+        queue.samples.extend(samples);
+    }
+
+    /// Wait until everything queued has been played.
+    pub async fn drain(&self) {
+        self.queue.lock().unwrap().draining = true;
+        while !self.stopped.load(Ordering::Relaxed)
+            && !self.queue.lock().unwrap().samples.is_empty()
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        // Let the device play out the last block it took
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    /// Stop playing right away; later pushes are ignored.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.queue.lock().unwrap().samples.clear();
+    }
+}
+
+fn samples(duration: Duration) -> usize {
+    (duration.as_secs_f64() * writer::SAMPLE_RATE as f64) as usize * writer::CHANNELS as usize
+}
+
+/// The device side of [`LiveOutput`]: silence while the buffer fills, then the
+/// queued samples, taken a block at a time.
+struct Feed {
+    output: std::sync::Weak<LiveOutput>,
+    block: Vec<f32>,
+    pos: usize,
+}
+
+impl Feed {
+    /// Take the next block, or `false` once playback is over.
+    fn refill(&mut self) -> bool {
+        let Some(output) = self.output.upgrade() else {
+            return false;
+        };
+        if output.stopped.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.block.clear();
+        self.pos = 0;
+        let mut queue = output.queue.lock().unwrap();
+        if !queue.playing
+            && (queue.samples.len() >= samples(PREBUFFER)
+                || (queue.draining && !queue.samples.is_empty()))
+        {
+            queue.playing = true;
+        }
+        if queue.playing && queue.samples.is_empty() {
+            queue.playing = false;
+            if !queue.draining {
+                tracing::info!(
+                    "Playback caught up with synthesis, pausing until more audio is ready"
+                );
+            }
+        }
+        if queue.playing {
+            let n = queue.samples.len().min(BLOCK);
+            self.block.extend(queue.samples.drain(..n));
+        } else {
+            self.block.resize(BLOCK, 0.0);
+        }
+        true
+    }
+}
+
+impl Iterator for Feed {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos == self.block.len() && !self.refill() {
+            return None;
+        }
+        let sample = self.block[self.pos];
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl rodio::Source for Feed {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        writer::CHANNELS as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        writer::SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
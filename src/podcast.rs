@@ -0,0 +1,183 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+/// One MP3 segment as a feed item.
+#[derive(Debug, Clone)]
+pub struct Episode {
+    pub path: String,
+    pub title: String,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeedSettings {
+    pub path: PathBuf,
+    /// Prefix for enclosure URLs; the segment path relative to the run folder is appended.
+    pub base_url: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub image: Option<String>,
+}
+
+/// The config file's `[podcast]` table, for the feed metadata the `--podcast-*`
+/// options don't give.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedMeta {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub image: Option<String>,
+}
+
+/// Write an RSS 2.0 feed with iTunes tags for `episodes`, keeping the items of an
+/// existing feed at the same path unless they are replaced by a new one.
+pub fn write_feed(
+    settings: &FeedSettings,
+    run_dir: &Path,
+    episodes: &[Episode],
+    pub_date: &str,
+) -> anyhow::Result<()> {
+    let mut items = Vec::new();
+    let mut guids = Vec::new();
+    for episode in episodes {
+        let path = Path::new(&episode.path);
+        let relative = path.strip_prefix(run_dir).unwrap_or(path);
+        let url = format!(
+            "{}/{}",
+            settings.base_url.trim_end_matches('/'),
+            relative
+                .iter()
+                .map(|part| url_encode(&part.to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join("/")
+        );
+        let length = std::fs::metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .len();
+
+        let mut item = String::new();
+        writeln!(item, "    <item>")?;
+        writeln!(item, "      <title>{}</title>", escape(&episode.title))?;
+        writeln!(
+            item,
+            "      <enclosure url=\"{}\" length=\"{}\" type=\"audio/mpeg\"/>",
+            escape(&url),
+            length
+        )?;
+        writeln!(
+            item,
+            "      <guid isPermaLink=\"false\">{}</guid>",
+            escape(&url)
+        )?;
+        writeln!(item, "      <pubDate>{}</pubDate>", pub_date)?;
+        writeln!(
+            item,
+            "      <itunes:duration>{}</itunes:duration>",
+            utils::format_hms(episode.duration)
+        )?;
+        write!(item, "    </item>")?;
+        guids.push(format!(
+            "<guid isPermaLink=\"false\">{}</guid>",
+            escape(&url)
+        ));
+        items.push(item);
+    }
+
+    // Earlier runs' items stay, after the new ones
+    let previous = match std::fs::read_to_string(&settings.path) {
+        Ok(xml) => existing_items(&xml)
+            .into_iter()
+            .filter(|item| !guids.iter().any(|g| item.contains(g.as_str())))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", settings.path.display()));
+        }
+    };
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        xml,
+        r#"<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">"#
+    )?;
+    writeln!(xml, "  <channel>")?;
+    writeln!(xml, "    <title>{}</title>", escape(&settings.title))?;
+    writeln!(xml, "    <link>{}</link>", escape(&settings.base_url))?;
+    writeln!(
+        xml,
+        "    <description>{}</description>",
+        escape(settings.description.as_deref().unwrap_or(&settings.title))
+    )?;
+    writeln!(xml, "    <lastBuildDate>{}</lastBuildDate>", pub_date)?;
+    if let Some(author) = &settings.author {
+        writeln!(xml, "    <itunes:author>{}</itunes:author>", escape(author))?;
+    }
+    if let Some(image) = &settings.image {
+        writeln!(xml, "    <itunes:image href=\"{}\"/>", escape(image))?;
+    }
+    for item in items.iter().chain(&previous) {
+        writeln!(xml, "{}", item)?;
+    }
+    writeln!(xml, "  </channel>")?;
+    writeln!(xml, "</rss>")?;
+
+    std::fs::write(&settings.path, xml)
+        .with_context(|| format!("Failed to write {}", settings.path.display()))?;
+    tracing::info!(
+        "Wrote podcast feed {} ({} new, {} kept)",
+        settings.path.display(),
+        items.len(),
+        previous.len()
+    );
+    Ok(())
+}
+
+/// The `<item>` blocks of a feed, verbatim (indentation included).
+fn existing_items(xml: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<item>") {
+        let Some(len) = rest[start..].find("</item>") else {
+            break;
+        };
+        let end = start + len + "</item>".len();
+        let line_start = rest[..start].rfind('\n').map_or(0, |i| i + 1);
+        let indent = &rest[line_start..start];
+        let indent = if indent.trim().is_empty() { indent } else { "" };
+        items.push(format!("{}{}", indent, &rest[start..end]));
+        rest = &rest[end..];
+    }
+    items
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Percent-encode one URL path segment.
+fn url_encode(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            let _ = write!(out, "%{:02X}", b);
+        }
+    }
+    out
+}
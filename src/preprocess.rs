@@ -0,0 +1,641 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+
+use crate::{
+    cast, chapters, dialogue, lang, markup, numbers, pauses, speakers, text, utils, voices,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Announce {
+    #[default]
+    Off,
+    Files,
+    Chapters,
+    Both,
+}
+
+impl Announce {
+    fn files(self) -> bool {
+        matches!(self, Announce::Files | Announce::Both)
+    }
+
+    fn chapters(self) -> bool {
+        matches!(self, Announce::Chapters | Announce::Both)
+    }
+}
+
+/// Where `--alternate-voices` starts over at the first voice.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AlternateReset {
+    /// Each input file starts with the first voice
+    #[default]
+    File,
+    /// Each paragraph (after a blank line) starts with the first voice
+    Paragraph,
+}
+
+/// What to do with lines the loaded models are unlikely to read well.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LangPolicy {
+    Skip,
+    #[default]
+    Warn,
+    Synthesize,
+}
+
+/// Text-side settings: everything needed to turn an input file into synthesis items,
+/// without touching the engine.
+#[derive(Default)]
+pub struct Preprocessor {
+    /// Resolves the voices named in tags and the like.
+    pub voice_names: utils::VoiceNames,
+    pub chapter_detector: Option<chapters::ChapterDetector>,
+    pub voice_tags: Option<markup::VoiceTagSyntax>,
+    pub speakers: Option<speakers::SpeakerMap>,
+    pub speak_speaker_names: bool,
+    pub cast: Option<cast::Cast>,
+    pub cast_debug: bool,
+    /// Voice for anything in quotes, on lines without a tag, speaker or cast character.
+    pub dialogue_voice: Option<Voice>,
+    /// Quotes shorter than this many readable chars stay with the narration.
+    pub dialogue_min_chars: usize,
+    /// Voices taking turns line by line, for lines without a tag or speaker.
+    pub alternate_voices: Vec<Voice>,
+    pub alternate_reset: AlternateReset,
+    pub announce: Announce,
+    pub announce_pause: Duration,
+    pub pause_after_heading: Duration,
+    /// Voice and speed for chapter heading lines, unless the line sets its own.
+    pub heading_voice: Option<Voice>,
+    pub heading_speed: Option<f32>,
+    pub pause_between_paragraphs: Duration,
+    /// Pause after every item by the punctuation it ends on, in place of a fixed gap.
+    pub pause_map: Option<pauses::PauseMap>,
+    pub lang_policy: LangPolicy,
+    /// Minimum share of a line in a language the models speak before the policy applies.
+    pub lang_threshold: f64,
+    /// An English model is loaded alongside the primary (Chinese) one.
+    pub english_model: bool,
+    /// Synthesize lines with nothing to pronounce instead of skipping them.
+    pub keep_unpronounceable: bool,
+    /// Silence standing in for skipped scene-break lines like `＊＊＊`.
+    pub scene_break_pause: Duration,
+    /// Punctuation long lines are cut after for synthesis.
+    pub sentence_ends: text::SentenceEnds,
+    /// How digits are spelled out in the items' text.
+    pub numbers: numbers::NumberStyle,
+}
+
+/// An input file after preprocessing.
+pub struct Prepared {
+    pub lines: Vec<text::SourceLine>,
+    pub chapters: Vec<Arc<chapters::Chapter>>,
+    pub items: Vec<text::Item>,
+}
+
+impl Preprocessor {
+    pub fn prepare(&self, txt_path: &Path, folder_mode: bool) -> anyhow::Result<Prepared> {
+        let mut stream = self.stream(txt_path, folder_mode)?;
+        let items = stream.by_ref().collect::<anyhow::Result<Vec<_>>>()?;
+        let lines = stream.source_lines()?.collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Prepared {
+            lines,
+            chapters: stream.chapters,
+            items,
+        })
+    }
+
+    /// Whether `txt_path` has a chapter heading, reading it up to the first one.
+    pub fn has_chapters(&self, txt_path: &Path) -> anyhow::Result<bool> {
+        let Some(detector) = &self.chapter_detector else {
+            return Ok(false);
+        };
+        for line in text::NonEmptyLines::open(txt_path)? {
+            if detector.title(&line?.text).is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The items of `txt_path` in order, adding announcements where requested, read
+    /// and preprocessed only as far as they are taken.
+    pub fn stream(&self, txt_path: &Path, folder_mode: bool) -> anyhow::Result<ItemStream<'_>> {
+        let lines = text::NonEmptyLines::open(txt_path)
+            .with_context(|| format!("Failed reading lines for {}", txt_path.display()))?;
+        let mut items = VecDeque::new();
+        if folder_mode && self.announce.files() {
+            let intro = text::spoken_file_name(&utils::file_stem_string(txt_path));
+            tracing::info!("Announcing file as \"{}\"", intro);
+            let lang = lang::detect(&intro).script;
+            items.push_back(text::Item {
+                text: intro,
+                source_line: None,
+                chapter: None,
+                pause_after: self.announce_pause,
+                voice: None,
+                speed: None,
+                lang,
+            });
+        }
+        Ok(ItemStream {
+            pre: self,
+            path: txt_path.to_path_buf(),
+            lines,
+            line_count: 0,
+            items,
+            chapters: Vec::new(),
+            turn: 0,
+            bad_tags: Vec::new(),
+            cast_stats: cast::CastStats::default(),
+            lang_counts: BTreeMap::new(),
+            low_confidence: 0,
+            unpronounceable: 0,
+            done: false,
+        })
+    }
+    /// Share of the line in a language one of the loaded models speaks. Routing sends
+    /// a whole line to a single engine, so the better-covered language counts.
+    fn lang_confidence(&self, detection: &lang::Detection) -> f64 {
+        let zh = detection.share(lang::Script::Zh);
+        if self.english_model {
+            zh.max(detection.share(lang::Script::En))
+        } else {
+            zh
+        }
+    }
+
+    /// Resolve a leading `NAME:` against the speaker map, stripping the name unless
+    /// it should be spoken.
+    fn line_speaker<'a>(&self, line: &'a str) -> (Option<speakers::Speaker>, &'a str) {
+        let Some(prefix) = self.speakers.as_ref().and_then(|m| m.take(line)) else {
+            return (None, line);
+        };
+        let text = if self.speak_speaker_names {
+            line
+        } else {
+            prefix.rest
+        };
+        (prefix.speaker, text)
+    }
+
+    fn line_voice<'a>(&self, line: &'a str) -> Result<(Option<Voice>, &'a str), String> {
+        match &self.voice_tags {
+            Some(syntax) => syntax.take_voice(line, &self.voice_names),
+            None => Ok((None, line)),
+        }
+    }
+
+    /// Check every voice referenced by the options and inside the inputs against the
+    /// voice model up front, so a typo fails the run before anything is synthesized.
+    /// All problems are reported together.
+    pub fn validate_inputs(
+        &self,
+        txt_files: &[PathBuf],
+        voice_bin: &voices::VoiceBin,
+        global_voices: &[(&str, Voice)],
+    ) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        let mut check = |origin: &str, voice: Voice| {
+            if let Some(e) = voice_bin.check(voice) {
+                problems.push(format!("{origin}: {e}"));
+            }
+        };
+        for (origin, voice) in global_voices {
+            check(origin, *voice);
+        }
+        for voice in &self.alternate_voices {
+            check("--alternate-voices", *voice);
+        }
+        if let Some(voice) = self.dialogue_voice {
+            check("--dialogue-voice", voice);
+        }
+        if let Some(voice) = self.heading_voice {
+            check("--heading-voice", voice);
+        }
+        for (name, voice) in self.speakers.iter().flat_map(|m| m.voices()) {
+            check(&format!("speaker map {name}"), voice);
+        }
+        if let Some(cast) = &self.cast {
+            if let Some(narrator) = cast.narrator {
+                check("cast narrator", narrator);
+            }
+            for character in &cast.characters {
+                check(&format!("cast {}", character.name), character.voice);
+            }
+        }
+
+        if self.voice_tags.is_none() && self.speakers.is_none() {
+            return report_problems(problems);
+        }
+
+        let mut unmapped = speakers::Unmapped::default();
+        for txt_path in txt_files {
+            let lines = text::NonEmptyLines::open(txt_path)
+                .with_context(|| format!("Failed reading lines for {}", txt_path.display()))?;
+            for line in lines {
+                let line = line?;
+                let rest = match self.line_voice(&line.text) {
+                    Ok((voice, rest)) => {
+                        if let Some(e) = voice.and_then(|v| voice_bin.check(v)) {
+                            problems.push(format!("{}:{}: {e}", txt_path.display(), line.number));
+                        }
+                        rest
+                    }
+                    Err(e) => {
+                        problems.push(format!("{}:{}: {e}", txt_path.display(), line.number));
+                        continue;
+                    }
+                };
+                let (_, rest) = markup::take_speed_directive(rest, line.number);
+                if let Some(prefix) = self.speakers.as_ref().and_then(|m| m.take(rest)) {
+                    unmapped.note(&prefix);
+                }
+            }
+        }
+        unmapped.report();
+
+        report_problems(problems)
+    }
+}
+
+/// The items of one input file, preprocessed a source line at a time as they are
+/// taken, so a file of any size is held only a few lines at a time. The last item
+/// so far is held back until the next one exists, since the lines after it (a
+/// paragraph or scene break, a leading `[break]`) may still lengthen its pause.
+pub struct ItemStream<'a> {
+    pre: &'a Preprocessor,
+    path: PathBuf,
+    lines: text::NonEmptyLines,
+    line_count: usize,
+    /// Items of the lines read so far that haven't been taken yet.
+    items: VecDeque<text::Item>,
+    /// Every heading found so far, including those merged into the previous chapter.
+    pub chapters: Vec<Arc<chapters::Chapter>>,
+    /// Every line takes a turn, tagged or not, so the voices stay in step with the text.
+    turn: usize,
+    bad_tags: Vec<String>,
+    cast_stats: cast::CastStats,
+    lang_counts: BTreeMap<lang::Script, usize>,
+    low_confidence: usize,
+    unpronounceable: usize,
+    done: bool,
+}
+
+impl Iterator for ItemStream<'_> {
+    type Item = anyhow::Result<text::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.items.len() < 2 && !self.done {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.read_line(self.line_count, line);
+                    self.line_count += 1;
+                }
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                None => {
+                    self.done = true;
+                    if let Err(e) = self.report() {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+        // Last, so headings and pauses still see the digits as written
+        self.items.pop_front().map(|mut item| {
+            item.text = numbers::spell_out(&item.text, self.pre.numbers);
+            Ok(item)
+        })
+    }
+}
+
+impl ItemStream<'_> {
+    /// The input's lines from the start, as this stream reads them.
+    pub fn source_lines(&self) -> anyhow::Result<text::NonEmptyLines> {
+        self.lines.reopen()
+    }
+
+    /// Turn the `line_index`th non-empty line into items.
+    fn read_line(&mut self, line_index: usize, line: text::SourceLine) {
+        let pre = self.pre;
+        let chapter = pre
+            .chapter_detector
+            .as_ref()
+            .and_then(|detector| detector.title(&line.text))
+            .map(|title| {
+                let chapter = Arc::new(chapters::Chapter::new(
+                    self.chapters.len() + 1,
+                    title,
+                    line_index,
+                    line.number,
+                ));
+                self.chapters.push(chapter.clone());
+                chapter
+            });
+        if line.paragraph_start && pre.alternate_reset == AlternateReset::Paragraph {
+            self.turn = 0;
+        }
+        let alternate = (!pre.alternate_voices.is_empty())
+            .then(|| pre.alternate_voices[self.turn % pre.alternate_voices.len()]);
+        self.turn += 1;
+
+        // The break belongs to the end of the previous paragraph
+        if line.paragraph_start
+            && let Some(prev) = self.items.back_mut()
+        {
+            prev.pause_after = prev.pause_after.max(pre.pause_between_paragraphs);
+            if let Some(map) = &pre.pause_map {
+                prev.pause_after = prev
+                    .pause_after
+                    .max(map.get(prev.lang, pauses::PauseClass::Paragraph));
+            }
+        }
+
+        let (voice, line_text) = match pre.line_voice(&line.text) {
+            Ok(v) => v,
+            Err(e) => {
+                self.bad_tags.push(format!("line {}: {e}", line.number));
+                (None, line.text.as_str())
+            }
+        };
+        let (speed, line_text) = markup::take_speed_directive(line_text, line.number);
+        let (speaker, line_text) = pre.line_speaker(line_text);
+        // Explicit tags and directives win over the speaker's defaults
+        let voice = voice.or(speaker.map(|s| s.voice));
+        let speed = speed.or(speaker.and_then(|s| s.speed));
+
+        let cast = pre.cast.as_ref().filter(|_| voice.is_none());
+        let character = cast.and_then(|c| {
+            let character = c.attribute(line_text);
+            if line_text
+                .chars()
+                .any(|ch| matches!(ch, '“' | '「' | '『' | '"'))
+            {
+                match character {
+                    Some(character) => {
+                        *self
+                            .cast_stats
+                            .attributed
+                            .entry(character.name.clone())
+                            .or_default() += 1;
+                        if pre.cast_debug {
+                            tracing::info!(
+                                "Cast line {}: {} <= {}",
+                                line.number,
+                                character.name,
+                                line_text
+                            );
+                        }
+                    }
+                    None => self.cast_stats.unattributed += 1,
+                }
+            }
+            character
+        });
+        let narrator = voice.or(alternate).or(cast.and_then(|c| c.narrator));
+
+        let detection = lang::detect(line_text);
+        *self.lang_counts.entry(detection.script).or_default() += 1;
+        let confidence = pre.lang_confidence(&detection);
+        let silent = !pre.keep_unpronounceable && !text::is_pronounceable(line_text);
+        if silent {
+            self.unpronounceable += 1;
+            tracing::warn!(
+                "Line {}: skipping line with nothing to pronounce: {}",
+                line.number,
+                line_text
+            );
+            if text::is_scene_break(line_text)
+                && let Some(prev) = self.items.back_mut()
+            {
+                prev.pause_after = prev.pause_after.max(pre.scene_break_pause);
+            }
+        }
+        let skip = silent
+            || (confidence < pre.lang_threshold && {
+                self.low_confidence += 1;
+                match pre.lang_policy {
+                    LangPolicy::Skip => {
+                        tracing::warn!(
+                            "Line {}: skipping {} line ({:.0}% readable): {}",
+                            line.number,
+                            detection.script.label(),
+                            confidence * 100.0,
+                            line_text
+                        );
+                        true
+                    }
+                    LangPolicy::Warn => {
+                        tracing::warn!(
+                            "Line {}: {} line ({:.0}% readable) may synthesize poorly: {}",
+                            line.number,
+                            detection.script.label(),
+                            confidence * 100.0,
+                            line_text
+                        );
+                        false
+                    }
+                    LangPolicy::Synthesize => false,
+                }
+            });
+        let pieces = if skip {
+            Vec::new()
+        } else {
+            markup::parse(line_text, line.number)
+        };
+
+        let first_item = self.items.len();
+        for piece in pieces {
+            match (piece, character) {
+                (markup::Piece::Text(text), Some(character)) => {
+                    for span in dialogue::split_quotes(&text) {
+                        let (voice, speed) = if span.quoted {
+                            (Some(character.voice), speed.or(character.speed))
+                        } else {
+                            (narrator, speed)
+                        };
+                        self.items.push_back(text::Item {
+                            text: span.text.to_string(),
+                            source_line: Some(line.number),
+                            chapter: None,
+                            pause_after: Duration::ZERO,
+                            voice,
+                            speed,
+                            lang: detection.script,
+                        });
+                    }
+                }
+                (markup::Piece::Text(text), None)
+                    if voice.is_none() && pre.dialogue_voice.is_some() =>
+                {
+                    // Pieces of one line share its source line, so they are counted
+                    // and timed as that line
+                    for span in dialogue::split_quotes_min(&text, pre.dialogue_min_chars) {
+                        self.items.push_back(text::Item {
+                            text: span.text.to_string(),
+                            source_line: Some(line.number),
+                            chapter: None,
+                            pause_after: Duration::ZERO,
+                            voice: if span.quoted {
+                                pre.dialogue_voice
+                            } else {
+                                narrator
+                            },
+                            speed,
+                            lang: detection.script,
+                        });
+                    }
+                }
+                (markup::Piece::Text(text), None) => self.items.push_back(text::Item {
+                    text,
+                    source_line: Some(line.number),
+                    chapter: None,
+                    pause_after: Duration::ZERO,
+                    voice: narrator,
+                    speed,
+                    lang: detection.script,
+                }),
+                // Breaks extend the pause after whatever was spoken before them
+                (markup::Piece::Break(pause), _) => match self.items.back_mut() {
+                    Some(prev) => prev.pause_after += pause,
+                    None => {
+                        tracing::warn!("Line {}: ignoring break before any speech", line.number)
+                    }
+                },
+            }
+        }
+
+        // Longer breaks and structural pauses set below still win
+        if let Some(map) = &pre.pause_map {
+            for item in self.items.range_mut(first_item..) {
+                item.pause_after = item.pause_after.max(map.after(item.lang, &item.text));
+            }
+        }
+
+        let Some(chapter) = chapter else {
+            return;
+        };
+        if self.items.len() == first_item {
+            tracing::warn!(
+                "Line {}: chapter heading has nothing to read, chapter {:03} merged into the previous one",
+                line.number,
+                chapter.number
+            );
+            return;
+        }
+
+        let heading = &mut self.items[first_item];
+        heading.chapter = Some(chapter.clone());
+        // The announced number goes into the heading item itself, so it is read
+        // once and in the heading's voice
+        if pre.announce.chapters() && !chapters::has_chapter_number(&heading.text) {
+            heading.text = format!("第{}章 {}", chapter.number, heading.text);
+        }
+
+        // Tags, directives and speakers on the heading line win, the cast doesn't
+        for item in self.items.range_mut(first_item..) {
+            if voice.is_none() {
+                item.voice = pre.heading_voice.or(item.voice);
+            }
+            if speed.is_none() {
+                item.speed = pre.heading_speed.or(item.speed);
+            }
+        }
+
+        let last = self.items.back_mut().unwrap();
+        if pre.announce.chapters() {
+            last.pause_after = last.pause_after.max(pre.announce_pause);
+        }
+        last.pause_after = last.pause_after.max(pre.pause_after_heading);
+        let pause = last.pause_after;
+        tracing::debug!(
+            "Line {}: heading of chapter {:03} read with voice {}, speed {}, then {:?} of silence",
+            line.number,
+            chapter.number,
+            self.items[first_item]
+                .voice
+                .map_or("(file voice)", utils::voice_name),
+            self.items[first_item]
+                .speed
+                .map_or("(file speed)".to_string(), |s| s.to_string()),
+            pause
+        );
+    }
+
+    /// Sum up the file once every line is read.
+    fn report(&mut self) -> anyhow::Result<()> {
+        let file_label = self
+            .path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown.txt");
+        tracing::info!("Target file {} total {} line", file_label, self.line_count);
+        if self.pre.chapter_detector.is_some() {
+            if self.chapters.is_empty() {
+                tracing::info!(
+                    "No chapter headings matched in {}, processing as a single unit",
+                    file_label
+                );
+            } else {
+                tracing::info!(
+                    "Detected {} chapters in {}",
+                    self.chapters.len(),
+                    file_label
+                );
+            }
+        }
+
+        if self.pre.cast.is_some() {
+            self.cast_stats.report(&self.path.display().to_string());
+        }
+
+        if self.unpronounceable > 0 {
+            tracing::info!(
+                "Skipped {} unpronounceable lines in {}",
+                self.unpronounceable,
+                self.path.display()
+            );
+        }
+
+        let breakdown = self
+            .lang_counts
+            .iter()
+            .map(|(script, n)| format!("{} {}", script.label(), n))
+            .collect::<Vec<_>>()
+            .join(", ");
+        tracing::info!(
+            "Languages in {}: {} ({} below {:.0}% readable)",
+            self.path.display(),
+            breakdown,
+            self.low_confidence,
+            self.pre.lang_threshold * 100.0
+        );
+
+        anyhow::ensure!(
+            self.bad_tags.is_empty(),
+            "Invalid voice tags in {}:\n  {}",
+            self.path.display(),
+            self.bad_tags.join("\n  ")
+        );
+        Ok(())
+    }
+}
+
+fn report_problems(problems: Vec<String>) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        problems.is_empty(),
+        "Invalid voices referenced in the input:\n  {}",
+        problems.join("\n  ")
+    );
+    Ok(())
+}
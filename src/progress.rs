@@ -0,0 +1,320 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use tracing_indicatif::span_ext::IndicatifSpanExt;
+
+use crate::utils;
+
+/// Weight of the newest sample in the throughput average.
+const EWMA_ALPHA: f64 = 0.1;
+
+/// Damped estimate of synthesis throughput in chars per second, fed as results are
+/// written in order.
+#[derive(Debug, Default)]
+pub struct Throughput {
+    /// Chars per second; `None` until the first interval has been measured.
+    rate: Option<f64>,
+    last: Option<Instant>,
+}
+
+impl Throughput {
+    /// Start out at `chars_per_sec`, e.g. from a calibration, instead of without
+    /// an estimate.
+    pub fn calibrated(chars_per_sec: f64) -> Self {
+        Self {
+            rate: Some(chars_per_sec),
+            last: None,
+        }
+    }
+
+    /// Start measuring from now, so idle time before it (between files, model
+    /// warm-up) doesn't count against the rate.
+    pub fn resume(&mut self) {
+        self.last = Some(Instant::now());
+    }
+
+    /// `chars` more have been written since the previous call (or `resume`).
+    pub fn record(&mut self, chars: usize) {
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            let secs = (now - last).as_secs_f64();
+            if secs > 0.0 {
+                let sample = chars as f64 / secs;
+                self.rate = Some(match self.rate {
+                    Some(rate) => rate + EWMA_ALPHA * (sample - rate),
+                    None => sample,
+                });
+            }
+        }
+        self.last = Some(now);
+    }
+
+    /// Time left for `remaining` chars at the current rate.
+    pub fn eta(&self, remaining: u64) -> Option<Duration> {
+        self.rate
+            .filter(|r| *r > 0.0)
+            .map(|r| Duration::from_secs_f64(remaining as f64 / r))
+    }
+}
+
+/// `HH:MM:SS`, or `--:--:--` while there is no estimate yet.
+pub fn format_eta(eta: Option<Duration>) -> String {
+    eta.map_or("--:--:--".to_string(), utils::format_hms)
+}
+
+/// Run-wide progress bar (folder mode) that the per-file bars nest under, counting
+/// source chars as their lines are written.
+pub struct RunProgress {
+    pub span: tracing::Span,
+    /// Unknown without the pre-scan.
+    total_chars: Option<u64>,
+    done_chars: AtomicU64,
+    /// Shown in front of the ETA, e.g. the current file.
+    label: Mutex<String>,
+}
+
+impl RunProgress {
+    pub fn new(span: tracing::Span, total_chars: Option<u64>) -> Self {
+        if let Some(total) = total_chars {
+            span.pb_set_length(total);
+        }
+        Self {
+            span,
+            total_chars,
+            done_chars: AtomicU64::new(0),
+            label: Mutex::new(String::new()),
+        }
+    }
+
+    pub fn set_label(&self, label: String) {
+        *self.label.lock().unwrap() = label;
+    }
+
+    pub fn add_chars(&self, chars: u64) {
+        self.done_chars.fetch_add(chars, Ordering::Relaxed);
+        self.span.pb_inc(chars);
+    }
+
+    /// Jump to `done` chars, e.g. to count a finished file's skipped lines.
+    pub fn set_chars(&self, done: u64) {
+        self.done_chars.store(done, Ordering::Relaxed);
+        self.span.pb_set_position(done);
+    }
+
+    pub fn refresh(&self, throughput: &Throughput) {
+        let Some(total) = self.total_chars else {
+            self.span.pb_set_message(&self.label.lock().unwrap());
+            return;
+        };
+        let remaining = total.saturating_sub(self.done_chars.load(Ordering::Relaxed));
+        self.span.pb_set_message(&format!(
+            "{}, ETA {}",
+            self.label.lock().unwrap(),
+            format_eta(throughput.eta(remaining))
+        ));
+    }
+}
+
+/// Interval between live status lines in the log, for runs without a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Audio produced so far, updated from the writer side and read by the progress
+/// display.
+pub struct LiveStats {
+    start: Instant,
+    /// Audio of the files already finished.
+    finished_ms: AtomicU64,
+    /// Audio of the file being written.
+    current_ms: AtomicU64,
+    segment: Mutex<String>,
+    last_log: Mutex<Instant>,
+}
+
+impl Default for LiveStats {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            finished_ms: AtomicU64::new(0),
+            current_ms: AtomicU64::new(0),
+            segment: Mutex::new(String::new()),
+            last_log: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl LiveStats {
+    /// The current file has `audio` written so far and is writing to `segment`.
+    pub fn update(&self, audio: Duration, segment: &str) {
+        self.current_ms
+            .store(audio.as_millis() as u64, Ordering::Relaxed);
+        let mut current = self.segment.lock().unwrap();
+        if *current != segment {
+            *current = segment.to_string();
+        }
+    }
+
+    /// Fold the current file's audio into the finished total.
+    pub fn finish_file(&self) {
+        let current = self.current_ms.swap(0, Ordering::Relaxed);
+        self.finished_ms.fetch_add(current, Ordering::Relaxed);
+    }
+
+    pub fn audio(&self) -> Duration {
+        Duration::from_millis(
+            self.finished_ms.load(Ordering::Relaxed) + self.current_ms.load(Ordering::Relaxed),
+        )
+    }
+
+    /// e.g. `01:02:03 audio, audio_003.mp3, 4.2x realtime`
+    pub fn describe(&self) -> String {
+        let audio = self.audio();
+        let segment = self.segment.lock().unwrap();
+        let segment = std::path::Path::new(segment.as_str())
+            .file_name()
+            .map_or(String::new(), |s| s.to_string_lossy().into_owned());
+        format!(
+            "{} audio, {}, {:.1}x realtime",
+            utils::format_hms(audio),
+            segment,
+            audio.as_secs_f64() / self.start.elapsed().as_secs_f64().max(f64::EPSILON)
+        )
+    }
+
+    /// Log `describe` every `LOG_INTERVAL`, since the bars don't reach the log file.
+    pub fn maybe_log(&self) {
+        let mut last = self.last_log.lock().unwrap();
+        if last.elapsed() >= LOG_INTERVAL {
+            *last = Instant::now();
+            tracing::info!("Progress: {}", self.describe());
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+struct Snapshot {
+    /// `running`, then `finished`, `failed` or `interrupted`.
+    status: &'static str,
+    started_at: String,
+    updated_at: String,
+    current_file: String,
+    lines_done: u64,
+    lines_total: u64,
+    chars_done: u64,
+    chars_total: u64,
+    audio_secs: f64,
+    current_segment: String,
+    failures: u64,
+    eta_secs: Option<f64>,
+}
+
+/// `progress.json` for watching a run from outside, replaced atomically at most
+/// every `interval` (and once more with the final status).
+pub struct StatusFile {
+    path: PathBuf,
+    interval: Duration,
+    snapshot: Mutex<Snapshot>,
+    last_write: Mutex<Option<Instant>>,
+}
+
+impl StatusFile {
+    pub fn new(path: PathBuf, interval: Duration, lines_total: u64, chars_total: u64) -> Self {
+        let now = chrono::Local::now().to_rfc3339();
+        Self {
+            path,
+            interval,
+            snapshot: Mutex::new(Snapshot {
+                status: "running",
+                started_at: now.clone(),
+                updated_at: now,
+                lines_total,
+                chars_total,
+                ..Default::default()
+            }),
+            last_write: Mutex::new(None),
+        }
+    }
+
+    pub fn set_file(&self, file: &str) {
+        self.snapshot.lock().unwrap().current_file = file.to_string();
+    }
+
+    /// A source line of `chars` chars has been written.
+    pub fn line_done(&self, chars: u64) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.lines_done += 1;
+        snapshot.chars_done += chars;
+    }
+
+    /// Jump to absolute counts, e.g. at the end of a file to include its skipped lines.
+    pub fn set_done(&self, lines: u64, chars: u64) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.lines_done = lines;
+        snapshot.chars_done = chars;
+    }
+
+    pub fn failed(&self) {
+        self.snapshot.lock().unwrap().failures += 1;
+    }
+
+    /// Lines written so far, and the total.
+    pub fn lines(&self) -> (u64, u64) {
+        let snapshot = self.snapshot.lock().unwrap();
+        (snapshot.lines_done, snapshot.lines_total)
+    }
+
+    /// Refresh from the live stats and write if the interval has passed.
+    pub fn tick(&self, live: &LiveStats, throughput: &Throughput) {
+        {
+            let mut last = self.last_write.lock().unwrap();
+            if last.is_some_and(|t| t.elapsed() < self.interval) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.audio_secs = live.audio().as_secs_f64();
+        snapshot.current_segment = live.segment.lock().unwrap().clone();
+        // A zero total is one nobody counted (`--no-prescan`)
+        snapshot.eta_secs = throughput
+            .eta(snapshot.chars_total.saturating_sub(snapshot.chars_done))
+            .filter(|_| snapshot.chars_total > 0)
+            .map(|d| d.as_secs_f64());
+        self.write(&mut snapshot);
+    }
+
+    /// Final write with a terminal `status`.
+    pub fn finish(&self, status: &'static str, live: &LiveStats) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.status = status;
+        snapshot.audio_secs = live.audio().as_secs_f64();
+        snapshot.eta_secs = (status == "finished").then_some(0.0);
+        self.write(&mut snapshot);
+    }
+
+    fn write(&self, snapshot: &mut Snapshot) {
+        snapshot.updated_at = chrono::Local::now().to_rfc3339();
+        // Write then rename, so readers never see a half-written file
+        let tmp = self.path.with_extension("json.tmp");
+        let res = serde_json::to_vec_pretty(&*snapshot)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                std::fs::write(&tmp, json)
+                    .with_context(|| format!("Failed to write {}", tmp.display()))
+            })
+            .and_then(|_| {
+                std::fs::rename(&tmp, &self.path)
+                    .with_context(|| format!("Failed to replace {}", self.path.display()))
+            });
+        if let Err(e) = res {
+            tracing::warn!("{:#}", e);
+        }
+    }
+}
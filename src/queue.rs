@@ -0,0 +1,455 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{Notify, oneshot};
+
+use crate::{pipeline, progress, serve, summary, text, utils};
+
+/// Each job's folder holds its record, its text and then its audio.
+const RECORD: &str = "job.json";
+const INPUT: &str = "input.txt";
+/// Interval between writes of a running job's `progress.json`.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Queued => "queued",
+            State::Running => "running",
+            State::Done => "done",
+            State::Failed => "failed",
+            State::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// What is kept about a job, in `<id>/job.json` under the queue folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    id: u64,
+    state: State,
+    voice: String,
+    speed: f32,
+    bitrate: u32,
+    created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    finished_at: Option<String>,
+    /// MP3 segments, relative to the job's folder.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audio_secs: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The job being worked on.
+struct Running {
+    id: u64,
+    cancel: Option<oneshot::Sender<()>>,
+    /// Set once the job's text is read; its status file has the line counts.
+    pipeline: Option<Arc<pipeline::Pipeline>>,
+}
+
+struct Inner {
+    next_id: u64,
+    jobs: BTreeMap<u64, Record>,
+    queued: VecDeque<u64>,
+    running: Option<Running>,
+}
+
+/// Why [`JobQueue::submit`] turned a job down.
+pub enum SubmitError {
+    /// `--max-queued-jobs` are already waiting.
+    Full(usize),
+    Io(anyhow::Error),
+}
+
+/// What [`JobQueue::cancel`] did.
+pub enum Cancelled {
+    /// The job was waiting and won't run.
+    Queued,
+    /// The job was running and is being stopped.
+    Running,
+    /// The job was over; it and its audio are gone.
+    Removed,
+}
+
+/// `POST /jobs`: texts synthesized one after the other into a folder each, with
+/// their state on disk so a restart picks the waiting ones up again.
+pub struct JobQueue {
+    dir: PathBuf,
+    max_queued: usize,
+    inner: Mutex<Inner>,
+    wake: Notify,
+}
+
+impl JobQueue {
+    /// Open the queue in `dir`, taking back the jobs a previous server left there.
+    /// Waiting jobs wait again; a job that was running when the server stopped is
+    /// marked failed, as its audio is incomplete.
+    pub fn open(dir: PathBuf, max_queued: usize) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let mut jobs = BTreeMap::new();
+        for entry in
+            std::fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let path = entry?.path().join(RECORD);
+            if !path.is_file() {
+                continue;
+            }
+            match std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_slice::<Record>(&json)?))
+            {
+                Ok(record) => {
+                    jobs.insert(record.id, record);
+                }
+                Err(e) => tracing::warn!("Skipping job {}: {:#}", path.display(), e),
+            }
+        }
+        let queued = jobs
+            .values()
+            .filter(|record| record.state == State::Queued)
+            .map(|record| record.id)
+            .collect::<VecDeque<_>>();
+        let queue = Self {
+            dir,
+            max_queued,
+            inner: Mutex::new(Inner {
+                next_id: jobs.keys().next_back().map_or(1, |id| id + 1),
+                jobs,
+                queued,
+                running: None,
+            }),
+            wake: Notify::new(),
+        };
+        let mut inner = queue.inner.lock().unwrap();
+        for record in inner.jobs.values_mut() {
+            if record.state == State::Running {
+                tracing::warn!("Job {} was interrupted by a server shutdown", record.id);
+                record.state = State::Failed;
+                record.error = Some("Interrupted by a server shutdown".to_string());
+                record.finished_at = Some(now());
+                queue.save(record);
+            }
+        }
+        if !inner.queued.is_empty() {
+            tracing::info!("{} jobs are still queued", inner.queued.len());
+        }
+        drop(inner);
+        Ok(queue)
+    }
+
+    /// Queue `text` to be read with `settings`, returning the job's id.
+    pub fn submit(
+        &self,
+        text: &str,
+        settings: &pipeline::FileSettings,
+    ) -> Result<u64, SubmitError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.queued.len() >= self.max_queued {
+            return Err(SubmitError::Full(self.max_queued));
+        }
+        let id = inner.next_id;
+        let job_dir = self.dir.join(id.to_string());
+        std::fs::create_dir_all(&job_dir)
+            .and_then(|_| std::fs::write(job_dir.join(INPUT), text))
+            .with_context(|| format!("Failed to save job {} in {}", id, job_dir.display()))
+            .map_err(SubmitError::Io)?;
+        let record = Record {
+            id,
+            state: State::Queued,
+            voice: utils::voice_name(settings.voice).to_string(),
+            speed: settings.speed,
+            bitrate: settings.bitrate,
+            created_at: now(),
+            finished_at: None,
+            segments: Vec::new(),
+            audio_secs: None,
+            error: None,
+        };
+        self.save(&record);
+        inner.next_id += 1;
+        inner.jobs.insert(id, record);
+        inner.queued.push_back(id);
+        drop(inner);
+        self.wake.notify_one();
+        Ok(id)
+    }
+
+    /// `GET /jobs/{id}`: the job's state, with its place in the queue, its
+    /// progress, its audio or its error.
+    pub fn status(&self, id: u64) -> Option<serde_json::Value> {
+        let inner = self.inner.lock().unwrap();
+        let record = inner.jobs.get(&id)?;
+        let mut status = json!({
+            "id": id,
+            "state": record.state,
+            "voice": record.voice,
+            "speed": record.speed,
+            "bitrate": record.bitrate,
+            "created_at": record.created_at,
+        });
+        match record.state {
+            State::Queued => {
+                status["position"] = inner.queued.iter().position(|&q| q == id).into();
+            }
+            State::Running => {
+                if let Some(pipeline) = inner
+                    .running
+                    .as_ref()
+                    .and_then(|running| running.pipeline.as_ref())
+                {
+                    let (done, total) = pipeline
+                        .status_file
+                        .as_ref()
+                        .map_or((0, 0), |status| status.lines());
+                    status["lines_done"] = done.into();
+                    status["lines_total"] = total.into();
+                    status["audio_secs"] = pipeline.live.audio().as_secs_f64().into();
+                }
+            }
+            State::Done => {
+                status["audio_secs"] = record.audio_secs.into();
+                status["files"] = record
+                    .segments
+                    .iter()
+                    .enumerate()
+                    .map(|(n, segment)| {
+                        json!({
+                            "path": self.dir.join(id.to_string()).join(segment),
+                            "url": format!("/jobs/{}/download?segment={}", id, n),
+                        })
+                    })
+                    .collect();
+            }
+            State::Failed => status["error"] = record.error.clone().into(),
+            State::Cancelled => {}
+        }
+        if let Some(finished_at) = &record.finished_at {
+            status["finished_at"] = finished_at.clone().into();
+        }
+        Some(status)
+    }
+
+    /// The state of job `id`, with the path of its segment `n` if it's done and
+    /// has that many.
+    pub fn segment(&self, id: u64, n: usize) -> Option<(State, Option<PathBuf>)> {
+        let inner = self.inner.lock().unwrap();
+        let record = inner.jobs.get(&id)?;
+        let path = record
+            .segments
+            .get(n)
+            .filter(|_| record.state == State::Done)
+            .map(|segment| self.dir.join(id.to_string()).join(segment));
+        Some((record.state, path))
+    }
+
+    /// `DELETE /jobs/{id}`: stop a waiting or running job, or remove one that is
+    /// over along with its audio.
+    pub fn cancel(&self, id: u64) -> Option<anyhow::Result<Cancelled>> {
+        let mut inner = self.inner.lock().unwrap();
+        let state = inner.jobs.get(&id)?.state;
+        Some(match state {
+            State::Queued => {
+                inner.queued.retain(|&q| q != id);
+                let record = inner.jobs.get_mut(&id).unwrap();
+                record.state = State::Cancelled;
+                record.finished_at = Some(now());
+                self.save(record);
+                Ok(Cancelled::Queued)
+            }
+            State::Running => {
+                // The worker records the outcome once the pipeline has stopped
+                if let Some(cancel) = inner
+                    .running
+                    .as_mut()
+                    .filter(|running| running.id == id)
+                    .and_then(|running| running.cancel.take())
+                {
+                    let _ = cancel.send(());
+                }
+                Ok(Cancelled::Running)
+            }
+            State::Done | State::Failed | State::Cancelled => {
+                let job_dir = self.dir.join(id.to_string());
+                std::fs::remove_dir_all(&job_dir)
+                    .with_context(|| format!("Failed to remove {}", job_dir.display()))
+                    .map(|()| {
+                        inner.jobs.remove(&id);
+                        Cancelled::Removed
+                    })
+            }
+        })
+    }
+
+    /// Wait for the next queued job and mark it running.
+    async fn next(&self) -> (Record, oneshot::Receiver<()>) {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(id) = inner.queued.pop_front() {
+                    let (tx, rx) = oneshot::channel();
+                    inner.running = Some(Running {
+                        id,
+                        cancel: Some(tx),
+                        pipeline: None,
+                    });
+                    let record = inner.jobs.get_mut(&id).unwrap();
+                    record.state = State::Running;
+                    self.save(record);
+                    return (record.clone(), rx);
+                }
+            }
+            self.wake.notified().await;
+        }
+    }
+
+    fn set_pipeline(&self, pipeline: Arc<pipeline::Pipeline>) {
+        if let Some(running) = self.inner.lock().unwrap().running.as_mut() {
+            running.pipeline = Some(pipeline);
+        }
+    }
+
+    /// Record how the running job ended.
+    fn finish(&self, id: u64, update: impl FnOnce(&mut Record)) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.running = None;
+        if let Some(record) = inner.jobs.get_mut(&id) {
+            update(record);
+            record.finished_at = Some(now());
+            self.save(record);
+        }
+    }
+
+    /// Write `record` to its job's folder. A failure only costs the record after a
+    /// restart, so it's logged rather than failing the job.
+    fn save(&self, record: &Record) {
+        let path = self.dir.join(record.id.to_string()).join(RECORD);
+        // Write then rename, so a crash never leaves half a record
+        let tmp = path.with_extension("json.tmp");
+        let res = serde_json::to_vec_pretty(record)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                std::fs::write(&tmp, json)
+                    .with_context(|| format!("Failed to write {}", tmp.display()))
+            })
+            .and_then(|_| {
+                std::fs::rename(&tmp, &path)
+                    .with_context(|| format!("Failed to replace {}", path.display()))
+            });
+        if let Err(e) = res {
+            tracing::warn!("{:#}", e);
+        }
+    }
+}
+
+/// Run queued jobs one at a time, each through the batch pipeline on the server's
+/// engines and synthesis slots, so requests and jobs share the sessions.
+pub async fn work(server: Arc<serve::Server>) {
+    let Some(queue) = server.queue() else {
+        return;
+    };
+    loop {
+        let (record, cancel) = queue.next().await;
+        let id = record.id;
+        tracing::info!("Job {}: started", id);
+        let job_dir = queue.dir.join(id.to_string());
+        let mut file = summary::FileSummary::new(job_dir.join(INPUT).display().to_string());
+        let cancel = async {
+            let _ = cancel.await;
+        };
+        match run(&server, queue, &record, &job_dir, &mut file, cancel).await {
+            Err(e) if e.is::<pipeline::Cancelled>() => {
+                tracing::info!("Job {}: cancelled", id);
+                queue.finish(id, |record| record.state = State::Cancelled);
+            }
+            Ok(()) => {
+                tracing::info!("Job {}: done", id);
+                let segments = file
+                    .segments
+                    .iter()
+                    .map(|segment| {
+                        Path::new(segment)
+                            .strip_prefix(&job_dir)
+                            .unwrap_or(Path::new(segment))
+                            .to_path_buf()
+                    })
+                    .collect();
+                queue.finish(id, |record| {
+                    record.state = State::Done;
+                    record.segments = segments;
+                    record.audio_secs = Some(file.audio.as_secs_f64());
+                });
+            }
+            Err(e) => {
+                tracing::error!("Job {}: {:#}", id, e);
+                queue.finish(id, |record| {
+                    record.state = State::Failed;
+                    record.error = Some(format!("{:#}", e));
+                });
+            }
+        }
+    }
+}
+
+async fn run(
+    server: &serve::Server,
+    queue: &JobQueue,
+    record: &Record,
+    job_dir: &Path,
+    file: &mut summary::FileSummary,
+    cancel: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let settings = pipeline::FileSettings {
+        voice: utils::parse_voice(&record.voice).map_err(|e| anyhow::anyhow!("{}", e))?,
+        speed: record.speed,
+        bitrate: record.bitrate,
+    };
+    let input = job_dir.join(INPUT);
+    let (lines, chars) = text::prescan(&input)?;
+    let mut pipeline = server.job_pipeline();
+    pipeline.status_file = Some(progress::StatusFile::new(
+        job_dir.join("progress.json"),
+        PROGRESS_INTERVAL,
+        lines,
+        chars,
+    ));
+    let pipeline = Arc::new(pipeline);
+    queue.set_pipeline(pipeline.clone());
+    let result = pipeline
+        .process_file(&input, job_dir, false, &settings, file, cancel)
+        .await;
+    if let Some(status) = &pipeline.status_file {
+        let state = match &result {
+            Ok(()) => "finished",
+            Err(e) if e.is::<pipeline::Cancelled>() => "cancelled",
+            Err(_) => "failed",
+        };
+        status.finish(state, &pipeline.live);
+    }
+    result
+}
+
+fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
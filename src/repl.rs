@@ -0,0 +1,262 @@
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use rustyline::error::ReadlineError;
+use tokio::sync::oneshot;
+
+use crate::{lang, tts, utils, voices, writer};
+
+const HELP: &str = "\
+Type text to hear it. Commands:
+  :voice NAME     switch voice, e.g. :voice zf_002
+  :speed N        set the speed, 0.5 to 2.0
+  :save on|off    also write each clip as an MP3
+  :help           show this
+  :quit           leave (Ctrl-D works too)";
+
+#[derive(clap::Args)]
+pub struct ReplArgs {
+    /// Path for onnx tts model
+    #[arg(long, short, default_value = "kokoro-v1.1-zh.onnx")]
+    tts_model: String,
+
+    /// Path for voice bin model
+    #[arg(long, short, default_value = "voices-v1.1-zh.bin")]
+    voice_model: String,
+
+    /// Voice to start with
+    #[arg(long, default_value = "zf_048")]
+    voice: String,
+
+    /// Speed to start with
+    #[arg(long, value_parser = utils::parse_speed, default_value_t = 1.0)]
+    speed: f32,
+
+    /// Start with saving on
+    #[arg(long)]
+    save: bool,
+
+    /// Folder for saved clips
+    #[arg(long, default_value = "repl")]
+    out_dir: PathBuf,
+
+    /// Keep the prompt history in this file across sessions
+    #[arg(long)]
+    history: Option<PathBuf>,
+
+    /// Append everything spoken, with its voice and speed, to this file
+    #[arg(long)]
+    transcript: Option<PathBuf>,
+
+    /// Don't play anything, e.g. without an audio device
+    #[arg(long)]
+    no_playback: bool,
+}
+
+/// Settings changed with `:` commands.
+struct State {
+    voice: Voice,
+    speed: f32,
+    save: bool,
+    saved: usize,
+}
+
+pub async fn run(args: &ReplArgs) -> anyhow::Result<()> {
+    let voice_bin = voices::VoiceBin::load(Path::new(&args.voice_model))?;
+    let voice = utils::parse_voice(&args.voice).map_err(|e| anyhow::anyhow!("--voice: {}", e))?;
+    if let Some(e) = voice_bin.check(voice) {
+        anyhow::bail!("--voice: {}", e);
+    }
+    let player = if args.no_playback {
+        None
+    } else {
+        Some(Player::new()?)
+    };
+
+    println!("Loading {}...", args.tts_model);
+    let engine = tts::init_tts(args.tts_model.clone(), args.voice_model.clone(), 1).await?;
+    let engines = Arc::new(tts::Engines::new(engine, None));
+    tts::warm_up(engines.clone(), voice, 1).await?;
+
+    let mut editor = rustyline::DefaultEditor::new()?;
+    if let Some(path) = &args.history
+        && path.exists()
+        && let Err(e) = editor.load_history(path)
+    {
+        eprintln!("Could not load history from {}: {}", path.display(), e);
+    }
+    let mut state = State {
+        voice,
+        speed: args.speed,
+        save: args.save,
+        saved: 0,
+    };
+    println!("{}", HELP);
+
+    loop {
+        let prompt = format!("{} x{}> ", utils::voice_name(state.voice), state.speed);
+        // Reading blocks, which the multi-threaded runtime can take on this thread
+        let line = match tokio::task::block_in_place(|| editor.readline(&prompt)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+
+        if let Some(command) = line.strip_prefix(':') {
+            let (name, value) = command
+                .split_once(char::is_whitespace)
+                .map_or((command, ""), |(name, value)| (name, value.trim()));
+            match name {
+                "voice" => match utils::parse_voice(value) {
+                    Ok(voice) => match voice_bin.check(voice) {
+                        Some(e) => eprintln!("{}", e),
+                        None => state.voice = voice,
+                    },
+                    Err(e) => eprintln!("{}", e),
+                },
+                "speed" => match utils::parse_speed(value) {
+                    Ok(speed) => state.speed = speed,
+                    Err(e) => eprintln!("{}", e),
+                },
+                "save" => match value {
+                    "on" => {
+                        state.save = true;
+                        println!("Saving clips to {}", args.out_dir.display());
+                    }
+                    "off" => state.save = false,
+                    _ => eprintln!("Use :save on or :save off"),
+                },
+                "help" => println!("{}", HELP),
+                "quit" | "q" | "exit" => break,
+                _ => eprintln!("Unknown command :{} (see :help)", name),
+            }
+            continue;
+        }
+
+        // Errors go back to the prompt; only the terminal itself ends the session
+        if let Err(e) = speak(args, &engines, player.as_ref(), &mut state, line).await {
+            eprintln!("{:#}", e);
+        }
+    }
+
+    if let Some(path) = &args.history
+        && let Err(e) = editor.save_history(path)
+    {
+        eprintln!("Could not save history to {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+async fn speak(
+    args: &ReplArgs,
+    engines: &tts::Engines,
+    player: Option<&Player>,
+    state: &mut State,
+    line: &str,
+) -> anyhow::Result<()> {
+    let kind = engines.route(lang::detect(line).script, line);
+    let voice = utils::change_voice_speed(state.voice, state.speed);
+    let (audio, took) = engines.synth(kind, line.to_string(), voice).await?;
+    let secs = audio.len() as f64 / writer::SAMPLE_RATE as f64;
+    println!("{:.2}s of audio in {:.2}s", secs, took.as_secs_f64());
+
+    let saved = if state.save {
+        std::fs::create_dir_all(&args.out_dir)
+            .with_context(|| format!("Failed to create {}", args.out_dir.display()))?;
+        state.saved += 1;
+        let path = args.out_dir.join(format!(
+            "{:03}_{}.mp3",
+            state.saved,
+            utils::voice_name(state.voice)
+        ));
+        writer::write_mp3(&path, writer::default_mono_24k_config(64), &audio)?;
+        println!("Saved {}", path.display());
+        Some(path)
+    } else {
+        None
+    };
+    if let Some(path) = &args.transcript {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            utils::voice_name(state.voice),
+            state.speed,
+            saved.map_or(String::new(), |p| p.display().to_string()),
+            line
+        )
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    if let Some(player) = player {
+        player.play(audio).await;
+    }
+    Ok(())
+}
+
+/// Plays clips on the default output device. The output stream can't move between
+/// threads, so it lives on a thread of its own and takes clips over a channel.
+struct Player {
+    tx: std::sync::mpsc::Sender<(Vec<f32>, oneshot::Sender<()>)>,
+}
+
+impl Player {
+    fn new() -> anyhow::Result<Self> {
+        let (tx, rx) = std::sync::mpsc::channel::<(Vec<f32>, oneshot::Sender<()>)>();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let (_stream, handle) = match rodio::OutputStream::try_default() {
+                Ok(output) => output,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e.to_string()));
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+            for (samples, done) in rx {
+                match rodio::Sink::try_new(&handle) {
+                    Ok(sink) => {
+                        sink.append(rodio::buffer::SamplesBuffer::new(
+                            writer::CHANNELS as u16,
+                            writer::SAMPLE_RATE,
+                            samples,
+                        ));
+                        sink.sleep_until_end();
+                    }
+                    Err(e) => eprintln!("Playback failed: {}", e),
+                }
+                let _ = done.send(());
+            }
+        });
+        ready_rx
+            .recv()
+            .context("Audio output thread stopped")?
+            .map_err(|e| {
+                anyhow::anyhow!("No audio output ({}); pass --no-playback to go without", e)
+            })?;
+        Ok(Self { tx })
+    }
+
+    /// Play `samples` and wait until they have been heard.
+    async fn play(&self, samples: Vec<f32>) {
+        let (done, heard) = oneshot::channel();
+        if self.tx.send((samples, done)).is_ok() {
+            let _ = heard.await;
+        }
+    }
+}
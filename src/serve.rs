@@ -0,0 +1,1140 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, tcp::OwnedReadHalf},
+    sync::{OnceCell, Semaphore, mpsc},
+};
+
+use crate::{
+    grpc, jobs, lang, lock, pipeline, preprocess, queue, text, tts, utils, voices, writer,
+};
+
+/// Largest request head (request line and headers).
+const MAX_HEAD: usize = 16 * 1024;
+/// Silence between the lines of a request's text.
+const LINE_PAUSE: Duration = Duration::from_millis(300);
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+/// MP3 bitrates the encoder supports at 24 kHz.
+const BITRATES: &[u32] = &[8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+#[derive(clap::Args)]
+pub struct ServeArgs {
+    /// Address to serve HTTP on [default: 127.0.0.1:8080, unless only --listen-socket or
+    /// --listen-grpc is given]
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+
+    /// Also serve the gRPC Synthesis service (proto/morganite.proto) on this address
+    #[arg(long)]
+    listen_grpc: Option<SocketAddr>,
+
+    /// Also take newline-delimited JSON jobs on this Unix socket (a pipe name such as
+    /// \\.\pipe\morganite on Windows)
+    #[arg(long)]
+    listen_socket: Option<PathBuf>,
+
+    /// Folder for the audio of socket jobs that don't give an out_path, and for the
+    /// state and audio of /jobs jobs in its queue subfolder [default: a morganite folder
+    /// in the system temp dir]
+    #[arg(long)]
+    job_dir: Option<PathBuf>,
+
+    /// Break the lock on --job-dir left by a server that is no longer running
+    #[arg(long)]
+    force_unlock: bool,
+
+    /// Jobs that may wait in the /jobs queue; more are turned down with 429
+    #[arg(long, default_value_t = 16)]
+    max_queued_jobs: usize,
+
+    /// Path for onnx tts model
+    #[arg(long, short, default_value = "kokoro-v1.1-zh.onnx")]
+    tts_model: String,
+
+    /// Path for voice bin model
+    #[arg(long, short, default_value = "voices-v1.1-zh.bin")]
+    voice_model: String,
+
+    /// Voice for requests that don't name one
+    #[arg(long, default_value = "zf_048")]
+    voice: String,
+
+    /// Speed for requests that don't give one
+    #[arg(long, value_parser = utils::parse_speed, default_value_t = 1.0)]
+    speed: f32,
+
+    /// MP3 bitrate in kbps for requests that don't give one
+    #[arg(long, default_value_t = 64)]
+    bitrate: u32,
+
+    /// Synthesis sessions shared by all requests [default: from the available cores]
+    #[arg(long, value_parser = utils::parse_count)]
+    concurrency: Option<usize>,
+
+    /// Longest text accepted per request, in chars; the reply is one MP3 of all of it
+    #[arg(long, default_value_t = 5000)]
+    max_chars: usize,
+
+    /// Largest request body accepted, in bytes
+    #[arg(long, default_value_t = 256 * 1024)]
+    max_body: usize,
+
+    /// Largest POST /jobs body accepted, in bytes; queued jobs have no --max-chars limit
+    #[arg(long, value_parser = utils::parse_size, default_value = "16MiB")]
+    max_job_body: u64,
+
+    /// Sentences a /stream response or gRPC Synthesize call may have waiting for a slow
+    /// client before it is dropped
+    #[arg(long, value_parser = utils::parse_count, default_value_t = 16)]
+    stream_buffer: usize,
+}
+
+/// `POST /synthesize` and `POST /stream` body.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SynthesizeRequest {
+    pub text: String,
+    pub voice: Option<String>,
+    pub speed: Option<f32>,
+    pub bitrate: Option<u32>,
+}
+
+/// Why a request was turned down.
+#[derive(Debug)]
+pub struct Rejected {
+    status: &'static str,
+    /// Stable for clients to match on, e.g. `unknown_voice`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Rejected {
+    fn bad(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: "400 Bad Request",
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Body size limits for [`read_request`].
+#[derive(Clone, Copy)]
+struct Limits {
+    body: usize,
+    job_body: usize,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+enum Reply<'a> {
+    Full(Response),
+    /// Audio sent sentence by sentence as it is synthesized.
+    Stream(&'a Server, pipeline::FileSettings, Vec<text::Item>),
+    /// A finished job's MP3, sent from disk.
+    File(PathBuf),
+}
+
+impl From<Rejected> for Response {
+    fn from(rejected: Rejected) -> Self {
+        Self::error(rejected.status, rejected.code, rejected.message)
+    }
+}
+
+impl Response {
+    fn json(status: &'static str, value: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    /// `{"error": code, "message": message}`, `code` being stable for clients to match on.
+    fn error(status: &'static str, code: &str, message: impl Into<String>) -> Self {
+        Self::json(status, json!({ "error": code, "message": message.into() }))
+    }
+}
+
+/// Everything a request needs once the engine is up.
+pub struct Server {
+    pipeline: pipeline::Pipeline,
+    /// Replaced along with the engines by [`Server::reload`].
+    voice_bin: ArcSwap<voices::VoiceBin>,
+    spec: tts::EngineSpec,
+    /// One reload at a time; requests keep going meanwhile.
+    reloading: tokio::sync::Mutex<()>,
+    /// `/jobs`, when serving HTTP.
+    queue: Option<queue::JobQueue>,
+    defaults: pipeline::FileSettings,
+    max_chars: usize,
+    stream_buffer: usize,
+    requests: AtomicU64,
+}
+
+pub async fn run(args: &ServeArgs) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        BITRATES.contains(&args.bitrate),
+        "--bitrate: {}",
+        bitrate_error(args.bitrate)
+    );
+    let voice_bin = voices::VoiceBin::load(Path::new(&args.voice_model))?;
+    let voice = utils::parse_voice(&args.voice).map_err(|e| anyhow::anyhow!("--voice: {}", e))?;
+    if let Some(e) = voice_bin.check(voice) {
+        anyhow::bail!("--voice: {}", e);
+    }
+    let concurrency = args
+        .concurrency
+        .unwrap_or_else(|| utils::auto_concurrency(utils::available_cores()));
+
+    let job_dir = args
+        .job_dir
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join("morganite"));
+    let server = Arc::new(OnceCell::new());
+    let listen = args.listen.or_else(|| {
+        (args.listen_socket.is_none() && args.listen_grpc.is_none())
+            .then(|| DEFAULT_LISTEN.parse().unwrap())
+    });
+    // The queue and socket jobs keep their state in the job folder, which one server
+    // at a time may write to
+    let _lock = (listen.is_some() || args.listen_socket.is_some())
+        .then(|| lock::Lock::dir(&job_dir, args.force_unlock))
+        .transpose()?;
+    if let Some(addr) = listen {
+        // Listen right away so /healthz can tell "starting" from "down"
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        println!("Listening on http://{}", addr);
+        let limits = Limits {
+            body: args.max_body,
+            job_body: args.max_job_body.try_into().unwrap_or(usize::MAX),
+        };
+        tokio::spawn(accept(listener, server.clone(), limits));
+    }
+    if let Some(addr) = args.listen_grpc {
+        // Same as HTTP: GetStatus answers while the engine warms up
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        println!("Serving gRPC on {}", addr);
+        tokio::spawn(grpc::serve(listener, server.clone(), args.stream_buffer));
+    }
+
+    let spec = tts::EngineSpec {
+        tts_model: args.tts_model.clone(),
+        voice_model: args.voice_model.clone(),
+        english: None,
+        options: tts::EngineOptions::default(),
+        concurrency,
+    };
+    let engines = Arc::new(spec.build().await?);
+    let took = tts::warm_up(engines.clone(), voice, concurrency).await?;
+    let mut pipeline =
+        pipeline::Pipeline::bare(engines, concurrency, preprocess::Preprocessor::default());
+    // Requests together never run more than `concurrency` syntheses
+    pipeline.slots = Some(Arc::new(Semaphore::new(concurrency)));
+    pipeline.lookahead = concurrency;
+    pipeline.queue_depth = concurrency * 2;
+    let ready = Arc::new(Server {
+        pipeline,
+        voice_bin: ArcSwap::from_pointee(voice_bin),
+        spec,
+        reloading: tokio::sync::Mutex::new(()),
+        queue: listen
+            .map(|_| queue::JobQueue::open(job_dir.join("queue"), args.max_queued_jobs))
+            .transpose()?,
+        defaults: pipeline::FileSettings {
+            voice,
+            speed: args.speed,
+            bitrate: args.bitrate,
+        },
+        max_chars: args.max_chars,
+        stream_buffer: args.stream_buffer,
+        requests: AtomicU64::new(0),
+    });
+    let _ = server.set(ready.clone());
+    tokio::spawn(queue::work(ready.clone()));
+    let mut hangups = hangups();
+    let reloader = ready.clone();
+    tokio::spawn(async move {
+        while let Some(()) = hangups.recv().await {
+            tracing::info!("SIGHUP: reloading the models");
+            let _ = reloader.reload().await;
+        }
+    });
+    // Socket clients only connect once there is something to answer them; the
+    // listener removes its socket file when dropped
+    let _jobs = match &args.listen_socket {
+        Some(path) => {
+            std::fs::create_dir_all(&job_dir)
+                .with_context(|| format!("Failed to create {}", job_dir.display()))?;
+            let listener = jobs::listen(path, ready, job_dir)?;
+            println!("Taking jobs on {}", path.display());
+            Some(listener)
+        }
+        None => None,
+    };
+    println!(
+        "Ready with {} sessions (warm-up took {:.1}s)",
+        concurrency,
+        took.as_secs_f64()
+    );
+
+    shutdown_signal().await?;
+    println!("Shutting down");
+    Ok(())
+}
+
+/// Every SIGHUP, for reloading the models. Elsewhere nothing ever arrives and the
+/// channel reports closed, which disables a `select!` branch on it.
+pub fn hangups() -> mpsc::UnboundedReceiver<()> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    #[cfg(unix)]
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    if tx.send(()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Err(e) => tracing::warn!("Can't listen for SIGHUP, reloading is off: {}", e),
+    }
+    #[cfg(not(unix))]
+    drop(tx);
+    rx
+}
+
+/// Ctrl-C, or on Unix also SIGTERM, so a service manager's stop cleans up too.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            r = tokio::signal::ctrl_c() => r,
+            _ = term.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+fn bitrate_error(bitrate: u32) -> String {
+    format!(
+        "unsupported bitrate {} kbps (one of {})",
+        bitrate,
+        BITRATES
+            .iter()
+            .map(|b| b.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+async fn accept(listener: TcpListener, server: Arc<OnceCell<Arc<Server>>>, limits: Limits) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, &server, limits).await {
+                        tracing::debug!("Request from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => tracing::warn!("Failed to accept connection: {}", e),
+        }
+    }
+}
+
+/// Serve one request; every connection is closed after its response.
+async fn respond(
+    mut stream: TcpStream,
+    server: &OnceCell<Arc<Server>>,
+    limits: Limits,
+) -> std::io::Result<()> {
+    let response = match read_request(&mut stream, limits).await? {
+        Ok(request) => match route(request, server.get().map(Arc::as_ref)).await {
+            Reply::Full(response) => response,
+            Reply::Stream(server, settings, items) => {
+                return server.stream(settings, items, stream).await;
+            }
+            Reply::File(path) => return send_file(stream, &path).await,
+        },
+        Err(response) => response,
+    };
+    write_response(stream, response).await
+}
+
+async fn write_response(mut stream: TcpStream, response: Response) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
+
+/// Send an MP3 from disk without reading it into memory first.
+async fn send_file(mut stream: TcpStream, path: &Path) -> std::io::Result<()> {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) => {
+            let response = Response::error(
+                "500 Internal Server Error",
+                "read_failed",
+                format!("Failed to read {}: {}", path.display(), e),
+            );
+            return write_response(stream, response).await;
+        }
+    };
+    let length = file.metadata().await?.len();
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        length
+    );
+    stream.write_all(head.as_bytes()).await?;
+    tokio::io::copy(&mut file, &mut stream).await?;
+    stream.shutdown().await
+}
+
+/// Queue a sentence for a streaming client without ever waiting on it.
+fn push(tx: &mpsc::Sender<Vec<u8>>, bytes: Vec<u8>, buffer: usize) -> anyhow::Result<()> {
+    match tx.try_send(bytes) {
+        Ok(()) => Ok(()),
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            anyhow::bail!("Client fell {} sentences behind", buffer)
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => anyhow::bail!("Client disconnected"),
+    }
+}
+
+/// Resolves once the client closes its side or the connection breaks.
+async fn closed(reader: &mut OwnedReadHalf) {
+    let mut buf = [0u8; 256];
+    while let Ok(n) = reader.read(&mut buf).await
+        && n > 0
+    {}
+}
+
+/// Read a request with its body, or the error response for a malformed or
+/// oversized one.
+async fn read_request(
+    stream: &mut TcpStream,
+    limits: Limits,
+) -> std::io::Result<Result<Request, Response>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_end = loop {
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if data.len() > MAX_HEAD {
+            return Ok(Err(Response::error(
+                "431 Request Header Fields Too Large",
+                "header_too_large",
+                format!("Request head is over {} bytes", MAX_HEAD),
+            )));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let mut request_line = head
+        .split("\r\n")
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(Err(Response::error(
+            "400 Bad Request",
+            "bad_request",
+            "Malformed request line",
+        )));
+    };
+    let header = |name: &str| {
+        head.split("\r\n").skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| value.trim().to_string())
+        })
+    };
+
+    let length = match header("content-length").map(|v| v.parse::<usize>()) {
+        None if method == "POST" => {
+            return Ok(Err(Response::error(
+                "411 Length Required",
+                "length_required",
+                "POST requests need a Content-Length",
+            )));
+        }
+        None => 0,
+        Some(Ok(length)) => length,
+        Some(Err(_)) => {
+            return Ok(Err(Response::error(
+                "400 Bad Request",
+                "bad_request",
+                "Invalid Content-Length",
+            )));
+        }
+    };
+    // Queued jobs take whole books
+    let max_body = if method == "POST" && path == "/jobs" {
+        limits.job_body
+    } else {
+        limits.body
+    };
+    if length > max_body {
+        return Ok(Err(Response::error(
+            "413 Content Too Large",
+            "body_too_large",
+            format!("Request body is over {} bytes", max_body),
+        )));
+    }
+    if header("expect").is_some_and(|v| v.eq_ignore_ascii_case("100-continue")) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+    }
+    let mut body = data.split_off(head_end + 4);
+    while body.len() < length {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+    body.truncate(length);
+    Ok(Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        content_type: header("content-type"),
+        body,
+    }))
+}
+
+async fn route(request: Request, server: Option<&Server>) -> Reply<'_> {
+    let not_ready = || {
+        Reply::Full(Response::error(
+            "503 Service Unavailable",
+            "not_ready",
+            "The engine is still warming up",
+        ))
+    };
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    let response = match (request.method.as_str(), path) {
+        ("GET", "/healthz") => match server {
+            Some(_) => Response::json("200 OK", json!({ "status": "ready" })),
+            None => Response::json("503 Service Unavailable", json!({ "status": "starting" })),
+        },
+        ("POST", "/synthesize") => match server {
+            Some(server) => server.synthesize(&request.body).await,
+            None => return not_ready(),
+        },
+        ("POST", "/reload") => match server {
+            Some(server) => match server.reload().await {
+                Ok(took) => Response::json(
+                    "200 OK",
+                    json!({ "status": "reloaded", "took_ms": took.as_millis() as u64 }),
+                ),
+                Err(e) => Response::error(
+                    "500 Internal Server Error",
+                    "reload_failed",
+                    format!("{:#}", e),
+                ),
+            },
+            None => return not_ready(),
+        },
+        ("POST", "/jobs") => match server {
+            Some(server) => server.submit_job(&request, query),
+            None => return not_ready(),
+        },
+        (method, path) if path.starts_with("/jobs/") => match server {
+            Some(server) => return server.job(method, &path["/jobs/".len()..], query),
+            None => return not_ready(),
+        },
+        ("POST", "/stream") => match server {
+            Some(server) => match server.parse(&request.body) {
+                Ok((settings, items)) => return Reply::Stream(server, settings, items),
+                Err(response) => response,
+            },
+            None => return not_ready(),
+        },
+        (_, "/healthz" | "/synthesize" | "/stream" | "/reload" | "/jobs") => Response::error(
+            "405 Method Not Allowed",
+            "method_not_allowed",
+            format!("{} is not supported on {}", request.method, request.path),
+        ),
+        _ => Response::error(
+            "404 Not Found",
+            "not_found",
+            format!("No endpoint {}", path),
+        ),
+    };
+    Reply::Full(response)
+}
+
+/// Value of `name` in a query string; values aren't percent-decoded.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+/// Split `items` into one item per sentence for streaming, each line's pause
+/// going after its last sentence.
+pub fn sentences(items: Vec<text::Item>) -> Vec<text::Item> {
+    items
+        .into_iter()
+        .flat_map(|item| {
+            // A target of 0 keeps every sentence on its own
+            let pieces = text::sentence_pieces(&item.text, 0, &text::SentenceEnds::default())
+                .into_iter()
+                .filter(|piece| text::is_pronounceable(piece))
+                .collect::<Vec<_>>();
+            let count = pieces.len();
+            pieces
+                .into_iter()
+                .enumerate()
+                .map(move |(i, piece)| text::Item {
+                    lang: lang::detect(&piece).script,
+                    text: piece,
+                    pause_after: if i + 1 == count {
+                        item.pause_after
+                    } else {
+                        Duration::ZERO
+                    },
+                    ..item.clone()
+                })
+        })
+        .collect()
+}
+
+impl Server {
+    /// Number for the next request's log lines and labels.
+    pub fn next_id(&self) -> u64 {
+        self.requests.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Requests taken so far, over every listener.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn pipeline(&self) -> &pipeline::Pipeline {
+        &self.pipeline
+    }
+
+    /// Sessions synthesizing right now, out of `pipeline().concurrency`.
+    pub fn busy_sessions(&self) -> usize {
+        self.pipeline.slots.as_ref().map_or(0, |slots| {
+            self.pipeline.concurrency - slots.available_permits()
+        })
+    }
+
+    /// Voices the voice model carries, and the default one.
+    pub fn voices(&self) -> (Vec<&'static str>, &'static str) {
+        let voices = utils::VOICE_NAMES
+            .iter()
+            .copied()
+            .filter(|name| self.voice_bin.load().contains(name))
+            .collect();
+        (voices, utils::voice_name(self.defaults.voice))
+    }
+
+    /// Load the model files again, e.g. after they were replaced, and switch over
+    /// once the new engines are warmed up. Requests in flight finish on the old
+    /// ones; if anything fails, the old ones stay.
+    pub async fn reload(&self) -> anyhow::Result<Duration> {
+        let _reloading = self.reloading.lock().await;
+        let result = async {
+            let voice_bin = voices::VoiceBin::load(Path::new(&self.spec.voice_model))?;
+            if let Some(e) = voice_bin.check(self.defaults.voice) {
+                anyhow::bail!("--voice: {}", e);
+            }
+            let took = tts::reload(&self.pipeline.engine, &self.spec, self.defaults.voice).await?;
+            self.voice_bin.store(Arc::new(voice_bin));
+            Ok(took)
+        }
+        .await;
+        match &result {
+            Ok(took) => tracing::info!("Reloaded the models in {:?}", took),
+            Err(e) => tracing::error!("Reload failed, keeping the loaded models: {:#}", e),
+        }
+        result
+    }
+
+    pub fn queue(&self) -> Option<&queue::JobQueue> {
+        self.queue.as_ref()
+    }
+
+    /// A pipeline of its own for a queued job, on the same engines and synthesis
+    /// slots as the requests.
+    pub fn job_pipeline(&self) -> pipeline::Pipeline {
+        let mut pipeline = pipeline::Pipeline::bare(
+            self.pipeline.engine.load(),
+            self.pipeline.concurrency,
+            preprocess::Preprocessor::default(),
+        );
+        pipeline.engine = self.pipeline.engine.clone();
+        pipeline.slots = self.pipeline.slots.clone();
+        pipeline.lookahead = self.pipeline.lookahead;
+        pipeline.queue_depth = self.pipeline.queue_depth;
+        pipeline
+    }
+
+    /// `POST /jobs`: a JSON body like `/synthesize`'s, or the text itself as
+    /// `text/plain` with the options in the query string.
+    fn submit_job(&self, request: &Request, query: &str) -> Response {
+        let Some(queue) = &self.queue else {
+            return Response::error("404 Not Found", "not_found", "No job queue");
+        };
+        let plain = request
+            .content_type
+            .as_deref()
+            .is_some_and(|t| t.starts_with("text/plain"));
+        let job = if plain {
+            let Ok(text) = String::from_utf8(request.body.clone()) else {
+                return Rejected::bad("invalid_text", "Body is not UTF-8").into();
+            };
+            let speed = match query_param(query, "speed")
+                .map(str::parse::<f32>)
+                .transpose()
+            {
+                Ok(speed) => speed,
+                Err(e) => return Rejected::bad("invalid_speed", format!("{}", e)).into(),
+            };
+            let bitrate = match query_param(query, "bitrate")
+                .map(str::parse::<u32>)
+                .transpose()
+            {
+                Ok(bitrate) => bitrate,
+                Err(e) => return Rejected::bad("invalid_bitrate", format!("{}", e)).into(),
+            };
+            SynthesizeRequest {
+                text,
+                voice: query_param(query, "voice").map(String::from),
+                speed,
+                bitrate,
+            }
+        } else {
+            match serde_json::from_slice(&request.body) {
+                Ok(job) => job,
+                Err(e) => return Rejected::bad("invalid_json", e.to_string()).into(),
+            }
+        };
+        let settings = match self.settings(job.voice.as_deref(), job.speed, job.bitrate) {
+            Ok(settings) => settings,
+            Err(rejected) => return rejected.into(),
+        };
+        if !job.text.lines().any(text::is_pronounceable) {
+            return Rejected::bad("empty_text", "Text has nothing to say").into();
+        }
+        match queue.submit(&job.text, &settings) {
+            Ok(id) => Response::json(
+                "202 Accepted",
+                json!({ "id": id, "state": "queued", "status_url": format!("/jobs/{}", id) }),
+            ),
+            Err(queue::SubmitError::Full(max)) => Response::error(
+                "429 Too Many Requests",
+                "queue_full",
+                format!("{} jobs are already queued", max),
+            ),
+            Err(queue::SubmitError::Io(e)) => Response::error(
+                "500 Internal Server Error",
+                "queue_failed",
+                format!("{:#}", e),
+            ),
+        }
+    }
+
+    /// `GET /jobs/{id}`, `GET /jobs/{id}/download[?segment=N]` and `DELETE /jobs/{id}`,
+    /// `rest` being what follows `/jobs/`.
+    fn job(&self, method: &str, rest: &str, query: &str) -> Reply<'_> {
+        let not_found = || {
+            Reply::Full(Response::error(
+                "404 Not Found",
+                "not_found",
+                format!("No endpoint /jobs/{}", rest),
+            ))
+        };
+        let Some(queue) = &self.queue else {
+            return not_found();
+        };
+        let (id, download) = match rest.strip_suffix("/download") {
+            Some(id) => (id, true),
+            None => (rest, false),
+        };
+        let Ok(id) = id.parse::<u64>() else {
+            return not_found();
+        };
+        let unknown = || Response::error("404 Not Found", "unknown_job", format!("No job {}", id));
+        let response = match (method, download) {
+            ("GET", false) => match queue.status(id) {
+                Some(status) => Response::json("200 OK", status),
+                None => unknown(),
+            },
+            ("DELETE", false) => match queue.cancel(id) {
+                Some(Ok(queue::Cancelled::Queued)) => {
+                    Response::json("200 OK", json!({ "id": id, "state": "cancelled" }))
+                }
+                // The worker marks it cancelled once the pipeline has stopped
+                Some(Ok(queue::Cancelled::Running)) => {
+                    Response::json("202 Accepted", json!({ "id": id, "state": "running" }))
+                }
+                Some(Ok(queue::Cancelled::Removed)) => {
+                    Response::json("200 OK", json!({ "id": id, "state": "removed" }))
+                }
+                Some(Err(e)) => Response::error(
+                    "500 Internal Server Error",
+                    "remove_failed",
+                    format!("{:#}", e),
+                ),
+                None => unknown(),
+            },
+            ("GET", true) => {
+                let segment = match query_param(query, "segment").map(str::parse::<usize>) {
+                    None => 0,
+                    Some(Ok(segment)) => segment,
+                    Some(Err(e)) => {
+                        return Reply::Full(Rejected::bad("invalid_segment", e.to_string()).into());
+                    }
+                };
+                match queue.segment(id, segment) {
+                    Some((queue::State::Done, Some(path))) => return Reply::File(path),
+                    Some((queue::State::Done, None)) => Response::error(
+                        "404 Not Found",
+                        "unknown_segment",
+                        format!("Job {} has no segment {}", id, segment),
+                    ),
+                    Some((state, _)) => Response::error(
+                        "409 Conflict",
+                        "not_done",
+                        format!("Job {} is {}", id, state.name()),
+                    ),
+                    None => unknown(),
+                }
+            }
+            _ => Response::error(
+                "405 Method Not Allowed",
+                "method_not_allowed",
+                format!("{} is not supported on /jobs/{}", method, rest),
+            ),
+        };
+        Reply::Full(response)
+    }
+
+    async fn synthesize(&self, body: &[u8]) -> Response {
+        let (settings, items) = match self.parse(body) {
+            Ok(parsed) => parsed,
+            Err(response) => return response,
+        };
+        let id = self.next_id();
+        match self
+            .render(format!("request {}", id), &settings, items)
+            .await
+        {
+            Ok((mp3, _)) => Response {
+                status: "200 OK",
+                content_type: "audio/mpeg",
+                body: mp3,
+            },
+            Err(e) => {
+                tracing::error!("Request {}: {:#}", id, e);
+                Response::error(
+                    "500 Internal Server Error",
+                    "synthesis_failed",
+                    format!("{:#}", e),
+                )
+            }
+        }
+    }
+
+    /// Synthesize `items` into one MP3, returning it with its duration.
+    pub async fn render(
+        &self,
+        label: String,
+        settings: &pipeline::FileSettings,
+        items: Vec<text::Item>,
+    ) -> anyhow::Result<(Vec<u8>, Duration)> {
+        let items = Arc::new(items);
+        let mut samples = Vec::new();
+        let (tx, mut rx) = mpsc::channel(1);
+        let (synthesized, ()) = tokio::join!(
+            self.pipeline
+                .synthesize(label, items.clone(), settings, None, tx),
+            async {
+                while let Some((idx, chunk)) = rx.recv().await {
+                    samples.extend_from_slice(&chunk.audio);
+                    if chunk.end.is_some() {
+                        let pause = items[idx].pause_after.as_secs_f64();
+                        samples.resize(
+                            samples.len() + (pause * writer::SAMPLE_RATE as f64) as usize,
+                            0.0,
+                        );
+                    }
+                }
+            }
+        );
+        synthesized?;
+        let duration = Duration::from_secs_f64(samples.len() as f64 / writer::SAMPLE_RATE as f64);
+        let config = writer::default_mono_24k_config(settings.bitrate);
+        let mp3 = tokio::task::spawn_blocking(move || writer::encode_clip(config, &samples))
+            .await
+            .context("mp3 encoder panicked")??;
+        Ok((mp3, duration))
+    }
+
+    /// Answer with chunked transfer encoding: each sentence's MP3 frames go out as
+    /// one chunk as soon as it and every sentence before it are synthesized. A client
+    /// that falls `stream_buffer` sentences behind, or disconnects, has its request
+    /// cancelled, and the connection then closes without the final empty chunk.
+    async fn stream(
+        &self,
+        settings: pipeline::FileSettings,
+        items: Vec<text::Item>,
+        stream: TcpStream,
+    ) -> std::io::Result<()> {
+        let id = self.next_id();
+        let items = Arc::new(sentences(items));
+        let pause = vec![0.0; (LINE_PAUSE.as_secs_f64() * writer::SAMPLE_RATE as f64) as usize];
+        let gap =
+            match writer::encode_clip(writer::default_mono_24k_config(settings.bitrate), &pause) {
+                Ok(gap) => gap,
+                Err(e) => {
+                    let response = Response::error(
+                        "500 Internal Server Error",
+                        "encode_failed",
+                        format!("{:#}", e),
+                    );
+                    return write_response(stream, response).await;
+                }
+            };
+
+        let (mut reader, mut writer) = stream.into_split();
+        writer
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+            )
+            .await?;
+        // Sentences ready for the client; the writer below drains it at the client's pace
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(self.stream_buffer);
+        let buffer = self.stream_buffer;
+        let encoding = Arc::new(pipeline::ClipEncoding {
+            bitrate: settings.bitrate,
+            channel_mode: writer::ChannelMode::Mono,
+            quality: writer::EncoderQuality::Standard,
+            gain_db: 0.0,
+            limiter: None,
+        });
+        let (chunk_tx, mut chunks) = mpsc::channel::<(usize, pipeline::Chunk)>(1);
+        let synth = async move {
+            let queue = async {
+                while let Some((idx, chunk)) = chunks.recv().await {
+                    let bytes = chunk.encoded.context("Clip was not encoded")?;
+                    push(&tx, bytes, buffer)?;
+                    if chunk.end.is_some() && !items[idx].pause_after.is_zero() {
+                        push(&tx, gap.clone(), buffer)?;
+                    }
+                }
+                anyhow::Ok(())
+            };
+            tokio::try_join!(
+                self.pipeline.synthesize(
+                    format!("stream {}", id),
+                    items.clone(),
+                    &settings,
+                    Some(encoding),
+                    chunk_tx,
+                ),
+                queue
+            )
+            .map(|_| ())
+        };
+        let send = async move {
+            while let Some(bytes) = rx.recv().await {
+                writer
+                    .write_all(format!("{:x}\r\n", bytes.len()).as_bytes())
+                    .await?;
+                writer.write_all(&bytes).await?;
+                writer.write_all(b"\r\n").await?;
+            }
+            anyhow::Ok(writer)
+        };
+
+        // Dropping the synthesis future cancels what is still in flight
+        let outcome = tokio::select! {
+            r = async { tokio::try_join!(synth, send) } => r,
+            _ = closed(&mut reader) => Err(anyhow::anyhow!("Client disconnected")),
+        };
+        match outcome {
+            Ok(((), mut writer)) => {
+                writer.write_all(b"0\r\n\r\n").await?;
+                writer.shutdown().await
+            }
+            Err(e) => {
+                tracing::warn!("Stream {}: {:#}", id, e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Validate a request body into the settings and items to synthesize.
+    fn parse(&self, body: &[u8]) -> Result<(pipeline::FileSettings, Vec<text::Item>), Response> {
+        let request: SynthesizeRequest = serde_json::from_slice(body)
+            .map_err(|e| Rejected::bad("invalid_json", e.to_string()))?;
+        self.validate(request).map_err(Response::from)
+    }
+
+    /// Check a request against the voices and limits, filling in the defaults.
+    pub fn validate(
+        &self,
+        request: SynthesizeRequest,
+    ) -> Result<(pipeline::FileSettings, Vec<text::Item>), Rejected> {
+        let settings = self.settings(request.voice.as_deref(), request.speed, request.bitrate)?;
+        let chars = request.text.chars().count();
+        if chars > self.max_chars {
+            return Err(Rejected {
+                status: "413 Content Too Large",
+                code: "text_too_long",
+                message: format!("Text has {} chars, the limit is {}", chars, self.max_chars),
+            });
+        }
+        let mut items = request
+            .text
+            .lines()
+            .map(str::trim)
+            .filter(|line| text::is_pronounceable(line))
+            .map(|line| text::Item {
+                text: line.to_string(),
+                source_line: None,
+                chapter: None,
+                pause_after: LINE_PAUSE,
+                voice: None,
+                speed: None,
+                lang: lang::detect(line).script,
+            })
+            .collect::<Vec<_>>();
+        let Some(last) = items.last_mut() else {
+            return Err(Rejected::bad("empty_text", "Text has nothing to say"));
+        };
+        last.pause_after = Duration::ZERO;
+        Ok((settings, items))
+    }
+
+    /// The voice, speed and bitrate a request asks for, checked, or the defaults.
+    fn settings(
+        &self,
+        voice: Option<&str>,
+        speed: Option<f32>,
+        bitrate: Option<u32>,
+    ) -> Result<pipeline::FileSettings, Rejected> {
+        let voice = match voice {
+            Some(name) => {
+                let voice =
+                    utils::parse_voice(name).map_err(|e| Rejected::bad("unknown_voice", e))?;
+                if let Some(e) = self.voice_bin.load().check(voice) {
+                    return Err(Rejected::bad("unknown_voice", e));
+                }
+                voice
+            }
+            None => self.defaults.voice,
+        };
+        let speed = match speed {
+            Some(speed) => {
+                utils::check_speed(speed).map_err(|e| Rejected::bad("invalid_speed", e))?
+            }
+            None => self.defaults.speed,
+        };
+        let bitrate = match bitrate {
+            Some(bitrate) if !BITRATES.contains(&bitrate) => {
+                return Err(Rejected::bad("invalid_bitrate", bitrate_error(bitrate)));
+            }
+            Some(bitrate) => bitrate,
+            None => self.defaults.bitrate,
+        };
+        Ok(pipeline::FileSettings {
+            voice,
+            speed,
+            bitrate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_queues_while_there_is_room() {
+        let (tx, mut rx) = mpsc::channel(2);
+        push(&tx, vec![1], 2).unwrap();
+        push(&tx, vec![2], 2).unwrap();
+        assert_eq!(rx.try_recv().unwrap(), vec![1]);
+        assert_eq!(rx.try_recv().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn push_gives_up_on_a_client_that_fell_behind() {
+        let (tx, _rx) = mpsc::channel(1);
+        push(&tx, vec![1], 1).unwrap();
+        let e = push(&tx, vec![2], 1).unwrap_err();
+        assert_eq!(e.to_string(), "Client fell 1 sentences behind");
+    }
+
+    #[test]
+    fn push_notices_a_disconnected_client() {
+        let (tx, rx) = mpsc::channel(4);
+        drop(rx);
+        let e = push(&tx, vec![1], 4).unwrap_err();
+        assert_eq!(e.to_string(), "Client disconnected");
+    }
+
+    #[tokio::test]
+    async fn closed_resolves_when_the_client_hangs_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let (mut reader, _writer) = server.into_split();
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), closed(&mut reader))
+            .await
+            .expect("closed() should resolve once the client is gone");
+    }
+}
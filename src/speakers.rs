@@ -0,0 +1,166 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::utils;
+
+/// `--speaker-map` file:
+///
+/// ```toml
+/// # Optional: any prefix matching this is a speaker even if it isn't listed below.
+/// pattern = '^(?P<name>[A-Z][A-Z ]{1,20})[:：]'
+///
+/// [speakers]
+/// 丽娜 = "zf_048"
+/// JOHN = { voice = "zm_029", speed = 0.9 }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpeakerMapFile {
+    pattern: Option<String>,
+    #[serde(default)]
+    speakers: BTreeMap<String, SpeakerEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SpeakerEntry {
+    Voice(String),
+    Full { voice: String, speed: Option<f32> },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Speaker {
+    pub voice: Voice,
+    pub speed: Option<f32>,
+}
+
+pub struct SpeakerMap {
+    /// Longest names first so `Anna` can't shadow `Annabel`.
+    speakers: Vec<(String, Speaker)>,
+    pattern: Option<Regex>,
+}
+
+/// A `NAME:` prefix found at the start of a line.
+pub struct SpeakerPrefix<'a> {
+    pub name: &'a str,
+    /// `None` when the name only matched the pattern and isn't mapped.
+    pub speaker: Option<Speaker>,
+    /// The line without the prefix.
+    pub rest: &'a str,
+}
+
+impl SpeakerMap {
+    pub fn load(path: &Path, names: &utils::VoiceNames) -> anyhow::Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read speaker map {}", path.display()))?;
+        let file: SpeakerMapFile = toml::from_str(&raw)
+            .with_context(|| format!("Failed to parse speaker map {}", path.display()))?;
+
+        let mut speakers = Vec::new();
+        let mut problems = Vec::new();
+        for (name, entry) in file.speakers {
+            let (voice, speed) = match entry {
+                SpeakerEntry::Voice(voice) => (voice, None),
+                SpeakerEntry::Full { voice, speed } => (voice, speed),
+            };
+            if let Some(Err(e)) = speed.map(utils::check_speed) {
+                problems.push(format!("{name}: {e}"));
+            }
+            match names.parse(&voice) {
+                Ok(voice) => speakers.push((name, Speaker { voice, speed })),
+                Err(e) => problems.push(format!("{name}: {e}")),
+            }
+        }
+        anyhow::ensure!(
+            problems.is_empty(),
+            "Invalid entries in speaker map {}:\n  {}",
+            path.display(),
+            problems.join("\n  ")
+        );
+        speakers.sort_by_key(|(name, _)| std::cmp::Reverse(name.chars().count()));
+
+        let pattern = file
+            .pattern
+            .map(|p| Regex::new(&p).context("Invalid speaker pattern"))
+            .transpose()?;
+        if let Some(pattern) = &pattern {
+            anyhow::ensure!(
+                pattern.capture_names().any(|n| n == Some("name")),
+                "Speaker pattern must have a (?P<name>..) group"
+            );
+        }
+
+        tracing::info!("Loaded {} speakers from {}", speakers.len(), path.display());
+        Ok(Self { speakers, pattern })
+    }
+
+    /// Every mapped name with its voice.
+    pub fn voices(&self) -> impl Iterator<Item = (&str, Voice)> {
+        self.speakers
+            .iter()
+            .map(|(name, s)| (name.as_str(), s.voice))
+    }
+
+    /// Find a speaker prefix. Known names need to be followed by `:`/`：`; anything else
+    /// only counts when it matches the explicit pattern, so ordinary colons in prose
+    /// stay narration.
+    pub fn take<'a>(&self, line: &'a str) -> Option<SpeakerPrefix<'a>> {
+        for (name, speaker) in &self.speakers {
+            if let Some(after) = line.strip_prefix(name.as_str())
+                && let Some(rest) = after
+                    .trim_start()
+                    .strip_prefix(':')
+                    .or_else(|| after.trim_start().strip_prefix('：'))
+            {
+                return Some(SpeakerPrefix {
+                    name: &line[..name.len()],
+                    speaker: Some(*speaker),
+                    rest: rest.trim_start(),
+                });
+            }
+        }
+
+        let caps = self.pattern.as_ref()?.captures(line)?;
+        let name = caps.name("name")?.as_str().trim();
+        let name = &line[line.find(name)?..][..name.len()];
+        let speaker = self
+            .speakers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, s)| *s);
+        Some(SpeakerPrefix {
+            name,
+            speaker,
+            rest: line[caps.get(0).unwrap().end()..].trim_start(),
+        })
+    }
+}
+
+/// Collects speaker names that matched the pattern but have no voice, so they can
+/// be reported once.
+#[derive(Default)]
+pub struct Unmapped(BTreeSet<String>);
+
+impl Unmapped {
+    pub fn note(&mut self, prefix: &SpeakerPrefix) {
+        if prefix.speaker.is_none() {
+            self.0.insert(prefix.name.to_string());
+        }
+    }
+
+    pub fn report(&self) {
+        if !self.0.is_empty() {
+            tracing::warn!(
+                "Speakers without a voice in the speaker map (using the default voice): {}",
+                self.0.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{calibrate, utils};
+
+/// Upper bounds (in chars) of the line-length histogram buckets; the last bucket is open.
+const LENGTH_BUCKETS: [usize; 6] = [10, 20, 50, 100, 200, 500];
+
+/// Rough speaking rates at speed 1.0, used without a matching calibration.
+const CJK_CHARS_PER_SEC: f64 = 4.5;
+const OTHER_CHARS_PER_SEC: f64 = 14.0;
+
+#[derive(clap::Args)]
+pub struct StatsArgs {
+    /// Path to a single .txt file OR a folder containing multiple .txt files
+    path: String,
+
+    /// Speech speed assumed for the duration estimate
+    #[arg(long, value_parser = utils::parse_speed, default_value_t = 1.0)]
+    speed: f32,
+
+    /// Voice to estimate for, with its `calibrate` results when there are any
+    #[arg(long)]
+    voice: Option<String>,
+
+    /// TTS model the calibration must have been measured with
+    #[arg(long, default_value = "kokoro-v1.1-zh.onnx")]
+    tts_model: String,
+
+    /// Voice model the calibration must have been measured with
+    #[arg(long, default_value = "voices-v1.1-zh.bin")]
+    voice_model: String,
+
+    /// Calibration file [default: $XDG_CACHE_HOME/morganite/calibration.json]
+    #[arg(long)]
+    calibration: Option<PathBuf>,
+
+    /// Print JSON instead of a table
+    #[arg(long)]
+    json: bool,
+
+    #[command(flatten)]
+    text: crate::TextArgs,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct FileStats {
+    file: String,
+    lines: usize,
+    chars: usize,
+    longest_line: usize,
+    longest_line_chars: usize,
+    /// `(upper bound, count)`; `None` is the open-ended last bucket.
+    length_histogram: Vec<(Option<usize>, usize)>,
+    /// Lines that produced no synthesis items at all.
+    skipped_lines: usize,
+    /// Lines whose spoken text differs from the source (tags, directives, markup).
+    cleaned_lines: usize,
+    chapters: usize,
+    /// Spoken lines per detected script.
+    languages: BTreeMap<&'static str, usize>,
+    estimated_secs: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    files: Vec<FileStats>,
+    total: FileStats,
+    /// What the duration estimates are based on.
+    estimate: String,
+}
+
+pub fn run(args: &StatsArgs) -> anyhow::Result<()> {
+    let (txt_files, folder_mode) = crate::collect_txt_files(&args.path, false)?;
+    let preprocessor = args.text.preprocessor()?;
+    let calibration = match &args.voice {
+        Some(voice) => {
+            let voice = preprocessor
+                .voice_names
+                .parse(voice)
+                .map_err(|e| anyhow::anyhow!(e))?;
+            let voice = utils::voice_name(voice);
+            let found = calibrate::lookup(
+                args.calibration.as_deref(),
+                &args.tts_model,
+                &args.voice_model,
+                voice,
+                args.speed,
+            )?;
+            if found.is_none() {
+                tracing::warn!(
+                    "No calibration for {} at {}x with these models; run calibrate for better estimates",
+                    voice,
+                    args.speed
+                );
+            }
+            found
+        }
+        None => None,
+    };
+
+    let mut files = Vec::new();
+    for txt_path in &txt_files {
+        files.push(file_stats(
+            &preprocessor,
+            txt_path,
+            folder_mode,
+            args.speed,
+            calibration.as_ref(),
+        )?);
+    }
+    let total = totals(&files);
+    let estimate = match &calibration {
+        Some(entry) => entry.describe(),
+        None => "default rates".to_string(),
+    };
+    let report = Report {
+        files,
+        total,
+        estimate,
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_table(&report);
+    }
+    Ok(())
+}
+
+fn file_stats(
+    preprocessor: &crate::preprocess::Preprocessor,
+    txt_path: &Path,
+    folder_mode: bool,
+    speed: f32,
+    calibration: Option<&calibrate::Entry>,
+) -> anyhow::Result<FileStats> {
+    let prepared = preprocessor.prepare(txt_path, folder_mode)?;
+
+    let mut stats = FileStats {
+        file: txt_path.display().to_string(),
+        lines: prepared.lines.len(),
+        chapters: prepared.chapters.len(),
+        length_histogram: LENGTH_BUCKETS
+            .iter()
+            .map(|b| Some(*b))
+            .chain([None])
+            .map(|b| (b, 0))
+            .collect(),
+        ..Default::default()
+    };
+
+    for line in &prepared.lines {
+        let chars = line.text.chars().count();
+        stats.chars += chars;
+        if chars > stats.longest_line_chars {
+            stats.longest_line_chars = chars;
+            stats.longest_line = line.number;
+        }
+        let bucket = LENGTH_BUCKETS
+            .iter()
+            .position(|b| chars <= *b)
+            .unwrap_or(LENGTH_BUCKETS.len());
+        stats.length_histogram[bucket].1 += 1;
+    }
+
+    let mut spoken: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+    let mut estimate = Duration::ZERO;
+    for item in &prepared.items {
+        if let Some(line) = item.source_line {
+            let parts = spoken.entry(line).or_default();
+            if parts.is_empty() {
+                *stats.languages.entry(item.lang.label()).or_default() += 1;
+            }
+            parts.push(&item.text);
+        }
+        let speed = item.speed.unwrap_or(speed);
+        let speech = match calibration {
+            Some(entry) => Duration::from_secs_f64(
+                item.text.chars().count() as f64 * entry.audio_secs_per_char * entry.speed as f64
+                    / speed as f64,
+            ),
+            None => estimate_speech(&item.text, speed),
+        };
+        estimate += speech + item.pause_after;
+    }
+    stats.estimated_secs = estimate.as_secs_f64();
+
+    for line in &prepared.lines {
+        match spoken.get(&line.number) {
+            None => stats.skipped_lines += 1,
+            Some(parts) if parts.concat() != line.text => stats.cleaned_lines += 1,
+            Some(_) => {}
+        }
+    }
+
+    Ok(stats)
+}
+
+fn totals(files: &[FileStats]) -> FileStats {
+    let mut total = FileStats {
+        file: "TOTAL".to_string(),
+        ..Default::default()
+    };
+    for f in files {
+        total.lines += f.lines;
+        total.chars += f.chars;
+        total.skipped_lines += f.skipped_lines;
+        total.cleaned_lines += f.cleaned_lines;
+        total.chapters += f.chapters;
+        for (lang, n) in &f.languages {
+            *total.languages.entry(lang).or_default() += n;
+        }
+        total.estimated_secs += f.estimated_secs;
+        if f.longest_line_chars > total.longest_line_chars {
+            total.longest_line_chars = f.longest_line_chars;
+            total.longest_line = f.longest_line;
+        }
+        if total.length_histogram.is_empty() {
+            total.length_histogram = f.length_histogram.clone();
+        } else {
+            for (t, (_, n)) in total.length_histogram.iter_mut().zip(&f.length_histogram) {
+                t.1 += n;
+            }
+        }
+    }
+    total
+}
+
+/// Estimated spoken duration of `text` at `speed`, from fixed per-script rates.
+pub fn estimate_speech(text: &str, speed: f32) -> Duration {
+    let (cjk, other) = text.chars().fold((0usize, 0usize), |(cjk, other), c| {
+        if utils::is_cjk(c) {
+            (cjk + 1, other)
+        } else if c.is_alphanumeric() {
+            (cjk, other + 1)
+        } else {
+            (cjk, other)
+        }
+    });
+    let secs = cjk as f64 / CJK_CHARS_PER_SEC + other as f64 / OTHER_CHARS_PER_SEC;
+    Duration::from_secs_f64(secs / speed.max(0.1) as f64)
+}
+
+fn print_table(report: &Report) {
+    println!(
+        "{:<40} {:>8} {:>10} {:>14} {:>8} {:>8} {:>9} {:>10}",
+        "file", "lines", "chars", "longest(line)", "skipped", "cleaned", "chapters", "est."
+    );
+    for f in report.files.iter().chain([&report.total]) {
+        println!(
+            "{:<40} {:>8} {:>10} {:>14} {:>8} {:>8} {:>9} {:>10}",
+            f.file,
+            f.lines,
+            f.chars,
+            format!("{}({})", f.longest_line_chars, f.longest_line),
+            f.skipped_lines,
+            f.cleaned_lines,
+            f.chapters,
+            utils::format_hms(Duration::from_secs_f64(f.estimated_secs))
+        );
+    }
+
+    println!("Estimated durations from {}", report.estimate);
+
+    println!();
+    println!("Line length distribution (all files):");
+    for (bound, count) in &report.total.length_histogram {
+        let label = match bound {
+            Some(b) => format!("<= {b}"),
+            None => format!("> {}", LENGTH_BUCKETS[LENGTH_BUCKETS.len() - 1]),
+        };
+        println!("  {:>8} chars: {}", label, count);
+    }
+
+    println!();
+    println!("Spoken lines by language (all files):");
+    for (lang, count) in &report.total.languages {
+        println!("  {:>8}: {}", lang, count);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{collections::BTreeMap, fmt::Write as _, path::Path, time::Duration};
+
+use anyhow::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Srt,
+    /// WebVTT, with the source line number as each cue's identifier.
+    Vtt,
+    /// LRC lyrics, `[mm:ss.xx]` with minutes counting on past 59 in long files.
+    Lrc,
+    /// Audacity label track (`<stem>_labels.txt`); lines that got no audio show
+    /// up as zero-length `FAILED` labels.
+    Labels,
+}
+
+/// Characters of the line kept in an Audacity label.
+const LABEL_CHARS: usize = 40;
+
+impl Format {
+    /// Appended to the input stem or the segment path without `.mp3`.
+    fn suffix(self) -> &'static str {
+        match self {
+            Format::Srt => ".srt",
+            Format::Vtt => ".vtt",
+            Format::Lrc => ".lrc",
+            Format::Labels => "_labels.txt",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub formats: Vec<Format>,
+    /// One subtitle file next to every MP3 segment instead of one per input file.
+    pub per_segment: bool,
+    /// Let each cue run on through the silence written after its line.
+    pub include_gap: bool,
+    /// In LRC, tag a line again where each further synthesis chunk of it starts.
+    pub chunk_tags: bool,
+}
+
+/// Where one source line landed in the written audio.
+#[derive(Debug, Clone)]
+pub struct Cue {
+    /// Physical line number in the source file.
+    pub line: usize,
+    pub text: String,
+    /// Segment file the line starts in; a line running past a rotation stays with it.
+    pub segment: String,
+    /// Offsets within `segment`.
+    pub start: Duration,
+    pub end: Duration,
+    /// Offsets within the input file's whole audio.
+    pub abs_start: Duration,
+    pub abs_end: Duration,
+    /// Absolute start of every chunk after the first when the line was split.
+    pub chunk_starts: Vec<Duration>,
+    /// False for a placeholder of a line that produced no audio.
+    pub spoken: bool,
+}
+
+/// Line-level timing of one input file, collected as the audio is written.
+#[derive(Default)]
+pub struct Timeline {
+    cues: Vec<Cue>,
+}
+
+impl Timeline {
+    /// Record that `line` was spoken from `abs_start` (at `offset` into `segment`)
+    /// until `abs_end`. Chunks of the same line are merged into one cue.
+    pub fn record(
+        &mut self,
+        line: usize,
+        text: &str,
+        (segment, offset): (String, Duration),
+        abs_start: Duration,
+        abs_end: Duration,
+    ) {
+        if let Some(last) = self.cues.last_mut()
+            && last.line == line
+        {
+            last.end += abs_end.saturating_sub(last.abs_end);
+            last.abs_end = abs_end;
+            last.chunk_starts.push(abs_start);
+            return;
+        }
+        self.cues.push(Cue {
+            line,
+            text: text.to_string(),
+            segment,
+            start: offset,
+            end: offset + abs_end.saturating_sub(abs_start),
+            abs_start,
+            abs_end,
+            chunk_starts: Vec::new(),
+            spoken: true,
+        });
+    }
+
+    /// The cues plus a zero-length placeholder for every source line in `lines`
+    /// that got none, placed where the previous cue ended.
+    fn with_unspoken(&self, lines: &BTreeMap<usize, String>) -> Vec<Cue> {
+        let mut cues = Vec::with_capacity(lines.len());
+        let mut spoken = self.cues.iter().peekable();
+        for (&line, text) in lines {
+            if let Some(cue) = spoken.next_if(|c| c.line == line) {
+                cues.push(cue.clone());
+                continue;
+            }
+            let (segment, at, abs_at) = match (cues.last(), spoken.peek()) {
+                (Some(prev), _) => (prev.segment.clone(), prev.end, prev.abs_end),
+                (None, Some(next)) => (next.segment.clone(), next.start, next.abs_start),
+                (None, None) => continue,
+            };
+            cues.push(Cue {
+                line,
+                text: text.clone(),
+                segment,
+                start: at,
+                end: at,
+                abs_start: abs_at,
+                abs_end: abs_at,
+                chunk_starts: Vec::new(),
+                spoken: false,
+            });
+        }
+        cues
+    }
+
+    /// Write every requested format, either as `<stem><suffix>` in `out_dir` or next
+    /// to each segment. Per segment, a cue straddling a rotation is not split: it
+    /// stays in the file it starts in and ends past that segment's end.
+    ///
+    /// `lines` are the file's source lines by number, for placeholders of lines
+    /// that got no audio.
+    pub fn write(
+        &self,
+        settings: &Settings,
+        out_dir: &Path,
+        stem: &str,
+        lines: &BTreeMap<usize, String>,
+    ) -> anyhow::Result<()> {
+        for &format in &settings.formats {
+            let with_unspoken;
+            let cues = if format == Format::Labels {
+                with_unspoken = self.with_unspoken(lines);
+                &with_unspoken
+            } else {
+                &self.cues
+            };
+            if settings.per_segment {
+                let mut start = 0;
+                while start < cues.len() {
+                    let segment = &cues[start].segment;
+                    let len = cues[start..]
+                        .iter()
+                        .take_while(|c| &c.segment == segment)
+                        .count();
+                    let path = format!(
+                        "{}{}",
+                        segment.strip_suffix(".mp3").unwrap_or(segment),
+                        format.suffix()
+                    );
+                    write_file(
+                        Path::new(&path),
+                        &cues[start..start + len],
+                        format,
+                        settings,
+                        false,
+                    )?;
+                    start += len;
+                }
+            } else {
+                let path = out_dir.join(format!("{stem}{}", format.suffix()));
+                write_file(&path, cues, format, settings, true)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_file(
+    path: &Path,
+    cues: &[Cue],
+    format: Format,
+    settings: &Settings,
+    absolute: bool,
+) -> anyhow::Result<()> {
+    let mut out = String::new();
+    if format == Format::Vtt {
+        out.push_str("WEBVTT\n\n");
+    }
+    for (i, cue) in cues.iter().enumerate() {
+        let (start, end) = if absolute {
+            (cue.abs_start, cue.abs_end)
+        } else {
+            (cue.start, cue.end)
+        };
+        match format {
+            Format::Srt => writeln!(
+                out,
+                "{}\n{} --> {}\n{}\n",
+                i + 1,
+                srt_time(start),
+                srt_time(end),
+                cue.text
+            )?,
+            Format::Vtt => writeln!(
+                out,
+                "line-{}\n{} --> {}\n{}\n",
+                cue.line,
+                vtt_time(start),
+                vtt_time(end),
+                cue.text
+            )?,
+            Format::Lrc => {
+                write!(out, "[{}]", lrc_time(start))?;
+                if settings.chunk_tags {
+                    for &chunk in &cue.chunk_starts {
+                        write!(out, "[{}]", lrc_time(start + (chunk - cue.abs_start)))?;
+                    }
+                }
+                writeln!(out, "{}", cue.text)?;
+            }
+            Format::Labels => {
+                let mut label = cue
+                    .text
+                    .chars()
+                    .take(LABEL_CHARS)
+                    .map(|c| if c == '\t' { ' ' } else { c })
+                    .collect::<String>();
+                if cue.text.chars().count() > LABEL_CHARS {
+                    label.push('…');
+                }
+                writeln!(
+                    out,
+                    "{:.6}\t{:.6}\t{}{}: {}",
+                    start.as_secs_f64(),
+                    end.as_secs_f64(),
+                    if cue.spoken { "" } else { "FAILED " },
+                    cue.line,
+                    label
+                )?;
+            }
+        }
+    }
+    std::fs::write(path, out).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// `HH:MM:SS,mmm`
+fn srt_time(d: Duration) -> String {
+    clock_time(d, ',')
+}
+
+/// `HH:MM:SS.mmm`
+fn vtt_time(d: Duration) -> String {
+    clock_time(d, '.')
+}
+
+/// `mm:ss.xx`; minutes simply grow past 59 rather than wrapping.
+fn lrc_time(d: Duration) -> String {
+    let cs = d.as_millis() / 10;
+    format!("{:02}:{:02}.{:02}", cs / 6000, cs / 100 % 60, cs % 100)
+}
+
+fn clock_time(d: Duration, ms_separator: char) -> String {
+    let ms = d.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms_separator,
+        ms % 1000
+    )
+}
@@ -0,0 +1,230 @@
+use std::{fmt::Write as _, path::Path, time::Duration};
+
+use anyhow::Context;
+
+use crate::{podcast, utils, verify};
+
+/// What one input file produced, filled in while it is processed so an aborted
+/// file still reports how far it got.
+#[derive(Debug, Default, Clone)]
+pub struct FileSummary {
+    pub file: String,
+    /// Source lines whose audio was written.
+    pub lines: usize,
+    pub failed: usize,
+    /// Source lines that never became audio (markup only, unpronounceable, ...).
+    pub skipped: usize,
+    pub audio: Duration,
+    /// Every MP3 segment opened for the file.
+    pub segments: Vec<String>,
+    /// Segments with titles and durations, once the file has finished.
+    pub episodes: Vec<podcast::Episode>,
+    pub wall: Duration,
+    pub finished: bool,
+    /// `--preview`: the source line the preview stopped after, when it stopped early.
+    pub preview_end: Option<usize>,
+}
+
+impl FileSummary {
+    pub fn new(file: impl Into<String>) -> Self {
+        Self {
+            file: file.into(),
+            ..Default::default()
+        }
+    }
+
+    fn bytes(&self) -> u64 {
+        self.segments
+            .iter()
+            .filter_map(|s| std::fs::metadata(s).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+}
+
+/// How `--throttle` worked out over the run.
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottleReport {
+    /// Duty cycle at the end of the run, after any SIGUSR1/SIGUSR2 steps.
+    pub target: f64,
+    /// Share of the synthesis slots' wall time spent synthesizing.
+    pub utilization: f64,
+    /// Wall time the idling added, roughly.
+    pub cost: Duration,
+}
+
+/// How `--upload` went.
+#[derive(Debug, Clone, Default)]
+pub struct UploadReport {
+    pub target: String,
+    pub files: usize,
+    pub bytes: u64,
+    /// Segments that didn't make it, even after retries.
+    pub failed: Vec<String>,
+    /// From the first upload starting to the last one finishing.
+    pub busy: Duration,
+    /// The part of `busy` after synthesis was done, i.e. not overlapped with it.
+    pub waited: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub files: Vec<FileSummary>,
+    /// Set when the run stopped before every file was done.
+    pub partial: bool,
+    pub wall: Duration,
+    /// MPEG channel mode every MP3 was encoded in.
+    pub channel_mode: &'static str,
+    /// `--encoder-quality` every MP3 was encoded at.
+    pub encoder_quality: &'static str,
+    pub throttle: Option<ThrottleReport>,
+    pub upload: Option<UploadReport>,
+    /// `--verify`: how the segments held up.
+    pub verify: Option<verify::Report>,
+    /// `--shard`, and the input files left to the other shards with theirs.
+    pub shard: Option<utils::Shard>,
+    pub other_shards: Vec<(String, usize)>,
+    /// `--skip-existing`: the input files earlier runs already have output for.
+    pub existing: Vec<String>,
+    /// `--hash-pcm`: the run-level hash and the lines it covers.
+    pub pcm_hash: Option<(String, usize)>,
+}
+
+impl RunSummary {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if self.partial {
+            out.push_str("PARTIAL RUN: stopped before all files were finished\n\n");
+        }
+        let _ = writeln!(
+            out,
+            "{:<40} {:>7} {:>6} {:>7} {:>9} {:>8} {:>10} {:>9}",
+            "file", "lines", "failed", "skipped", "audio", "segments", "size", "wall"
+        );
+
+        let mut total = FileSummary::new("TOTAL");
+        let mut total_bytes = 0;
+        for f in &self.files {
+            let bytes = f.bytes();
+            let name = if f.finished {
+                f.file.clone()
+            } else {
+                format!("{} (partial)", f.file)
+            };
+            let _ = writeln!(
+                out,
+                "{:<40} {:>7} {:>6} {:>7} {:>9} {:>8} {:>10} {:>9}",
+                name,
+                f.lines,
+                f.failed,
+                f.skipped,
+                utils::format_hms(f.audio),
+                f.segments.len(),
+                utils::format_bytes(bytes),
+                utils::format_hms(f.wall)
+            );
+            total.lines += f.lines;
+            total.failed += f.failed;
+            total.skipped += f.skipped;
+            total.audio += f.audio;
+            total.segments.extend(f.segments.iter().cloned());
+            total_bytes += bytes;
+        }
+        let _ = writeln!(
+            out,
+            "{:<40} {:>7} {:>6} {:>7} {:>9} {:>8} {:>10} {:>9}",
+            total.file,
+            total.lines,
+            total.failed,
+            total.skipped,
+            utils::format_hms(total.audio),
+            total.segments.len(),
+            utils::format_bytes(total_bytes),
+            utils::format_hms(self.wall)
+        );
+        let previews = self
+            .files
+            .iter()
+            .filter_map(|f| Some((&f.file, f.preview_end?)))
+            .collect::<Vec<_>>();
+        if !previews.is_empty() {
+            out.push('\n');
+        }
+        for (file, line) in previews {
+            let _ = writeln!(out, "Preview of {} ended after line {}", file, line);
+        }
+        if let Some(shard) = self.shard {
+            let _ = writeln!(
+                out,
+                "\nShard {}: {} files left to the other shards",
+                shard,
+                self.other_shards.len()
+            );
+            for (file, index) in &self.other_shards {
+                let _ = writeln!(out, "  {} (shard {}/{})", file, index, shard.total);
+            }
+        }
+        if !self.existing.is_empty() {
+            let _ = writeln!(
+                out,
+                "\nSkipped {} files with output from earlier runs",
+                self.existing.len()
+            );
+            for file in &self.existing {
+                let _ = writeln!(out, "  {}", file);
+            }
+        }
+        let _ = writeln!(
+            out,
+            "\nRealtime factor: {:.3} ({} of audio in {})",
+            self.wall.as_secs_f64() / total.audio.as_secs_f64().max(f64::EPSILON),
+            utils::format_hms(total.audio),
+            utils::format_hms(self.wall)
+        );
+        let _ = writeln!(
+            out,
+            "Encoded as {} MP3, {} quality",
+            self.channel_mode, self.encoder_quality
+        );
+        if let Some((hash, lines)) = &self.pcm_hash {
+            let _ = writeln!(out, "PCM hash over {} lines: {}", lines, hash);
+        }
+        if let Some(throttle) = &self.throttle {
+            let _ = writeln!(
+                out,
+                "Throttled to {:.0}%: synthesis busy {:.1}% of the time, about {} added",
+                throttle.target * 100.0,
+                throttle.utilization * 100.0,
+                utils::format_hms(throttle.cost)
+            );
+        }
+        if let Some(upload) = &self.upload {
+            let _ = writeln!(
+                out,
+                "Uploaded {} segments ({}) to {} over {}, {} of it alongside synthesis",
+                upload.files,
+                utils::format_bytes(upload.bytes),
+                upload.target,
+                utils::format_hms(upload.busy),
+                utils::format_hms(upload.busy.saturating_sub(upload.waited))
+            );
+            for failed in &upload.failed {
+                let _ = writeln!(out, "NOT UPLOADED: {}", failed);
+            }
+        }
+        if let Some(verify) = &self.verify {
+            out.push_str(&verify.render());
+        }
+        out
+    }
+
+    /// Log the table and write it to `summary.txt` in `dir`.
+    pub fn report(&self, dir: &Path) -> anyhow::Result<()> {
+        let table = self.render();
+        for line in table.lines() {
+            tracing::info!("{}", line);
+        }
+        let path = dir.join("summary.txt");
+        std::fs::write(&path, table).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
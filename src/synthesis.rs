@@ -0,0 +1,428 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use kokoro_tts::Voice;
+
+use crate::{lang, pipeline, preprocess, summary, text, tts, utils, voices, writer};
+
+/// Pause between the lines of text given to [`Synthesizer::to_mp3`].
+const LINE_PAUSE: Duration = Duration::from_millis(300);
+
+/// Everything that can go wrong in the library API.
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let error = morganite::Synthesizer::load("missing.onnx", "voices.bin", 1)
+///     .await
+///     .err()
+///     .unwrap();
+/// assert!(matches!(error, morganite::Error::ModelNotFound(_)));
+/// assert_eq!(error.to_string(), "Model file missing.onnx not found");
+/// # }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A model file doesn't exist.
+    ModelNotFound(PathBuf),
+    /// The voice doesn't parse, or the voice model doesn't have it.
+    Voice(String),
+    /// A setting out of range, e.g. speed or bitrate.
+    Setting(String),
+    /// An input that can't be read as text files.
+    Input(String),
+    /// Text with nothing to say.
+    EmptyText,
+    /// Loading the models or synthesizing failed.
+    Synthesis(anyhow::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ModelNotFound(path) => write!(f, "Model file {} not found", path.display()),
+            Self::Voice(e) => write!(f, "Invalid voice: {}", e),
+            Self::Setting(e) => write!(f, "Invalid setting: {}", e),
+            Self::Input(e) => write!(f, "Invalid input: {}", e),
+            Self::EmptyText => write!(f, "Text has nothing to say"),
+            Self::Synthesis(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Synthesis(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Synthesis(e)
+    }
+}
+
+/// What [`run`] reports while it works, through [`SynthesisJob::on_event`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Event<'a> {
+    FileStarted {
+        path: &'a Path,
+        /// Position of the file in the job, from 0.
+        index: usize,
+        total: usize,
+    },
+    FileFinished {
+        path: &'a Path,
+        summary: &'a summary::FileSummary,
+    },
+    /// The file's output is incomplete; the job goes on with the next one.
+    FileFailed {
+        path: &'a Path,
+        error: &'a anyhow::Error,
+    },
+}
+
+type EventHandler = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/// Where and how [`run`] writes its MP3s.
+///
+/// ```
+/// use morganite::{OutputConfig, SynthesisJob};
+///
+/// let output = OutputConfig {
+///     dir: "book".into(),
+///     bitrate: 96,
+/// };
+/// assert_eq!(OutputConfig::default().bitrate, 64);
+/// let job = SynthesisJob::new("kokoro-v1.1-zh.onnx", "voices-v1.1-zh.bin")
+///     .input("book.txt")
+///     .output(output);
+/// # drop(job);
+/// ```
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    /// `audio_000.mp3`, ... go here, in a folder per input file when there are several.
+    pub dir: PathBuf,
+    /// MP3 bitrate in kbps.
+    pub bitrate: u32,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("out"),
+            bitrate: 64,
+        }
+    }
+}
+
+/// The input files and settings for [`run`].
+///
+/// ```no_run
+/// # async fn demo() -> Result<(), morganite::Error> {
+/// let job = morganite::SynthesisJob::new("kokoro-v1.1-zh.onnx", "voices-v1.1-zh.bin")
+///     .input("book.txt")
+///     .voice("zf_048")
+///     .speed(1.1)
+///     .concurrency(4)
+///     .output_dir("book")
+///     .on_event(|event| println!("{:?}", event));
+/// let summary = morganite::run(job).await?;
+/// print!("{}", summary.render());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SynthesisJob {
+    tts_model: PathBuf,
+    voice_model: PathBuf,
+    inputs: Vec<PathBuf>,
+    voice: String,
+    speed: f32,
+    concurrency: Option<usize>,
+    output: OutputConfig,
+    on_event: Option<EventHandler>,
+}
+
+impl SynthesisJob {
+    pub fn new(tts_model: impl Into<PathBuf>, voice_model: impl Into<PathBuf>) -> Self {
+        Self {
+            tts_model: tts_model.into(),
+            voice_model: voice_model.into(),
+            inputs: Vec::new(),
+            voice: "zf_048".to_string(),
+            speed: 1.0,
+            concurrency: None,
+            output: OutputConfig::default(),
+            on_event: None,
+        }
+    }
+
+    /// Add a `.txt` file, or a folder whose `.txt` files are all read.
+    pub fn input(mut self, path: impl Into<PathBuf>) -> Self {
+        self.inputs.push(path.into());
+        self
+    }
+
+    pub fn voice(mut self, voice: impl Into<String>) -> Self {
+        self.voice = voice.into();
+        self
+    }
+
+    pub fn speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Engine sessions synthesizing at once; by default picked from the CPU count.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
+    pub fn output(mut self, output: OutputConfig) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.output.dir = dir.into();
+        self
+    }
+
+    pub fn bitrate(mut self, bitrate: u32) -> Self {
+        self.output.bitrate = bitrate;
+        self
+    }
+
+    /// Called for every [`Event`], on the task running the job.
+    pub fn on_event(mut self, handler: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Arc::new(handler));
+        self
+    }
+}
+
+/// Loaded models, ready to synthesize. Loading takes a while, so keep one around
+/// for repeated use.
+///
+/// ```no_run
+/// # async fn demo() -> Result<(), morganite::Error> {
+/// let synthesizer =
+///     morganite::Synthesizer::load("kokoro-v1.1-zh.onnx", "voices-v1.1-zh.bin", 2).await?;
+/// let mp3: Vec<u8> = synthesizer.to_mp3("你好，世界。", "zf_048", 1.0).await?;
+/// std::fs::write("hello.mp3", mp3).unwrap();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Synthesizer {
+    pipeline: pipeline::Pipeline,
+    voice_bin: voices::VoiceBin,
+}
+
+impl Synthesizer {
+    /// Load the models with `concurrency` engine sessions.
+    pub async fn load(
+        tts_model: impl AsRef<Path>,
+        voice_model: impl AsRef<Path>,
+        concurrency: usize,
+    ) -> Result<Self, Error> {
+        let (tts_model, voice_model) = (tts_model.as_ref(), voice_model.as_ref());
+        for model in [tts_model, voice_model] {
+            if !model.exists() {
+                return Err(Error::ModelNotFound(model.to_path_buf()));
+            }
+        }
+        if concurrency == 0 {
+            return Err(Error::Setting("concurrency must be at least 1".to_string()));
+        }
+        let voice_bin = voices::VoiceBin::load(voice_model)?;
+        let engine = tts::init_tts(
+            tts_model.display().to_string(),
+            voice_model.display().to_string(),
+            concurrency,
+        )
+        .await
+        .map_err(Error::Synthesis)?;
+        let engines = Arc::new(tts::Engines::new(engine, None));
+        let pipeline =
+            pipeline::Pipeline::bare(engines, concurrency, preprocess::Preprocessor::default());
+        Ok(Self {
+            pipeline,
+            voice_bin,
+        })
+    }
+
+    fn settings(
+        &self,
+        voice: &str,
+        speed: f32,
+        bitrate: u32,
+    ) -> Result<pipeline::FileSettings, Error> {
+        let voice: Voice = utils::parse_voice(voice).map_err(Error::Voice)?;
+        if let Some(e) = self.voice_bin.check(voice) {
+            return Err(Error::Voice(e));
+        }
+        let speed = utils::check_speed(speed).map_err(Error::Setting)?;
+        writer::default_mono_24k_config(bitrate)
+            .validate()
+            .map_err(|e| Error::Setting(format!("bitrate {}: {:?}", bitrate, e)))?;
+        Ok(pipeline::FileSettings {
+            voice,
+            speed,
+            bitrate,
+        })
+    }
+
+    /// Synthesize `text` line by line into one 64 kbps MP3.
+    pub async fn to_mp3(&self, text: &str, voice: &str, speed: f32) -> Result<Vec<u8>, Error> {
+        let settings = self.settings(voice, speed, 64)?;
+        let mut items = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| text::is_pronounceable(line))
+            .map(|line| text::Item {
+                text: line.to_string(),
+                source_line: None,
+                chapter: None,
+                pause_after: LINE_PAUSE,
+                voice: None,
+                speed: None,
+                lang: lang::detect(line).script,
+            })
+            .collect::<Vec<_>>();
+        let Some(last) = items.last_mut() else {
+            return Err(Error::EmptyText);
+        };
+        last.pause_after = Duration::ZERO;
+
+        let items = Arc::new(items);
+        let mut samples = Vec::new();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let (synthesized, ()) = tokio::join!(
+            self.pipeline
+                .synthesize("library".to_string(), items.clone(), &settings, None, tx),
+            async {
+                while let Some((idx, chunk)) = rx.recv().await {
+                    samples.extend_from_slice(&chunk.audio);
+                    if chunk.end.is_some() {
+                        let pause = items[idx].pause_after.as_secs_f64();
+                        samples.resize(
+                            samples.len() + (pause * writer::SAMPLE_RATE as f64) as usize,
+                            0.0,
+                        );
+                    }
+                }
+            }
+        );
+        synthesized?;
+        let config = writer::default_mono_24k_config(settings.bitrate);
+        let mp3 = tokio::task::spawn_blocking(move || writer::encode_clip(config, &samples))
+            .await
+            .map_err(|e| Error::Synthesis(anyhow::anyhow!("mp3 encoder panicked: {}", e)))??;
+        Ok(mp3)
+    }
+
+    /// Read every input of `job` into segmented MP3s, the way the command line
+    /// does. The job's model paths are ignored; this synthesizer's models are used.
+    pub async fn run(&self, job: &SynthesisJob) -> Result<summary::RunSummary, Error> {
+        let settings = self.settings(&job.voice, job.speed, job.output.bitrate)?;
+        let mut files = Vec::new();
+        let mut any_folder = false;
+        for input in &job.inputs {
+            let (found, folder) = utils::collect_txt_files(&input.display().to_string(), false)
+                .map_err(|e| Error::Input(format!("{:#}", e)))?;
+            files.extend(found);
+            any_folder |= folder;
+        }
+        if files.is_empty() {
+            return Err(Error::Input("no input files".to_string()));
+        }
+        let folder_mode = any_folder || files.len() > 1;
+        let emit = |event: Event| {
+            if let Some(handler) = &job.on_event {
+                handler(&event);
+            }
+        };
+
+        let start = Instant::now();
+        let mut run = summary::RunSummary {
+            channel_mode: writer::ChannelMode::Mono.name(),
+            encoder_quality: writer::EncoderQuality::Standard.name(),
+            ..Default::default()
+        };
+        let total = files.len();
+        for (index, path) in files.iter().enumerate() {
+            emit(Event::FileStarted { path, index, total });
+            let out_dir = if folder_mode {
+                job.output.dir.join(utils::file_stem_string(path))
+            } else {
+                job.output.dir.clone()
+            };
+            std::fs::create_dir_all(&out_dir).map_err(|e| {
+                Error::Input(format!("Failed to create {}: {}", out_dir.display(), e))
+            })?;
+            let file_start = Instant::now();
+            let mut file = summary::FileSummary::new(path.display().to_string());
+            let result = self
+                .pipeline
+                .process_file(
+                    path,
+                    &out_dir,
+                    folder_mode,
+                    &settings,
+                    &mut file,
+                    std::future::pending(),
+                )
+                .await;
+            file.wall = file_start.elapsed();
+            file.finished = result.is_ok();
+            match &result {
+                Ok(()) => emit(Event::FileFinished {
+                    path,
+                    summary: &file,
+                }),
+                Err(error) => {
+                    run.partial = true;
+                    emit(Event::FileFailed { path, error });
+                }
+            }
+            run.files.push(file);
+        }
+        run.wall = start.elapsed();
+        Ok(run)
+    }
+}
+
+/// Load the job's models and run it; see [`SynthesisJob`]. Settings and models
+/// are checked before anything is synthesized:
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let job = morganite::SynthesisJob::new("missing.onnx", "voices.bin").input("book.txt");
+/// match morganite::run(job).await {
+///     Err(morganite::Error::ModelNotFound(path)) => {
+///         assert_eq!(path.to_str(), Some("missing.onnx"))
+///     }
+///     other => panic!("expected a missing model, got {other:?}"),
+/// }
+/// # }
+/// ```
+pub async fn run(job: SynthesisJob) -> Result<summary::RunSummary, Error> {
+    let concurrency = job
+        .concurrency
+        .unwrap_or_else(|| utils::auto_concurrency(utils::available_cores()));
+    Synthesizer::load(&job.tts_model, &job.voice_model, concurrency)
+        .await?
+        .run(&job)
+        .await
+}
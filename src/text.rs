@@ -0,0 +1,308 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Take},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+
+use crate::{chapters, lang};
+
+/// A trimmed, non-empty input line together with its 1-based physical line number.
+#[derive(Debug, Clone)]
+pub struct SourceLine {
+    pub number: usize,
+    pub text: String,
+    /// Preceded by at least one blank line (the first line never is).
+    pub paragraph_start: bool,
+}
+
+pub fn read_non_empty_lines(path: &Path) -> anyhow::Result<Vec<SourceLine>> {
+    NonEmptyLines::open(path)?.collect()
+}
+
+/// Non-empty lines and their chars in `path`, read through once without keeping
+/// them, for progress totals.
+pub fn prescan(path: &Path) -> anyhow::Result<(u64, u64)> {
+    NonEmptyLines::open(path)?.try_fold((0, 0), |(lines, chars), line| {
+        anyhow::Ok((lines + 1, chars + line?.text.chars().count() as u64))
+    })
+}
+
+/// The lines of [`read_non_empty_lines`], read one at a time. The file is taken as
+/// it was when opened: nothing past its length then is read, and a file that
+/// changed on disk meanwhile is warned about once the end is reached.
+pub struct NonEmptyLines {
+    path: PathBuf,
+    reader: Take<BufReader<File>>,
+    /// Length and modification time when opened.
+    len: u64,
+    modified: Option<SystemTime>,
+    /// Warn at the end if the file changed; a second reader leaves it to the first.
+    check: bool,
+    number: usize,
+    after_blank: bool,
+    any: bool,
+    buf: String,
+    done: bool,
+}
+
+impl NonEmptyLines {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let f = File::open(path)
+            .with_context(|| format!("Failed to open text file {}", path.display()))?;
+        let meta = f
+            .metadata()
+            .with_context(|| format!("Failed to stat text file {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            reader: BufReader::new(f).take(meta.len()),
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            check: true,
+            number: 0,
+            after_blank: false,
+            any: false,
+            buf: String::new(),
+            done: false,
+        })
+    }
+
+    /// Another reader from the start of the same snapshot, e.g. to follow behind
+    /// this one.
+    pub fn reopen(&self) -> anyhow::Result<Self> {
+        let f = File::open(&self.path)
+            .with_context(|| format!("Failed to open text file {}", self.path.display()))?;
+        Ok(Self {
+            path: self.path.clone(),
+            reader: BufReader::new(f).take(self.len),
+            len: self.len,
+            modified: self.modified,
+            check: false,
+            number: 0,
+            after_blank: false,
+            any: false,
+            buf: String::new(),
+            done: false,
+        })
+    }
+
+    fn warn_if_changed(&self) {
+        let Ok(meta) = std::fs::metadata(&self.path) else {
+            tracing::warn!(
+                "{} went away while it was read; its first {} bytes were used",
+                self.path.display(),
+                self.len
+            );
+            return;
+        };
+        if meta.len() != self.len || meta.modified().ok() != self.modified {
+            tracing::warn!(
+                "{} changed on disk while it was read; only its first {} bytes as of the start were used, and lines rewritten meanwhile may be mixed in",
+                self.path.display(),
+                self.len
+            );
+        }
+    }
+}
+
+impl Iterator for NonEmptyLines {
+    type Item = anyhow::Result<SourceLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.buf.clear();
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => {
+                    self.done = true;
+                    if self.check {
+                        self.warn_if_changed();
+                    }
+                }
+                Ok(_) => {
+                    self.number += 1;
+                    let text = self.buf.trim();
+                    if text.is_empty() {
+                        self.after_blank = true;
+                        continue;
+                    }
+                    let line = SourceLine {
+                        number: self.number,
+                        text: text.to_string(),
+                        paragraph_start: self.after_blank && self.any,
+                    };
+                    self.after_blank = false;
+                    self.any = true;
+                    return Some(Ok(line));
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e).with_context(|| {
+                        format!(
+                            "Failed to read line {} of {}",
+                            self.number + 1,
+                            self.path.display()
+                        )
+                    }));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// One unit of synthesis, in output order.
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub text: String,
+    /// Physical source line; `None` for generated text such as announcements.
+    pub source_line: Option<usize>,
+    /// Set on the item that opens a chapter.
+    pub chapter: Option<Arc<chapters::Chapter>>,
+    /// Silence written after the item's audio.
+    pub pause_after: Duration,
+    /// Per-line voice tag override of the global voice.
+    pub voice: Option<Voice>,
+    /// Per-line `{speed=..}` override of the global speed.
+    pub speed: Option<f32>,
+    /// Detected script of the source line (of the text itself for generated items).
+    pub lang: lang::Script,
+}
+
+/// Anything the engine could actually say: letters (CJK included) or digits.
+pub fn is_pronounceable(text: &str) -> bool {
+    text.chars().any(char::is_alphanumeric)
+}
+
+/// Punctuation a long line may be cut after unless `--sentence-ends` says otherwise.
+pub const SENTENCE_ENDS: &str = "。！？!?；;…";
+/// Closing marks that stay with the sentence they end.
+pub const SENTENCE_CLOSERS: &str = "”’」』）)\"'";
+
+/// `--sentence-ends`: the punctuation a long line may be cut after. An ASCII `.`
+/// only counts before whitespace, a closing mark or the end of the text, so
+/// decimals like `3.14` stay whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentenceEnds(String);
+
+impl Default for SentenceEnds {
+    fn default() -> Self {
+        Self(SENTENCE_ENDS.to_string())
+    }
+}
+
+impl SentenceEnds {
+    /// `--sentence-ends` value parser.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let marks = s.trim();
+        if marks.is_empty() {
+            return Err("No sentence-end punctuation given".to_string());
+        }
+        match marks
+            .chars()
+            .find(|c| c.is_alphanumeric() || c.is_whitespace())
+        {
+            Some(c) => Err(format!("{c:?} is not punctuation")),
+            None => Ok(Self(marks.to_string())),
+        }
+    }
+
+    fn contains(&self, c: char) -> bool {
+        self.0.contains(c)
+    }
+
+    /// Whether `c`, followed by `next`, ends a sentence.
+    fn cuts_after(&self, c: char, next: Option<char>) -> bool {
+        self.contains(c)
+            && (c != '.'
+                || next.is_none_or(|next| next.is_whitespace() || SENTENCE_CLOSERS.contains(next)))
+    }
+}
+
+/// Cut a line into runs of whole sentences of up to about `target` chars, to be
+/// synthesized one after another. Cuts only fall after `ends` (and the closers
+/// that follow), never inside a sentence or at a comma, so a line without them is
+/// one piece. A longer sentence stays whole, and sentences with nothing to
+/// pronounce ride along with the previous run.
+pub fn sentence_pieces(text: &str, target: usize, ends: &SentenceEnds) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        current.push(c);
+        if ends.cuts_after(c, chars.peek().copied()) {
+            while let Some(&next) = chars.peek()
+                && (ends.contains(next) || SENTENCE_CLOSERS.contains(next))
+            {
+                current.push(next);
+                chars.next();
+            }
+            sentences.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        sentences.push(current);
+    }
+
+    let mut pieces: Vec<String> = Vec::new();
+    let mut len = 0;
+    for sentence in sentences {
+        let n = sentence.chars().count();
+        match pieces.last_mut() {
+            Some(last) if len + n <= target || !is_pronounceable(&sentence) => {
+                last.push_str(&sentence);
+                len += n;
+            }
+            _ => {
+                pieces.push(sentence);
+                len = n;
+            }
+        }
+    }
+    pieces
+}
+
+/// `text` ending in sentence punctuation, so that lines joined into one utterance
+/// keep a pause between them.
+pub fn with_sentence_end(text: &str) -> String {
+    let text = text.trim_end();
+    match text.chars().last() {
+        Some(c) if SENTENCE_ENDS.contains(c) || SENTENCE_CLOSERS.contains(c) => {
+            format!("{text} ")
+        }
+        Some(c) if c.is_ascii() => format!("{text}. "),
+        _ => format!("{text}。"),
+    }
+}
+
+/// Symbols that make up scene-break lines.
+const SCENE_BREAK_SYMBOLS: &str = "*＊-－—~～·•#＃=◇◆○●☆★";
+
+/// A line of at least three separator symbols such as `＊＊＊`, `- - -` or `◇◇◇`.
+pub fn is_scene_break(text: &str) -> bool {
+    let mut symbols = text.chars().filter(|c| !c.is_whitespace());
+    symbols.clone().count() >= 3 && symbols.all(|c| SCENE_BREAK_SYMBOLS.contains(c))
+}
+
+/// Turn a file stem like `chapter_05-the_end` into something speakable:
+/// separators become spaces and leading zeros are dropped from numbers.
+pub fn spoken_file_name(stem: &str) -> String {
+    let mut out = String::new();
+    let mut chars = stem.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '_' | '-' | '.' => {
+                if !out.is_empty() && !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            }
+            '0' if !out.ends_with(|p: char| p.is_ascii_digit())
+                && chars.peek().is_some_and(|n| n.is_ascii_digit()) => {}
+            _ => out.push(c),
+        }
+    }
+    out.trim_end().to_string()
+}
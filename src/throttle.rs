@@ -0,0 +1,103 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// How far one [`Throttle::adjust`] step (SIGUSR1 or SIGUSR2) moves the duty cycle.
+pub const STEP: f64 = 0.1;
+/// Lowest duty cycle stepping down reaches, unless `--throttle` started lower.
+pub const MIN_DUTY: f64 = 0.1;
+
+/// Keeps every synthesis slot busy for about `duty` of the time: after each call a
+/// slot idles, still holding its permit, in proportion to the work it just did.
+pub struct Throttle {
+    /// The duty cycle's `f64` bits, so it can change while slots read it.
+    duty: AtomicU64,
+    busy_us: AtomicU64,
+    idle_us: AtomicU64,
+}
+
+impl Throttle {
+    pub fn new(duty: f64) -> Self {
+        Self {
+            duty: AtomicU64::new(duty.to_bits()),
+            busy_us: AtomicU64::new(0),
+            idle_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn duty(&self) -> f64 {
+        f64::from_bits(self.duty.load(Ordering::Relaxed))
+    }
+
+    /// Move the duty cycle by `by`, within [`MIN_DUTY`] and 100%, from the next
+    /// call on. Returns the new duty cycle.
+    pub fn adjust(&self, by: f64) -> f64 {
+        let step = |bits: u64| {
+            let duty = f64::from_bits(bits);
+            Some((duty + by).clamp(MIN_DUTY.min(duty), 1.0).to_bits())
+        };
+        let previous = self
+            .duty
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, step)
+            .unwrap();
+        f64::from_bits(step(previous).unwrap())
+    }
+
+    /// A slot spent `busy` synthesizing; idle it for its share of the cycle.
+    pub async fn idle_after(&self, busy: Duration) {
+        let idle = busy.mul_f64(1.0 / self.duty() - 1.0);
+        self.busy_us
+            .fetch_add(busy.as_micros() as u64, Ordering::Relaxed);
+        self.idle_us
+            .fetch_add(idle.as_micros() as u64, Ordering::Relaxed);
+        tokio::time::sleep(idle).await;
+    }
+
+    /// Synthesis time over all slots.
+    pub fn busy(&self) -> Duration {
+        Duration::from_micros(self.busy_us.load(Ordering::Relaxed))
+    }
+
+    /// Time slots spent idling on purpose.
+    pub fn idle(&self) -> Duration {
+        Duration::from_micros(self.idle_us.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjust_steps_within_the_bounds() {
+        let throttle = Throttle::new(0.5);
+        assert!((throttle.adjust(STEP) - 0.6).abs() < 1e-9);
+        for _ in 0..10 {
+            throttle.adjust(STEP);
+        }
+        assert_eq!(throttle.duty(), 1.0);
+        for _ in 0..20 {
+            throttle.adjust(-STEP);
+        }
+        assert_eq!(throttle.duty(), MIN_DUTY);
+    }
+
+    #[test]
+    fn adjust_keeps_a_lower_starting_duty() {
+        let throttle = Throttle::new(0.05);
+        assert_eq!(throttle.adjust(-STEP), 0.05);
+        assert!((throttle.adjust(STEP) - 0.15).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn idling_follows_the_adjusted_duty() {
+        let throttle = Throttle::new(0.5);
+        throttle.idle_after(Duration::from_millis(10)).await;
+        assert_eq!(throttle.idle(), Duration::from_millis(10));
+        throttle.adjust(-0.25);
+        throttle.idle_after(Duration::from_millis(10)).await;
+        assert_eq!(throttle.idle(), Duration::from_millis(40));
+        assert_eq!(throttle.busy(), Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,227 @@
+use std::{path::Path, sync::Mutex, time::Duration};
+
+use anyhow::Context;
+use serde::Serialize;
+
+/// Synthesis performance of one item.
+#[derive(Debug, Clone, Serialize)]
+pub struct LineTiming {
+    pub file: String,
+    pub index: usize,
+    pub source_line: Option<usize>,
+    pub chars: usize,
+    pub synth_secs: f64,
+    /// Synthesis time until the first chunk was ready; below `synth_secs` for
+    /// lines streamed sentence by sentence.
+    pub first_audio_secs: f64,
+    pub audio_secs: f64,
+    /// Synthesis time over audio time; below 1 is faster than realtime.
+    pub rtf: f64,
+    /// Wait between submission and the start of synthesis.
+    pub queue_secs: f64,
+    /// Since the start of the file, when the consumer finished writing the item.
+    pub written_secs: f64,
+    pub cached: bool,
+    /// See [`crate::pipeline::Timing::attempt`].
+    pub attempt: u32,
+}
+
+/// Histogram buckets per factor of ten.
+const BUCKETS_PER_DECADE: usize = 5;
+/// Lower bound of the first bucket; smaller values land in it.
+const HISTOGRAM_MIN: f64 = 1e-3;
+/// Span of the buckets; larger values land in the last one.
+const HISTOGRAM_DECADES: usize = 6;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Streaming histogram over fixed log-scale buckets, so memory stays the same
+/// however many values go in. Bucket `i` starts at `min * 10^(i / buckets_per_decade)`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    min: f64,
+    buckets_per_decade: usize,
+    counts: Vec<u64>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            min: HISTOGRAM_MIN,
+            buckets_per_decade: BUCKETS_PER_DECADE,
+            counts: vec![0; BUCKETS_PER_DECADE * HISTOGRAM_DECADES],
+        }
+    }
+}
+
+impl Histogram {
+    pub fn add(&mut self, value: f64) {
+        let decades = (value.max(self.min) / self.min).log10();
+        let i = (decades * self.buckets_per_decade as f64).floor() as usize;
+        let last = self.counts.len() - 1;
+        self.counts[i.min(last)] += 1;
+    }
+
+    fn lower_bound(&self, i: usize) -> f64 {
+        self.min * 10f64.powf(i as f64 / self.buckets_per_decade as f64)
+    }
+
+    /// Value below which a share `p` of the values fall, to within a bucket.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let total = self.counts.iter().sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((total - 1) as f64 * p).round() as u64;
+        let mut seen = 0;
+        let i = self.counts.iter().position(|&c| {
+            seen += c;
+            seen > rank
+        })?;
+        // Geometric middle of the bucket
+        Some((self.lower_bound(i) * self.lower_bound(i + 1)).sqrt())
+    }
+
+    /// `p50 0.812s p90 1.93s p99 3.05s  0.631s..5.01s ▁▃█▆▂ ▁`
+    pub fn render(&self, unit: &str) -> String {
+        let (Some(first), Some(last)) = (
+            self.counts.iter().position(|&c| c > 0),
+            self.counts.iter().rposition(|&c| c > 0),
+        ) else {
+            return "no data".to_string();
+        };
+        let max = self.counts[first..=last].iter().max().copied().unwrap_or(1);
+        let spark = self.counts[first..=last]
+            .iter()
+            .map(|&c| match c {
+                0 => ' ',
+                c => SPARKS[(c * (SPARKS.len() as u64 - 1)).div_ceil(max) as usize],
+            })
+            .collect::<String>();
+        let value = |v: Option<f64>| format!("{:.3}{}", v.unwrap_or_default(), unit);
+        format!(
+            "p50 {} p90 {} p99 {}  {}..{} {}",
+            value(self.percentile(0.5)),
+            value(self.percentile(0.9)),
+            value(self.percentile(0.99)),
+            value(Some(self.lower_bound(first))),
+            value(Some(self.lower_bound(last + 1))),
+            spark
+        )
+    }
+}
+
+/// Latency distributions of one input file.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileHistograms {
+    pub file: String,
+    /// Synthesized (non-cached) items only.
+    pub synth_secs: Histogram,
+    pub rtf: Histogram,
+    /// Wait for a free synthesis slot, all items.
+    pub queue_secs: Histogram,
+}
+
+impl FileHistograms {
+    pub fn new(file: impl Into<String>) -> Self {
+        Self {
+            file: file.into(),
+            synth_secs: Histogram::default(),
+            rtf: Histogram::default(),
+            queue_secs: Histogram::default(),
+        }
+    }
+
+    pub fn record(&mut self, took: Duration, audio_secs: f64, queued: Duration, cached: bool) {
+        self.queue_secs.add(queued.as_secs_f64());
+        if cached || audio_secs <= 0.0 {
+            return;
+        }
+        self.synth_secs.add(took.as_secs_f64());
+        self.rtf.add(took.as_secs_f64() / audio_secs);
+    }
+
+    pub fn log(&self) {
+        tracing::info!("Synth time {}: {}", self.file, self.synth_secs.render("s"));
+        tracing::info!("RTF {}: {}", self.file, self.rtf.render(""));
+        tracing::info!("Queue wait {}: {}", self.file, self.queue_secs.render("s"));
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Aggregate {
+    lines: usize,
+    median_rtf: f64,
+    p95_rtf: f64,
+    /// Sum of per-item synthesis time, across all concurrent slots.
+    total_synth_secs: f64,
+    total_audio_secs: f64,
+    wall_secs: f64,
+    /// Items that only succeeded on a later attempt.
+    retried: usize,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    aggregate: Aggregate,
+    files: &'a [FileHistograms],
+    lines: &'a [LineTiming],
+}
+
+/// Run-wide collection of per-item timings, written out as `timings.json`.
+#[derive(Default)]
+pub struct Timings {
+    lines: Mutex<Vec<LineTiming>>,
+    files: Mutex<Vec<FileHistograms>>,
+}
+
+impl Timings {
+    pub fn push(&self, timing: LineTiming) {
+        self.lines.lock().unwrap().push(timing);
+    }
+
+    pub fn push_file(&self, histograms: FileHistograms) {
+        self.files.lock().unwrap().push(histograms);
+    }
+
+    /// Write all timings plus an aggregate over the synthesized (non-cached) items.
+    pub fn write(&self, path: &Path, wall: Duration) -> anyhow::Result<()> {
+        let lines = self.lines.lock().unwrap();
+        let files = self.files.lock().unwrap();
+        let mut rtfs = lines
+            .iter()
+            .filter(|l| !l.cached && l.audio_secs > 0.0)
+            .map(|l| l.rtf)
+            .collect::<Vec<_>>();
+        rtfs.sort_by(f64::total_cmp);
+        let percentile = |p: f64| {
+            if rtfs.is_empty() {
+                0.0
+            } else {
+                rtfs[((rtfs.len() - 1) as f64 * p).round() as usize]
+            }
+        };
+
+        let report = Report {
+            aggregate: Aggregate {
+                lines: lines.len(),
+                median_rtf: percentile(0.5),
+                p95_rtf: percentile(0.95),
+                total_synth_secs: lines.iter().map(|l| l.synth_secs).sum(),
+                total_audio_secs: lines.iter().map(|l| l.audio_secs).sum(),
+                wall_secs: wall.as_secs_f64(),
+                retried: lines.iter().filter(|l| l.attempt > 1).count(),
+            },
+            files: &files,
+            lines: &lines,
+        };
+        tracing::info!(
+            "Synthesis RTF median {:.3}, p95 {:.3}; {:.1}s synth time in {:.1}s wall time",
+            report.aggregate.median_rtf,
+            report.aggregate.p95_rtf,
+            report.aggregate.total_synth_secs,
+            report.aggregate.wall_secs
+        );
+        let json = serde_json::to_string_pretty(&report)?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
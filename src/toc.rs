@@ -0,0 +1,143 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{chapters::Chapter, podcast, utils};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TocEntry {
+    pub number: usize,
+    pub title: String,
+    /// Segment file, relative to the output folder.
+    pub segment: String,
+    /// Offset of the heading within `segment`, in seconds.
+    pub offset_secs: f64,
+    /// Physical line number of the heading in the source file.
+    pub source_line: usize,
+}
+
+/// Table of contents for one output folder, rewritten on every new entry so an
+/// aborted run still leaves an up-to-date `toc.txt` / `toc.json` behind.
+pub struct Toc {
+    dir: PathBuf,
+    entries: Vec<TocEntry>,
+}
+
+impl Toc {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Record `chapter` as starting at `offset` within `segment_path`.
+    pub fn push(
+        &mut self,
+        chapter: &Chapter,
+        segment_path: &Path,
+        offset: Duration,
+    ) -> anyhow::Result<()> {
+        let segment = segment_path
+            .strip_prefix(&self.dir)
+            .unwrap_or(segment_path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        self.entries.push(TocEntry {
+            number: chapter.number,
+            title: chapter.title.clone(),
+            segment,
+            offset_secs: offset.as_secs_f64(),
+            source_line: chapter.source_line,
+        });
+        self.flush()
+    }
+
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let mut txt = String::new();
+        for e in &self.entries {
+            writeln!(
+                txt,
+                "{:03}  {}  {}  {}",
+                e.number,
+                utils::format_hms(Duration::from_secs_f64(e.offset_secs)),
+                e.segment,
+                e.title
+            )?;
+        }
+        let txt_path = self.dir.join("toc.txt");
+        std::fs::write(&txt_path, txt)
+            .with_context(|| format!("Failed to write {}", txt_path.display()))?;
+
+        let json_path = self.dir.join("toc.json");
+        let json = serde_json::to_string_pretty(&self.entries)?;
+        std::fs::write(&json_path, json)
+            .with_context(|| format!("Failed to write {}", json_path.display()))?;
+        Ok(())
+    }
+}
+
+/// `--playlist`: the output folder's playlist, naming every segment in order.
+pub const PLAYLIST: &str = "playlist.m3u8";
+
+/// Write [`PLAYLIST`] in `dir` listing `episodes` (segments under `dir`) as an
+/// extended M3U with their titles and durations.
+pub fn write_playlist(dir: &Path, episodes: &[podcast::Episode]) -> anyhow::Result<()> {
+    let mut m3u = String::from("#EXTM3U\n");
+    for episode in episodes {
+        let path = Path::new(&episode.path);
+        writeln!(
+            m3u,
+            "#EXTINF:{},{}",
+            episode.duration.as_secs_f64().round(),
+            episode.title
+        )?;
+        writeln!(
+            m3u,
+            "{}",
+            path.strip_prefix(dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/")
+        )?;
+    }
+    let path = dir.join(PLAYLIST);
+    std::fs::write(&path, m3u).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playlist_names_the_segments_relative_to_the_folder() {
+        let dir = std::env::temp_dir().join(format!("morganite-{}-playlist", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let episode = |name: &str, title: &str, secs| podcast::Episode {
+            path: dir.join(name).to_string_lossy().into_owned(),
+            title: title.to_string(),
+            duration: Duration::from_secs_f64(secs),
+        };
+        write_playlist(
+            &dir,
+            &[
+                episode("012_风雪夜.mp3", "风雪夜 (part 1)", 7199.6),
+                episode("012_风雪夜_b.mp3", "风雪夜 (part 2)", 12.2),
+            ],
+        )
+        .unwrap();
+        let m3u = std::fs::read_to_string(dir.join(PLAYLIST)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            m3u,
+            "#EXTM3U\n\
+             #EXTINF:7200,风雪夜 (part 1)\n012_风雪夜.mp3\n\
+             #EXTINF:12,风雪夜 (part 2)\n012_风雪夜_b.mp3\n"
+        );
+    }
+}
@@ -1,19 +1,273 @@
-use kokoro_tts::KokoroTts;
-use tokio::sync::OnceCell;
-use tracing_unwrap::ResultExt;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-static KOKORO_TTS: OnceCell<KokoroTts> = OnceCell::const_new();
+use arc_swap::ArcSwap;
+use kokoro_tts::{KokoroTts, Voice};
+use tokio::task::JoinSet;
 
+use crate::{lang, utils, wav, writer};
+
+/// Settings for the audio the engines produce.
+#[derive(Debug, Clone, Copy)]
+pub struct EngineOptions {
+    /// Rate the model's samples come out at; anything but [`writer::SAMPLE_RATE`]
+    /// is resampled to it.
+    pub sample_rate: u32,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: writer::SAMPLE_RATE,
+        }
+    }
+}
+
+/// Native rates the linear resampler is trusted with.
+const ENGINE_RATES: std::ops::RangeInclusive<u32> = 8_000..=192_000;
+
+impl EngineOptions {
+    /// Check that the engine's rate can be brought to the output's.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            ENGINE_RATES.contains(&self.sample_rate),
+            "The engine's {} Hz can't be resampled to the {} Hz output (supported: {} to {} Hz)",
+            self.sample_rate,
+            writer::SAMPLE_RATE,
+            ENGINE_RATES.start(),
+            ENGINE_RATES.end()
+        );
+        Ok(())
+    }
+}
+
+/// Load one engine with a pool of `concurrency` sessions.
+///
+/// kokoro_tts sets up the ort sessions itself and `new_with_pool` takes no session
+/// options, so the sessions run on whichever execution providers kokoro_tts
+/// registers; nothing here selects or reports one.
 pub async fn init_tts(
     tts_model: String,
     voice_model: String,
     concurrency: usize,
-) -> &'static KokoroTts {
-    KOKORO_TTS
-        .get_or_init(|| async {
-            KokoroTts::new_with_pool(tts_model, voice_model, concurrency)
-                .await
-                .expect_or_log("Failed to initialize KokoroTTS engine")
-        })
+) -> anyhow::Result<KokoroTts> {
+    KokoroTts::new_with_pool(tts_model, voice_model, concurrency)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to initialize KokoroTTS engine: {:?}", e))
+}
+
+/// The model files and settings [`Engines`] are built from, kept so a reload can
+/// load the same paths again.
+#[derive(Clone)]
+pub struct EngineSpec {
+    pub tts_model: String,
+    pub voice_model: String,
+    /// `--tts-model-en`, `--voice-model-en` and `--voice-en`.
+    pub english: Option<(String, String, Voice)>,
+    pub options: EngineOptions,
+    pub concurrency: usize,
+}
+
+impl EngineSpec {
+    pub async fn build(&self) -> anyhow::Result<Engines> {
+        let primary = init_tts(
+            self.tts_model.clone(),
+            self.voice_model.clone(),
+            self.concurrency,
+        )
+        .await?;
+        tracing::info!("Initialized KokoroTTS engine");
+
+        // Both engines draw from the same per-file semaphore, so total load stays bounded
+        let english = match &self.english {
+            Some((tts_model, voice_model, voice)) => {
+                let engine = init_tts(tts_model.clone(), voice_model.clone(), self.concurrency)
+                    .await
+                    .map_err(|e| e.context("English engine"))?;
+                tracing::info!("Initialized English KokoroTTS engine");
+                Some(EnglishEngine {
+                    engine,
+                    voice: *voice,
+                })
+            }
+            None => None,
+        };
+        if self.options.sample_rate != writer::SAMPLE_RATE {
+            tracing::info!(
+                "Engine outputs {} Hz, resampling every clip to the {} Hz output",
+                self.options.sample_rate,
+                writer::SAMPLE_RATE
+            );
+        }
+        Ok(Engines::new(primary, english).with_sample_rate(self.options.sample_rate))
+    }
+}
+
+/// The engines in use, replaced whole by [`reload`]. Work takes a snapshot with
+/// [`EngineCell::load`] and finishes on it, so replaced engines are only dropped
+/// once the last call on them is done. Clones share the cell, so a reload reaches
+/// every pipeline holding one.
+#[derive(Clone)]
+pub struct EngineCell(Arc<ArcSwap<Engines>>);
+
+impl EngineCell {
+    pub fn new(engines: Arc<Engines>) -> Self {
+        Self(Arc::new(ArcSwap::new(engines)))
+    }
+
+    pub fn load(&self) -> Arc<Engines> {
+        self.0.load_full()
+    }
+}
+
+/// Build fresh engines from `spec`, warm them up with `voice` and switch `cell`
+/// over to them. Until then, and for good if anything fails, the engines already
+/// in `cell` keep serving. Returns how long it took.
+pub async fn reload(
+    cell: &EngineCell,
+    spec: &EngineSpec,
+    voice: Voice,
+) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    let engines = Arc::new(spec.build().await?);
+    warm_up(engines.clone(), voice, spec.concurrency)
         .await
+        .map_err(|e| e.context("Warm-up of the reloaded engines failed"))?;
+    cell.0.store(engines);
+    Ok(start.elapsed())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    Primary,
+    English,
+}
+
+impl EngineKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EngineKind::Primary => "primary",
+            EngineKind::English => "en",
+        }
+    }
+}
+
+/// A secondary engine for English lines, with its own voice.
+pub struct EnglishEngine {
+    pub engine: KokoroTts,
+    pub voice: Voice,
+}
+
+/// All loaded engines. Lines go to the primary engine unless they are clearly
+/// English and an English engine is loaded.
+pub struct Engines {
+    pub primary: KokoroTts,
+    pub english: Option<EnglishEngine>,
+    /// Native rate of the engines' samples; see [`EngineOptions::sample_rate`].
+    sample_rate: u32,
+}
+
+impl Engines {
+    pub fn new(primary: KokoroTts, english: Option<EnglishEngine>) -> Self {
+        Self {
+            primary,
+            english,
+            sample_rate: writer::SAMPLE_RATE,
+        }
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Synthesize `text` on the engine for `kind`, at [`writer::SAMPLE_RATE`].
+    pub async fn synth(
+        &self,
+        kind: EngineKind,
+        text: String,
+        voice: Voice,
+    ) -> anyhow::Result<(Vec<f32>, Duration)> {
+        let (audio, took) = self
+            .get(kind)
+            .synth::<String>(text, voice)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        let audio = match self.sample_rate {
+            writer::SAMPLE_RATE => audio,
+            rate => wav::resample(&audio, 1, rate, writer::SAMPLE_RATE),
+        };
+        Ok((audio, took))
+    }
+
+    /// Pick the engine for a line from its detected script (see [`lang::detect`]).
+    pub fn route(&self, script: lang::Script, text: &str) -> EngineKind {
+        if self.english.is_none() {
+            return EngineKind::Primary;
+        }
+        match script {
+            lang::Script::En => EngineKind::English,
+            lang::Script::Mixed => {
+                tracing::debug!("Mixed-script line routed to primary engine: {}", text);
+                EngineKind::Primary
+            }
+            _ => EngineKind::Primary,
+        }
+    }
+
+    pub fn get(&self, kind: EngineKind) -> &KokoroTts {
+        match (kind, &self.english) {
+            (EngineKind::English, Some(en)) => &en.engine,
+            _ => &self.primary,
+        }
+    }
+}
+
+/// Throwaway utterances for [`warm_up`].
+const WARM_UP_TEXT: &str = "你好。";
+const WARM_UP_TEXT_EN: &str = "Hello.";
+
+/// The warm-up utterance in `voice`'s language: Chinese for the Chinese voices,
+/// English for the rest, so an English model is never warmed up on Chinese.
+fn warm_up_text(voice: Voice) -> &'static str {
+    if utils::voice_name(voice).starts_with('z') {
+        WARM_UP_TEXT
+    } else {
+        WARM_UP_TEXT_EN
+    }
+}
+
+/// Synthesize a throwaway utterance on each of the `sessions` pooled sessions of
+/// every engine at once, so session setup and first-run costs (and session
+/// failures) show up before the real text. Returns how long it took.
+pub async fn warm_up(
+    engines: Arc<Engines>,
+    voice: Voice,
+    sessions: usize,
+) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    let mut set = JoinSet::new();
+    for _ in 0..sessions {
+        let engines = engines.clone();
+        set.spawn(async move {
+            engines
+                .primary
+                .synth::<String>(warm_up_text(voice).to_string(), voice)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))?;
+            if let Some(en) = &engines.english {
+                en.engine
+                    .synth::<String>(warm_up_text(en.voice).to_string(), en.voice)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("English engine: {}", e))?;
+            }
+            anyhow::Ok(())
+        });
+    }
+    while let Some(r) = set.join_next().await {
+        r??;
+    }
+    Ok(start.elapsed())
 }
@@ -0,0 +1,263 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::utils;
+
+/// Shortest time spent at a slot count before it is judged.
+const MIN_DWELL: Duration = Duration::from_secs(30);
+/// Longest dwell; reached after repeated reversals or flat results.
+const MAX_DWELL: Duration = Duration::from_secs(300);
+/// Change in throughput that counts as better or worse rather than noise.
+const MARGIN: f64 = 0.05;
+/// Back off once the time per line grows this much over the first measurement.
+const LATENCY_LIMIT: f64 = 2.0;
+/// Back off once resident memory grows this much over the first measurement.
+const MEMORY_LIMIT: f64 = 1.5;
+
+/// `--concurrency auto-tune`: hill-climbs the number of synthesis slots in use
+/// between `min` and the pool size, judging each setting by its chars per second.
+/// The dwell at a setting doubles whenever a move doesn't pay off, so the tuner
+/// settles instead of oscillating.
+pub struct AutoTuner {
+    slots: Arc<Semaphore>,
+    min: usize,
+    max: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    limit: usize,
+    /// Permits held back to keep only `limit` slots usable.
+    parked: Vec<OwnedSemaphorePermit>,
+    step: isize,
+    dwell: Duration,
+    window_start: Instant,
+    chars: u64,
+    lines: u64,
+    synth: Duration,
+    /// Slot count and chars per second of the previous window.
+    previous: Option<(usize, f64)>,
+    baseline_latency: Option<f64>,
+    baseline_rss: Option<u64>,
+}
+
+impl AutoTuner {
+    pub fn new(start: usize, min: usize, max: usize) -> Self {
+        let slots = Arc::new(Semaphore::new(max));
+        let parked = (start..max)
+            .map(|_| slots.clone().try_acquire_owned().unwrap())
+            .collect();
+        Self {
+            slots,
+            min,
+            max,
+            state: Mutex::new(State {
+                limit: start,
+                parked,
+                step: 1,
+                dwell: MIN_DWELL,
+                window_start: Instant::now(),
+                chars: 0,
+                lines: 0,
+                synth: Duration::ZERO,
+                previous: None,
+                baseline_latency: None,
+                baseline_rss: None,
+            }),
+        }
+    }
+
+    /// Slots currently in use.
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Wait for a free synthesis slot.
+    pub async fn slot(&self) -> anyhow::Result<OwnedSemaphorePermit> {
+        Ok(self.slots.clone().acquire_owned().await?)
+    }
+
+    /// A synthesis call of `chars` chars finished after `took`.
+    pub fn record(&self, chars: usize, took: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.chars += chars as u64;
+        state.lines += 1;
+        state.synth += took;
+        self.park(&mut state);
+        let elapsed = state.window_start.elapsed();
+        if elapsed >= state.dwell {
+            self.adjust(&mut state, elapsed, resident_bytes());
+        }
+    }
+
+    /// Judge the window that ran for `elapsed` and pick the next slot count.
+    fn adjust(&self, state: &mut State, elapsed: Duration, rss: Option<u64>) {
+        let rate = state.chars as f64 / elapsed.as_secs_f64();
+        let latency = state.synth.as_secs_f64() / state.lines as f64;
+        let baseline_latency = *state.baseline_latency.get_or_insert(latency);
+        let baseline_rss = rss.map(|rss| *state.baseline_rss.get_or_insert(rss));
+        let limit = state.limit;
+
+        let target = if latency > baseline_latency * LATENCY_LIMIT
+            || rss
+                .zip(baseline_rss)
+                .is_some_and(|(rss, base)| rss as f64 > base as f64 * MEMORY_LIMIT)
+        {
+            tracing::warn!(
+                "Auto-tune: {:.2}s per line (started at {:.2}s), {} resident; backing off from {} slots",
+                latency,
+                baseline_latency,
+                rss.map_or("unknown".to_string(), utils::format_bytes),
+                limit
+            );
+            state.step = -1;
+            limit.saturating_sub(1)
+        } else {
+            match state.previous {
+                Some((previous, previous_rate)) if rate < previous_rate * (1.0 - MARGIN) => {
+                    tracing::info!(
+                        "Auto-tune: {:.1} chars/s at {} slots, down from {:.1} at {}; going back",
+                        rate,
+                        limit,
+                        previous_rate,
+                        previous
+                    );
+                    state.step = -state.step;
+                    state.dwell = (state.dwell * 2).min(MAX_DWELL);
+                    previous
+                }
+                Some((previous, previous_rate))
+                    if rate <= previous_rate * (1.0 + MARGIN) && state.dwell < MAX_DWELL =>
+                {
+                    tracing::info!(
+                        "Auto-tune: {:.1} chars/s at {} slots, about the same as {:.1} at {}; staying",
+                        rate,
+                        limit,
+                        previous_rate,
+                        previous
+                    );
+                    state.dwell = (state.dwell * 2).min(MAX_DWELL);
+                    limit
+                }
+                _ => {
+                    tracing::info!(
+                        "Auto-tune: {:.1} chars/s at {} slots; trying {}",
+                        rate,
+                        limit,
+                        limit.saturating_add_signed(state.step)
+                    );
+                    limit.saturating_add_signed(state.step)
+                }
+            }
+        };
+
+        // Bounce off the bounds rather than leaving them
+        let target = if (self.min..=self.max).contains(&target) {
+            target
+        } else {
+            state.step = -state.step;
+            limit
+        };
+        state.previous = Some((limit, rate));
+        state.limit = target;
+        self.park(state);
+        state.window_start = Instant::now();
+        state.chars = 0;
+        state.lines = 0;
+        state.synth = Duration::ZERO;
+    }
+
+    /// Hold back or release permits until `limit` slots are usable. Slots still in
+    /// use are parked on a later call, once they come free.
+    fn park(&self, state: &mut State) {
+        while state.parked.len() > self.max - state.limit {
+            state.parked.pop();
+        }
+        while state.parked.len() < self.max - state.limit {
+            match self.slots.clone().try_acquire_owned() {
+                Ok(permit) => state.parked.push(permit),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// Resident set size of this process, where the platform tells.
+fn resident_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(pages * 4096)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chars per second of a simulated workload that peaks at 4 slots.
+    fn peaks_at_four(slots: usize) -> f64 {
+        [0.0, 100.0, 190.0, 260.0, 300.0, 290.0, 270.0, 250.0, 230.0][slots]
+    }
+
+    /// Run one full window at the current setting, with `latency` seconds per
+    /// line, and return the setting picked next.
+    fn window(tuner: &AutoTuner, rate: impl Fn(usize) -> f64, latency: f64) -> usize {
+        let mut state = tuner.state.lock().unwrap();
+        let elapsed = state.dwell;
+        state.lines = 100;
+        state.chars = (rate(state.limit) * elapsed.as_secs_f64()) as u64;
+        state.synth = Duration::from_secs_f64(latency * state.lines as f64);
+        tuner.adjust(&mut state, elapsed, None);
+        state.limit
+    }
+
+    #[test]
+    fn climbs_while_throughput_improves() {
+        let tuner = AutoTuner::new(1, 1, 8);
+        let limits: Vec<usize> = (0..4).map(|_| window(&tuner, peaks_at_four, 1.0)).collect();
+        assert_eq!(limits, [2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn settles_around_the_peak() {
+        let tuner = AutoTuner::new(1, 1, 8);
+        let limits: Vec<usize> = (0..60)
+            .map(|_| window(&tuner, peaks_at_four, 1.0))
+            .collect();
+        // Flat results and reversals stretch the dwell until moves are rare
+        assert_eq!(tuner.state.lock().unwrap().dwell, MAX_DWELL);
+        assert!(
+            limits[10..].iter().all(|limit| (3..=6).contains(limit)),
+            "{limits:?}"
+        );
+    }
+
+    #[test]
+    fn stays_within_the_bounds() {
+        let tuner = AutoTuner::new(2, 2, 4);
+        for _ in 0..30 {
+            let limit = window(&tuner, |slots| 100.0 * slots as f64, 1.0);
+            assert!((2..=4).contains(&limit));
+        }
+    }
+
+    #[test]
+    fn backs_off_when_lines_slow_down() {
+        let tuner = AutoTuner::new(4, 1, 8);
+        window(&tuner, peaks_at_four, 1.0);
+        let limits: Vec<usize> = (0..3).map(|_| window(&tuner, peaks_at_four, 2.5)).collect();
+        assert_eq!(limits, [4, 3, 2]);
+    }
+
+    #[test]
+    fn only_the_limit_is_usable() {
+        let tuner = AutoTuner::new(3, 1, 8);
+        assert_eq!(tuner.slots.available_permits(), 3);
+        window(&tuner, peaks_at_four, 1.0);
+        assert_eq!(tuner.limit(), 4);
+        assert_eq!(tuner.slots.available_permits(), 4);
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use base64::Engine as _;
+use sha2::{Digest, Sha256};
+use tokio::{
+    sync::{Semaphore, mpsc, oneshot},
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::{summary, writer};
+
+/// Wait before the first retry; doubled for each one after.
+const BACKOFF: Duration = Duration::from_secs(1);
+
+/// Where `--upload` sends the segments.
+enum Target {
+    /// `s3://bucket/prefix/`, with credentials and region from the AWS
+    /// environment/config chain.
+    S3 {
+        client: aws_sdk_s3::Client,
+        bucket: String,
+        prefix: String,
+    },
+    /// `https://host/path/` (or `webdav(s)://`), with credentials from the URL or
+    /// `MORGANITE_WEBDAV_USER`/`MORGANITE_WEBDAV_PASSWORD`.
+    WebDav {
+        client: reqwest::Client,
+        base: reqwest::Url,
+        auth: Option<(String, String)>,
+        /// Collections known to exist, so each is only created once.
+        collections: Mutex<HashSet<String>>,
+    },
+}
+
+impl Target {
+    async fn parse(url: &str) -> anyhow::Result<Self> {
+        if let Some(rest) = url.strip_prefix("s3://") {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            anyhow::ensure!(!bucket.is_empty(), "--upload {} has no bucket", url);
+            let mut prefix = prefix.to_string();
+            if !prefix.is_empty() && !prefix.ends_with('/') {
+                prefix.push('/');
+            }
+            let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+            return Ok(Self::S3 {
+                client: aws_sdk_s3::Client::new(&config),
+                bucket: bucket.to_string(),
+                prefix,
+            });
+        }
+
+        let url = if let Some(rest) = url.strip_prefix("webdavs://") {
+            format!("https://{}", rest)
+        } else if let Some(rest) = url.strip_prefix("webdav://") {
+            format!("http://{}", rest)
+        } else {
+            url.to_string()
+        };
+        let mut base = reqwest::Url::parse(&url).with_context(|| format!("--upload {}", url))?;
+        anyhow::ensure!(
+            matches!(base.scheme(), "http" | "https"),
+            "--upload takes s3://bucket/prefix/ or a WebDAV http(s):// URL, not {}",
+            url
+        );
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        let auth = if base.username().is_empty() {
+            std::env::var("MORGANITE_WEBDAV_USER").ok().map(|user| {
+                let password = std::env::var("MORGANITE_WEBDAV_PASSWORD").unwrap_or_default();
+                (user, password)
+            })
+        } else {
+            Some((
+                base.username().to_string(),
+                base.password().unwrap_or_default().to_string(),
+            ))
+        };
+        // Kept out of the URL so it never shows up in the log
+        let _ = base.set_username("");
+        let _ = base.set_password(None);
+        Ok(Self::WebDav {
+            client: reqwest::Client::new(),
+            base,
+            auth,
+            collections: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::S3 { bucket, prefix, .. } => format!("s3://{}/{}", bucket, prefix),
+            Self::WebDav { base, .. } => base.to_string(),
+        }
+    }
+
+    /// Upload `path` under `key` and check that the copy is complete.
+    async fn put(&self, path: &Path, key: &str) -> anyhow::Result<u64> {
+        let body = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let len = body.len() as u64;
+        match self {
+            Self::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                let key = format!("{}{}", prefix, key);
+                // S3 rejects the upload if the body doesn't match the checksum
+                let checksum =
+                    base64::engine::general_purpose::STANDARD.encode(Sha256::digest(&body));
+                client
+                    .put_object()
+                    .bucket(bucket)
+                    .key(&key)
+                    .content_type("audio/mpeg")
+                    .checksum_sha256(checksum)
+                    .body(body.into())
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        anyhow::anyhow!("{}", aws_sdk_s3::error::DisplayErrorContext(e))
+                    })?;
+                let head = client
+                    .head_object()
+                    .bucket(bucket)
+                    .key(&key)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        anyhow::anyhow!("{}", aws_sdk_s3::error::DisplayErrorContext(e))
+                    })?;
+                anyhow::ensure!(
+                    head.content_length() == Some(len as i64),
+                    "Uploaded size {:?} doesn't match {} bytes",
+                    head.content_length(),
+                    len
+                );
+            }
+            Self::WebDav {
+                client,
+                base,
+                auth,
+                collections,
+            } => {
+                let request = |method: reqwest::Method, url: reqwest::Url| {
+                    let request = client.request(method, url);
+                    match auth {
+                        Some((user, password)) => request.basic_auth(user, Some(password)),
+                        None => request,
+                    }
+                };
+                // Servers don't create missing parent collections on PUT
+                let parents = key.rsplit_once('/').map_or("", |(parents, _)| parents);
+                let mut dir = String::new();
+                for part in parents.split('/').filter(|p| !p.is_empty()) {
+                    dir.push_str(part);
+                    dir.push('/');
+                    if collections.lock().unwrap().contains(&dir) {
+                        continue;
+                    }
+                    let status = request(
+                        reqwest::Method::from_bytes(b"MKCOL").unwrap(),
+                        base.join(&dir)?,
+                    )
+                    .send()
+                    .await?
+                    .status();
+                    // 405: the collection is already there
+                    anyhow::ensure!(
+                        status.is_success() || status == reqwest::StatusCode::METHOD_NOT_ALLOWED,
+                        "Creating {} failed: {}",
+                        dir,
+                        status
+                    );
+                    collections.lock().unwrap().insert(dir.clone());
+                }
+                let url = base.join(key)?;
+                request(reqwest::Method::PUT, url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, "audio/mpeg")
+                    .body(body)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                let head = request(reqwest::Method::HEAD, url)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                let size = head
+                    .headers()
+                    .get(reqwest::header::CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<u64>().ok());
+                anyhow::ensure!(
+                    size == Some(len),
+                    "Uploaded size {:?} doesn't match {} bytes",
+                    size,
+                    len
+                );
+            }
+        }
+        Ok(len)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Transfers running at once.
+    pub concurrency: usize,
+    /// Further attempts after a failed one.
+    pub retries: u32,
+    /// Delete the local file once its upload is confirmed.
+    pub remove_local: bool,
+}
+
+/// `--upload`: sends every segment to the target as soon as it is closed, next
+/// to synthesis, and reports on it at the end of the run.
+pub struct Uploader {
+    tx: mpsc::UnboundedSender<PathBuf>,
+    done: oneshot::Sender<()>,
+    task: JoinHandle<summary::UploadReport>,
+}
+
+impl Uploader {
+    pub async fn start(url: &str, options: Options) -> anyhow::Result<Self> {
+        let target = Arc::new(Target::parse(url).await?);
+        tracing::info!("Uploading segments to {}", target.describe());
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (done, done_rx) = oneshot::channel();
+        let task = tokio::spawn(run(target, options, rx, done_rx));
+        Ok(Self { tx, done, task })
+    }
+
+    /// For [`writer::Mp3Splitter::with_segment_hook`]: queue each closed segment.
+    pub fn hook(&self) -> writer::SegmentHook {
+        let tx = self.tx.clone();
+        Arc::new(move |segment: &str| {
+            let _ = tx.send(PathBuf::from(segment));
+        })
+    }
+
+    /// Wait for every queued upload, once no more segments are coming.
+    pub async fn finish(self) -> summary::UploadReport {
+        let _ = self.done.send(());
+        self.task.await.unwrap_or_else(|e| summary::UploadReport {
+            failed: vec![format!("upload task panicked: {}", e)],
+            ..Default::default()
+        })
+    }
+}
+
+async fn run(
+    target: Arc<Target>,
+    options: Options,
+    mut rx: mpsc::UnboundedReceiver<PathBuf>,
+    mut done: oneshot::Receiver<()>,
+) -> summary::UploadReport {
+    let slots = Arc::new(Semaphore::new(options.concurrency));
+    let mut uploads = JoinSet::new();
+    let mut first = None;
+    let mut spawn = |path: PathBuf| {
+        first.get_or_insert_with(Instant::now);
+        let (target, slots) = (target.clone(), slots.clone());
+        uploads.spawn(async move {
+            let _slot = slots.acquire_owned().await.unwrap();
+            let result = upload(&target, &path, options).await;
+            (path, result)
+        });
+    };
+    loop {
+        tokio::select! {
+            Some(path) = rx.recv() => spawn(path),
+            _ = &mut done => break,
+        }
+    }
+    while let Ok(path) = rx.try_recv() {
+        spawn(path);
+    }
+
+    let synthesis_done = Instant::now();
+    if !uploads.is_empty() {
+        tracing::info!("Waiting for {} uploads to finish", uploads.len());
+    }
+    let mut report = summary::UploadReport {
+        target: target.describe(),
+        ..Default::default()
+    };
+    while let Some(joined) = uploads.join_next().await {
+        match joined {
+            Ok((_, Ok(bytes))) => {
+                report.files += 1;
+                report.bytes += bytes;
+            }
+            Ok((path, Err(e))) => {
+                tracing::error!("Upload of {} failed: {:#}", path.display(), e);
+                report.failed.push(path.display().to_string());
+            }
+            Err(e) => report.failed.push(format!("upload task panicked: {}", e)),
+        }
+    }
+    if let Some(first) = first {
+        report.busy = first.elapsed();
+        report.waited = synthesis_done.elapsed();
+    }
+    report.failed.sort();
+    report
+}
+
+/// Upload one segment with retries, then drop the local copy if asked to.
+async fn upload(target: &Target, path: &Path, options: Options) -> anyhow::Result<u64> {
+    let key = key(path);
+    let mut delay = BACKOFF;
+    let mut attempt = 0;
+    let bytes = loop {
+        attempt += 1;
+        match target.put(path, &key).await {
+            Ok(bytes) => break bytes,
+            Err(e) if attempt <= options.retries => {
+                tracing::warn!(
+                    "Upload of {} failed (attempt {}), retrying in {:?}: {:#}",
+                    path.display(),
+                    attempt,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    tracing::info!("Uploaded {}", path.display());
+    if options.remove_local
+        && let Err(e) = tokio::fs::remove_file(path).await
+    {
+        tracing::warn!("Failed to remove {}: {}", path.display(), e);
+    }
+    Ok(bytes)
+}
+
+/// The remote name of `path`: its path under the working directory, which keeps
+/// the run folder layout.
+fn key(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -1,117 +1,676 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
 use kokoro_tts::Voice;
+use sha2::Digest;
+
+/// Speed range the engine produces usable audio for.
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2.0;
+
+/// Resolves the voice names a run accepts: canonical names (`zf_048`), `--alias`
+/// names, shorthands (`zf:48`) and bare indices expanded with the `--voice-prefix`.
+/// Built once from the resolved options and handed to everything that reads a
+/// voice, so the CLI, inline tags, speaker maps and overrides agree.
+#[derive(Debug, Clone, Default)]
+pub struct VoiceNames {
+    prefix: Option<String>,
+    /// Already followed down to canonical names.
+    aliases: BTreeMap<String, &'static str>,
+}
+
+impl VoiceNames {
+    /// Follow alias chains (`hero=narrator`, `narrator=zf_048`) down to a real voice.
+    /// Aliases shadowing a voice name and cycles are rejected.
+    pub fn new(prefix: Option<&str>, aliases: &[(String, String)]) -> anyhow::Result<Self> {
+        let mut names = Self {
+            prefix: prefix.map(|p| p.trim_end_matches(['_', ':']).to_string()),
+            aliases: BTreeMap::new(),
+        };
+        let targets = aliases.iter().cloned().collect::<BTreeMap<_, _>>();
+        let mut resolved = BTreeMap::new();
+        for name in targets.keys() {
+            anyhow::ensure!(
+                canonical_voice(name).is_none(),
+                "Alias {name} collides with a voice of the same name"
+            );
+            let mut chain = vec![name.as_str()];
+            let mut target = &targets[name];
+            while let Some(next) = targets.get(target) {
+                anyhow::ensure!(
+                    !chain.contains(&target.as_str()),
+                    "Alias cycle: {} -> {}",
+                    chain.join(" -> "),
+                    target
+                );
+                chain.push(target);
+                target = next;
+            }
+            let voice = names
+                .parse(target)
+                .map_err(|e| anyhow::anyhow!("Alias {name}: {e}"))?;
+            tracing::info!("Voice alias {} => {}", name, voice_name(voice));
+            resolved.insert(name.clone(), voice_name(voice));
+        }
+        names.aliases = resolved;
+        Ok(names)
+    }
+
+    /// `--voice-prefix`, without its separator.
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    /// Resolve a voice in any of the accepted forms.
+    pub fn parse(&self, s: &str) -> Result<Voice, String> {
+        let s = s.trim();
+        if let Some(voice) = canonical_voice(s) {
+            return Ok(voice);
+        }
+        if let Some(name) = self.aliases.get(s) {
+            return Ok(canonical_voice(name).unwrap());
+        }
+        if s.contains(['+', '*']) {
+            return Err(format!(
+                "Voice blends like {s} aren't supported: kokoro_tts only selects whole voices \
+                 and doesn't expose its style embeddings for mixing"
+            ));
+        }
+
+        let (prefix, index) = match s.split_once(':') {
+            Some((prefix, index)) => (Some(prefix), index),
+            None => (self.prefix.as_deref(), s),
+        };
+        if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(unknown_voice(s, |_| true));
+        }
+        // Leading zeros are fine; anything too long for a u32 can't match either
+        let index = index.parse::<u32>().ok();
 
+        let candidates = VOICE_NAMES
+            .iter()
+            .filter(|name| {
+                name.split_once('_').is_some_and(|(p, i)| {
+                    prefix.is_none_or(|want| p == want) && i.parse::<u32>().ok() == index
+                })
+            })
+            .copied()
+            .collect::<Vec<_>>();
+
+        match candidates.as_slice() {
+            [name] => {
+                tracing::debug!("Resolved voice {} to {}", s, name);
+                Ok(canonical_voice(name).unwrap())
+            }
+            [] => match prefix.and_then(prefix_range) {
+                Some((lo, hi)) => Err(format!(
+                    "No voice matches {s}: {}_ voices go from {lo:03} to {hi:03}",
+                    prefix.unwrap()
+                )),
+                None => Err(unknown_voice(s, |_| true)),
+            },
+            many => Err(format!(
+                "Ambiguous voice {s}, could be any of {} (use a prefix like {}:{})",
+                many.join(", "),
+                many[0].split_once('_').unwrap().0,
+                s
+            )),
+        }
+    }
+}
+
+/// [`VoiceNames::parse`] without a prefix or aliases, for voices given outside a
+/// run's options.
 pub fn parse_voice(s: &str) -> Result<Voice, String> {
+    VoiceNames::default().parse(s)
+}
+
+/// Parse a `NAME=VOICE` alias definition.
+pub fn parse_alias(s: &str) -> Result<(String, String), String> {
+    let (name, voice) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected NAME=VOICE, got {s}"))?;
+    let (name, voice) = (name.trim(), voice.trim());
+    if name.is_empty() || voice.is_empty() {
+        return Err(format!("Expected NAME=VOICE, got {s}"));
+    }
+    Ok((name.to_string(), voice.to_string()))
+}
+
+/// Lowest and highest numeric index among the voices with `prefix`.
+fn prefix_range(prefix: &str) -> Option<(u32, u32)> {
+    let indices = VOICE_NAMES
+        .iter()
+        .filter_map(|name| name.split_once('_'))
+        .filter(|(p, _)| *p == prefix)
+        .filter_map(|(_, i)| i.parse::<u32>().ok());
+    Some((indices.clone().min()?, indices.max()?))
+}
+
+fn canonical_voice(s: &str) -> Option<Voice> {
     let speed = 0.;
 
     match s {
-        "zm_029" => Ok(Voice::Zm029(speed)),
-        "zf_048" => Ok(Voice::Zf048(speed)),
-        "zf_008" => Ok(Voice::Zf008(speed)),
-        "zm_014" => Ok(Voice::Zm014(speed)),
-        "zf_003" => Ok(Voice::Zf003(speed)),
-        "zf_047" => Ok(Voice::Zf047(speed)),
-        "zm_080" => Ok(Voice::Zm080(speed)),
-        "zf_094" => Ok(Voice::Zf094(speed)),
-        "zf_046" => Ok(Voice::Zf046(speed)),
-        "zm_054" => Ok(Voice::Zm054(speed)),
-        "zf_001" => Ok(Voice::Zf001(speed)),
-        "zm_062" => Ok(Voice::Zm062(speed)),
-        "bf_vale" => Ok(Voice::BfVale(speed)),
-        "zf_044" => Ok(Voice::Zf044(speed)),
-        "zf_005" => Ok(Voice::Zf005(speed)),
-        "zf_028" => Ok(Voice::Zf028(speed)),
-        "zf_059" => Ok(Voice::Zf059(speed)),
-        "zm_030" => Ok(Voice::Zm030(speed)),
-        "zf_074" => Ok(Voice::Zf074(speed)),
-        "zm_009" => Ok(Voice::Zm009(speed)),
-        "zf_004" => Ok(Voice::Zf004(speed)),
-        "zf_021" => Ok(Voice::Zf021(speed)),
-        "zm_095" => Ok(Voice::Zm095(speed)),
-        "zm_041" => Ok(Voice::Zm041(speed)),
-        "zf_087" => Ok(Voice::Zf087(speed)),
-        "zf_039" => Ok(Voice::Zf039(speed)),
-        "zm_031" => Ok(Voice::Zm031(speed)),
-        "zf_007" => Ok(Voice::Zf007(speed)),
-        "zf_038" => Ok(Voice::Zf038(speed)),
-        "zf_092" => Ok(Voice::Zf092(speed)),
-        "zm_056" => Ok(Voice::Zm056(speed)),
-        "zf_099" => Ok(Voice::Zf099(speed)),
-        "zm_010" => Ok(Voice::Zm010(speed)),
-        "zm_069" => Ok(Voice::Zm069(speed)),
-        "zm_016" => Ok(Voice::Zm016(speed)),
-        "zm_068" => Ok(Voice::Zm068(speed)),
-        "zf_083" => Ok(Voice::Zf083(speed)),
-        "zf_093" => Ok(Voice::Zf093(speed)),
-        "zf_006" => Ok(Voice::Zf006(speed)),
-        "zf_026" => Ok(Voice::Zf026(speed)),
-        "zm_053" => Ok(Voice::Zm053(speed)),
-        "zm_064" => Ok(Voice::Zm064(speed)),
-        "af_sol" => Ok(Voice::AfSol(speed)),
-        "zf_042" => Ok(Voice::Zf042(speed)),
-        "zf_084" => Ok(Voice::Zf084(speed)),
-        "zf_073" => Ok(Voice::Zf073(speed)),
-        "zf_067" => Ok(Voice::Zf067(speed)),
-        "zm_025" => Ok(Voice::Zm025(speed)),
-        "zm_020" => Ok(Voice::Zm020(speed)),
-        "zm_050" => Ok(Voice::Zm050(speed)),
-        "zf_070" => Ok(Voice::Zf070(speed)),
-        "zf_002" => Ok(Voice::Zf002(speed)),
-        "zf_032" => Ok(Voice::Zf032(speed)),
-        "zm_091" => Ok(Voice::Zm091(speed)),
-        "zm_066" => Ok(Voice::Zm066(speed)),
-        "zm_089" => Ok(Voice::Zm089(speed)),
-        "zm_034" => Ok(Voice::Zm034(speed)),
-        "zm_100" => Ok(Voice::Zm100(speed)),
-        "zf_086" => Ok(Voice::Zf086(speed)),
-        "zf_040" => Ok(Voice::Zf040(speed)),
-        "zm_011" => Ok(Voice::Zm011(speed)),
-        "zm_098" => Ok(Voice::Zm098(speed)),
-        "zm_015" => Ok(Voice::Zm015(speed)),
-        "zf_051" => Ok(Voice::Zf051(speed)),
-        "zm_065" => Ok(Voice::Zm065(speed)),
-        "zf_076" => Ok(Voice::Zf076(speed)),
-        "zf_036" => Ok(Voice::Zf036(speed)),
-        "zm_033" => Ok(Voice::Zm033(speed)),
-        "zf_018" => Ok(Voice::Zf018(speed)),
-        "zf_017" => Ok(Voice::Zf017(speed)),
-        "zf_049" => Ok(Voice::Zf049(speed)),
-        "af_maple" => Ok(Voice::AfMaple(speed)),
-        "zm_082" => Ok(Voice::Zm082(speed)),
-        "zm_057" => Ok(Voice::Zm057(speed)),
-        "zf_079" => Ok(Voice::Zf079(speed)),
-        "zf_022" => Ok(Voice::Zf022(speed)),
-        "zm_063" => Ok(Voice::Zm063(speed)),
-        "zf_060" => Ok(Voice::Zf060(speed)),
-        "zf_019" => Ok(Voice::Zf019(speed)),
-        "zm_097" => Ok(Voice::Zm097(speed)),
-        "zm_096" => Ok(Voice::Zm096(speed)),
-        "zf_023" => Ok(Voice::Zf023(speed)),
-        "zf_027" => Ok(Voice::Zf027(speed)),
-        "zf_085" => Ok(Voice::Zf085(speed)),
-        "zf_077" => Ok(Voice::Zf077(speed)),
-        "zm_035" => Ok(Voice::Zm035(speed)),
-        "zf_088" => Ok(Voice::Zf088(speed)),
-        "zf_024" => Ok(Voice::Zf024(speed)),
-        "zf_072" => Ok(Voice::Zf072(speed)),
-        "zm_055" => Ok(Voice::Zm055(speed)),
-        "zm_052" => Ok(Voice::Zm052(speed)),
-        "zf_071" => Ok(Voice::Zf071(speed)),
-        "zm_061" => Ok(Voice::Zm061(speed)),
-        "zf_078" => Ok(Voice::Zf078(speed)),
-        "zm_013" => Ok(Voice::Zm013(speed)),
-        "zm_081" => Ok(Voice::Zm081(speed)),
-        "zm_037" => Ok(Voice::Zm037(speed)),
-        "zf_090" => Ok(Voice::Zf090(speed)),
-        "zf_043" => Ok(Voice::Zf043(speed)),
-        "zm_058" => Ok(Voice::Zm058(speed)),
-        "zm_012" => Ok(Voice::Zm012(speed)),
-        "zm_045" => Ok(Voice::Zm045(speed)),
-        "zf_075" => Ok(Voice::Zf075(speed)),
-        _ => Err(format!("Unknown voice: {s}")),
+        "zm_029" => Some(Voice::Zm029(speed)),
+        "zf_048" => Some(Voice::Zf048(speed)),
+        "zf_008" => Some(Voice::Zf008(speed)),
+        "zm_014" => Some(Voice::Zm014(speed)),
+        "zf_003" => Some(Voice::Zf003(speed)),
+        "zf_047" => Some(Voice::Zf047(speed)),
+        "zm_080" => Some(Voice::Zm080(speed)),
+        "zf_094" => Some(Voice::Zf094(speed)),
+        "zf_046" => Some(Voice::Zf046(speed)),
+        "zm_054" => Some(Voice::Zm054(speed)),
+        "zf_001" => Some(Voice::Zf001(speed)),
+        "zm_062" => Some(Voice::Zm062(speed)),
+        "bf_vale" => Some(Voice::BfVale(speed)),
+        "zf_044" => Some(Voice::Zf044(speed)),
+        "zf_005" => Some(Voice::Zf005(speed)),
+        "zf_028" => Some(Voice::Zf028(speed)),
+        "zf_059" => Some(Voice::Zf059(speed)),
+        "zm_030" => Some(Voice::Zm030(speed)),
+        "zf_074" => Some(Voice::Zf074(speed)),
+        "zm_009" => Some(Voice::Zm009(speed)),
+        "zf_004" => Some(Voice::Zf004(speed)),
+        "zf_021" => Some(Voice::Zf021(speed)),
+        "zm_095" => Some(Voice::Zm095(speed)),
+        "zm_041" => Some(Voice::Zm041(speed)),
+        "zf_087" => Some(Voice::Zf087(speed)),
+        "zf_039" => Some(Voice::Zf039(speed)),
+        "zm_031" => Some(Voice::Zm031(speed)),
+        "zf_007" => Some(Voice::Zf007(speed)),
+        "zf_038" => Some(Voice::Zf038(speed)),
+        "zf_092" => Some(Voice::Zf092(speed)),
+        "zm_056" => Some(Voice::Zm056(speed)),
+        "zf_099" => Some(Voice::Zf099(speed)),
+        "zm_010" => Some(Voice::Zm010(speed)),
+        "zm_069" => Some(Voice::Zm069(speed)),
+        "zm_016" => Some(Voice::Zm016(speed)),
+        "zm_068" => Some(Voice::Zm068(speed)),
+        "zf_083" => Some(Voice::Zf083(speed)),
+        "zf_093" => Some(Voice::Zf093(speed)),
+        "zf_006" => Some(Voice::Zf006(speed)),
+        "zf_026" => Some(Voice::Zf026(speed)),
+        "zm_053" => Some(Voice::Zm053(speed)),
+        "zm_064" => Some(Voice::Zm064(speed)),
+        "af_sol" => Some(Voice::AfSol(speed)),
+        "zf_042" => Some(Voice::Zf042(speed)),
+        "zf_084" => Some(Voice::Zf084(speed)),
+        "zf_073" => Some(Voice::Zf073(speed)),
+        "zf_067" => Some(Voice::Zf067(speed)),
+        "zm_025" => Some(Voice::Zm025(speed)),
+        "zm_020" => Some(Voice::Zm020(speed)),
+        "zm_050" => Some(Voice::Zm050(speed)),
+        "zf_070" => Some(Voice::Zf070(speed)),
+        "zf_002" => Some(Voice::Zf002(speed)),
+        "zf_032" => Some(Voice::Zf032(speed)),
+        "zm_091" => Some(Voice::Zm091(speed)),
+        "zm_066" => Some(Voice::Zm066(speed)),
+        "zm_089" => Some(Voice::Zm089(speed)),
+        "zm_034" => Some(Voice::Zm034(speed)),
+        "zm_100" => Some(Voice::Zm100(speed)),
+        "zf_086" => Some(Voice::Zf086(speed)),
+        "zf_040" => Some(Voice::Zf040(speed)),
+        "zm_011" => Some(Voice::Zm011(speed)),
+        "zm_098" => Some(Voice::Zm098(speed)),
+        "zm_015" => Some(Voice::Zm015(speed)),
+        "zf_051" => Some(Voice::Zf051(speed)),
+        "zm_065" => Some(Voice::Zm065(speed)),
+        "zf_076" => Some(Voice::Zf076(speed)),
+        "zf_036" => Some(Voice::Zf036(speed)),
+        "zm_033" => Some(Voice::Zm033(speed)),
+        "zf_018" => Some(Voice::Zf018(speed)),
+        "zf_017" => Some(Voice::Zf017(speed)),
+        "zf_049" => Some(Voice::Zf049(speed)),
+        "af_maple" => Some(Voice::AfMaple(speed)),
+        "zm_082" => Some(Voice::Zm082(speed)),
+        "zm_057" => Some(Voice::Zm057(speed)),
+        "zf_079" => Some(Voice::Zf079(speed)),
+        "zf_022" => Some(Voice::Zf022(speed)),
+        "zm_063" => Some(Voice::Zm063(speed)),
+        "zf_060" => Some(Voice::Zf060(speed)),
+        "zf_019" => Some(Voice::Zf019(speed)),
+        "zm_097" => Some(Voice::Zm097(speed)),
+        "zm_096" => Some(Voice::Zm096(speed)),
+        "zf_023" => Some(Voice::Zf023(speed)),
+        "zf_027" => Some(Voice::Zf027(speed)),
+        "zf_085" => Some(Voice::Zf085(speed)),
+        "zf_077" => Some(Voice::Zf077(speed)),
+        "zm_035" => Some(Voice::Zm035(speed)),
+        "zf_088" => Some(Voice::Zf088(speed)),
+        "zf_024" => Some(Voice::Zf024(speed)),
+        "zf_072" => Some(Voice::Zf072(speed)),
+        "zm_055" => Some(Voice::Zm055(speed)),
+        "zm_052" => Some(Voice::Zm052(speed)),
+        "zf_071" => Some(Voice::Zf071(speed)),
+        "zm_061" => Some(Voice::Zm061(speed)),
+        "zf_078" => Some(Voice::Zf078(speed)),
+        "zm_013" => Some(Voice::Zm013(speed)),
+        "zm_081" => Some(Voice::Zm081(speed)),
+        "zm_037" => Some(Voice::Zm037(speed)),
+        "zf_090" => Some(Voice::Zf090(speed)),
+        "zf_043" => Some(Voice::Zf043(speed)),
+        "zm_058" => Some(Voice::Zm058(speed)),
+        "zm_012" => Some(Voice::Zm012(speed)),
+        "zm_045" => Some(Voice::Zm045(speed)),
+        "zf_075" => Some(Voice::Zf075(speed)),
+        "af_heart" => Some(Voice::AfHeart(speed)),
+        "af_alloy" => Some(Voice::AfAlloy(speed)),
+        "af_aoede" => Some(Voice::AfAoede(speed)),
+        "af_bella" => Some(Voice::AfBella(speed)),
+        "af_jessica" => Some(Voice::AfJessica(speed)),
+        "af_kore" => Some(Voice::AfKore(speed)),
+        "af_nicole" => Some(Voice::AfNicole(speed)),
+        "af_nova" => Some(Voice::AfNova(speed)),
+        "af_river" => Some(Voice::AfRiver(speed)),
+        "af_sarah" => Some(Voice::AfSarah(speed)),
+        "af_sky" => Some(Voice::AfSky(speed)),
+        "am_adam" => Some(Voice::AmAdam(speed)),
+        "am_echo" => Some(Voice::AmEcho(speed)),
+        "am_eric" => Some(Voice::AmEric(speed)),
+        "am_fenrir" => Some(Voice::AmFenrir(speed)),
+        "am_liam" => Some(Voice::AmLiam(speed)),
+        "am_michael" => Some(Voice::AmMichael(speed)),
+        "am_onyx" => Some(Voice::AmOnyx(speed)),
+        "am_puck" => Some(Voice::AmPuck(speed)),
+        "am_santa" => Some(Voice::AmSanta(speed)),
+        "bf_alice" => Some(Voice::BfAlice(speed)),
+        "bf_emma" => Some(Voice::BfEmma(speed)),
+        "bf_isabella" => Some(Voice::BfIsabella(speed)),
+        "bf_lily" => Some(Voice::BfLily(speed)),
+        "bm_daniel" => Some(Voice::BmDaniel(speed)),
+        "bm_fable" => Some(Voice::BmFable(speed)),
+        "bm_george" => Some(Voice::BmGeorge(speed)),
+        "bm_lewis" => Some(Voice::BmLewis(speed)),
+        _ => None,
+    }
+}
+
+/// Error for a voice name that isn't available, with the closest available names.
+pub fn unknown_voice(s: &str, available: impl Fn(&str) -> bool) -> String {
+    let suggestions = suggest_voices(s, available);
+    if suggestions.is_empty() {
+        format!("Unknown voice: {s} (see `morganite list-voices`)")
+    } else {
+        format!(
+            "Unknown voice: {s} (did you mean {}? see `morganite list-voices`)",
+            suggestions.join(", ")
+        )
+    }
+}
+
+/// Up to three voice names within a small edit distance of `s`, closest first.
+pub fn suggest_voices(s: &str, available: impl Fn(&str) -> bool) -> Vec<&'static str> {
+    let mut candidates = VOICE_NAMES
+        .iter()
+        .filter(|name| available(name))
+        .map(|&name| (edit_distance(s, name), name))
+        .filter(|(d, _)| *d <= 2)
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(3)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Levenshtein distance over chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = substitute.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Every voice of the v1.1-zh model files, all accepted by [`parse_voice`].
+pub const VOICE_NAMES: &[&str] = &[
+    "zm_029", "zf_048", "zf_008", "zm_014", "zf_003", "zf_047", "zm_080", "zf_094", "zf_046",
+    "zm_054", "zf_001", "zm_062", "bf_vale", "zf_044", "zf_005", "zf_028", "zf_059", "zm_030",
+    "zf_074", "zm_009", "zf_004", "zf_021", "zm_095", "zm_041", "zf_087", "zf_039", "zm_031",
+    "zf_007", "zf_038", "zf_092", "zm_056", "zf_099", "zm_010", "zm_069", "zm_016", "zm_068",
+    "zf_083", "zf_093", "zf_006", "zf_026", "zm_053", "zm_064", "af_sol", "zf_042", "zf_084",
+    "zf_073", "zf_067", "zm_025", "zm_020", "zm_050", "zf_070", "zf_002", "zf_032", "zm_091",
+    "zm_066", "zm_089", "zm_034", "zm_100", "zf_086", "zf_040", "zm_011", "zm_098", "zm_015",
+    "zf_051", "zm_065", "zf_076", "zf_036", "zm_033", "zf_018", "zf_017", "zf_049", "af_maple",
+    "zm_082", "zm_057", "zf_079", "zf_022", "zm_063", "zf_060", "zf_019", "zm_097", "zm_096",
+    "zf_023", "zf_027", "zf_085", "zf_077", "zm_035", "zf_088", "zf_024", "zf_072", "zm_055",
+    "zm_052", "zf_071", "zm_061", "zf_078", "zm_013", "zm_081", "zm_037", "zf_090", "zf_043",
+    "zm_058", "zm_012", "zm_045", "zf_075",
+];
+
+/// The English voices of the v1.0 model files, which the `en` preset uses. Kept
+/// apart from [`VOICE_NAMES`]: the v1.1-zh files don't have them.
+pub const V1_0_VOICE_NAMES: &[&str] = &[
+    "af_heart",
+    "af_alloy",
+    "af_aoede",
+    "af_bella",
+    "af_jessica",
+    "af_kore",
+    "af_nicole",
+    "af_nova",
+    "af_river",
+    "af_sarah",
+    "af_sky",
+    "am_adam",
+    "am_echo",
+    "am_eric",
+    "am_fenrir",
+    "am_liam",
+    "am_michael",
+    "am_onyx",
+    "am_puck",
+    "am_santa",
+    "bf_alice",
+    "bf_emma",
+    "bf_isabella",
+    "bf_lily",
+    "bm_daniel",
+    "bm_fable",
+    "bm_george",
+    "bm_lewis",
+];
+
+/// Canonical name of a voice, the inverse of [`parse_voice`].
+pub fn voice_name(voice: Voice) -> &'static str {
+    match voice {
+        Voice::Zm029(_) => "zm_029",
+        Voice::Zf048(_) => "zf_048",
+        Voice::Zf008(_) => "zf_008",
+        Voice::Zm014(_) => "zm_014",
+        Voice::Zf003(_) => "zf_003",
+        Voice::Zf047(_) => "zf_047",
+        Voice::Zm080(_) => "zm_080",
+        Voice::Zf094(_) => "zf_094",
+        Voice::Zf046(_) => "zf_046",
+        Voice::Zm054(_) => "zm_054",
+        Voice::Zf001(_) => "zf_001",
+        Voice::Zm062(_) => "zm_062",
+        Voice::BfVale(_) => "bf_vale",
+        Voice::Zf044(_) => "zf_044",
+        Voice::Zf005(_) => "zf_005",
+        Voice::Zf028(_) => "zf_028",
+        Voice::Zf059(_) => "zf_059",
+        Voice::Zm030(_) => "zm_030",
+        Voice::Zf074(_) => "zf_074",
+        Voice::Zm009(_) => "zm_009",
+        Voice::Zf004(_) => "zf_004",
+        Voice::Zf021(_) => "zf_021",
+        Voice::Zm095(_) => "zm_095",
+        Voice::Zm041(_) => "zm_041",
+        Voice::Zf087(_) => "zf_087",
+        Voice::Zf039(_) => "zf_039",
+        Voice::Zm031(_) => "zm_031",
+        Voice::Zf007(_) => "zf_007",
+        Voice::Zf038(_) => "zf_038",
+        Voice::Zf092(_) => "zf_092",
+        Voice::Zm056(_) => "zm_056",
+        Voice::Zf099(_) => "zf_099",
+        Voice::Zm010(_) => "zm_010",
+        Voice::Zm069(_) => "zm_069",
+        Voice::Zm016(_) => "zm_016",
+        Voice::Zm068(_) => "zm_068",
+        Voice::Zf083(_) => "zf_083",
+        Voice::Zf093(_) => "zf_093",
+        Voice::Zf006(_) => "zf_006",
+        Voice::Zf026(_) => "zf_026",
+        Voice::Zm053(_) => "zm_053",
+        Voice::Zm064(_) => "zm_064",
+        Voice::AfSol(_) => "af_sol",
+        Voice::Zf042(_) => "zf_042",
+        Voice::Zf084(_) => "zf_084",
+        Voice::Zf073(_) => "zf_073",
+        Voice::Zf067(_) => "zf_067",
+        Voice::Zm025(_) => "zm_025",
+        Voice::Zm020(_) => "zm_020",
+        Voice::Zm050(_) => "zm_050",
+        Voice::Zf070(_) => "zf_070",
+        Voice::Zf002(_) => "zf_002",
+        Voice::Zf032(_) => "zf_032",
+        Voice::Zm091(_) => "zm_091",
+        Voice::Zm066(_) => "zm_066",
+        Voice::Zm089(_) => "zm_089",
+        Voice::Zm034(_) => "zm_034",
+        Voice::Zm100(_) => "zm_100",
+        Voice::Zf086(_) => "zf_086",
+        Voice::Zf040(_) => "zf_040",
+        Voice::Zm011(_) => "zm_011",
+        Voice::Zm098(_) => "zm_098",
+        Voice::Zm015(_) => "zm_015",
+        Voice::Zf051(_) => "zf_051",
+        Voice::Zm065(_) => "zm_065",
+        Voice::Zf076(_) => "zf_076",
+        Voice::Zf036(_) => "zf_036",
+        Voice::Zm033(_) => "zm_033",
+        Voice::Zf018(_) => "zf_018",
+        Voice::Zf017(_) => "zf_017",
+        Voice::Zf049(_) => "zf_049",
+        Voice::AfMaple(_) => "af_maple",
+        Voice::Zm082(_) => "zm_082",
+        Voice::Zm057(_) => "zm_057",
+        Voice::Zf079(_) => "zf_079",
+        Voice::Zf022(_) => "zf_022",
+        Voice::Zm063(_) => "zm_063",
+        Voice::Zf060(_) => "zf_060",
+        Voice::Zf019(_) => "zf_019",
+        Voice::Zm097(_) => "zm_097",
+        Voice::Zm096(_) => "zm_096",
+        Voice::Zf023(_) => "zf_023",
+        Voice::Zf027(_) => "zf_027",
+        Voice::Zf085(_) => "zf_085",
+        Voice::Zf077(_) => "zf_077",
+        Voice::Zm035(_) => "zm_035",
+        Voice::Zf088(_) => "zf_088",
+        Voice::Zf024(_) => "zf_024",
+        Voice::Zf072(_) => "zf_072",
+        Voice::Zm055(_) => "zm_055",
+        Voice::Zm052(_) => "zm_052",
+        Voice::Zf071(_) => "zf_071",
+        Voice::Zm061(_) => "zm_061",
+        Voice::Zf078(_) => "zf_078",
+        Voice::Zm013(_) => "zm_013",
+        Voice::Zm081(_) => "zm_081",
+        Voice::Zm037(_) => "zm_037",
+        Voice::Zf090(_) => "zf_090",
+        Voice::Zf043(_) => "zf_043",
+        Voice::Zm058(_) => "zm_058",
+        Voice::Zm012(_) => "zm_012",
+        Voice::Zm045(_) => "zm_045",
+        Voice::Zf075(_) => "zf_075",
+        Voice::AfHeart(_) => "af_heart",
+        Voice::AfAlloy(_) => "af_alloy",
+        Voice::AfAoede(_) => "af_aoede",
+        Voice::AfBella(_) => "af_bella",
+        Voice::AfJessica(_) => "af_jessica",
+        Voice::AfKore(_) => "af_kore",
+        Voice::AfNicole(_) => "af_nicole",
+        Voice::AfNova(_) => "af_nova",
+        Voice::AfRiver(_) => "af_river",
+        Voice::AfSarah(_) => "af_sarah",
+        Voice::AfSky(_) => "af_sky",
+        Voice::AmAdam(_) => "am_adam",
+        Voice::AmEcho(_) => "am_echo",
+        Voice::AmEric(_) => "am_eric",
+        Voice::AmFenrir(_) => "am_fenrir",
+        Voice::AmLiam(_) => "am_liam",
+        Voice::AmMichael(_) => "am_michael",
+        Voice::AmOnyx(_) => "am_onyx",
+        Voice::AmPuck(_) => "am_puck",
+        Voice::AmSanta(_) => "am_santa",
+        Voice::BfAlice(_) => "bf_alice",
+        Voice::BfEmma(_) => "bf_emma",
+        Voice::BfIsabella(_) => "bf_isabella",
+        Voice::BfLily(_) => "bf_lily",
+        Voice::BmDaniel(_) => "bm_daniel",
+        Voice::BmFable(_) => "bm_fable",
+        Voice::BmGeorge(_) => "bm_george",
+        Voice::BmLewis(_) => "bm_lewis",
+        _ => unreachable!(),
+    }
+}
+
+/// Check a speed against the supported range.
+pub fn check_speed(speed: f32) -> Result<f32, String> {
+    if (MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        Ok(speed)
+    } else {
+        Err(format!(
+            "speed {speed} outside the supported range {MIN_SPEED}..={MAX_SPEED}"
+        ))
+    }
+}
+
+/// `--lang`: defaults that belong together for one language's Kokoro model, as
+/// option names and the values they take when nothing else gives one. Adding a
+/// language is an entry here.
+#[derive(Debug)]
+pub struct LangPreset {
+    pub name: &'static str,
+    pub options: &'static [(&'static str, &'static str)],
+}
+
+pub const LANG_PRESETS: &[LangPreset] = &[
+    LangPreset {
+        name: "zh",
+        options: &[
+            ("tts_model", "kokoro-v1.1-zh.onnx"),
+            ("voice_model", "voices-v1.1-zh.bin"),
+            ("voice", "zf_048"),
+            ("voice_prefix", "zf"),
+            ("speed", "1.0"),
+            ("sentence_ends", "。！？!?；;…"),
+            ("numbers", "zh"),
+        ],
+    },
+    LangPreset {
+        name: "en",
+        options: &[
+            ("tts_model", "kokoro-v1.0.onnx"),
+            ("voice_model", "voices-v1.0.bin"),
+            ("voice", "af_heart"),
+            ("speed", "1.0"),
+            ("sentence_ends", ".!?;…"),
+            ("numbers", "en"),
+        ],
+    },
+];
+
+pub fn parse_lang_preset(s: &str) -> Result<&'static LangPreset, String> {
+    LANG_PRESETS
+        .iter()
+        .find(|preset| preset.name == s.trim())
+        .ok_or_else(|| {
+            format!(
+                "Unknown language {s:?} (one of {})",
+                LANG_PRESETS
+                    .iter()
+                    .map(|preset| preset.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+/// `--speed` value parser.
+pub fn parse_speed(s: &str) -> Result<f32, String> {
+    let speed = s
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| format!("Invalid speed: {s}"))?;
+    check_speed(speed)
+}
+
+/// `--batch-short-lines` value parser; a single line is no batch, and longer runs
+/// make the pauses between lines too hard to find again.
+pub fn parse_batch_size(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if (2..=16).contains(&n) => Ok(n),
+        Ok(n) => Err(format!("batch size {n} outside the supported range 2..=16")),
+        Err(_) => Err(format!("Invalid batch size: {s}")),
+    }
+}
+
+/// Parse a share like `50%` (or plain `50`) into a fraction in (0, 1].
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent = s
+        .trim()
+        .trim_end_matches('%')
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid percentage: {s}"))?;
+    if percent > 0.0 && percent <= 100.0 {
+        Ok(percent / 100.0)
+    } else {
+        Err(format!("{percent}% outside the supported range 0%..=100%"))
+    }
+}
+
+/// `--tempo` value parser; the stretch stays natural-sounding within this range.
+pub fn parse_tempo(s: &str) -> Result<f32, String> {
+    let tempo = s
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| format!("Invalid tempo: {s}"))?;
+    if (0.5..=2.0).contains(&tempo) {
+        Ok(tempo)
+    } else {
+        Err(format!(
+            "tempo {tempo} outside the supported range 0.5..=2.0"
+        ))
+    }
+}
+
+/// Parse a gain like `6`, `+6dB` or `-3.5 dB`.
+pub fn parse_db(s: &str) -> Result<f32, String> {
+    let t = s.trim();
+    let t = t
+        .strip_suffix("dB")
+        .or_else(|| t.strip_suffix("db"))
+        .unwrap_or(t)
+        .trim();
+    let db = t.parse::<f32>().map_err(|_| format!("Invalid gain: {s}"))?;
+    if db.is_finite() && db.abs() <= 40.0 {
+        Ok(db)
+    } else {
+        Err(format!("gain {s} outside -40..=40 dB"))
+    }
+}
+
+/// Parse a level threshold like `-50` or `-45dB`, in dBFS: full scale is the
+/// loudest a sample gets, so anything above 0 could never be reached.
+pub fn parse_dbfs(s: &str) -> Result<f32, String> {
+    let t = s.trim();
+    let t = t
+        .strip_suffix("dBFS")
+        .or_else(|| t.strip_suffix("dB"))
+        .or_else(|| t.strip_suffix("db"))
+        .unwrap_or(t)
+        .trim();
+    let db = t
+        .parse::<f32>()
+        .map_err(|_| format!("Invalid threshold: {s}"))?;
+    if (-100.0..=0.0).contains(&db) {
+        Ok(db)
+    } else {
+        Err(format!("threshold {s} outside -100..=0 dBFS"))
     }
 }
 
 pub fn change_voice_speed(voice: Voice, speed: f32) -> Voice {
+    // Inputs are validated where they enter; this only keeps a slip from reaching the engine
+    let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
     match voice {
         Voice::Zm029(_) => Voice::Zm029(speed),
         Voice::Zf048(_) => Voice::Zf048(speed),
@@ -216,6 +775,556 @@ pub fn change_voice_speed(voice: Voice, speed: f32) -> Voice {
         Voice::Zm012(_) => Voice::Zm012(speed),
         Voice::Zm045(_) => Voice::Zm045(speed),
         Voice::Zf075(_) => Voice::Zf075(speed),
+        Voice::AfHeart(_) => Voice::AfHeart(speed),
+        Voice::AfAlloy(_) => Voice::AfAlloy(speed),
+        Voice::AfAoede(_) => Voice::AfAoede(speed),
+        Voice::AfBella(_) => Voice::AfBella(speed),
+        Voice::AfJessica(_) => Voice::AfJessica(speed),
+        Voice::AfKore(_) => Voice::AfKore(speed),
+        Voice::AfNicole(_) => Voice::AfNicole(speed),
+        Voice::AfNova(_) => Voice::AfNova(speed),
+        Voice::AfRiver(_) => Voice::AfRiver(speed),
+        Voice::AfSarah(_) => Voice::AfSarah(speed),
+        Voice::AfSky(_) => Voice::AfSky(speed),
+        Voice::AmAdam(_) => Voice::AmAdam(speed),
+        Voice::AmEcho(_) => Voice::AmEcho(speed),
+        Voice::AmEric(_) => Voice::AmEric(speed),
+        Voice::AmFenrir(_) => Voice::AmFenrir(speed),
+        Voice::AmLiam(_) => Voice::AmLiam(speed),
+        Voice::AmMichael(_) => Voice::AmMichael(speed),
+        Voice::AmOnyx(_) => Voice::AmOnyx(speed),
+        Voice::AmPuck(_) => Voice::AmPuck(speed),
+        Voice::AmSanta(_) => Voice::AmSanta(speed),
+        Voice::BfAlice(_) => Voice::BfAlice(speed),
+        Voice::BfEmma(_) => Voice::BfEmma(speed),
+        Voice::BfIsabella(_) => Voice::BfIsabella(speed),
+        Voice::BfLily(_) => Voice::BfLily(speed),
+        Voice::BmDaniel(_) => Voice::BmDaniel(speed),
+        Voice::BmFable(_) => Voice::BmFable(speed),
+        Voice::BmGeorge(_) => Voice::BmGeorge(speed),
+        Voice::BmLewis(_) => Voice::BmLewis(speed),
         _ => unreachable!(),
     }
 }
+
+/// What `--announce-parts` says at the start of part `part`: Chinese for the
+/// Chinese voices, English for the rest.
+pub fn part_announcement(part: u32, voice: Voice) -> String {
+    if voice_name(voice).starts_with('z') {
+        format!("第{}部分", chinese_number(part))
+    } else {
+        format!("Part {}", english_number(part))
+    }
+}
+
+/// `n` in Chinese numerals, e.g. 十二, 一百零五; digits from 10000 on.
+fn chinese_number(n: u32) -> String {
+    const DIGITS: [char; 10] = ['零', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+    if n == 0 || n >= 10_000 {
+        return n.to_string();
+    }
+    // 十二, not 一十二
+    if (10..20).contains(&n) {
+        let mut out = "十".to_string();
+        if n > 10 {
+            out.push(DIGITS[n as usize % 10]);
+        }
+        return out;
+    }
+    let mut out = String::new();
+    let mut zero = false;
+    for (unit, place) in [("千", 1000), ("百", 100), ("十", 10), ("", 1)] {
+        let digit = n / place % 10;
+        if digit == 0 {
+            zero = !out.is_empty();
+            continue;
+        }
+        if zero {
+            out.push('零');
+            zero = false;
+        }
+        out.push(DIGITS[digit as usize]);
+        out.push_str(unit);
+    }
+    out
+}
+
+/// `n` in English words, e.g. twenty-one; digits from 1000 on.
+fn english_number(n: u32) -> String {
+    const ONES: [&str; 20] = [
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+    const TENS: [&str; 10] = [
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+    match n {
+        0..20 => ONES[n as usize].to_string(),
+        20..100 if n % 10 == 0 => TENS[n as usize / 10].to_string(),
+        20..100 => format!("{}-{}", TENS[n as usize / 10], ONES[n as usize % 10]),
+        100..1000 if n % 100 == 0 => format!("{} hundred", ONES[n as usize / 100]),
+        100..1000 => format!(
+            "{} hundred {}",
+            ONES[n as usize / 100],
+            english_number(n % 100)
+        ),
+        _ => n.to_string(),
+    }
+}
+
+/// Format a duration as `HH:MM:SS`.
+pub fn format_hms(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Parse durations like `250ms`, `1.5s`, `10m` or `2h`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .ok_or_else(|| format!("Missing unit in duration {s:?} (use ms, s, m or h)"))?;
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("Invalid number in duration {s:?}"))?;
+    let secs = match unit.trim() {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" | "min" => value * 60.0,
+        "h" => value * 3600.0,
+        other => return Err(format!("Unknown duration unit {other:?} in {s:?}")),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("Invalid duration {s:?}: {e}"))
+}
+
+/// Count value parser for workers, sessions and the like, of which zero would do nothing.
+pub fn parse_count(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("Invalid count: {s}")),
+    }
+}
+
+/// `1040-1060` or a single index like `1040`.
+pub fn parse_index_range(s: &str) -> Result<std::ops::RangeInclusive<usize>, String> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid index {n:?} in {s:?}"))
+    };
+    let (start, end) = match s.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if start > end {
+        return Err(format!("Range {s:?} ends before it starts"));
+    }
+    Ok(start..=end)
+}
+
+/// `--shard INDEX/TOTAL`: the part of an input folder one machine reads, so the
+/// same command with each index covers the folder exactly once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// From 1.
+    pub index: usize,
+    pub total: usize,
+}
+
+impl Shard {
+    /// The shard index `path` belongs to, from a hash of its file name: every machine
+    /// agrees on it, and a file keeps its shard as others are added or removed.
+    pub fn of(&self, path: &Path) -> usize {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let digest = sha2::Sha256::digest(name.as_bytes());
+        let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        (hash % self.total as u64) as usize + 1
+    }
+
+    pub fn owns(&self, path: &Path) -> bool {
+        self.of(path) == self.index
+    }
+}
+
+impl std::fmt::Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index, self.total)
+    }
+}
+
+pub fn parse_shard(s: &str) -> Result<Shard, String> {
+    let (index, total) = s
+        .split_once('/')
+        .ok_or_else(|| format!("Expected INDEX/TOTAL like 1/3, got {s:?}"))?;
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid shard number {n:?} in {s:?}"))
+    };
+    let (index, total) = (parse(index)?, parse(total)?);
+    if total == 0 || !(1..=total).contains(&index) {
+        return Err(format!("Shard {s:?} must be 1/N to N/N"));
+    }
+    Ok(Shard { index, total })
+}
+
+/// `--file-separator`: what goes between two input files of the `--stdout` stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSeparator {
+    None,
+    Tone,
+    Wav(PathBuf),
+}
+
+pub fn parse_file_separator(s: &str) -> Result<FileSeparator, String> {
+    match s.trim() {
+        "none" => Ok(FileSeparator::None),
+        "tone" => Ok(FileSeparator::Tone),
+        path if Path::new(path)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("wav")) =>
+        {
+            Ok(FileSeparator::Wav(PathBuf::from(path)))
+        }
+        _ => Err(format!(
+            "Invalid file separator {s:?} (use tone, none or a .wav file)"
+        )),
+    }
+}
+
+/// Cores the process may run on, 1 if unknown.
+pub fn available_cores() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// `--concurrency`: a session count, `auto` to derive one from the machine, or
+/// `auto-tune` to keep adjusting it during the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Concurrency {
+    Auto,
+    AutoTune,
+    Fixed(usize),
+}
+
+pub fn parse_concurrency(s: &str) -> Result<Concurrency, String> {
+    match s.trim() {
+        "auto" => Ok(Concurrency::Auto),
+        "auto-tune" => Ok(Concurrency::AutoTune),
+        n => match n.parse::<usize>() {
+            Ok(0) => Err("concurrency must be at least 1".to_string()),
+            Ok(n) => Ok(Concurrency::Fixed(n)),
+            Err(_) => Err(format!(
+                "Invalid concurrency {s:?} (use a number, auto or auto-tune)"
+            )),
+        },
+    }
+}
+
+/// Threads each ort session is assumed to keep busy with its own threading.
+const SESSION_THREADS: usize = 2;
+
+/// Sessions to run on `cores` so that together they stay within them.
+pub fn auto_concurrency(cores: usize) -> usize {
+    (cores / SESSION_THREADS).max(1)
+}
+
+/// `--crossfade` value parser: off, or long enough to smooth a join but too short to smear it.
+pub fn parse_crossfade(s: &str) -> Result<Duration, String> {
+    let d = parse_duration(s)?;
+    if d.is_zero() || (Duration::from_millis(2)..=Duration::from_millis(10)).contains(&d) {
+        Ok(d)
+    } else {
+        Err(format!(
+            "crossfade {d:?} outside the supported range 2ms..=10ms"
+        ))
+    }
+}
+
+/// Parse sizes like `512MB`, `2G` or `1.5GiB` (binary units).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("Invalid number in size {s:?}"))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        other => return Err(format!("Unknown size unit {other:?} in {s:?}")),
+    };
+    Ok((value * multiplier as f64) as u64)
+}
+
+/// Format a byte count for humans, e.g. `1.4 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// CJK ideographs (plus the common extension A block).
+pub fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '\u{F900}'..='\u{FAFF}')
+}
+
+/// ```
+/// use std::path::Path;
+///
+/// assert!(morganite::utils::is_txt(Path::new("book/Chapter 1.TXT")));
+/// assert!(!morganite::utils::is_txt(Path::new("book/cover.jpg")));
+/// ```
+pub fn is_txt(p: &Path) -> bool {
+    p.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case("txt"))
+        .unwrap_or(false)
+}
+
+/// ```
+/// use std::path::Path;
+///
+/// assert_eq!(
+///     morganite::utils::file_stem_string(Path::new("book/01.txt")),
+///     "01"
+/// );
+/// ```
+pub fn file_stem_string(p: &Path) -> String {
+    p.file_stem()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// `.txt` files directly in `dir`, sorted.
+pub fn list_txt_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read input directory {}", dir.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && is_txt(p))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// `$XDG_CACHE_HOME/morganite`, falling back to `~/.cache`, or
+/// `%LOCALAPPDATA%\morganite` on Windows.
+pub fn cache_home() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    base.map(|dir| dir.join("morganite"))
+}
+
+/// Build the list of txt files to process, and whether the input was a folder. An
+/// empty folder is only an error when `allow_empty` is false.
+pub fn collect_txt_files(input: &str, allow_empty: bool) -> anyhow::Result<(Vec<PathBuf>, bool)> {
+    let input_path = PathBuf::from(input);
+    anyhow::ensure!(input_path.exists(), "Unable to find input path {}", input);
+
+    if input_path.is_file() {
+        anyhow::ensure!(
+            is_txt(&input_path),
+            "Input file is not a .txt: {}",
+            input_path.display()
+        );
+        Ok((vec![input_path], false))
+    } else if input_path.is_dir() {
+        let files = list_txt_files(&input_path)?;
+        anyhow::ensure!(
+            allow_empty || !files.is_empty(),
+            "No .txt files found in folder {}",
+            input_path.display()
+        );
+        Ok((files, true))
+    } else {
+        anyhow::bail!(
+            "Input path is neither a file nor a directory: {}",
+            input_path.display()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_concurrency_fits_the_cores() {
+        assert_eq!(auto_concurrency(1), 1);
+        assert_eq!(auto_concurrency(2), 1);
+        assert_eq!(auto_concurrency(7), 3);
+        assert_eq!(auto_concurrency(64), 32);
+    }
+
+    #[test]
+    fn auto_concurrency_never_drops_to_zero() {
+        assert_eq!(auto_concurrency(0), 1);
+    }
+
+    #[test]
+    fn parse_db_accepts_the_range_ends() {
+        assert_eq!(parse_db("40"), Ok(40.0));
+        assert_eq!(parse_db("-40dB"), Ok(-40.0));
+        assert_eq!(parse_db("+6dB"), Ok(6.0));
+        assert_eq!(parse_db(" -3.5 db "), Ok(-3.5));
+        assert_eq!(parse_db("0"), Ok(0.0));
+    }
+
+    #[test]
+    fn parse_db_rejects_gains_past_the_range() {
+        assert!(parse_db("40.1").is_err());
+        assert!(parse_db("-40.5dB").is_err());
+        assert!(parse_db("inf").is_err());
+        assert!(parse_db("NaN").is_err());
+    }
+
+    #[test]
+    fn parse_db_rejects_malformed_input() {
+        for s in ["", "dB", "loud", "6 dBs", "++6", "6dB dB", "3,5"] {
+            assert_eq!(parse_db(s), Err(format!("Invalid gain: {s}")), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn parse_speed_accepts_the_supported_range() {
+        assert_eq!(parse_speed("0.5"), Ok(MIN_SPEED));
+        assert_eq!(parse_speed("2"), Ok(MAX_SPEED));
+        assert_eq!(parse_speed(" 1.1 "), Ok(1.1));
+    }
+
+    #[test]
+    fn parse_speed_rejects_speeds_past_the_range() {
+        for s in ["0.49", "2.01", "0.1", "5.0", "-1", "inf", "NaN"] {
+            assert!(
+                parse_speed(s)
+                    .unwrap_err()
+                    .contains("outside the supported range"),
+                "{s:?}"
+            );
+        }
+        assert_eq!(parse_speed("fast"), Err("Invalid speed: fast".to_string()));
+    }
+
+    #[test]
+    fn change_voice_speed_clamps_to_the_range() {
+        let speed = |speed| match change_voice_speed(Voice::Zf048(0.), speed) {
+            Voice::Zf048(speed) => speed,
+            _ => unreachable!(),
+        };
+        assert_eq!(speed(1.2), 1.2);
+        assert_eq!(speed(0.1), MIN_SPEED);
+        assert_eq!(speed(5.0), MAX_SPEED);
+    }
+
+    #[test]
+    fn parse_voice_rejects_blends() {
+        for s in ["zf_048+zf_002", "zf_048*0.7+zf_002*0.3", "48+2"] {
+            let err = parse_voice(s).unwrap_err();
+            assert!(err.starts_with(&format!("Voice blends like {s} ")), "{err}");
+        }
+    }
+
+    #[test]
+    fn presets_pass_validation() {
+        for lang in LANG_PRESETS {
+            let option = |id| {
+                lang.options
+                    .iter()
+                    .find(|&&(option, _)| option == id)
+                    .map(|&(_, value)| value)
+            };
+            let names = VoiceNames::new(option("voice_prefix"), &[]).unwrap();
+            let voice = option("voice").unwrap();
+            assert!(names.parse(voice).is_ok(), "--lang {}: {voice}", lang.name);
+            if let Some(speed) = option("speed") {
+                assert!(parse_speed(speed).is_ok(), "--lang {}: {speed}", lang.name);
+            }
+            if let Some(numbers) = option("numbers") {
+                assert!(
+                    <crate::numbers::NumberStyle as clap::ValueEnum>::from_str(numbers, false)
+                        .is_ok(),
+                    "--lang {}: {numbers}",
+                    lang.name
+                );
+            }
+            if let Some(ends) = option("sentence_ends") {
+                assert!(
+                    crate::text::SentenceEnds::parse(ends).is_ok(),
+                    "--lang {}: {ends}",
+                    lang.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn preset_voices_belong_to_their_model() {
+        for lang in LANG_PRESETS {
+            let option = |id| {
+                lang.options
+                    .iter()
+                    .find(|&&(option, _)| option == id)
+                    .map(|&(_, value)| value)
+                    .unwrap()
+            };
+            let voices = match option("voice_model") {
+                "voices-v1.0.bin" => V1_0_VOICE_NAMES,
+                "voices-v1.1-zh.bin" => VOICE_NAMES,
+                other => panic!("--lang {}: unknown voice model {other}", lang.name),
+            };
+            let voice = option("voice");
+            assert!(voices.contains(&voice), "--lang {}: {voice}", lang.name);
+        }
+    }
+
+    #[test]
+    fn presets_are_found_by_name() {
+        for lang in LANG_PRESETS {
+            assert_eq!(parse_lang_preset(lang.name).unwrap().name, lang.name);
+        }
+        assert_eq!(
+            parse_lang_preset("fr").unwrap_err(),
+            r#"Unknown language "fr" (one of zh, en)"#
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crate::{utils, writer};
+
+/// Per-segment record of what the splitter wrote, in the run folder.
+pub const SEGMENTS_CSV: &str = "segments.csv";
+
+/// Layer III bitrates in kbps by header index, MPEG-1 and MPEG-2/2.5.
+const BITRATES_V1: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const BITRATES_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+#[derive(clap::Args)]
+pub struct VerifyArgs {
+    /// Run folder, with the segments.csv the run wrote
+    dir: PathBuf,
+}
+
+/// What a walk over the frames of an MP3 file found.
+#[derive(Debug, Clone, Copy)]
+pub struct Mp3Frames {
+    pub frames: u64,
+    /// Samples (per channel) the frames decode to.
+    pub samples: u64,
+    pub sample_rate: u32,
+    pub samples_per_frame: u32,
+}
+
+/// Length in bytes, sample rate and samples per frame of the Layer III frame
+/// starting with `header`, if it is one.
+fn frame_header(header: [u8; 4]) -> Option<(u64, u32, u32)> {
+    if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    // 0: MPEG-2.5, 1: reserved, 2: MPEG-2, 3: MPEG-1
+    let version = (header[1] >> 3) & 0b11;
+    let layer = (header[1] >> 1) & 0b11;
+    if version == 1 || layer != 1 {
+        return None;
+    }
+    let bitrate = (header[2] >> 4) as usize;
+    let rate = ((header[2] >> 2) & 0b11) as usize;
+    // Free format and the invalid indices
+    if bitrate == 0 || bitrate == 15 || rate == 3 {
+        return None;
+    }
+    let padding = ((header[2] >> 1) & 1) as u64;
+    let (kbps, sample_rate, samples_per_frame, slot) = match version {
+        3 => (
+            BITRATES_V1[bitrate],
+            [44_100, 48_000, 32_000][rate],
+            1152,
+            144,
+        ),
+        2 => (
+            BITRATES_V2[bitrate],
+            [22_050, 24_000, 16_000][rate],
+            576,
+            72,
+        ),
+        _ => (BITRATES_V2[bitrate], [11_025, 12_000, 8_000][rate], 576, 72),
+    };
+    let len = slot * kbps as u64 * 1000 / sample_rate as u64 + padding;
+    Some((len, sample_rate, samples_per_frame))
+}
+
+/// Walk the frames of the MP3 file at `path` from header to header, without
+/// decoding or loading it. Fails on anything but whole Layer III frames after an
+/// optional ID3v2 tag (and before an optional ID3v1 one).
+pub fn walk(path: &Path) -> anyhow::Result<Mp3Frames> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut pos = 0u64;
+
+    let mut id3 = [0u8; 10];
+    if len >= 10 {
+        reader.read_exact(&mut id3)?;
+        if &id3[..3] == b"ID3" {
+            let size = id3[6..10]
+                .iter()
+                .fold(0u64, |size, &b| (size << 7) | (b & 0x7F) as u64);
+            // A footer repeats the header at the end of the tag
+            let footer = if id3[5] & 0x10 != 0 { 10 } else { 0 };
+            pos = 10 + size + footer;
+        }
+        reader.seek(SeekFrom::Start(pos))?;
+    }
+
+    let mut walked = Mp3Frames {
+        frames: 0,
+        samples: 0,
+        sample_rate: 0,
+        samples_per_frame: 0,
+    };
+    while pos < len {
+        let mut header = [0u8; 4];
+        if len - pos < 4 {
+            anyhow::bail!(
+                "{} trailing bytes at offset {} are no frame",
+                len - pos,
+                pos
+            );
+        }
+        reader.read_exact(&mut header)?;
+        if &header[..3] == b"TAG" && len - pos == 128 {
+            break;
+        }
+        let Some((frame_len, sample_rate, samples_per_frame)) = frame_header(header) else {
+            anyhow::bail!("no MP3 frame at offset {} (frame {})", pos, walked.frames);
+        };
+        if walked.frames > 0 && sample_rate != walked.sample_rate {
+            anyhow::bail!(
+                "frame {} at offset {} switches to {} Hz",
+                walked.frames,
+                pos,
+                sample_rate
+            );
+        }
+        if pos + frame_len > len {
+            anyhow::bail!(
+                "truncated: frame {} at offset {} needs {} bytes, {} left",
+                walked.frames,
+                pos,
+                frame_len,
+                len - pos
+            );
+        }
+        walked.frames += 1;
+        walked.samples += samples_per_frame as u64;
+        walked.sample_rate = sample_rate;
+        walked.samples_per_frame = samples_per_frame;
+        pos += frame_len;
+        reader.seek_relative(frame_len as i64 - 4)?;
+    }
+    anyhow::ensure!(walked.frames > 0, "no MP3 frames");
+    Ok(walked)
+}
+
+/// A segment that failed verification.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub segment: String,
+    pub reason: String,
+}
+
+/// Outcome of checking every segment of a run.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if self.problems.is_empty() {
+            let _ = writeln!(out, "Verified {} segments: all complete", self.checked);
+        } else {
+            let _ = writeln!(
+                out,
+                "VERIFY FAILED: {} of {} segments",
+                self.problems.len(),
+                self.checked
+            );
+            for p in &self.problems {
+                let _ = writeln!(out, "  {}: {}", p.segment, p.reason);
+            }
+        }
+        out
+    }
+}
+
+/// One row of [`SEGMENTS_CSV`].
+struct Written {
+    segment: String,
+    frames: u64,
+    sample_rate: u32,
+    streams: u32,
+}
+
+/// Add the segments in `levels` to the [`SEGMENTS_CSV`] at `path`, named
+/// relative to the folder it is in.
+pub fn append_segments(path: &Path, levels: &[writer::SegmentLevels]) -> anyhow::Result<()> {
+    let new = !path.exists();
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    if new {
+        writeln!(f, "frames,sample_rate,streams,segment")?;
+    }
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for s in levels {
+        let segment = Path::new(&s.segment);
+        let segment = segment.strip_prefix(dir).unwrap_or(segment);
+        writeln!(
+            f,
+            "{},{},{},{}",
+            s.frames,
+            writer::SAMPLE_RATE,
+            s.streams,
+            segment.display()
+        )?;
+    }
+    Ok(())
+}
+
+fn read_segments(path: &Path) -> anyhow::Result<Vec<Written>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut rows = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate().skip(1) {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        // The segment goes last, so commas in its name don't matter
+        let row = (|| {
+            let mut fields = line.splitn(4, ',');
+            Some(Written {
+                frames: fields.next()?.parse().ok()?,
+                sample_rate: fields.next()?.parse().ok()?,
+                streams: fields.next()?.parse().ok()?,
+                segment: fields.next()?.to_string(),
+            })
+        })();
+        rows.push(row.with_context(|| format!("{} line {} is malformed", path.display(), i + 1))?);
+    }
+    Ok(rows)
+}
+
+/// `--skip-existing`: the output folders, named after their input files, that the
+/// earlier runs in `output_dir` finished, going by the segments each run's
+/// [`SEGMENTS_CSV`] lists and that are still there.
+pub fn finished_outputs(output_dir: &Path) -> anyhow::Result<BTreeSet<String>> {
+    let mut finished = BTreeSet::new();
+    let runs = match std::fs::read_dir(output_dir) {
+        Ok(runs) => runs,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(finished),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", output_dir.display()));
+        }
+    };
+    for run in runs {
+        let run = run?.path();
+        let csv = run.join(SEGMENTS_CSV);
+        if !csv.is_file() {
+            continue;
+        }
+        for row in read_segments(&csv)? {
+            let segment = Path::new(&row.segment);
+            // Single-file runs put their segments straight into the run folder
+            let mut parts = segment.components();
+            if let (Some(first), Some(_)) = (parts.next(), parts.next())
+                && run.join(segment).is_file()
+            {
+                finished.insert(first.as_os_str().to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(finished)
+}
+
+/// Check every segment listed in the run folder's [`SEGMENTS_CSV`]: each must be
+/// whole MP3 frames lasting what the splitter wrote, give or take the padding of
+/// each MP3 stream in it (one frame, plus one per separately encoded clip).
+pub fn verify_run(dir: &Path) -> anyhow::Result<Report> {
+    let rows = read_segments(&dir.join(SEGMENTS_CSV))?;
+    let mut report = Report::default();
+    for row in rows {
+        report.checked += 1;
+        let path = dir.join(&row.segment);
+        let problem = match walk(&path) {
+            Err(e) => Some(format!("undecodable: {:#}", e)),
+            Ok(mp3) if mp3.sample_rate != row.sample_rate => Some(format!(
+                "{} Hz, but {} Hz was written",
+                mp3.sample_rate, row.sample_rate
+            )),
+            Ok(mp3) => {
+                let slack = mp3.samples_per_frame as u64 * row.streams.max(1) as u64;
+                let short = mp3.samples + mp3.samples_per_frame as u64 <= row.frames;
+                (short || mp3.samples > row.frames + slack).then(|| {
+                    let secs = |samples: u64| {
+                        Duration::from_secs_f64(samples as f64 / row.sample_rate as f64)
+                    };
+                    format!(
+                        "{} frames last {} ({:.3}s), but {:.3}s was written{}",
+                        mp3.frames,
+                        utils::format_hms(secs(mp3.samples)),
+                        secs(mp3.samples).as_secs_f64(),
+                        secs(row.frames).as_secs_f64(),
+                        if short { " (truncated?)" } else { "" }
+                    )
+                })
+            }
+        };
+        if let Some(reason) = problem {
+            tracing::warn!("{}: {}", path.display(), reason);
+            report.problems.push(Problem {
+                segment: row.segment,
+                reason,
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// `morganite verify <dir>`: whether every segment passed.
+pub fn run(args: &VerifyArgs) -> anyhow::Result<bool> {
+    let report = verify_run(&args.dir)?;
+    print!("{}", report.render());
+    Ok(report.problems.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finished_outputs_are_the_folders_with_segments_left() {
+        let dir = std::env::temp_dir().join(format!("morganite-{}-finished", std::process::id()));
+        for (run, segments) in [
+            (
+                "20260101_120000",
+                "a/audio_000.mp3\n1,24000,1,b/audio_000.mp3",
+            ),
+            (
+                "20260102_120000",
+                "c/001_one/audio_000.mp3\n1,24000,1,audio_000.mp3",
+            ),
+        ] {
+            let run = dir.join(run);
+            let csv = format!("frames,sample_rate,streams,segment\n1,24000,1,{segments}\n");
+            for row in csv.lines().skip(1) {
+                let segment = run.join(row.splitn(4, ',').nth(3).unwrap());
+                std::fs::create_dir_all(segment.parent().unwrap()).unwrap();
+                std::fs::write(segment, b"").unwrap();
+            }
+            std::fs::write(run.join(SEGMENTS_CSV), csv).unwrap();
+        }
+        // Deleted since
+        std::fs::remove_file(dir.join("20260101_120000/b/audio_000.mp3")).unwrap();
+
+        let finished = finished_outputs(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(finished.into_iter().collect::<Vec<_>>(), ["a", "c"]);
+    }
+
+    #[test]
+    fn no_output_folder_is_nothing_finished() {
+        let dir = std::env::temp_dir().join(format!("morganite-{}-no-output", std::process::id()));
+        assert!(finished_outputs(&dir).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use kokoro_tts::Voice;
+use serde::Serialize;
+
+use crate::utils;
+
+#[derive(clap::Args)]
+pub struct ListVoicesArgs {
+    /// Voice bin model to check the known voices against
+    #[arg(long, short = 'v')]
+    voice_model: Option<PathBuf>,
+
+    /// Print JSON instead of a list
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct VoiceGroup {
+    prefix: String,
+    voices: Vec<VoiceEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct VoiceEntry {
+    name: &'static str,
+    /// Whether the voice model contains it; `None` when no model was given.
+    present: Option<bool>,
+}
+
+/// How each input file gets its voice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum VoicePolicy {
+    /// Every file uses `--voice`.
+    Fixed,
+    /// A seeded random pick from the pool per file.
+    Random,
+    /// Cycle through the pool in file order.
+    Rotate,
+}
+
+/// Picks the voice for each input file under a [`VoicePolicy`]. The choice is made
+/// once per file, so a file never changes voice midway.
+pub struct VoicePicker {
+    policy: VoicePolicy,
+    fixed: Voice,
+    pool: Vec<Voice>,
+    state: u64,
+    last: Option<usize>,
+}
+
+impl VoicePicker {
+    /// An empty pool means every Chinese voice for `random`; `rotate` needs a list.
+    pub fn new(
+        policy: VoicePolicy,
+        fixed: Voice,
+        pool: Vec<Voice>,
+        seed: u64,
+    ) -> anyhow::Result<Self> {
+        let pool = match (policy, pool.is_empty()) {
+            (VoicePolicy::Rotate, true) => {
+                anyhow::bail!("--voice-policy rotate needs --voice-pool")
+            }
+            (VoicePolicy::Random, true) => utils::VOICE_NAMES
+                .iter()
+                .filter(|name| name.starts_with('z'))
+                .map(|name| utils::parse_voice(name).unwrap())
+                .collect(),
+            _ => pool,
+        };
+        Ok(Self {
+            policy,
+            fixed,
+            pool,
+            state: seed,
+            last: None,
+        })
+    }
+
+    pub fn pick(&mut self, file_index: usize) -> Voice {
+        let index = match self.policy {
+            VoicePolicy::Fixed => return self.fixed,
+            VoicePolicy::Rotate => file_index % self.pool.len(),
+            VoicePolicy::Random => {
+                let len = self.pool.len();
+                let mut index = (self.next_random() % len as u64) as usize;
+                // Neighbouring files should sound different
+                if len > 1 && self.last == Some(index) {
+                    index = (index + 1 + (self.next_random() % (len as u64 - 1)) as usize) % len;
+                }
+                index
+            }
+        };
+        self.last = Some(index);
+        self.pool[index]
+    }
+
+    /// splitmix64, good enough for shuffling voices and stable across platforms.
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// A voice bin file, kept in memory to answer which voices it carries.
+pub struct VoiceBin {
+    path: PathBuf,
+    bytes: Vec<u8>,
+}
+
+impl VoiceBin {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read voice model {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            bytes,
+        })
+    }
+
+    /// The bin stores each voice under its name as plain UTF-8, so a byte search
+    /// is enough to tell whether the file carries it.
+    pub fn contains(&self, name: &str) -> bool {
+        self.bytes.windows(name.len()).any(|w| w == name.as_bytes())
+    }
+
+    /// `None` if the voice is present, otherwise an error naming the closest voices
+    /// that are.
+    pub fn check(&self, voice: Voice) -> Option<String> {
+        let name = utils::voice_name(voice);
+        (!self.contains(name)).then(|| {
+            format!(
+                "{} (not in {})",
+                utils::unknown_voice(name, |n| self.contains(n)),
+                self.path.display()
+            )
+        })
+    }
+}
+
+pub fn run(args: &ListVoicesArgs) -> anyhow::Result<()> {
+    let bin = args
+        .voice_model
+        .as_deref()
+        .map(VoiceBin::load)
+        .transpose()?;
+
+    let mut groups: BTreeMap<&str, Vec<VoiceEntry>> = BTreeMap::new();
+    for &name in utils::VOICE_NAMES {
+        let prefix = name.split_once('_').map_or(name, |(p, _)| p);
+        groups.entry(prefix).or_default().push(VoiceEntry {
+            name,
+            present: bin.as_ref().map(|bin| bin.contains(name)),
+        });
+    }
+    let groups = groups
+        .into_iter()
+        .map(|(prefix, mut voices)| {
+            voices.sort_by_key(|v| v.name);
+            VoiceGroup {
+                prefix: format!("{prefix}_"),
+                voices,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&groups)?);
+        return Ok(());
+    }
+
+    for group in &groups {
+        println!("{} ({})", group.prefix, group.voices.len());
+        let names = group
+            .voices
+            .iter()
+            .map(|v| match v.present {
+                Some(false) => format!("{} (missing)", v.name),
+                _ => v.name.to_string(),
+            })
+            .collect::<Vec<_>>();
+        for row in names.chunks(8) {
+            println!("  {}", row.join("  "));
+        }
+    }
+
+    let missing = groups
+        .iter()
+        .flat_map(|g| &g.voices)
+        .filter(|v| v.present == Some(false))
+        .map(|v| v.name)
+        .collect::<Vec<_>>();
+    if let Some(path) = &args.voice_model {
+        if missing.is_empty() {
+            println!(
+                "All {} known voices found in {}",
+                utils::VOICE_NAMES.len(),
+                path.display()
+            );
+        } else {
+            println!(
+                "{} known voices missing from {}: {}",
+                missing.len(),
+                path.display(),
+                missing.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
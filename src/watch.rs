@@ -0,0 +1,249 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use notify::Watcher;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::utils;
+
+/// A file counts as complete once its size and mtime hold still this long.
+const SETTLE: Duration = Duration::from_secs(2);
+/// Rescan interval with filesystem events, as a safety net for missed ones.
+const RESCAN: Duration = Duration::from_secs(30);
+/// Rescan interval when events are unavailable.
+const POLL: Duration = Duration::from_secs(2);
+
+/// Size and modification time; a file whose fingerprint changes is processed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    size: u64,
+    modified_secs: u64,
+}
+
+impl Fingerprint {
+    fn of(path: &Path) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        let modified_secs = meta
+            .modified()
+            .ok()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Some(Self {
+            size: meta.len(),
+            modified_secs,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Done,
+    /// Failed as it was; retried once the file changes.
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    fingerprint: Fingerprint,
+    outcome: Outcome,
+    finished_at: String,
+    output: String,
+}
+
+/// `--watch-state`: the input files already handled, kept across restarts.
+pub struct WatchState {
+    path: PathBuf,
+    files: BTreeMap<String, Entry>,
+}
+
+impl WatchState {
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let files = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        Ok(Self { path, files })
+    }
+
+    fn key(path: &Path) -> String {
+        std::fs::canonicalize(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .display()
+            .to_string()
+    }
+
+    /// Whether `path` was handled as it is now.
+    pub fn is_handled(&self, path: &Path) -> bool {
+        self.files
+            .get(&Self::key(path))
+            .is_some_and(|e| Fingerprint::of(path) == Some(e.fingerprint))
+    }
+
+    /// Record `path` as handled with `outcome`; write failures are only logged.
+    pub fn record(&mut self, path: &Path, outcome: Outcome, output: &Path) {
+        let Some(fingerprint) = Fingerprint::of(path) else {
+            return;
+        };
+        self.files.insert(
+            Self::key(path),
+            Entry {
+                fingerprint,
+                outcome,
+                finished_at: chrono::Local::now().to_rfc3339(),
+                output: output.display().to_string(),
+            },
+        );
+        if let Err(e) = self.save() {
+            tracing::warn!("{:#}", e);
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&self.files)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+}
+
+/// Watches a folder for new or changed `.txt` files, through filesystem events
+/// where the platform has them and by polling otherwise.
+pub struct Folder {
+    dir: PathBuf,
+    /// Kept alive for its events.
+    _watcher: Option<notify::RecommendedWatcher>,
+    events: mpsc::UnboundedReceiver<()>,
+    rescan: Duration,
+    /// Unhandled files and when they last changed.
+    pending: BTreeMap<PathBuf, (Fingerprint, Instant)>,
+    /// `--shard`: only this shard's files are taken.
+    shard: Option<utils::Shard>,
+    /// Files seen belonging to other shards, logged once each.
+    others: BTreeSet<PathBuf>,
+}
+
+impl Folder {
+    pub fn new(dir: PathBuf) -> Self {
+        let (tx, events) = mpsc::unbounded_channel();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if res.is_ok() {
+                let _ = tx.send(());
+            }
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        let (watcher, rescan) = match watcher {
+            Ok(watcher) => (Some(watcher), RESCAN),
+            Err(e) => {
+                tracing::warn!(
+                    "Filesystem events unavailable for {} ({}), polling every {:?}",
+                    dir.display(),
+                    e,
+                    POLL
+                );
+                (None, POLL)
+            }
+        };
+        Self {
+            dir,
+            _watcher: watcher,
+            events,
+            rescan,
+            pending: BTreeMap::new(),
+            shard: None,
+            others: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_shard(mut self, shard: Option<utils::Shard>) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    /// Wait for the next file that is new or changed since `state` saw it and
+    /// has stopped growing.
+    pub async fn next(&mut self, state: &WatchState) -> PathBuf {
+        loop {
+            if let Some(path) = self.scan(state) {
+                return path;
+            }
+            // Come back in time to see pending files settle
+            let wait = if self.pending.is_empty() {
+                self.rescan
+            } else {
+                SETTLE.min(self.rescan)
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                Some(()) = self.events.recv() => {
+                    // Let a burst of events for one write arrive before rescanning
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    while self.events.try_recv().is_ok() {}
+                }
+            }
+        }
+    }
+
+    /// Update the pending files and return one that has settled, if any. Files that
+    /// vanished are dropped, so a short-lived file never blocks the others.
+    fn scan(&mut self, state: &WatchState) -> Option<PathBuf> {
+        let files = match crate::list_txt_files(&self.dir) {
+            Ok(files) => files,
+            Err(e) => {
+                tracing::warn!("{:#}", e);
+                return None;
+            }
+        };
+        self.pending.retain(|path, _| files.contains(path));
+        for path in files {
+            if let Some(shard) = self.shard
+                && !shard.owns(&path)
+            {
+                if self.others.insert(path.clone()) {
+                    tracing::info!(
+                        "Leaving {} to shard {}/{}",
+                        path.display(),
+                        shard.of(&path),
+                        shard.total
+                    );
+                }
+                continue;
+            }
+            if state.is_handled(&path) {
+                self.pending.remove(&path);
+                continue;
+            }
+            let Some(fingerprint) = Fingerprint::of(&path) else {
+                self.pending.remove(&path);
+                continue;
+            };
+            match self.pending.get(&path) {
+                Some((seen, _)) if *seen == fingerprint => {}
+                _ => {
+                    self.pending.insert(path, (fingerprint, Instant::now()));
+                }
+            }
+        }
+        let settled = self
+            .pending
+            .iter()
+            .find(|(_, (_, since))| since.elapsed() >= SETTLE)
+            .map(|(path, _)| path.clone())?;
+        self.pending.remove(&settled);
+        Some(settled)
+    }
+}
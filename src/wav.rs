@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+/// Read a WAV or MP3 file (by extension) as interleaved f32 samples converted to
+/// `sample_rate` and `channels` (downmixed by averaging, upmixed by duplication,
+/// resampled linearly).
+pub fn load(path: &Path, sample_rate: u32, channels: usize) -> anyhow::Result<Vec<f32>> {
+    let is_mp3 = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("mp3"));
+    let (samples, src_channels, src_rate) = if is_mp3 {
+        decode_mp3(path)?
+    } else {
+        decode_wav(path)?
+    };
+
+    anyhow::ensure!(src_channels > 0, "{} has no channels", path.display());
+    let mut mixed = Vec::with_capacity(samples.len() / src_channels * channels);
+    for frame in samples.chunks_exact(src_channels) {
+        match channels {
+            1 => mixed.push(frame.iter().sum::<f32>() / src_channels as f32),
+            _ => (0..channels).for_each(|c| mixed.push(frame[c.min(src_channels - 1)])),
+        }
+    }
+
+    Ok(resample(&mixed, channels, src_rate, sample_rate))
+}
+
+/// Interleaved samples, channel count and rate of a WAV file.
+fn decode_wav(path: &Path) -> anyhow::Result<(Vec<f32>, usize, u32)> {
+    let mut reader = hound::WavReader::open(path)
+        .with_context(|| format!("Failed to open audio file {}", path.display()))?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>(),
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect()
+        }
+    }
+    .with_context(|| format!("Failed to decode audio file {}", path.display()))?;
+    Ok((samples, spec.channels as usize, spec.sample_rate))
+}
+
+/// Interleaved samples, channel count and rate of an MP3 file.
+fn decode_mp3(path: &Path) -> anyhow::Result<(Vec<f32>, usize, u32)> {
+    use rodio::Source;
+
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open audio file {}", path.display()))?;
+    let decoder = rodio::Decoder::new_mp3(std::io::BufReader::new(file))
+        .with_context(|| format!("Failed to decode audio file {}", path.display()))?;
+    let (channels, rate) = (decoder.channels() as usize, decoder.sample_rate());
+    let samples = decoder
+        .map(|s| s as f32 / -(i16::MIN as f32))
+        .collect::<Vec<_>>();
+    anyhow::ensure!(!samples.is_empty(), "{} has no audio", path.display());
+    Ok((samples, channels, rate))
+}
+
+/// Write mono f32 samples as a 32-bit float WAV file.
+pub fn save(path: &Path, samples: &[f32], sample_rate: u32) -> anyhow::Result<()> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    for &s in samples {
+        writer.write_sample(s)?;
+    }
+    writer
+        .finalize()
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Linear-interpolation resampling of interleaved audio.
+pub fn resample(samples: &[f32], channels: usize, from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let in_frames = samples.len() / channels;
+    let out_frames = (in_frames as u64 * to as u64 / from as u64) as usize;
+    let mut out = Vec::with_capacity(out_frames * channels);
+    for t in 0..out_frames {
+        let pos = t as f64 * from as f64 / to as f64;
+        let i = (pos as usize).min(in_frames - 1);
+        let next = (i + 1).min(in_frames - 1);
+        let frac = (pos - i as f64) as f32;
+        for c in 0..channels {
+            let a = samples[i * channels + c];
+            let b = samples[next * channels + c];
+            out.push(a + (b - a) * frac);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer;
+
+    #[test]
+    fn mp3_clips_load_like_wav_clips() {
+        let dir = std::env::temp_dir().join(format!("morganite-{}-wav-load", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tone = (0..writer::SAMPLE_RATE as usize)
+            .map(|i| 0.5 * (i as f32 * 0.05).sin())
+            .collect::<Vec<_>>();
+        let mp3 = dir.join("intro.mp3");
+        writer::write_mp3(&mp3, writer::default_mono_24k_config(64), &tone).unwrap();
+        let wav = dir.join("intro.wav");
+        save(&wav, &tone, writer::SAMPLE_RATE).unwrap();
+
+        let from_wav = load(&wav, writer::SAMPLE_RATE, 1).unwrap();
+        assert_eq!(from_wav, tone);
+        let from_mp3 = load(&mp3, writer::SAMPLE_RATE, 1).unwrap();
+        // Encoder delay and padding to whole frames shift the length a little
+        let drift = from_mp3.len().abs_diff(tone.len());
+        assert!(
+            drift < writer::SAMPLE_RATE as usize / 10,
+            "{drift} samples off"
+        );
+        // Stereo output duplicates the decoded channel
+        let stereo = load(&mp3, writer::SAMPLE_RATE / 2, 2).unwrap();
+        assert_eq!(stereo.len() / 2, from_mp3.len() / 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_files_fail_to_load() {
+        for name in ["missing.wav", "missing.mp3"] {
+            let error = load(Path::new(name), writer::SAMPLE_RATE, 1).unwrap_err();
+            assert!(format!("{error:#}").contains("Failed to open"), "{error:#}");
+        }
+    }
+}
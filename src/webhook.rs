@@ -0,0 +1,277 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::summary;
+
+/// Bumped whenever a field changes meaning or goes away; new fields don't bump it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Attempts per event, with doubling waits starting at [`BACKOFF`].
+const ATTEMPTS: u32 = 4;
+const BACKOFF: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest the end of the run waits for events still being delivered.
+const FLUSH: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "snake_case")]
+pub enum EventKind {
+    RunStarted,
+    FileCompleted,
+    RunCompleted,
+    RunFailed,
+}
+
+impl EventKind {
+    /// The payload's `event` field and the `X-Morganite-Event` header.
+    fn name(self) -> &'static str {
+        match self {
+            Self::RunStarted => "run_started",
+            Self::FileCompleted => "file_completed",
+            Self::RunCompleted => "run_completed",
+            Self::RunFailed => "run_failed",
+        }
+    }
+}
+
+/// One input file, as reported in `file_completed` and the run summaries.
+#[derive(Debug, Serialize)]
+pub struct FileStats {
+    pub file: String,
+    pub finished: bool,
+    pub lines: usize,
+    pub failed_lines: usize,
+    pub skipped_lines: usize,
+    pub audio_secs: f64,
+    pub wall_secs: f64,
+    pub segments: Vec<String>,
+}
+
+impl From<&summary::FileSummary> for FileStats {
+    fn from(f: &summary::FileSummary) -> Self {
+        Self {
+            file: f.file.clone(),
+            finished: f.finished,
+            lines: f.lines,
+            failed_lines: f.failed,
+            skipped_lines: f.skipped,
+            audio_secs: f.audio.as_secs_f64(),
+            wall_secs: f.wall.as_secs_f64(),
+            segments: f.segments.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunStats {
+    pub partial: bool,
+    pub audio_secs: f64,
+    pub wall_secs: f64,
+    pub files: Vec<FileStats>,
+}
+
+impl From<&summary::RunSummary> for RunStats {
+    fn from(run: &summary::RunSummary) -> Self {
+        Self {
+            partial: run.partial,
+            audio_secs: run.files.iter().map(|f| f.audio.as_secs_f64()).sum(),
+            wall_secs: run.wall.as_secs_f64(),
+            files: run.files.iter().map(FileStats::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    RunStarted { inputs: Vec<String> },
+    FileCompleted { file: FileStats },
+    RunCompleted { summary: RunStats },
+    RunFailed { error: String, summary: RunStats },
+}
+
+impl Event {
+    fn kind(&self) -> EventKind {
+        match self {
+            Self::RunStarted { .. } => EventKind::RunStarted,
+            Self::FileCompleted { .. } => EventKind::FileCompleted,
+            Self::RunCompleted { .. } => EventKind::RunCompleted,
+            Self::RunFailed { .. } => EventKind::RunFailed,
+        }
+    }
+}
+
+/// The JSON body POSTed for every event.
+#[derive(Debug, Serialize)]
+pub struct Payload<'a> {
+    pub version: u32,
+    /// The run folder's name, the same in every event of a run.
+    pub run: &'a str,
+    pub sent_at: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// `--webhook-url`: POSTs run events from a background task, in order. Delivery
+/// problems are logged and never touch the run.
+pub struct Notifier {
+    run: String,
+    events: Vec<EventKind>,
+    tx: mpsc::UnboundedSender<(EventKind, Vec<u8>)>,
+    task: JoinHandle<()>,
+}
+
+impl Notifier {
+    /// `secret` signs every body in `X-Morganite-Signature: sha256=<hex HMAC>`.
+    pub fn start(url: String, secret: Option<String>, events: Vec<EventKind>, run: String) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(EventKind, Vec<u8>)>();
+        let task = tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            while let Some((kind, body)) = rx.recv().await {
+                deliver(&client, &url, secret.as_deref(), kind, body).await;
+            }
+        });
+        Self {
+            run,
+            events,
+            tx,
+            task,
+        }
+    }
+
+    /// Queue `event`, unless `--webhook-events` leaves it out.
+    pub fn send(&self, event: Event) {
+        let kind = event.kind();
+        if !self.events.contains(&kind) {
+            return;
+        }
+        let payload = Payload {
+            version: SCHEMA_VERSION,
+            run: &self.run,
+            sent_at: chrono::Local::now().to_rfc3339(),
+            event,
+        };
+        let _ = self.tx.send((kind, serde_json::to_vec(&payload).unwrap()));
+    }
+
+    /// Give queued events a little while to go out before the process ends.
+    pub async fn finish(self) {
+        drop(self.tx);
+        if tokio::time::timeout(FLUSH, self.task).await.is_err() {
+            tracing::warn!("Gave up on webhook events not delivered within {:?}", FLUSH);
+        }
+    }
+}
+
+/// The `X-Morganite-Signature` value for `body`.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    url: &str,
+    secret: Option<&str>,
+    kind: EventKind,
+    body: Vec<u8>,
+) {
+    let signature = secret.map(|secret| sign(secret, &body));
+    let name = kind.name();
+    let mut delay = BACKOFF;
+    for attempt in 1..=ATTEMPTS {
+        let mut request = client
+            .post(url)
+            .timeout(TIMEOUT)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Morganite-Event", name)
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header("X-Morganite-Signature", signature);
+        }
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => response.status().to_string(),
+            Err(e) => e.to_string(),
+        };
+        if attempt == ATTEMPTS {
+            tracing::warn!("Webhook {} not delivered: {}", name, error);
+            return;
+        }
+        tracing::debug!("Webhook {} attempt {} failed: {}", name, attempt, error);
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_the_hex_hmac_of_the_body() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn payload_carries_the_event_name_beside_the_run() {
+        let payload = Payload {
+            version: SCHEMA_VERSION,
+            run: "20260101_120000",
+            sent_at: "2026-01-01T12:00:00+00:00".to_string(),
+            event: Event::RunStarted {
+                inputs: vec!["a.txt".to_string()],
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({
+                "version": 1,
+                "run": "20260101_120000",
+                "sent_at": "2026-01-01T12:00:00+00:00",
+                "event": "run_started",
+                "inputs": ["a.txt"],
+            })
+        );
+    }
+
+    #[test]
+    fn event_names_match_the_payload_tags() {
+        let summary = || RunStats::from(&summary::RunSummary::default());
+        let file = FileStats::from(&summary::FileSummary::new("a.txt"));
+        for event in [
+            Event::RunStarted { inputs: Vec::new() },
+            Event::FileCompleted { file },
+            Event::RunCompleted { summary: summary() },
+            Event::RunFailed {
+                error: "boom".to_string(),
+                summary: summary(),
+            },
+        ] {
+            let name = event.kind().name();
+            assert_eq!(serde_json::to_value(&event).unwrap()["event"], name);
+        }
+    }
+
+    #[test]
+    fn run_stats_add_up_the_files() {
+        let mut run = summary::RunSummary::default();
+        for secs in [1.5, 2.5] {
+            let mut file = summary::FileSummary::new("a.txt");
+            file.audio = Duration::from_secs_f64(secs);
+            run.files.push(file);
+        }
+        let stats = RunStats::from(&run);
+        assert_eq!(stats.audio_secs, 4.0);
+        assert_eq!(stats.files.len(), 2);
+    }
+}
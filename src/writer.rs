@@ -1,11 +1,24 @@
 use std::{
     fs::File,
     io::{BufWriter, Write},
+    pin::Pin,
+    sync::Arc,
     time::Duration,
 };
 
 use anyhow::Context;
 use shine_rs::{Mp3Encoder, Mp3EncoderConfig, StereoMode};
+use tokio::sync::mpsc;
+
+use crate::{dsp, pipe, playback};
+
+/// Called with the path of every segment once its file is complete.
+pub type SegmentHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Synthesizes what opens part `n` (from 1) of a group; see
+/// [`Mp3Splitter::with_announcer`].
+pub type Announcer = Arc<dyn Fn(u32) -> Announcement + Send + Sync>;
+pub type Announcement = Pin<Box<dyn Future<Output = anyhow::Result<Vec<f32>>> + Send>>;
 
 pub const SAMPLE_RATE: u32 = 24_000;
 pub const CHANNELS: u8 = 1;
@@ -26,21 +39,287 @@ fn frames_for_duration(sample_rate: u32, d: Duration) -> anyhow::Result<u64> {
     Ok(frames as u64)
 }
 
-pub struct Mp3Splitter {
+/// How the segment files of a group are named.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SegmentNaming {
+    /// `audio_000.mp3`, `audio_001.mp3`, ..., in a folder per chapter
+    #[default]
+    Index,
+    /// `012_title.mp3`, then `012_title_b.mp3`, ... when a chapter spans several
+    Chapter,
+}
+
+/// `_b`, `_c`, ... for the parts after the first, then numbers past `_z`.
+fn part_suffix(index: u32) -> String {
+    match index {
+        0 => String::new(),
+        1..=25 => format!("_{}", (b'a' + index as u8) as char),
+        _ => format!("_{}", index + 1),
+    }
+}
+
+/// Which segment the audio lands in and where, kept apart from the encoding so a
+/// copy can follow the writer's progress without touching any files.
+#[derive(Debug, Clone)]
+pub struct Layout {
     prefix: String,
+    naming: SegmentNaming,
+    /// Ends every file name, before `.mp3`.
+    suffix: &'static str,
+    /// Every prefix used so far, lowercased.
+    groups: Vec<String>,
     index: u32,
-
-    config: Mp3EncoderConfig,
+    sample_rate: u32,
+    channels: u64,
 
     /// Max frames (per channel) per file.
     frames_per_file: u64,
     written_frames: u64,
+    /// A segment is open (its file exists and takes frames).
+    open: bool,
+    total_samples: u64,
+
+    /// Path of every segment opened so far, across groups.
+    opened: Vec<String>,
+    /// Frames of the announcement opening the segment at each index, once
+    /// synthesized; the same in every group.
+    announce: Vec<u64>,
+}
+
+impl Layout {
+    fn new(prefix: String, config: &Mp3EncoderConfig, frames_per_file: u64) -> Self {
+        Self {
+            groups: vec![prefix.to_lowercase()],
+            prefix,
+            naming: SegmentNaming::Index,
+            suffix: "",
+            index: 0,
+            sample_rate: config.sample_rate,
+            channels: config.channels as u64,
+            frames_per_file,
+            written_frames: 0,
+            open: false,
+            total_samples: 0,
+            opened: Vec::new(),
+            announce: Vec::new(),
+        }
+    }
+
+    /// Frames the announcement of segment `index` takes, leaving room for at least
+    /// as much other audio.
+    fn announce_frames(&self, index: u32) -> u64 {
+        self.announce
+            .get(index as usize)
+            .map_or(0, |&frames| frames.min(self.frames_per_file / 2))
+    }
+
+    /// Index of the segment the next written frame lands in.
+    fn next_index(&self) -> u32 {
+        if !self.open || self.written_frames >= self.frames_per_file {
+            self.index
+        } else {
+            self.index - 1
+        }
+    }
+
+    fn segment_path(&self, index: u32) -> String {
+        match self.naming {
+            SegmentNaming::Index => format!("{}_{:03}{}.mp3", self.prefix, index, self.suffix),
+            SegmentNaming::Chapter => {
+                format!("{}{}{}.mp3", self.prefix, part_suffix(index), self.suffix)
+            }
+        }
+    }
+
+    /// Start the next segment, its announcement included, and return its path.
+    fn open_next(&mut self) -> String {
+        let path = self.segment_path(self.index);
+        let announce = self.announce_frames(self.index);
+        self.index += 1;
+        self.opened.push(path.clone());
+        self.written_frames = 0;
+        self.open = true;
+        self.advance(announce);
+        path
+    }
+
+    /// Close the open segment and return its path.
+    fn close(&mut self) -> String {
+        self.open = false;
+        self.written_frames = 0;
+        self.segment_path(self.index - 1)
+    }
 
-    out: Option<BufWriter<File>>,
+    /// Frames the open segment still takes; zero when none is open.
+    fn remaining(&self) -> u64 {
+        if self.open {
+            self.frames_per_file.saturating_sub(self.written_frames)
+        } else {
+            0
+        }
+    }
+
+    fn advance(&mut self, frames: u64) {
+        self.written_frames += frames;
+        self.total_samples += frames * self.channels;
+    }
+
+    fn start_group(&mut self, prefix: String) {
+        if self.open {
+            self.close();
+        }
+        // Two groups must never share files; case alone doesn't keep them apart on
+        // every filesystem
+        let mut unique = prefix.clone();
+        let mut n = 1;
+        while self.groups.contains(&unique.to_lowercase()) {
+            n += 1;
+            unique = format!("{}_{}", prefix, n);
+        }
+        self.groups.push(unique.to_lowercase());
+        self.prefix = unique;
+        self.index = 0;
+    }
+
+    /// Account for `frames` written the way [`Mp3Splitter::write_f32_interleaved`]
+    /// spreads them over segments.
+    fn record_write(&mut self, mut frames: u64) {
+        if !self.open {
+            self.open_next();
+        }
+        while frames > 0 {
+            let remaining = self.remaining();
+            if remaining == 0 {
+                self.open_next();
+                continue;
+            }
+            let take = remaining.min(frames);
+            self.advance(take);
+            frames -= take;
+        }
+    }
+
+    /// Whether a separately encoded clip of `frames` starts a new segment: clips are
+    /// never split, so one that doesn't fit rotates unless the segment is still empty.
+    fn clip_opens_segment(&self, frames: u64) -> bool {
+        !self.open || (self.written_frames > 0 && self.remaining() < frames)
+    }
+
+    /// Account for a clip written by [`Mp3Splitter::write_encoded`].
+    fn record_clip(&mut self, frames: u64) {
+        if self.clip_opens_segment(frames) {
+            self.open_next();
+        }
+        self.advance(frames);
+    }
+
+    /// Frames of a `duration` pause that actually get written: without lead-in
+    /// padding a pause never spills past the end of the open segment.
+    fn silence_frames(&self, duration: Duration, lead_in_padding: bool) -> u64 {
+        let frames = (duration.as_secs_f64() * self.sample_rate as f64).round() as u64;
+        if lead_in_padding {
+            frames
+        } else {
+            frames.min(self.remaining())
+        }
+    }
+
+    /// Segment file and offset within it where the next written frame will land,
+    /// taking a pending rotation into account.
+    pub fn position(&self) -> (String, Duration) {
+        if !self.open || self.written_frames >= self.frames_per_file {
+            let announce = self.announce_frames(self.index);
+            let offset = Duration::from_secs_f64(announce as f64 / self.sample_rate as f64);
+            return (self.segment_path(self.index), offset);
+        }
+        let offset = Duration::from_secs_f64(self.written_frames as f64 / self.sample_rate as f64);
+        (self.segment_path(self.index - 1), offset)
+    }
+
+    /// Every segment file opened so far, in order.
+    pub fn segment_paths(&self) -> &[String] {
+        &self.opened
+    }
+
+    /// Audio written so far across all segments.
+    pub fn elapsed(&self) -> Duration {
+        let frames = self.total_samples / self.channels;
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+/// Splits the audio into MP3 segments as it is written.
+pub struct Mp3Splitter {
+    state: SplitterState,
+    /// Encoder of the open segment. shine's encoder isn't `Send`, so it is only
+    /// ever created on the thread that writes with it (see [`Self::spawn`]).
     enc: Option<Mp3Encoder>,
+}
+
+/// Everything of an [`Mp3Splitter`] but its encoder: what [`Mp3Splitter::spawn`]
+/// moves to the writer thread.
+struct SplitterState {
+    config: Mp3EncoderConfig,
+    layout: Layout,
+
+    out: Option<Box<dyn Write + Send>>,
+    /// `--stdout`: segments go one after another to standard output, untagged,
+    /// flushed after every write.
+    stdout: bool,
+    /// `enc` holds samples not yet flushed to `out`.
+    pending: bool,
+    /// MP3 streams started in the open segment.
+    streams: u32,
 
     /// Scratch buffer for PCM conversion (interleaved i16).
     pcm_i16: Vec<i16>,
+
+    /// Allow `write_silence` to spill into (or start) a new segment.
+    lead_in_padding: bool,
+
+    /// ID3v2 text frames (`TPE1`, ...) written at the start of every segment.
+    tags: Vec<(&'static str, String)>,
+
+    /// Linear output gain; 1.0 skips the scaling entirely.
+    gain: f32,
+    gain_db: f32,
+    /// Samples pushed past full scale by the gain, out of all samples written.
+    clipped: u64,
+
+    /// `--encoder-quality`: band-limits after the gain and music, before the limiter.
+    low_pass: Option<dsp::LowPass>,
+    /// Runs after the gain, right before conversion to i16.
+    limiter: Option<dsp::Limiter>,
+    /// Scratch buffer for gain and limiting.
+    scaled: Vec<f32>,
+
+    /// Background track mixed under all audio before limiting.
+    music: Option<dsp::MusicBed>,
+
+    /// Levels of the final samples of the open segment, and of every closed one.
+    meter: dsp::LevelMeter,
+    levels: Vec<SegmentLevels>,
+
+    /// Also gets the final samples, for `--play`.
+    tap: Option<Arc<playback::LiveOutput>>,
+    /// `--pipe-to`: also gets the final samples, and may hold up writing.
+    pipe: Option<pipe::PcmSink>,
+    on_segment_closed: Option<SegmentHook>,
+
+    announcer: Option<Announcer>,
+    /// Samples of the announcement opening the segment at each index.
+    announcements: Vec<Vec<f32>>,
+}
+
+/// Measured levels of one finished segment file.
+#[derive(Debug, Clone)]
+pub struct SegmentLevels {
+    pub segment: String,
+    pub levels: dsp::Levels,
+    /// Frames (per channel) written to the segment.
+    pub frames: u64,
+    /// Separately finished MP3 streams in the file, each padded to a whole MP3 frame.
+    pub streams: u32,
 }
 
 impl Mp3Splitter {
@@ -63,26 +342,134 @@ impl Mp3Splitter {
             config.channels
         );
 
+        let sample_rate = config.sample_rate;
         Ok(Self {
-            prefix: prefix.into(),
-            index: 0,
-            config,
-            frames_per_file,
-            written_frames: 0,
-            out: None,
+            state: SplitterState {
+                layout: Layout::new(prefix.into(), &config, frames_per_file),
+                config,
+                out: None,
+                stdout: false,
+                pending: false,
+                streams: 0,
+                pcm_i16: Vec::new(),
+                lead_in_padding: false,
+                tags: Vec::new(),
+                gain: 1.0,
+                gain_db: 0.0,
+                clipped: 0,
+                low_pass: None,
+                limiter: None,
+                scaled: Vec::new(),
+                music: None,
+                meter: dsp::LevelMeter::new(sample_rate),
+                levels: Vec::new(),
+                tap: None,
+                pipe: None,
+                on_segment_closed: None,
+                announcer: None,
+                announcements: Vec::new(),
+            },
             enc: None,
-            pcm_i16: Vec::new(),
         })
     }
 
+    pub fn with_segment_naming(mut self, naming: SegmentNaming) -> Self {
+        self.state.layout.naming = naming;
+        self
+    }
+
+    /// End every file name with `suffix`, e.g. `audio_000_preview.mp3`.
+    pub fn with_suffix(mut self, suffix: &'static str) -> Self {
+        self.state.layout.suffix = suffix;
+        self
+    }
+
+    /// By default silence is never used to start a segment: pauses that would cross a
+    /// rotation are cut at the segment end. Enable to keep them whole.
+    pub fn with_lead_in_padding(mut self, on: bool) -> Self {
+        self.state.lead_in_padding = on;
+        self
+    }
+
+    /// Tag every segment with an ID3v2 text frame, e.g. `TPE1` for the narrator.
+    pub fn with_tag(mut self, frame_id: &'static str, value: impl Into<String>) -> Self {
+        self.state.tags.push((frame_id, value.into()));
+        self
+    }
+
+    /// Scale everything written by `db` decibels before conversion to i16.
+    pub fn with_gain_db(mut self, db: f32) -> Self {
+        self.state.gain_db = db;
+        self.state.gain = 10f32.powf(db / 20.0);
+        self
+    }
+
+    pub fn with_low_pass(mut self, low_pass: Option<dsp::LowPass>) -> Self {
+        self.state.low_pass = low_pass;
+        self
+    }
+
+    pub fn with_limiter(mut self, limiter: Option<dsp::Limiter>) -> Self {
+        self.state.limiter = limiter;
+        self
+    }
+
+    pub fn with_music(mut self, music: Option<dsp::MusicBed>) -> Self {
+        self.state.music = music;
+        self
+    }
+
+    pub fn with_tap(mut self, tap: Option<Arc<playback::LiveOutput>>) -> Self {
+        self.state.tap = tap;
+        self
+    }
+
+    pub fn with_pipe(mut self, pipe: Option<pipe::PcmSink>) -> Self {
+        self.state.pipe = pipe;
+        self
+    }
+
+    pub fn with_segment_hook(mut self, hook: Option<SegmentHook>) -> Self {
+        self.state.on_segment_closed = hook;
+        self
+    }
+
+    /// Open every segment with what `announcer` synthesizes for its part number,
+    /// counted toward the segment's length. Announcements are synthesized ahead of
+    /// the writes that need them, so only the [`BackgroundSplitter`] from
+    /// [`Self::spawn`] plays them.
+    pub fn with_announcer(mut self, announcer: Option<Announcer>) -> Self {
+        self.state.announcer = announcer;
+        self
+    }
+
+    /// Write to standard output instead of segment files. Pair with a segment
+    /// duration no file reaches to get one continuous stream.
+    pub fn with_stdout(mut self, on: bool) -> Self {
+        self.state.stdout = on;
+        self
+    }
+
+    /// Let the music bed play on alone for `fade`, fading out. No-op without music.
+    pub fn write_music_tail(&mut self, fade: Duration) -> anyhow::Result<()> {
+        let Some(music) = self.state.music.as_mut() else {
+            return Ok(());
+        };
+        let ch = self.state.config.channels as usize;
+        let frames = (fade.as_secs_f64() * self.state.config.sample_rate as f64).round() as usize;
+        music.fade_out(frames * ch);
+        self.write_f32_interleaved(&vec![0.0; frames * ch])
+    }
+
     fn finish_current(&mut self) -> anyhow::Result<()> {
         // If nothing opened yet, nothing to do.
-        if self.out.is_none() && self.enc.is_none() {
+        if self.state.out.is_none() && self.enc.is_none() {
             return Ok(());
         }
 
         // Take writer first so we can write tail bytes then drop/flush.
         let mut out = self
+            .state
             .out
             .take()
             .context("internal error: encoder exists without writer")?;
@@ -96,32 +483,93 @@ impl Mp3Splitter {
         }
 
         out.flush().context("failed flushing mp3 output")?;
-        self.written_frames = 0;
+        drop(out);
+        let frames = self.state.layout.written_frames;
+        let segment = self.state.layout.close();
+        if let Some(hook) = &self.state.on_segment_closed {
+            hook(&segment);
+        }
+        self.state.levels.push(SegmentLevels {
+            segment,
+            levels: self.state.meter.finish(),
+            frames,
+            streams: self.state.streams,
+        });
         Ok(())
     }
 
     fn open_next(&mut self) -> anyhow::Result<()> {
         self.finish_current()?;
 
-        let path = format!("{}_{:03}.mp3", self.prefix, self.index);
-        self.index += 1;
-
-        let file = File::create(&path).with_context(|| format!("create {}", path))?;
-        let out = BufWriter::new(file);
+        let path = self.state.layout.open_next();
+        let mut out: Box<dyn Write + Send> = if self.state.stdout {
+            Box::new(std::io::stdout())
+        } else {
+            let file = File::create(&path).with_context(|| format!("create {}", path))?;
+            Box::new(BufWriter::new(file))
+        };
+        // A tag in the middle of a stream would be played as noise by some decoders
+        if !self.state.tags.is_empty() && !self.state.stdout {
+            out.write_all(&id3v2_tag(&self.state.tags))
+                .context("failed writing ID3 tag")?;
+        }
 
-        let enc = Mp3Encoder::new(self.config.clone()).context("create mp3 encoder")?;
+        let enc = Mp3Encoder::new(self.state.config.clone()).context("create mp3 encoder")?;
 
-        self.out = Some(out);
+        self.state.out = Some(out);
         self.enc = Some(enc);
-        self.written_frames = 0;
+        self.state.pending = false;
+        self.state.streams = 1;
+
+        // The layout has counted the announcement already
+        let index = self.state.layout.index - 1;
+        let frames = self.state.layout.announce_frames(index) as usize;
+        if frames > 0 {
+            let ch = self.state.config.channels as usize;
+            let announcement = std::mem::take(&mut self.state.announcements[index as usize]);
+            self.encode(&announcement[..frames * ch])?;
+            self.state.announcements[index as usize] = announcement;
+        }
 
         Ok(())
     }
 
+    /// Flush the open segment's encoder and start a fresh one, so a separately
+    /// encoded clip can follow on a frame boundary.
+    fn restart_encoder(&mut self) -> anyhow::Result<()> {
+        if !self.state.pending {
+            return Ok(());
+        }
+        let enc = self
+            .enc
+            .replace(Mp3Encoder::new(self.state.config.clone()).context("create mp3 encoder")?);
+        let tail = enc
+            .context("internal error: writer exists without encoder")?
+            .finish()
+            .context("mp3 encoder finish failed")?;
+        self.state
+            .out
+            .as_mut()
+            .context("internal error: encoder exists without writer")?
+            .write_all(&tail)
+            .context("failed writing mp3 tail")?;
+        self.state.pending = false;
+        self.state.streams += 1;
+        Ok(())
+    }
+
+    /// Close the current segment and continue under a new prefix, restarting the
+    /// segment numbering (e.g. at a chapter boundary).
+    pub fn start_group(&mut self, prefix: impl Into<String>) -> anyhow::Result<()> {
+        self.finish_current()?;
+        self.state.layout.start_group(prefix.into());
+        Ok(())
+    }
+
     /// Write interleaved f32 samples (`[L, R, L, R, ...]` for stereo; `[M, M, ...]` for mono),
     /// splitting to new MP3 files once `segment_duration` worth of frames is reached.
     pub fn write_f32_interleaved(&mut self, samples: &[f32]) -> anyhow::Result<()> {
-        let ch = self.config.channels as usize;
+        let ch = self.state.config.channels as usize;
         anyhow::ensure!(ch == 1 || ch == 2, "only 1 or 2 channels supported");
         anyhow::ensure!(
             samples.len().is_multiple_of(ch),
@@ -136,7 +584,7 @@ impl Mp3Splitter {
         let mut frame_offset = 0usize;
 
         while frame_offset < total_frames {
-            let remaining_frames_in_file = (self.frames_per_file - self.written_frames) as usize;
+            let remaining_frames_in_file = self.state.layout.remaining() as usize;
             if remaining_frames_in_file == 0 {
                 // Close current file & start next segment
                 self.open_next()?;
@@ -147,56 +595,615 @@ impl Mp3Splitter {
 
             let start = frame_offset * ch;
             let end = (frame_offset + take_frames) * ch;
+            self.encode(&samples[start..end])?;
+
+            self.state.layout.advance(take_frames as u64);
+            frame_offset += take_frames;
+        }
+
+        Ok(())
+    }
 
-            self.pcm_i16.clear();
-            self.pcm_i16.reserve(end - start);
-            for &s in &samples[start..end] {
-                self.pcm_i16.push(f32_to_i16(s));
+    /// Process and encode `samples` into the open segment, without accounting for
+    /// them in the layout.
+    fn encode(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.state.pcm_i16.clear();
+        self.state.pcm_i16.reserve(samples.len());
+        if self.state.gain == 1.0
+            && self.state.low_pass.is_none()
+            && self.state.limiter.is_none()
+            && self.state.music.is_none()
+        {
+            self.state.meter.add(samples);
+            if let Some(tap) = &self.state.tap {
+                tap.push(samples);
             }
+            if let Some(pipe) = self.state.pipe.as_mut() {
+                pipe.write(samples)?;
+            }
+            for &s in samples {
+                self.state.pcm_i16.push(f32_to_i16(s));
+            }
+        } else {
+            self.state.scaled.clear();
+            self.state
+                .scaled
+                .extend(samples.iter().map(|s| s * self.state.gain));
+            if let Some(music) = self.state.music.as_mut() {
+                music.mix(&mut self.state.scaled);
+            }
+            if let Some(low_pass) = self.state.low_pass.as_mut() {
+                low_pass.process(&mut self.state.scaled);
+            }
+            if let Some(limiter) = self.state.limiter.as_mut() {
+                limiter.process(&mut self.state.scaled);
+            }
+            self.state.meter.add(&self.state.scaled);
+            if let Some(tap) = &self.state.tap {
+                tap.push(&self.state.scaled);
+            }
+            if let Some(pipe) = self.state.pipe.as_mut() {
+                pipe.write(&self.state.scaled)?;
+            }
+            for &s in &self.state.scaled {
+                if s.abs() > 1.0 {
+                    self.state.clipped += 1;
+                }
+                self.state.pcm_i16.push(f32_to_i16(s));
+            }
+        }
+
+        let enc = self.enc.as_mut().unwrap();
+        let mp3_blocks = enc
+            .encode_interleaved(&self.state.pcm_i16)
+            .context("mp3 encode_interleaved failed")?;
+
+        let out = self.state.out.as_mut().unwrap();
+        for b in mp3_blocks {
+            out.write_all(&b)
+                .context("failed writing mp3 frame block")?;
+        }
+        if self.state.stdout {
+            out.flush().context("failed flushing mp3 output")?;
+        }
+        self.state.pending = true;
+        Ok(())
+    }
+
+    /// Append a clip already encoded by [`encode_clip`] from `samples`, which must be
+    /// final (gain and limiting applied). The clip goes whole into the open segment,
+    /// or starts the next one if it doesn't fit.
+    pub fn write_encoded(&mut self, samples: &[f32], bytes: &[u8]) -> anyhow::Result<()> {
+        let frames = (samples.len() / self.state.config.channels as usize) as u64;
+        if self.enc.is_none() || self.state.layout.clip_opens_segment(frames) {
+            self.open_next()?;
+        }
+        // Also flushes an announcement opening the segment, which would otherwise
+        // come out of the encoder after the clip
+        self.restart_encoder()?;
+        self.state.meter.add(samples);
+        if let Some(tap) = &self.state.tap {
+            tap.push(samples);
+        }
+        if let Some(pipe) = self.state.pipe.as_mut() {
+            pipe.write(samples)?;
+        }
+        let out = self.state.out.as_mut().unwrap();
+        out.write_all(bytes)
+            .context("failed writing encoded clip")?;
+        self.state.streams += 1;
+        if self.state.stdout {
+            out.flush().context("failed flushing mp3 output")?;
+        }
+        self.state.layout.advance(frames);
+        Ok(())
+    }
+
+    /// Append `duration` of silence (all channels), counted like any other audio.
+    pub fn write_silence(&mut self, duration: Duration) -> anyhow::Result<()> {
+        let ch = self.state.config.channels as usize;
+        let frames = self
+            .state
+            .layout
+            .silence_frames(duration, self.state.lead_in_padding) as usize;
+        if frames == 0 {
+            return Ok(());
+        }
+        self.write_f32_interleaved(&vec![0.0; frames * ch])
+    }
+
+    /// Close the last segment and return the levels of every segment written.
+    pub fn finalize(mut self) -> anyhow::Result<Vec<SegmentLevels>> {
+        if let Some(limiter) = &self.state.limiter {
+            tracing::info!(
+                "Limiter reduced gain on {} samples ({:.2}%)",
+                limiter.engaged,
+                limiter.engaged as f64 * 100.0 / self.state.layout.total_samples.max(1) as f64
+            );
+        }
+        if self.state.clipped > 0 {
+            tracing::warn!(
+                "gain of {:+} dB clipped {:.2}% of samples; consider a limiter",
+                self.state.gain_db,
+                self.state.clipped as f64 * 100.0 / self.state.layout.total_samples as f64
+            );
+        }
+        self.finish_current()?;
+        if let Some(pipe) = self.state.pipe.take() {
+            pipe.finish()?;
+        }
+        Ok(self.state.levels)
+    }
+
+    /// Move the splitter to a blocking thread, fed through a queue of up to
+    /// `queue` clips, so encoding and file writes stay off the async runtime.
+    /// Only its state goes along; the thread creates the encoders it writes with,
+    /// so this has to come before anything is written.
+    pub fn spawn(self, queue: usize) -> BackgroundSplitter {
+        assert!(self.enc.is_none(), "spawn after writing");
+        let state = self.state;
+        let layout = state.layout.clone();
+        let lead_in_padding = state.lead_in_padding;
+        let music = state.music.is_some();
+        let announcer = state.announcer.clone();
+        let (tx, mut rx) = mpsc::channel::<WriteCmd>(queue);
+        let task = tokio::task::spawn_blocking(move || {
+            let mut splitter = Self { state, enc: None };
+            // Returning early drops the receiver, which the sender sees as a failure
+            while let Some(cmd) = rx.blocking_recv() {
+                match cmd {
+                    WriteCmd::Samples(samples) => splitter.write_f32_interleaved(&samples)?,
+                    WriteCmd::Encoded(samples, bytes) => {
+                        splitter.write_encoded(&samples, &bytes)?
+                    }
+                    WriteCmd::Silence(duration) => splitter.write_silence(duration)?,
+                    WriteCmd::StartGroup(prefix) => splitter.start_group(prefix)?,
+                    WriteCmd::MusicTail(fade) => splitter.write_music_tail(fade)?,
+                    WriteCmd::Announcement(samples) => {
+                        let ch = splitter.state.config.channels as u64;
+                        splitter
+                            .state
+                            .layout
+                            .announce
+                            .push(samples.len() as u64 / ch);
+                        splitter.state.announcements.push(samples);
+                    }
+                }
+            }
+            splitter.finalize()
+        });
+        BackgroundSplitter {
+            layout,
+            lead_in_padding,
+            music,
+            announcer,
+            tx,
+            task,
+        }
+    }
+}
 
-            let enc = self.enc.as_mut().unwrap();
-            let mp3_blocks = enc
-                .encode_interleaved(&self.pcm_i16)
-                .context("mp3 encode_interleaved failed")?;
+enum WriteCmd {
+    Samples(Vec<f32>),
+    Encoded(Vec<f32>, Vec<u8>),
+    Silence(Duration),
+    StartGroup(String),
+    MusicTail(Duration),
+    /// Opens the segments at the next index of every group.
+    Announcement(Vec<f32>),
+}
 
-            let out = self.out.as_mut().unwrap();
-            for b in mp3_blocks {
-                out.write_all(&b)
-                    .context("failed writing mp3 frame block")?;
+/// Async side of an [`Mp3Splitter`] running on its own thread. Positions are
+/// answered from a copy of the segment layout that follows every queued write,
+/// so they never wait for the encoder.
+pub struct BackgroundSplitter {
+    layout: Layout,
+    lead_in_padding: bool,
+    music: bool,
+    announcer: Option<Announcer>,
+    tx: mpsc::Sender<WriteCmd>,
+    task: tokio::task::JoinHandle<anyhow::Result<Vec<SegmentLevels>>>,
+}
+
+impl BackgroundSplitter {
+    async fn send(&mut self, cmd: WriteCmd) -> anyhow::Result<()> {
+        if self.tx.send(cmd).await.is_ok() {
+            return Ok(());
+        }
+        // The writer only hangs up after an error; surface it
+        match (&mut self.task).await {
+            Ok(Err(e)) => Err(e),
+            Ok(Ok(_)) => Err(anyhow::anyhow!("mp3 writer stopped unexpectedly")),
+            Err(e) => Err(e).context("mp3 writer panicked"),
+        }
+    }
+
+    /// Synthesize the announcements of the segments `write` opens, and of the one
+    /// the write after it would open, before `write` is recorded: they count toward
+    /// their segments, and positions must include them.
+    async fn announce_ahead(&mut self, write: impl Fn(&mut Layout)) -> anyhow::Result<()> {
+        let Some(announcer) = self.announcer.clone() else {
+            return Ok(());
+        };
+        loop {
+            let mut probe = self.layout.clone();
+            write(&mut probe);
+            let needed = probe.next_index() as usize + 1;
+            if needed <= self.layout.announce.len() {
+                return Ok(());
             }
+            for part in self.layout.announce.len()..needed {
+                let samples = announcer(part as u32 + 1).await.with_context(|| {
+                    format!("Failed to synthesize the part {} announcement", part + 1)
+                })?;
+                self.layout
+                    .announce
+                    .push(samples.len() as u64 / self.layout.channels);
+                self.send(WriteCmd::Announcement(samples)).await?;
+            }
+        }
+    }
 
-            self.written_frames += take_frames as u64;
-            frame_offset += take_frames;
+    /// Have the announcement of the first segment ready, so [`Self::position`]
+    /// counts it before anything is written.
+    pub async fn announce_first(&mut self) -> anyhow::Result<()> {
+        self.announce_ahead(|_| {}).await
+    }
 
-            // If we exactly filled the segment AND still have more input to write in this call,
-            // rotate immediately (so one input call can produce multiple files).
-            if self.written_frames == self.frames_per_file && frame_offset < total_frames {
-                self.open_next()?;
+    pub async fn write_f32_interleaved(&mut self, samples: Vec<f32>) -> anyhow::Result<()> {
+        let frames = samples.len() as u64 / self.layout.channels;
+        self.announce_ahead(|layout| layout.record_write(frames))
+            .await?;
+        self.layout.record_write(frames);
+        self.send(WriteCmd::Samples(samples)).await
+    }
+
+    pub async fn write_encoded(&mut self, samples: Vec<f32>, bytes: Vec<u8>) -> anyhow::Result<()> {
+        let frames = samples.len() as u64 / self.layout.channels;
+        self.announce_ahead(|layout| layout.record_clip(frames))
+            .await?;
+        self.layout.record_clip(frames);
+        self.send(WriteCmd::Encoded(samples, bytes)).await
+    }
+
+    pub async fn write_silence(&mut self, duration: Duration) -> anyhow::Result<()> {
+        let lead_in_padding = self.lead_in_padding;
+        self.announce_ahead(|layout| {
+            let frames = layout.silence_frames(duration, lead_in_padding);
+            if frames > 0 {
+                layout.record_write(frames);
             }
+        })
+        .await?;
+        let frames = self.layout.silence_frames(duration, self.lead_in_padding);
+        if frames == 0 {
+            return Ok(());
         }
+        self.layout.record_write(frames);
+        self.send(WriteCmd::Silence(duration)).await
+    }
 
-        Ok(())
+    pub async fn start_group(&mut self, prefix: impl Into<String>) -> anyhow::Result<()> {
+        let prefix = prefix.into();
+        self.layout.start_group(prefix.clone());
+        self.send(WriteCmd::StartGroup(prefix)).await
     }
 
-    /// Convenience for mono, like your original API.
-    pub fn write_f32_mono(&mut self, samples: &[f32]) -> anyhow::Result<()> {
-        anyhow::ensure!(
-            self.config.channels == 1,
-            "config.channels must be 1 for mono"
-        );
-        self.write_f32_interleaved(samples)
+    pub async fn write_music_tail(&mut self, fade: Duration) -> anyhow::Result<()> {
+        if !self.music {
+            return Ok(());
+        }
+        let frames = (fade.as_secs_f64() * self.layout.sample_rate as f64).round() as u64;
+        self.announce_ahead(|layout| layout.record_write(frames))
+            .await?;
+        self.layout.record_write(frames);
+        self.send(WriteCmd::MusicTail(fade)).await
+    }
+
+    pub fn position(&self) -> (String, Duration) {
+        self.layout.position()
+    }
+
+    pub fn segment_paths(&self) -> &[String] {
+        self.layout.segment_paths()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.layout.elapsed()
     }
 
-    pub fn finalize(mut self) -> anyhow::Result<()> {
-        self.finish_current()
+    /// Close the queue, wait for the writer to drain it and return the levels of
+    /// every segment written.
+    pub async fn finalize(self) -> anyhow::Result<Vec<SegmentLevels>> {
+        drop(self.tx);
+        self.task.await.context("mp3 writer panicked")?
     }
 }
 
-/// Example config matching your constants (24kHz mono).
-pub fn default_mono_24k_config(bitrate_kbps: u32) -> Mp3EncoderConfig {
+/// Encode a whole mono clip into a self-contained MP3 stream. The last frame is
+/// padded, so back-to-back clips gain up to one frame (24ms) of silence each.
+///
+/// ```
+/// use morganite::writer;
+///
+/// let second = vec![0.0; writer::SAMPLE_RATE as usize];
+/// let mp3 = writer::encode_clip(writer::default_mono_24k_config(64), &second).unwrap();
+/// // Starts on an MPEG frame sync
+/// assert_eq!((mp3[0], mp3[1] & 0xE0), (0xFF, 0xE0));
+/// ```
+pub fn encode_clip(config: Mp3EncoderConfig, samples: &[f32]) -> anyhow::Result<Vec<u8>> {
+    let mut enc = Mp3Encoder::new(config).context("create mp3 encoder")?;
+    let pcm = samples.iter().map(|&s| f32_to_i16(s)).collect::<Vec<_>>();
+    let mut bytes = Vec::new();
+    for b in enc
+        .encode_interleaved(&pcm)
+        .context("mp3 encode_interleaved failed")?
+    {
+        bytes.extend_from_slice(&b);
+    }
+    bytes.extend(enc.finish().context("mp3 encoder finish failed")?);
+    Ok(bytes)
+}
+
+/// Encode a whole mono clip into a single MP3 file.
+pub fn write_mp3(
+    path: &std::path::Path,
+    config: Mp3EncoderConfig,
+    samples: &[f32],
+) -> anyhow::Result<()> {
+    let bytes = encode_clip(config, samples)?;
+    let file = File::create(path).with_context(|| format!("create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    out.write_all(&bytes)
+        .context("failed writing mp3 frame block")?;
+    out.flush().context("failed flushing mp3 output")
+}
+
+/// Minimal ID3v2.4 tag holding UTF-8 text frames.
+fn id3v2_tag(frames: &[(&str, String)]) -> Vec<u8> {
+    fn syncsafe(n: usize) -> [u8; 4] {
+        [
+            (n >> 21) as u8 & 0x7f,
+            (n >> 14) as u8 & 0x7f,
+            (n >> 7) as u8 & 0x7f,
+            n as u8 & 0x7f,
+        ]
+    }
+
+    let mut body = Vec::new();
+    for (id, value) in frames {
+        body.extend_from_slice(id.as_bytes());
+        body.extend_from_slice(&syncsafe(value.len() + 1));
+        body.extend_from_slice(&[0, 0, 3]); // no flags, UTF-8
+        body.extend_from_slice(value.as_bytes());
+    }
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend_from_slice(&syncsafe(body.len()));
+    tag.extend(body);
+    tag
+}
+
+/// MPEG channel mode the encoder writes. The audio is mono, so the other modes
+/// only pass validation once there is stereo output to use them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ChannelMode {
+    #[default]
+    Mono,
+    Stereo,
+    JointStereo,
+    DualChannel,
+}
+
+impl ChannelMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelMode::Mono => "mono",
+            ChannelMode::Stereo => "stereo",
+            ChannelMode::JointStereo => "joint stereo",
+            ChannelMode::DualChannel => "dual channel",
+        }
+    }
+
+    fn stereo_mode(self) -> StereoMode {
+        match self {
+            ChannelMode::Mono => StereoMode::Mono,
+            ChannelMode::Stereo => StereoMode::Stereo,
+            ChannelMode::JointStereo => StereoMode::JointStereo,
+            ChannelMode::DualChannel => StereoMode::DualChannel,
+        }
+    }
+}
+
+/// What the samples go through before the encoder. shine has no quality or
+/// complexity setting of its own, and spreads its bits over the whole band.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum EncoderQuality {
+    /// Samples go to the encoder as they are.
+    #[default]
+    Standard,
+    /// Low-pass first, lower the lower the bitrate, so the bits go to the speech
+    /// band rather than to artifacts above it.
+    High,
+}
+
+impl EncoderQuality {
+    pub fn name(self) -> &'static str {
+        match self {
+            EncoderQuality::Standard => "standard",
+            EncoderQuality::High => "high",
+        }
+    }
+
+    /// The filter to run ahead of a `bitrate_kbps` encoder, if any.
+    pub fn low_pass(self, bitrate_kbps: u32) -> Option<dsp::LowPass> {
+        let cutoff_hz = match (self, bitrate_kbps) {
+            (EncoderQuality::Standard, _) => return None,
+            (EncoderQuality::High, ..=32) => 7_000.0,
+            (EncoderQuality::High, 33..=48) => 9_000.0,
+            (EncoderQuality::High, _) => 11_000.0,
+        };
+        Some(dsp::LowPass::new(cutoff_hz, SAMPLE_RATE))
+    }
+}
+
+/// Encoder config for our 24kHz audio in `mode`.
+pub fn mp3_config(bitrate_kbps: u32, mode: ChannelMode) -> Mp3EncoderConfig {
     Mp3EncoderConfig::new()
         .sample_rate(SAMPLE_RATE)
         .bitrate(bitrate_kbps)
         .channels(CHANNELS)
-        .stereo_mode(StereoMode::Mono)
+        .stereo_mode(mode.stereo_mode())
+}
+
+/// Example config matching your constants (24kHz mono).
+pub fn default_mono_24k_config(bitrate_kbps: u32) -> Mp3EncoderConfig {
+    mp3_config(bitrate_kbps, ChannelMode::Mono)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh scratch folder for one test.
+    fn scratch(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("morganite-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn splitter(prefix: &std::path::Path) -> Mp3Splitter {
+        Mp3Splitter::new(
+            prefix.to_str().unwrap(),
+            default_mono_24k_config(64),
+            Duration::from_secs(1),
+        )
+        .unwrap()
+    }
+
+    fn clip(ms: usize) -> Vec<f32> {
+        (0..SAMPLE_RATE as usize * ms / 1000)
+            .map(|i| 0.3 * (i as f32 * 0.05).sin())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn background_writer_matches_the_splitter() {
+        let dir = scratch("writer-match");
+        let pause = Duration::from_millis(300);
+
+        let mut direct = splitter(&dir.join("direct"));
+        let mut background = splitter(&dir.join("background")).spawn(2);
+        for ms in [700, 450, 900] {
+            direct.write_f32_interleaved(&clip(ms)).unwrap();
+            direct.write_silence(pause).unwrap();
+            background.write_f32_interleaved(clip(ms)).await.unwrap();
+            background.write_silence(pause).await.unwrap();
+        }
+        let position = background.position();
+        let direct = direct.finalize().unwrap();
+        let background = background.finalize().await.unwrap();
+
+        assert_eq!(direct.len(), background.len());
+        assert_eq!(position.0, background.last().unwrap().segment);
+        for (a, b) in direct.iter().zip(&background) {
+            assert_eq!(a.frames, b.frames);
+            assert_eq!(
+                std::fs::read(&a.segment).unwrap(),
+                std::fs::read(&b.segment).unwrap()
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn announcements_come_before_encoded_clips() {
+        let dir = scratch("writer-announce");
+        let config = default_mono_24k_config(64);
+        let announcement = clip(200);
+        let announcer: Announcer = {
+            let announcement = announcement.clone();
+            Arc::new(move |_| {
+                let announcement = announcement.clone();
+                Box::pin(async move { Ok(announcement) })
+            })
+        };
+        let mut background = splitter(&dir.join("announce"))
+            .with_announcer(Some(announcer))
+            .spawn(2);
+        let samples = clip(700);
+        let bytes = encode_clip(config.clone(), &samples).unwrap();
+        for _ in 0..2 {
+            background
+                .write_encoded(samples.clone(), bytes.clone())
+                .await
+                .unwrap();
+        }
+        let segments = background.finalize().await.unwrap();
+
+        // Each clip opens a segment, right after that segment's announcement
+        let announced = encode_clip(config, &announcement).unwrap();
+        assert_eq!(segments.len(), 2);
+        for segment in &segments {
+            let file = std::fs::read(&segment.segment).unwrap();
+            assert!(file.starts_with(&announced), "{}", segment.segment);
+            assert_eq!(
+                &file[announced.len()..announced.len() + bytes.len()],
+                &bytes[..],
+                "{}",
+                segment.segment
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finalize_drains_every_queued_write() {
+        let dir = scratch("writer-drain");
+        let mut background = splitter(&dir.join("drain")).spawn(1);
+        for _ in 0..20 {
+            background.write_f32_interleaved(clip(100)).await.unwrap();
+        }
+        let segments = background.finalize().await.unwrap();
+        let frames: u64 = segments.iter().map(|s| s.frames).sum();
+        assert_eq!(frames, 20 * clip(100).len() as u64);
+        assert_eq!(segments.len(), 2);
+        assert!(
+            segments
+                .iter()
+                .all(|s| std::path::Path::new(&s.segment).exists())
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_errors_reach_the_async_side() {
+        let dir = scratch("writer-error");
+        let mut background = splitter(&dir.join("missing").join("audio")).spawn(1);
+        let mut result = Ok(());
+        for _ in 0..4 {
+            result = background.write_f32_interleaved(clip(100)).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        let error = match result {
+            Err(e) => e,
+            Ok(()) => background.finalize().await.unwrap_err(),
+        };
+        assert!(format!("{error:#}").contains("create"), "{error:#}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_high_quality_filters_before_the_encoder() {
+        assert!(EncoderQuality::Standard.low_pass(32).is_none());
+        for bitrate in [32, 48, 64, 128] {
+            assert!(
+                EncoderQuality::High.low_pass(bitrate).is_some(),
+                "{bitrate}"
+            );
+        }
+    }
 }